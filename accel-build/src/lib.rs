@@ -0,0 +1,380 @@
+//! Compile GPGPU kernels in `build.rs`
+//! ------------------------------------
+//!
+//! `#[kernel]` proc-macro runs the nvptx toolchain during macro expansion,
+//! which is also triggered by `cargo check` and rust-analyzer.
+//! This crate compiles kernels ahead of time in the build script instead.
+//!
+//! Kernels are written in separate source files, which are not modules of the host crate:
+//!
+//! ```ignore
+//! // kernels/add.rs
+//! #[kernel]
+//! unsafe fn add(a: *const f64, b: *const f64, c: *mut f64, n: usize) {
+//!     let i = accel_core::index();
+//!     if (i as usize) < n {
+//!         *c.offset(i) = *a.offset(i) + *b.offset(i);
+//!     }
+//! }
+//! ```
+//!
+//! [Builder] collects `#[kernel]` functions in these files, compiles all of them by a single cargo invocation,
+//! and writes `OUT_DIR/kernels.rs` containing the same items as `#[kernel]` generates,
+//! i.e. `mod add { PTX_STR, Module }` and the caller function `add`.
+//! The PTX of all kernels is embedded once as `PTX_STR`, and `add::PTX_STR` refers to it:
+//!
+//! ```no_run
+//! // in `main` of build.rs
+//! accel_build::Builder::new()
+//!     .file("kernels/add.rs")
+//!     .build()
+//!     .expect("Failed to compile kernels");
+//! ```
+//!
+//! ```ignore
+//! // src/lib.rs
+//! include!(concat!(env!("OUT_DIR"), "/kernels.rs"));
+//! ```
+//!
+//! Items in the kernel files other than `#[kernel]` functions, e.g. helper functions,
+//! are compiled into the device code as a module named by the file stem.
+//! `#[dependencies(...)]` of all kernels are merged into a single manifest.
+
+use accel_codegen::{host, parser::MetaData};
+use failure::*;
+use quote::quote;
+use std::{
+    collections::{BTreeSet, HashMap},
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const NIGHTLY_VERSION: &str = "nightly-2020-09-20";
+const TARGET: &str = "nvptx64-nvidia-cuda";
+
+/// Name of the generated device crate
+const DEVICE_CRATE: &str = "accel_kernels";
+
+/// Kernel source file
+#[derive(Debug)]
+struct KernelFile {
+    /// Module name in the device crate
+    name: syn::Ident,
+    /// `#[kernel]` functions
+    kernels: Vec<syn::ItemFn>,
+    /// Other items
+    items: Vec<syn::Item>,
+}
+
+fn is_kernel(func: &syn::ItemFn) -> bool {
+    func.attrs.iter().any(|attr| {
+        attr.path
+            .segments
+            .last()
+            .map_or(false, |seg| seg.ident == "kernel")
+    })
+}
+
+/// `use accel_derive::kernel;` is meaningless in device code
+fn is_host_use(item: &syn::ItemUse) -> bool {
+    match &item.tree {
+        syn::UseTree::Path(path) => path.ident == "accel_derive" || path.ident == "accel",
+        _ => false,
+    }
+}
+
+impl KernelFile {
+    fn parse(path: &Path, source: &str) -> Fallible<Self> {
+        let file = syn::parse_file(source)
+            .map_err(|e| format_err!("Cannot parse {}: {}", path.display(), e))?;
+        let stem = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| format_err!("Invalid kernel file name: {}", path.display()))?;
+        let stem: String = stem
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let mut kernels = Vec::new();
+        let mut items = Vec::new();
+        for item in file.items {
+            match item {
+                syn::Item::Fn(func) if is_kernel(&func) => kernels.push(func),
+                syn::Item::Use(ref u) if is_host_use(u) => {}
+                _ => items.push(item),
+            }
+        }
+        Ok(KernelFile {
+            name: syn::Ident::new(&format!("_{}", stem), proc_macro2::Span::call_site()),
+            kernels,
+            items,
+        })
+    }
+
+    /// Module in the device crate
+    fn device_module(&self) -> proc_macro2::TokenStream {
+        let name = &self.name;
+        let items = &self.items;
+        let kernels = self.kernels.iter().map(|func| {
+            let ident = &func.sig.ident;
+            let unsafety = &func.sig.unsafety;
+            let block = &func.block;
+            let output = &func.sig.output;
+            let mut inputs = func.sig.inputs.clone();
+            inputs.iter_mut().for_each(|i| {
+                if let syn::FnArg::Typed(arg) = i {
+                    arg.attrs.retain(|a| !a.path.is_ident("type_substitute"));
+                }
+            });
+            quote! {
+                #[no_mangle]
+                pub #unsafety extern "ptx-kernel" fn #ident(#inputs) #output #block
+            }
+        });
+        quote! {
+            mod #name {
+                #![allow(unused_imports)]
+                use super::*;
+                #(#items)*
+                #(#kernels)*
+            }
+        }
+    }
+}
+
+/// Generate `lib.rs` of the device crate
+fn device_lib_rs(files: &[KernelFile]) -> String {
+    let modules = files.iter().map(|file| file.device_module());
+    quote! {
+        #![feature(abi_ptx, stdsimd, alloc_error_handler)]
+        #![no_std]
+        extern crate alloc;
+        #[global_allocator]
+        static _GLOBAL_ALLOCATOR: accel_core::PTXAllocator = accel_core::PTXAllocator;
+        #(#modules)*
+        #[panic_handler]
+        fn panic(_info: &::core::panic::PanicInfo) -> ! {
+            unsafe { ::core::arch::nvptx::trap() }
+        }
+        #[alloc_error_handler]
+        fn alloc_error_handler(_: core::alloc::Layout) -> ! {
+            unsafe { ::core::arch::nvptx::trap() }
+        }
+    }
+    .to_string()
+}
+
+/// Generate `kernels.rs` included into the host crate
+fn host_kernels_rs(ptx: &str, files: &[KernelFile]) -> String {
+    let funcs: Vec<syn::ItemFn> = files
+        .iter()
+        .flat_map(|file| file.kernels.iter().cloned())
+        .collect();
+    host::funcs2callers(ptx, &funcs).to_string()
+}
+
+fn check_run(command: &mut Command) -> Fallible<()> {
+    // Filter envs set by cargo for this build script, e.g. `RUSTC` of the host toolchain
+    let filtered_env: HashMap<String, String> = env::vars()
+        .filter(|(k, _)| !(k.starts_with("CARGO") || k.starts_with("RUSTC") || k == "OUT_DIR"))
+        .collect();
+    let output = command.env_clear().envs(&filtered_env).output()?;
+    if !output.status.success() {
+        bail!(
+            "External command failed: {:?}\n{}",
+            command,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(())
+}
+
+/// Compile `#[kernel]` functions in `build.rs`
+#[derive(Debug, Clone)]
+pub struct Builder {
+    files: Vec<PathBuf>,
+    toolchain: String,
+    out_dir: Option<PathBuf>,
+    out_file: String,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder {
+            files: Vec::new(),
+            toolchain: NIGHTLY_VERSION.into(),
+            out_dir: None,
+            out_file: "kernels.rs".into(),
+        }
+    }
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a kernel source file, relative to `CARGO_MANIFEST_DIR`
+    pub fn file(mut self, path: impl AsRef<Path>) -> Self {
+        self.files.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Nightly toolchain used for nvptx target, `nightly-2020-09-20` by default
+    pub fn toolchain(mut self, toolchain: &str) -> Self {
+        self.toolchain = toolchain.into();
+        self
+    }
+
+    /// Output directory, `OUT_DIR` by default
+    pub fn out_dir(mut self, out_dir: impl AsRef<Path>) -> Self {
+        self.out_dir = Some(out_dir.as_ref().to_owned());
+        self
+    }
+
+    /// Name of the generated host code, `kernels.rs` by default
+    pub fn out_file(mut self, name: &str) -> Self {
+        self.out_file = name.into();
+        self
+    }
+
+    /// Compile kernels, and returns the path of the generated host code
+    pub fn build(self) -> Fallible<PathBuf> {
+        let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR")?);
+        let out_dir = match self.out_dir {
+            Some(out_dir) => out_dir,
+            None => PathBuf::from(env::var("OUT_DIR")?),
+        };
+
+        let mut files = Vec::new();
+        let mut metas = Vec::new();
+        let mut names = BTreeSet::new();
+        for path in &self.files {
+            let path = manifest_dir.join(path);
+            println!("cargo:rerun-if-changed={}", path.display());
+            let file = KernelFile::parse(&path, &fs::read_to_string(&path)?)?;
+            for func in &file.kernels {
+                if !names.insert(func.sig.ident.to_string()) {
+                    bail!("Kernel `{}` is defined twice", func.sig.ident);
+                }
+                metas.push(MetaData::from_token(func)?);
+            }
+            files.push(file);
+        }
+        let meta = MetaData::merge(DEVICE_CRATE, &metas, &manifest_dir)?;
+
+        // Create device crate
+        let dir = out_dir.join(DEVICE_CRATE);
+        fs::create_dir_all(dir.join("src"))?;
+        let mut lib_rs = fs::File::create(dir.join("src/lib.rs"))?;
+        lib_rs.write_all(device_lib_rs(&files).as_bytes())?;
+        let mut cargo_toml = fs::File::create(dir.join("Cargo.toml"))?;
+        cargo_toml.write_all(toml::to_string(&meta)?.as_bytes())?;
+
+        // Build all kernels at once
+        check_run(
+            Command::new("cargo")
+                .args(&[
+                    &format!("+{}", self.toolchain),
+                    "build",
+                    "--release",
+                    "--target",
+                    TARGET,
+                ])
+                .current_dir(&dir),
+        )?;
+        let ptx = fs::read_to_string(
+            dir.join(format!("target/{}/release/{}.ptx", TARGET, DEVICE_CRATE)),
+        )?;
+
+        let kernels_rs = out_dir.join(&self.out_file);
+        fs::write(&kernels_rs, host_kernels_rs(&ptx, &files))?;
+        Ok(kernels_rs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"
+    use accel_derive::kernel;
+    use core::ops::Add;
+
+    fn helper(a: f32) -> f32 { a }
+
+    #[kernel]
+    #[dependencies("accel-core" = "0.3.0-alpha.4")]
+    unsafe fn add(a: *const f32, b: *mut f32, n: usize) {}
+
+    #[accel_derive::kernel]
+    fn do_nothing() {}
+    "#;
+
+    #[test]
+    fn parse() {
+        let file = KernelFile::parse(Path::new("kernels/add-f32.rs"), SOURCE).unwrap();
+        assert_eq!(file.name, "_add_f32");
+        let names: Vec<_> = file
+            .kernels
+            .iter()
+            .map(|f| f.sig.ident.to_string())
+            .collect();
+        assert_eq!(names, vec!["add", "do_nothing"]);
+        // `use accel_derive::kernel` is dropped
+        assert_eq!(file.items.len(), 2);
+    }
+
+    #[test]
+    fn device_lib_rs() {
+        let file = KernelFile::parse(Path::new("kernels/add.rs"), SOURCE).unwrap();
+        let lib_rs: syn::File = syn::parse_str(&super::device_lib_rs(&[file])).unwrap();
+        let lib_rs = quote! { #lib_rs }.to_string();
+        assert!(lib_rs.contains("extern \"ptx-kernel\" fn add"));
+        assert!(lib_rs.contains("extern \"ptx-kernel\" fn do_nothing"));
+        assert!(!lib_rs.contains("dependencies"));
+        assert!(!lib_rs.contains("accel_derive"));
+    }
+
+    #[test]
+    fn host_kernels_rs() {
+        let add = KernelFile::parse(Path::new("kernels/add.rs"), SOURCE).unwrap();
+        let sub = KernelFile::parse(
+            Path::new("kernels/sub.rs"),
+            "#[kernel] unsafe fn sub(a: *const f32, b: *mut f32, n: usize) {}",
+        )
+        .unwrap();
+        let ptx = "// PTX of all kernels";
+        let kernels_rs = super::host_kernels_rs(ptx, &[add, sub]);
+        // PTX is embedded only once
+        assert_eq!(kernels_rs.matches(ptx).count(), 1);
+        assert_eq!(kernels_rs.matches("super :: PTX_STR").count(), 3);
+
+        // `PTX_STR`, and a submodule and a caller for each kernel
+        let file: syn::File = syn::parse_str(&kernels_rs).unwrap();
+        let items: Vec<_> = file
+            .items
+            .iter()
+            .map(|item| match item {
+                syn::Item::Const(c) => format!("const {}", c.ident),
+                syn::Item::Mod(m) => format!("mod {}", m.ident),
+                syn::Item::Fn(f) => format!("fn {}", f.sig.ident),
+                _ => panic!("Unexpected item: {}", quote! { #item }),
+            })
+            .collect();
+        assert_eq!(
+            items,
+            vec![
+                "const PTX_STR",
+                "mod add",
+                "fn add",
+                "mod do_nothing",
+                "fn do_nothing",
+                "mod sub",
+                "fn sub",
+            ]
+        );
+    }
+}
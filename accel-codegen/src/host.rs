@@ -0,0 +1,398 @@
+use crate::signature;
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+
+/// Split out types from function definition
+///
+/// - Reference type, e.g. `&i32` will be modified into lifetimed reference `&'arg i32`
+///
+fn input_types(func: &syn::ItemFn) -> Vec<syn::Type> {
+    func.sig
+        .inputs
+        .iter()
+        .map(|arg| match arg {
+            syn::FnArg::Typed(ref val) => {
+                let attrs = val.attrs.clone();
+                let mut ty = *val.ty.clone();
+
+                attrs.iter().for_each(|a| {
+                    if a.path.is_ident("type_substitute") {
+                        ty = syn::parse2(a.tokens.clone()).expect("not a type");
+                    }
+                });
+                match &mut ty {
+                    syn::Type::Reference(re) => {
+                        re.lifetime = Some(syn::Lifetime::new("'arg", Span::call_site()))
+                    }
+                    _ => {}
+                }
+                ty
+            }
+            _ => panic!("Unsupported kernel input type sigunature"),
+        })
+        .collect()
+}
+
+fn accel_path() -> String {
+    if let Ok(name) = proc_macro_crate::crate_name("accel") {
+        // accel exists as an external crate
+        return name;
+    }
+
+    if std::env::var("CARGO_PKG_NAME").unwrap() == "accel" {
+        // doctest in accel
+        //
+        // "--crate-type bin" should be specified for doctest
+        let mut find_flag = false;
+        for arg in std::env::args() {
+            if arg == "--crate-type" {
+                find_flag = true;
+            }
+            if find_flag {
+                if arg == "bin" {
+                    return "accel".into();
+                }
+            }
+        }
+
+        // in accel crate
+        return "crate".into();
+    }
+    unreachable!("Cannot determine accel crate name");
+}
+
+/// `ptx` is the value of `PTX_STR` in the submodule, which is verified against `ptx_str`
+fn impl_submodule(ptx_str: &str, ptx: TokenStream, func: &syn::ItemFn) -> TokenStream {
+    let input_types = input_types(func);
+    let signature_check = signature::verify(ptx_str, func, &input_types);
+    let accel = accel_path();
+
+    let launchable: syn::Path = syn::parse_str(&format!(
+        "{}::execution::Launchable{}",
+        accel,
+        input_types.len()
+    ))
+    .unwrap();
+
+    let targets: Vec<syn::Ident> = (1..=input_types.len())
+        .into_iter()
+        .map(|k| syn::Ident::new(&format!("Target{}", k), Span::call_site()))
+        .collect();
+
+    let ident = &func.sig.ident;
+
+    let accel = syn::Ident::new(&accel, Span::call_site());
+    let kernel_name = quote! { #ident }.to_string();
+    quote! {
+        /// Auto-generated by accel-derive
+        mod #ident {
+            pub const PTX_STR: &'static str = #ptx;
+
+            #signature_check
+
+            pub struct Module(::std::sync::Arc<#accel::Module>);
+
+            impl Module {
+                /// Get the module loaded into the context, which is loaded only at the first call
+                pub fn new(ctx: &#accel::Context) -> #accel::error::Result<Self> {
+                    Ok(Module(#accel::Module::cached(ctx, PTX_STR)?))
+                }
+            }
+
+            impl<'arg> #launchable <'arg> for Module {
+                #(
+                    type #targets = #input_types;
+                )*
+                fn get_kernel(&self) -> #accel::error::Result<#accel::Kernel> {
+                    Ok(self.0.get_kernel(#kernel_name)?)
+                }
+            }
+        }
+    }
+}
+
+/// Caller function of a kernel
+///
+/// `module` is an expression loading the module, and `launcher` is the launcher of the kernel got from it
+fn caller(
+    func: &syn::ItemFn,
+    vis: &syn::Visibility,
+    module: TokenStream,
+    launcher: TokenStream,
+) -> TokenStream {
+    let accel = accel_path();
+    let ident = &func.sig.ident;
+    let fn_token = &func.sig.fn_token;
+
+    let input_types = input_types(func);
+
+    let args_types: Vec<syn::Ident> = (1..=input_types.len())
+        .into_iter()
+        .map(|k| syn::Ident::new(&format!("Arg{}", k), Span::call_site()))
+        .collect();
+
+    let launchable: syn::Path = syn::parse_str(&format!(
+        "{}::execution::Launchable{}",
+        accel,
+        input_types.len()
+    ))
+    .unwrap();
+
+    let accel = syn::Ident::new(&accel, Span::call_site());
+
+    quote! {
+        #vis #fn_token #ident<'arg, #(#args_types),* >(
+            ctx: &#accel::Context,
+            grid: impl Into<#accel::Grid>,
+            block: impl Into<#accel::Block>,
+            args: (#(#args_types,)*)
+        ) -> #accel::error::Result<()>
+        where
+            #(
+                #args_types: #accel::execution::DeviceSend<Target = #input_types>
+            ),*
+        {
+            use #launchable;
+            let module = #module;
+            #launcher.launch(grid, block, args)?;
+            Ok(())
+        }
+    }
+}
+
+/// Caller of `#[kernel]`, which loads the module in the submodule of the same name
+fn func_caller(func: &syn::ItemFn) -> TokenStream {
+    let ident = &func.sig.ident;
+    caller(
+        func,
+        &func.vis,
+        quote! { #ident::Module::new(ctx)? },
+        quote! { module },
+    )
+}
+
+/// Launcher of an entry in `#[kernel_mod]`
+fn impl_launcher(func: &syn::ItemFn) -> TokenStream {
+    let input_types = input_types(func);
+    let accel = accel_path();
+
+    let launchable: syn::Path = syn::parse_str(&format!(
+        "{}::execution::Launchable{}",
+        accel,
+        input_types.len()
+    ))
+    .unwrap();
+
+    let targets: Vec<syn::Ident> = (1..=input_types.len())
+        .map(|k| syn::Ident::new(&format!("Target{}", k), Span::call_site()))
+        .collect();
+
+    let ident = &func.sig.ident;
+    let accel = syn::Ident::new(&accel, Span::call_site());
+    let kernel_name = ident.to_string();
+    quote! {
+        pub mod #ident {
+            use super::*;
+
+            pub struct Launcher<'module>(pub(super) &'module #accel::Module);
+
+            impl<'module, 'arg> #launchable <'arg> for Launcher<'module> {
+                #(
+                    type #targets = #input_types;
+                )*
+                fn get_kernel(&self) -> #accel::error::Result<#accel::Kernel> {
+                    Ok(self.0.get_kernel(#kernel_name)?)
+                }
+            }
+        }
+    }
+}
+
+/// Generate a module for `#[kernel_mod]`
+///
+/// All kernels are compiled into a single PTX, and launched through a `Module` which is loaded once.
+/// `content` is the host copy of non-kernel items in the module, e.g. types of kernel arguments.
+pub fn mod2module(
+    ptx_str: &str,
+    module: &syn::ItemMod,
+    funcs: &[syn::ItemFn],
+    content: &[syn::Item],
+) -> TokenStream {
+    let accel = syn::Ident::new(&accel_path(), Span::call_site());
+    let vis = &module.vis;
+    let ident = &module.ident;
+    let signature_checks = funcs
+        .iter()
+        .map(|func| signature::verify(ptx_str, func, &input_types(func)));
+    let launchers = funcs.iter().map(impl_launcher);
+    let pub_vis: syn::Visibility = syn::parse_quote! { pub };
+    let callers = funcs.iter().map(|func| {
+        let kernel = &func.sig.ident;
+        caller(
+            func,
+            &pub_vis,
+            quote! { Module::new(ctx)? },
+            quote! { module.#kernel() },
+        )
+    });
+    let kernels: Vec<&syn::Ident> = funcs.iter().map(|func| &func.sig.ident).collect();
+    let docs = kernels
+        .iter()
+        .map(|kernel| format!("Launcher of `{}` kernel", kernel));
+    quote! {
+        /// Auto-generated by accel-derive
+        #vis mod #ident {
+            #![allow(unused)]
+            use super::*;
+
+            #(#content)*
+
+            pub const PTX_STR: &'static str = #ptx_str;
+
+            #(#signature_checks)*
+
+            pub struct Module(::std::sync::Arc<#accel::Module>);
+
+            impl Module {
+                /// Get the module loaded into the context, which is loaded only at the first call
+                pub fn new(ctx: &#accel::Context) -> #accel::error::Result<Self> {
+                    Ok(Module(#accel::Module::cached(ctx, PTX_STR)?))
+                }
+
+                #(
+                    #[doc = #docs]
+                    pub fn #kernels(&self) -> #kernels::Launcher<'_> {
+                        #kernels::Launcher(&*self.0)
+                    }
+                )*
+            }
+
+            #(#launchers)*
+
+            #(#callers)*
+        }
+    }
+}
+
+pub fn func2caller(ptx_str: &str, func: &syn::ItemFn) -> TokenStream {
+    let impl_submodule = impl_submodule(ptx_str, quote! { #ptx_str }, func);
+    let caller = func_caller(func);
+    quote! {
+        #impl_submodule
+        #caller
+    }
+}
+
+/// Generate callers of kernels compiled into a single PTX
+///
+/// The PTX is embedded only once as `PTX_STR`, and referred from the submodule of each kernel.
+pub fn funcs2callers(ptx_str: &str, funcs: &[syn::ItemFn]) -> TokenStream {
+    let callers = funcs.iter().map(|func| {
+        let impl_submodule = impl_submodule(ptx_str, quote! { super::PTX_STR }, func);
+        let caller = func_caller(func);
+        quote! {
+            #impl_submodule
+            #caller
+        }
+    });
+    quote! {
+        /// PTX of all kernels, auto-generated by accel-build
+        pub const PTX_STR: &'static str = #ptx_str;
+
+        #(#callers)*
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use std::{
+        io::Write,
+        process::{Command, Stdio},
+    };
+
+    const TEST_KERNEL: &'static str = r#"
+    fn kernel_name(arg1: i32, arg2: f64) {}
+    "#;
+
+    /// Format TokenStream by rustfmt
+    ///
+    /// This can test if the input TokenStream is valid in terms of rustfmt.
+    fn pretty_print(tt: &impl ToString) -> Result<()> {
+        let mut fmt = Command::new("rustfmt")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        fmt.stdin
+            .as_mut()
+            .unwrap()
+            .write(tt.to_string().as_bytes())?;
+        let out = fmt.wait_with_output()?;
+        println!("{}", String::from_utf8_lossy(&out.stdout));
+        Ok(())
+    }
+
+    #[test]
+    fn impl_submodule() -> Result<()> {
+        let func: syn::ItemFn = syn::parse_str(TEST_KERNEL)?;
+        let ts = super::impl_submodule("", quote::quote! { "" }, &func);
+        pretty_print(&ts)?;
+        Ok(())
+    }
+
+    #[test]
+    fn mod2module() -> Result<()> {
+        let module: syn::ItemMod = syn::parse_str(
+            r#"
+            mod kernels {
+                struct Point { x: f32, y: f32 }
+                #[kernel_func]
+                fn kernel_name(arg1: i32, arg2: f64) {}
+                #[kernel_func]
+                fn other(p: Point) {}
+            }
+            "#,
+        )?;
+        let (_, items) = module.content.as_ref().unwrap();
+        let content = vec![items[0].clone()];
+        let funcs: Vec<syn::ItemFn> = items[1..]
+            .iter()
+            .map(|item| match item {
+                syn::Item::Fn(func) => func.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+        let ts = super::mod2module("", &module, &funcs, &content);
+        pretty_print(&ts)?;
+        let ts = ts.to_string();
+        assert!(ts.contains("Launchable2"));
+        assert!(ts.contains("Launchable1"));
+        // Caller functions are kept next to launchers
+        assert!(ts.contains("pub fn kernel_name < 'arg"));
+        assert!(ts.contains("pub fn other < 'arg"));
+        Ok(())
+    }
+
+    #[test]
+    fn funcs2callers() -> Result<()> {
+        let funcs: Vec<syn::ItemFn> = vec![
+            syn::parse_str(TEST_KERNEL)?,
+            syn::parse_str("fn other(arg1: i32) {}")?,
+        ];
+        let ptx = "// PTX of both kernels";
+        let ts = super::funcs2callers(ptx, &funcs);
+        pretty_print(&ts)?;
+        let ts = ts.to_string();
+        assert_eq!(ts.matches(ptx).count(), 1);
+        assert_eq!(ts.matches("super :: PTX_STR").count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn caller() -> Result<()> {
+        let func: syn::ItemFn = syn::parse_str(TEST_KERNEL)?;
+        let ts = super::func_caller(&func);
+        pretty_print(&ts)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,9 @@
+//! Host code generation shared by accel-derive and accel-build
+//!
+//! This crate is an implementation detail of `#[kernel]` proc-macro and `accel_build::Builder`,
+//! and not intended to be used directly.
+
+pub mod host;
+pub mod parser;
+pub mod ptx;
+pub mod signature;
@@ -1,22 +1,25 @@
 use failure::*;
-use maplit::hashmap;
+use maplit::btreemap;
 use quote::ToTokens;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
 #[derive(Debug, Serialize)]
 pub struct MetaData {
-    package: HashMap<&'static str, String>,
-    lib: HashMap<&'static str, Vec<&'static str>>,
-    dependencies: HashMap<String, Depenency>,
+    package: BTreeMap<&'static str, String>,
+    lib: BTreeMap<&'static str, Vec<&'static str>>,
+    dependencies: BTreeMap<String, Depenency>,
 }
 
 impl MetaData {
     fn new(name: &str) -> Self {
         MetaData {
-            package: hashmap! { "version" => "0.0.0".into(), "name" => name.into(), "edition" => "2018".into() },
-            lib: hashmap! { "crate-type" => vec![ "cdylib" ] },
-            dependencies: HashMap::new(),
+            package: btreemap! { "version" => "0.0.0".into(), "name" => name.into(), "edition" => "2018".into() },
+            lib: btreemap! { "crate-type" => vec![ "cdylib" ] },
+            dependencies: BTreeMap::new(),
         }
     }
 
@@ -24,6 +27,53 @@ impl MetaData {
         &self.package["name"]
     }
 
+    /// Paths of dependencies given by `path = "..."`
+    pub fn local_dependencies(&self) -> Vec<PathBuf> {
+        self.dependencies
+            .values()
+            .filter_map(|dep| match dep {
+                Depenency::Path { path, .. } => Some(PathBuf::from(path)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Merge manifests of kernels into the one of a crate which builds all of them at once
+    ///
+    /// Relative paths of local dependencies are resolved from `base_dir`.
+    pub fn merge(name: &str, kernels: &[MetaData], base_dir: &Path) -> Fallible<Self> {
+        let mut merged = MetaData::new(name);
+        for kernel in kernels {
+            for (key, dep) in &kernel.dependencies {
+                let dep = match dep {
+                    Depenency::Path {
+                        path,
+                        default_features,
+                        features,
+                    } => Depenency::Path {
+                        path: base_dir.join(path).to_string_lossy().into(),
+                        default_features: *default_features,
+                        features: features.clone(),
+                    },
+                    _ => dep.clone(),
+                };
+                match merged.dependencies.get(key) {
+                    Some(prev) if *prev != dep => bail!(
+                        "Dependency `{}` of kernel `{}` conflicts with other kernels: {:?} != {:?}",
+                        key,
+                        kernel.name(),
+                        dep,
+                        prev
+                    ),
+                    _ => {
+                        merged.dependencies.insert(key.clone(), dep);
+                    }
+                }
+            }
+        }
+        Ok(merged)
+    }
+
     pub fn from_token(func: &syn::ItemFn) -> Fallible<Self> {
         let attrs = &func.attrs;
         let mut kernel_attrs = MetaData::new(&func.sig.ident.to_string());
@@ -95,7 +145,7 @@ impl MetaData {
 
 // Should I use `cargo::core::dependency::Depenency`?
 // https://docs.rs/cargo/0.41.0/cargo/core/dependency/struct.Dependency.html
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(untagged, deny_unknown_fields)]
 enum Depenency {
     Version(String),
@@ -122,7 +172,7 @@ enum Depenency {
     },
 }
 
-fn parse_dependency(dep: &str) -> Fallible<HashMap<String, Depenency>> {
+fn parse_dependency(dep: &str) -> Fallible<BTreeMap<String, Depenency>> {
     Ok(toml::from_str(&dep.replace("\n", ""))?)
 }
 
@@ -156,4 +206,35 @@ mod tests {
         )
         .is_err());
     }
+
+    #[test]
+    fn merge() {
+        let func: syn::ItemFn = syn::parse_str(
+            r#"
+            #[dependencies("local" = { path = "../local" })]
+            fn a() {}
+            "#,
+        )
+        .unwrap();
+        let a = super::MetaData::from_token(&func).unwrap();
+        let func: syn::ItemFn = syn::parse_str("fn b() {}").unwrap();
+        let b = super::MetaData::from_token(&func).unwrap();
+        let base = std::path::Path::new("/base");
+        let merged = super::MetaData::merge("kernels", &[a, b], base).unwrap();
+        assert_eq!(merged.name(), "kernels");
+        assert_eq!(merged.local_dependencies(), vec![base.join("../local")]);
+        assert!(merged.dependencies.contains_key("accel-core"));
+
+        let func: syn::ItemFn = syn::parse_str(
+            r#"
+            #[dependencies("accel-core" = "0.1.0")]
+            fn c() {}
+            "#,
+        )
+        .unwrap();
+        let c = super::MetaData::from_token(&func).unwrap();
+        let func: syn::ItemFn = syn::parse_str("fn d() {}").unwrap();
+        let d = super::MetaData::from_token(&func).unwrap();
+        assert!(super::MetaData::merge("kernels", &[c, d], base).is_err());
+    }
 }
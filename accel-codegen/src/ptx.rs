@@ -0,0 +1,845 @@
+//! Parser of [PTX] module headers
+//!
+//! [PtxModule] reads the module-level directives of PTX without GPU:
+//!
+//! - `.version`, `.target` and `.address_size`
+//! - `.entry` and `.func` declarations with their parameters
+//! - `.global`, `.const` and `.shared` variables
+//!
+//! Function bodies are skipped, i.e. instructions are not parsed.
+//!
+//! ```
+//! use accel_codegen::ptx::*;
+//!
+//! let ptx = PtxModule::parse(r#"
+//! .version 6.5
+//! .target sm_30
+//! .address_size 64
+//!
+//! .visible .entry add(
+//!     .param .u64 add_param_0,
+//!     .param .u32 add_param_1
+//! )
+//! {
+//!     ret;
+//! }
+//! "#).unwrap();
+//! assert_eq!(ptx.version, (6, 5));
+//! assert_eq!(ptx.target, vec!["sm_30"]);
+//!
+//! let add = ptx.kernel("add").unwrap();
+//! assert_eq!(add.params.len(), 2);
+//! assert_eq!(add.params[0].ty, PtxType::U64);
+//! assert_eq!(add.params[1].ty, PtxType::U32);
+//! ```
+//!
+//! [PTX]: https://docs.nvidia.com/cuda/parallel-thread-execution/index.html
+//! [PtxModule]: struct.PtxModule.html
+
+use std::{error, fmt, str::FromStr};
+
+/// Error of parsing PTX
+#[derive(Debug, Clone, PartialEq)]
+pub struct PtxError {
+    /// Line number in PTX, `0` if unknown
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for PtxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid PTX at line {}: {}", self.line, self.message)
+    }
+}
+
+impl error::Error for PtxError {}
+
+pub type Result<T> = ::std::result::Result<T, PtxError>;
+
+/// Fundamental types of PTX
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PtxType {
+    B8,
+    B16,
+    B32,
+    B64,
+    B128,
+    U8,
+    U16,
+    U32,
+    U64,
+    S8,
+    S16,
+    S32,
+    S64,
+    F16,
+    F16x2,
+    BF16,
+    F32,
+    F64,
+    Pred,
+}
+
+impl PtxType {
+    /// Parse type directive, e.g. `.u64`
+    pub fn from_directive(directive: &str) -> Option<Self> {
+        use PtxType::*;
+        Some(match directive {
+            ".b8" => B8,
+            ".b16" => B16,
+            ".b32" => B32,
+            ".b64" => B64,
+            ".b128" => B128,
+            ".u8" => U8,
+            ".u16" => U16,
+            ".u32" => U32,
+            ".u64" => U64,
+            ".s8" => S8,
+            ".s16" => S16,
+            ".s32" => S32,
+            ".s64" => S64,
+            ".f16" => F16,
+            ".f16x2" => F16x2,
+            ".bf16" => BF16,
+            ".f32" => F32,
+            ".f64" => F64,
+            ".pred" => Pred,
+            _ => return None,
+        })
+    }
+
+    /// Size in bytes
+    pub fn size(&self) -> usize {
+        use PtxType::*;
+        match self {
+            B8 | U8 | S8 | Pred => 1,
+            B16 | U16 | S16 | F16 | BF16 => 2,
+            B32 | U32 | S32 | F32 | F16x2 => 4,
+            B64 | U64 | S64 | F64 => 8,
+            B128 => 16,
+        }
+    }
+}
+
+impl fmt::Display for PtxType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = format!("{:?}", self).to_lowercase();
+        write!(f, ".{}", name)
+    }
+}
+
+/// Linking directive of functions and variables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Linkage {
+    /// No linking directive, i.e. visible only in this module
+    Internal,
+    /// `.visible`
+    Visible,
+    /// `.extern`, defined in another module
+    Extern,
+    /// `.weak`
+    Weak,
+    /// `.common`
+    Common,
+}
+
+/// State space of module-scope variables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StateSpace {
+    /// `.global`
+    Global,
+    /// `.const`
+    Const,
+    /// `.shared`
+    Shared,
+}
+
+/// Kind of function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FunctionKind {
+    /// `.entry`, i.e. kernel which can be launched from host
+    Entry,
+    /// `.func`, i.e. device function
+    Func,
+}
+
+/// Parameter of `.entry` or `.func`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub ty: PtxType,
+    /// Alignment in bytes given by `.align`
+    pub align: Option<usize>,
+    /// Length of array parameters, e.g. `.param .align 8 .b8 s[16]`
+    pub array_len: Option<usize>,
+}
+
+impl Param {
+    /// Size in bytes
+    pub fn size(&self) -> usize {
+        self.ty.size() * self.array_len.unwrap_or(1)
+    }
+
+    /// Alignment in bytes. The size of type if `.align` is not specified.
+    pub fn alignment(&self) -> usize {
+        self.align.unwrap_or_else(|| self.ty.size())
+    }
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, ".param ")?;
+        if let Some(align) = self.align {
+            write!(f, ".align {} ", align)?;
+        }
+        write!(f, "{} {}", self.ty, self.name)?;
+        if let Some(n) = self.array_len {
+            write!(f, "[{}]", n)?;
+        }
+        Ok(())
+    }
+}
+
+/// Declaration or definition of `.entry` or `.func`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub kind: FunctionKind,
+    pub linkage: Linkage,
+    pub params: Vec<Param>,
+    /// Return parameters of `.func`. Always empty for `.entry`
+    pub returns: Vec<Param>,
+    /// If this function has a body, or is only declared
+    pub defined: bool,
+    /// Maximum number of registers per thread given by `.maxnreg`
+    pub max_registers: Option<u32>,
+    /// Maximum (`.maxntid`) or required (`.reqntid`) number of threads per block
+    pub max_threads: Option<u32>,
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            FunctionKind::Entry => ".entry",
+            FunctionKind::Func => ".func",
+        };
+        write!(f, "{} ", kind)?;
+        if !self.returns.is_empty() {
+            write!(f, "({}) ", join(&self.returns))?;
+        }
+        write!(f, "{}({})", self.name, join(&self.params))
+    }
+}
+
+fn join(params: &[Param]) -> String {
+    params
+        .iter()
+        .map(|p| p.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Module-scope variable in `.global`, `.const` or `.shared` state space
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    pub name: String,
+    pub space: StateSpace,
+    pub linkage: Linkage,
+    pub ty: PtxType,
+    /// Alignment in bytes given by `.align`
+    pub align: Option<usize>,
+    /// Number of vector elements given by `.v2` or `.v4`
+    pub vector: Option<usize>,
+    /// Length of each array dimension. Unsized dimension, e.g. `.extern .shared .b8 buf[]`, is `None`
+    pub dims: Vec<Option<usize>>,
+}
+
+impl Variable {
+    /// Size in bytes, `None` if the variable has an unsized dimension
+    pub fn size(&self) -> Option<usize> {
+        let mut size = self.ty.size() * self.vector.unwrap_or(1);
+        for dim in &self.dims {
+            size *= (*dim)?;
+        }
+        Some(size)
+    }
+}
+
+/// Module-level information of PTX
+#[derive(Debug, Clone, PartialEq)]
+pub struct PtxModule {
+    /// PTX ISA version in `.version` directive, e.g. `(6, 5)`
+    pub version: (u32, u32),
+    /// Targets in `.target` directive, e.g. `["sm_30"]`
+    pub target: Vec<String>,
+    /// Address size in `.address_size` directive. PTX assumes 32 if not given.
+    pub address_size: u32,
+    /// `.entry` and `.func` in declared order
+    pub functions: Vec<Function>,
+    /// `.global`, `.const` and `.shared` variables in declared order
+    pub variables: Vec<Variable>,
+}
+
+impl PtxModule {
+    /// Parse PTX string
+    pub fn parse(ptx: &str) -> Result<Self> {
+        Parser::new(ptx).module()
+    }
+
+    /// Iterate over kernels, i.e. `.entry` functions
+    pub fn kernels(&self) -> impl Iterator<Item = &Function> {
+        self.functions
+            .iter()
+            .filter(|f| f.kind == FunctionKind::Entry)
+    }
+
+    /// Find a kernel by name
+    pub fn kernel(&self, name: &str) -> Option<&Function> {
+        self.kernels().find(|f| f.name == name)
+    }
+
+    /// Find a module-scope variable by name
+    pub fn variable(&self, name: &str) -> Option<&Variable> {
+        self.variables.iter().find(|v| v.name == name)
+    }
+}
+
+impl FromStr for PtxModule {
+    type Err = PtxError;
+    fn from_str(ptx: &str) -> Result<Self> {
+        Self::parse(ptx)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+}
+
+/// Split PTX into tokens, stripping comments
+///
+/// - Directives starting with `.` end at the next `.`, i.e. `.ptr.global` is split into `.ptr` and `.global`
+/// - Numbers may contain `.`, e.g. `6.5`
+/// - String literals are kept with their quotes
+fn tokenize(ptx: &str) -> Result<Vec<Token<'_>>> {
+    let bytes = ptx.as_bytes();
+    let is_ident = |b: u8| b.is_ascii_alphanumeric() || b == b'_' || b == b'$' || b == b'%';
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        let start = i;
+        if b == b'\n' {
+            line += 1;
+            i += 1;
+        } else if b.is_ascii_whitespace() {
+            i += 1;
+        } else if ptx[i..].starts_with("//") {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+        } else if ptx[i..].starts_with("/*") {
+            let end = match ptx[i + 2..].find("*/") {
+                Some(end) => i + 2 + end + 2,
+                None => return Err(invalid(line, "Unterminated comment")),
+            };
+            line += ptx[i..end].matches('\n').count();
+            i = end;
+        } else if b == b'"' {
+            i += 1;
+            while i < bytes.len() && bytes[i] != b'"' {
+                i += 1;
+            }
+            if i == bytes.len() {
+                return Err(invalid(line, "Unterminated string"));
+            }
+            i += 1;
+            tokens.push(Token {
+                text: &ptx[start..i],
+                line,
+            });
+        } else if b == b'.' || is_ident(b) {
+            i += 1;
+            while i < bytes.len()
+                && (is_ident(bytes[i]) || (bytes[i] == b'.' && b.is_ascii_digit()))
+            {
+                i += 1;
+            }
+            tokens.push(Token {
+                text: &ptx[start..i],
+                line,
+            });
+        } else {
+            // punctuation, e.g. `(`, `;`
+            let len = ptx[i..].chars().next().unwrap().len_utf8();
+            i += len;
+            tokens.push(Token {
+                text: &ptx[start..i],
+                line,
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+fn invalid(line: usize, message: impl Into<String>) -> PtxError {
+    PtxError {
+        line,
+        message: message.into(),
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    error: Option<PtxError>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(ptx: &'a str) -> Self {
+        match tokenize(ptx) {
+            Ok(tokens) => Parser {
+                tokens,
+                pos: 0,
+                error: None,
+            },
+            Err(e) => Parser {
+                tokens: Vec::new(),
+                pos: 0,
+                error: Some(e),
+            },
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(|t| t.text)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or_else(|| self.tokens.last())
+            .map(|t| t.line)
+            .unwrap_or(0)
+    }
+
+    fn next(&mut self) -> Result<&'a str> {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token.text)
+            }
+            None => Err(invalid(self.line(), "Unexpected end of PTX")),
+        }
+    }
+
+    fn eat(&mut self, text: &str) -> bool {
+        if self.peek() == Some(text) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, text: &str) -> Result<()> {
+        let line = self.line();
+        let token = self.next()?;
+        if token != text {
+            return Err(invalid(
+                line,
+                format!("Expected `{}`, but found `{}`", text, token),
+            ));
+        }
+        Ok(())
+    }
+
+    fn integer<T: FromStr>(&mut self) -> Result<T> {
+        let line = self.line();
+        let token = self.next()?;
+        token
+            .parse()
+            .map_err(|_| invalid(line, format!("Expected integer, but found `{}`", token)))
+    }
+
+    fn identifier(&mut self) -> Result<String> {
+        let line = self.line();
+        let token = self.next()?;
+        let head = token.as_bytes()[0];
+        if !(head.is_ascii_alphabetic() || head == b'_' || head == b'$' || head == b'%') {
+            return Err(invalid(
+                line,
+                format!("Expected identifier, but found `{}`", token),
+            ));
+        }
+        Ok(token.to_string())
+    }
+
+    /// Skip a `{ ... }` block, including nested blocks
+    fn skip_block(&mut self) -> Result<()> {
+        self.expect("{")?;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next()? {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Skip tokens until `;`, including initializer blocks
+    fn skip_statement(&mut self) -> Result<()> {
+        loop {
+            match self.peek() {
+                Some("{") => self.skip_block()?,
+                Some(";") => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                _ => {
+                    self.next()?;
+                }
+            }
+        }
+    }
+
+    /// Skip tokens in the current line
+    fn skip_line(&mut self, line: usize) {
+        while self.pos < self.tokens.len() && self.tokens[self.pos].line == line {
+            self.pos += 1;
+        }
+    }
+
+    fn align(&mut self) -> Result<Option<usize>> {
+        if self.eat(".align") {
+            Ok(Some(self.integer()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn ty(&mut self) -> Result<PtxType> {
+        let line = self.line();
+        let token = self.next()?;
+        PtxType::from_directive(token)
+            .ok_or_else(|| invalid(line, format!("Unknown type `{}`", token)))
+    }
+
+    fn module(mut self) -> Result<PtxModule> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        let mut version = None;
+        let mut target = Vec::new();
+        let mut address_size = 32;
+        let mut functions = Vec::new();
+        let mut variables = Vec::new();
+        let mut linkage = Linkage::Internal;
+        while let Some(token) = self.peek() {
+            let line = self.line();
+            self.pos += 1;
+            match token {
+                ".version" => {
+                    let version_str = self.next()?;
+                    let mut it = version_str.splitn(2, '.').map(|v| v.parse::<u32>());
+                    match (it.next(), it.next()) {
+                        (Some(Ok(major)), Some(Ok(minor))) => version = Some((major, minor)),
+                        _ => {
+                            return Err(invalid(line, format!("Invalid version `{}`", version_str)))
+                        }
+                    }
+                }
+                ".target" => loop {
+                    target.push(self.identifier()?);
+                    if !self.eat(",") {
+                        break;
+                    }
+                },
+                ".address_size" => address_size = self.integer()?,
+                ".visible" => linkage = Linkage::Visible,
+                ".extern" => linkage = Linkage::Extern,
+                ".weak" => linkage = Linkage::Weak,
+                ".common" => linkage = Linkage::Common,
+                ".entry" | ".func" => {
+                    let kind = if token == ".entry" {
+                        FunctionKind::Entry
+                    } else {
+                        FunctionKind::Func
+                    };
+                    functions.push(self.function(kind, linkage)?);
+                    linkage = Linkage::Internal;
+                }
+                ".global" | ".const" | ".shared" => {
+                    let space = match token {
+                        ".global" => StateSpace::Global,
+                        ".const" => StateSpace::Const,
+                        _ => StateSpace::Shared,
+                    };
+                    variables.push(self.variable(space, linkage)?);
+                    linkage = Linkage::Internal;
+                }
+                ".file" | ".loc" => self.skip_line(line),
+                ".section" => {
+                    self.next()?;
+                    self.skip_block()?;
+                }
+                ".pragma" | ".alias" => self.skip_statement()?,
+                _ => return Err(invalid(line, format!("Unexpected token `{}`", token))),
+            }
+        }
+        let version = version.ok_or_else(|| invalid(0, "`.version` directive is missing"))?;
+        Ok(PtxModule {
+            version,
+            target,
+            address_size,
+            functions,
+            variables,
+        })
+    }
+
+    /// Parse parameter list `( .param .u64 a, ... )`
+    fn params(&mut self) -> Result<Vec<Param>> {
+        let mut params = Vec::new();
+        self.expect("(")?;
+        if self.eat(")") {
+            return Ok(params);
+        }
+        loop {
+            let line = self.line();
+            let space = self.next()?;
+            if space != ".param" && space != ".reg" {
+                return Err(invalid(
+                    line,
+                    format!("Expected `.param` or `.reg`, but found `{}`", space),
+                ));
+            }
+            let mut align = self.align()?;
+            let ty = self.ty()?;
+            // `.ptr` attributes of kernel parameters, e.g. `.ptr .global .align 4`
+            if self.eat(".ptr") {
+                while let Some(attr) = self.peek() {
+                    match attr {
+                        ".global" | ".const" | ".local" | ".shared" => self.pos += 1,
+                        ".align" => {
+                            self.pos += 1;
+                            let _pointee_align: usize = self.integer()?;
+                        }
+                        _ => break,
+                    }
+                }
+            }
+            if align.is_none() {
+                align = self.align()?;
+            }
+            let name = self.identifier()?;
+            let array_len = if self.eat("[") {
+                let n = self.integer()?;
+                self.expect("]")?;
+                Some(n)
+            } else {
+                None
+            };
+            params.push(Param {
+                name,
+                ty,
+                align,
+                array_len,
+            });
+            if !self.eat(",") {
+                break;
+            }
+        }
+        self.expect(")")?;
+        Ok(params)
+    }
+
+    fn function(&mut self, kind: FunctionKind, linkage: Linkage) -> Result<Function> {
+        // `.func (.param .u32 ret) name(...)`
+        let returns = if kind == FunctionKind::Func && self.peek() == Some("(") {
+            self.params()?
+        } else {
+            Vec::new()
+        };
+        let name = self.identifier()?;
+        let params = if self.peek() == Some("(") {
+            self.params()?
+        } else {
+            Vec::new()
+        };
+        // Performance-tuning directives, e.g. `.maxntid 256, 1, 1`, and `.noreturn`
+        let mut max_registers = None;
+        let mut max_threads = None;
+        let defined = loop {
+            match self.peek() {
+                Some("{") => {
+                    self.skip_block()?;
+                    break true;
+                }
+                Some(";") => {
+                    self.pos += 1;
+                    break false;
+                }
+                Some(".maxnreg") => {
+                    self.pos += 1;
+                    max_registers = Some(self.integer()?);
+                }
+                Some(".maxntid") | Some(".reqntid") => {
+                    self.pos += 1;
+                    let mut threads: u32 = self.integer()?;
+                    while self.eat(",") {
+                        threads *= self.integer::<u32>()?;
+                    }
+                    max_threads = Some(threads);
+                }
+                _ => {
+                    self.next()?;
+                }
+            }
+        };
+        Ok(Function {
+            name,
+            kind,
+            linkage,
+            params,
+            returns,
+            defined,
+            max_registers,
+            max_threads,
+        })
+    }
+
+    fn variable(&mut self, space: StateSpace, linkage: Linkage) -> Result<Variable> {
+        let mut align = self.align()?;
+        let vector = match self.peek() {
+            Some(".v2") => Some(2),
+            Some(".v4") => Some(4),
+            _ => None,
+        };
+        if vector.is_some() {
+            self.pos += 1;
+        }
+        let ty = self.ty()?;
+        if align.is_none() {
+            align = self.align()?;
+        }
+        let name = self.identifier()?;
+        let mut dims = Vec::new();
+        while self.eat("[") {
+            if self.eat("]") {
+                dims.push(None);
+            } else {
+                dims.push(Some(self.integer()?));
+                self.expect("]")?;
+            }
+        }
+        // Skip initializer `= {...}`
+        self.skip_statement()?;
+        Ok(Variable {
+            name,
+            space,
+            linkage,
+            ty,
+            align,
+            vector,
+            dims,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn declarations() -> Result<()> {
+        let ptx = PtxModule::parse(
+            r#"
+            .version 7.0 /* multi
+                            line comment */
+            .target sm_70, debug
+            .address_size 64
+            .file 1 "kernel.cu"
+
+            .extern .global .align 4 .u32 table[256];
+            .visible .const .align 8 .v2 .f32 coef[4] = {1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0};
+            .extern .shared .align 16 .b8 buffer[];
+            .global .u64 counter;
+
+            .func  (.param .b32 func_retval0) square(
+                .param .b32 square_param_0
+            )
+            {
+                ret;
+            }
+
+            .weak .func noop;
+
+            .visible .entry reduce(
+                .param .u64 .ptr .global .align 4 reduce_param_0,
+                .param .align 8 .b8 reduce_param_1[16]
+            )
+            .maxntid 256, 2, 1
+            .maxnreg 32
+            {
+                { .reg .b32 nested; }
+                ret;
+            }
+            "#,
+        )?;
+        assert_eq!(ptx.version, (7, 0));
+        assert_eq!(ptx.target, vec!["sm_70", "debug"]);
+
+        let table = ptx.variable("table").unwrap();
+        assert_eq!(table.space, StateSpace::Global);
+        assert_eq!(table.linkage, Linkage::Extern);
+        assert_eq!(table.size(), Some(4 * 256));
+
+        let coef = ptx.variable("coef").unwrap();
+        assert_eq!(coef.space, StateSpace::Const);
+        assert_eq!(coef.vector, Some(2));
+        assert_eq!(coef.align, Some(8));
+        assert_eq!(coef.size(), Some(4 * 2 * 4));
+
+        let buffer = ptx.variable("buffer").unwrap();
+        assert_eq!(buffer.space, StateSpace::Shared);
+        assert_eq!(buffer.dims, vec![None]);
+        assert_eq!(buffer.size(), None);
+
+        assert_eq!(ptx.variable("counter").unwrap().linkage, Linkage::Internal);
+
+        assert_eq!(ptx.functions.len(), 3);
+        let square = &ptx.functions[0];
+        assert_eq!(square.kind, FunctionKind::Func);
+        assert_eq!(square.returns.len(), 1);
+        assert_eq!(square.params.len(), 1);
+        let noop = &ptx.functions[1];
+        assert_eq!(noop.linkage, Linkage::Weak);
+        assert!(!noop.defined);
+
+        let reduce = ptx.kernel("reduce").unwrap();
+        assert_eq!(reduce.params[0].ty, PtxType::U64);
+        assert_eq!(reduce.params[0].align, None);
+        assert_eq!(reduce.params[1].size(), 16);
+        assert_eq!(reduce.params[1].alignment(), 8);
+        assert_eq!(reduce.max_threads, Some(512));
+        assert_eq!(reduce.max_registers, Some(32));
+        assert_eq!(
+            reduce.to_string(),
+            ".entry reduce(.param .u64 reduce_param_0, .param .align 8 .b8 reduce_param_1[16])"
+        );
+        assert!(ptx.kernel("square").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(PtxModule::parse(".target sm_30").is_err());
+        let e = PtxModule::parse(".version 6.5\n.entry f(.param .u65 a) {}").unwrap_err();
+        assert_eq!(e.line, 2);
+        assert_eq!(e.message, "Unknown type `.u65`");
+    }
+}
@@ -0,0 +1,196 @@
+//! Verify the Rust signature of kernel against the `.entry` parameters in compiled PTX
+//!
+//! rustc may lower an argument into several `.param` entries, e.g. slices and 128-bit integers.
+//! Launching such kernel with the Rust signature silently corrupts arguments,
+//! and thus it is rejected at compile time:
+//!
+//! - The number of arguments must match the number of `.param` entries.
+//!   Otherwise `compile_error!` is emitted at the argument which is lowered into several parameters.
+//! - The size and alignment of each argument type are checked by constant evaluation on host,
+//!   which fails to compile at the offending argument.
+
+use crate::ptx::PtxModule;
+use proc_macro2::TokenStream;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+
+/// Size and alignment of a `.param` entry in bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParamLayout {
+    pub size: usize,
+    pub align: usize,
+}
+
+/// Get layouts of parameters of `.entry <name>` in PTX
+pub fn entry_params(ptx: &str, name: &str) -> Result<Vec<ParamLayout>, String> {
+    let module = PtxModule::parse(ptx).map_err(|e| e.to_string())?;
+    let kernel = module
+        .kernel(name)
+        .ok_or_else(|| format!("Kernel `{}` is not found in PTX", name))?;
+    Ok(kernel
+        .params
+        .iter()
+        .map(|param| ParamLayout {
+            size: param.size(),
+            align: param.alignment(),
+        })
+        .collect())
+}
+
+/// If the type may be lowered into several `.param` entries
+fn is_split(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Reference(re) => match &*re.elem {
+            syn::Type::Slice(_) => true,
+            syn::Type::Path(p) => p.path.is_ident("str"),
+            _ => false,
+        },
+        syn::Type::Path(p) => p.path.is_ident("u128") || p.path.is_ident("i128"),
+        syn::Type::Tuple(t) => !t.elems.is_empty(),
+        syn::Type::Paren(p) => is_split(&p.elem),
+        _ => false,
+    }
+}
+
+/// Replace lifetimes of references by `'static` to evaluate size in constant context
+fn static_lifetime(ty: &syn::Type) -> syn::Type {
+    let mut ty = ty.clone();
+    if let syn::Type::Reference(re) = &mut ty {
+        re.lifetime = Some(syn::Lifetime::new("'static", re.and_token.span()));
+        *re.elem = static_lifetime(&re.elem);
+    }
+    ty
+}
+
+/// Generate compile-time checks of kernel arguments against the `.entry` in PTX
+///
+/// `input_types` are host-side types, i.e. `DeviceSend::Target` of each argument.
+pub fn verify(ptx_str: &str, func: &syn::ItemFn, input_types: &[syn::Type]) -> TokenStream {
+    let name = func.sig.ident.to_string();
+    let params = match entry_params(ptx_str, &name) {
+        Ok(params) => params,
+        Err(msg) => return syn::Error::new_spanned(&func.sig.ident, msg).to_compile_error(),
+    };
+
+    if params.len() != input_types.len() {
+        let msg = format!(
+            "Kernel `{}` has {} argument(s), but its PTX entry has {} parameter(s)",
+            name,
+            input_types.len(),
+            params.len()
+        );
+        return match input_types.iter().find(|ty| is_split(ty)) {
+            Some(ty) => syn::Error::new(
+                ty.span(),
+                format!(
+                    "{}. This argument is lowered into several parameters, use raw pointers instead",
+                    msg
+                ),
+            ),
+            None => syn::Error::new_spanned(&func.sig.inputs, msg),
+        }
+        .to_compile_error();
+    }
+
+    let checks = input_types.iter().zip(params.iter()).map(|(ty, param)| {
+        let host = static_lifetime(ty);
+        let size = param.size;
+        let align = param.align;
+        // Array length mismatch is reported at the argument type
+        quote_spanned! { ty.span() =>
+            const _: [(); #size] = [(); ::core::mem::size_of::<#host>()];
+            const _: [(); #align] = [(); ::core::mem::align_of::<#host>()];
+        }
+    });
+    quote! { #(#checks)* }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PTX: &str = r#"
+    .version 6.5
+    .target sm_30
+    .address_size 64
+
+    // .globl  add
+    .visible .entry add(
+        .param .u64 add_param_0,  // a
+        .param .u64 .ptr .global .align 4 add_param_1,
+        .param .u32 add_param_2
+    )
+    {
+        ret;
+    }
+
+    .visible .entry sum(
+        .param .u64 sum_param_0,
+        .param .u64 sum_param_1,
+        .param .align 8 .b8 sum_param_2[16]
+    )
+    {
+        ret;
+    }
+
+    .visible .entry do_nothing()
+    {
+        ret;
+    }
+    "#;
+
+    #[test]
+    fn entry_params() {
+        let add = super::entry_params(PTX, "add").unwrap();
+        assert_eq!(
+            add,
+            vec![
+                ParamLayout { size: 8, align: 8 },
+                ParamLayout { size: 8, align: 8 },
+                ParamLayout { size: 4, align: 4 },
+            ]
+        );
+        let sum = super::entry_params(PTX, "sum").unwrap();
+        assert_eq!(sum[2], ParamLayout { size: 16, align: 8 });
+        assert_eq!(super::entry_params(PTX, "do_nothing").unwrap(), vec![]);
+        assert!(super::entry_params(PTX, "not_found").is_err());
+    }
+
+    fn verify(kernel: &str) -> String {
+        let func: syn::ItemFn = syn::parse_str(kernel).unwrap();
+        let input_types: Vec<syn::Type> = func
+            .sig
+            .inputs
+            .iter()
+            .map(|arg| match arg {
+                syn::FnArg::Typed(val) => *val.ty.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+        super::verify(PTX, &func, &input_types).to_string()
+    }
+
+    #[test]
+    fn verify_ok() {
+        let ts = verify("unsafe fn add(a: *const f32, b: *mut f32, n: u32) {}");
+        assert!(!ts.contains("compile_error"));
+        assert!(ts.contains("size_of"));
+        let ts = verify("fn do_nothing() {}");
+        assert!(ts.is_empty());
+    }
+
+    #[test]
+    fn verify_count_mismatch() {
+        let ts = verify("fn sum(a: &[f32], b: &mut f32) {}");
+        assert!(ts.contains("compile_error"));
+        assert!(ts.contains("lowered into several parameters"));
+        let ts = verify("fn add(a: *const f32) {}");
+        assert!(ts.contains("compile_error"));
+    }
+
+    #[test]
+    fn verify_not_found() {
+        let ts = verify("fn not_found() {}");
+        assert!(ts.contains("compile_error"));
+    }
+}
@@ -0,0 +1,95 @@
+//! Half-precision floating point numbers on device, enabled by `half` feature
+//!
+//! `half::f16` and `half::bf16` are sent from the host as they are.
+//! Conversions and arithmetic of `f16` use PTX instructions,
+//! and those of `bf16` are computed in `f32` since `bf16` instructions require sm_80.
+
+use half::{bf16, f16};
+
+macro_rules! f16_binary {
+    ($(#[$attr:meta])* $name:ident, $inst:tt) => {
+        $(#[$attr])*
+        pub fn $name(a: f16, b: f16) -> f16 {
+            let c: u16;
+            unsafe {
+                llvm_asm!($inst
+                    : "=h"(c)
+                    : "h"(a.to_bits()), "h"(b.to_bits())
+                );
+            }
+            f16::from_bits(c)
+        }
+    };
+}
+
+/// Convert `f16` into `f32` by `cvt.f32.f16`
+pub fn f16_to_f32(x: f16) -> f32 {
+    let y: f32;
+    unsafe {
+        llvm_asm!("cvt.f32.f16 $0, $1;"
+            : "=f"(y)
+            : "h"(x.to_bits())
+        );
+    }
+    y
+}
+
+/// Convert `f32` into `f16` rounding to nearest even by `cvt.rn.f16.f32`
+pub fn f32_to_f16(x: f32) -> f16 {
+    let y: u16;
+    unsafe {
+        llvm_asm!("cvt.rn.f16.f32 $0, $1;"
+            : "=h"(y)
+            : "f"(x)
+        );
+    }
+    f16::from_bits(y)
+}
+
+f16_binary!(
+    /// `a + b` by `add.f16`, which requires sm_53
+    hadd,
+    "add.f16 $0, $1, $2;"
+);
+f16_binary!(
+    /// `a - b` by `sub.f16`, which requires sm_53
+    hsub,
+    "sub.f16 $0, $1, $2;"
+);
+f16_binary!(
+    /// `a * b` by `mul.f16`, which requires sm_53
+    hmul,
+    "mul.f16 $0, $1, $2;"
+);
+
+/// `a * b + c` without intermediate rounding by `fma.rn.f16`, which requires sm_53
+pub fn hfma(a: f16, b: f16, c: f16) -> f16 {
+    let d: u16;
+    unsafe {
+        llvm_asm!("fma.rn.f16 $0, $1, $2, $3;"
+            : "=h"(d)
+            : "h"(a.to_bits()), "h"(b.to_bits()), "h"(c.to_bits())
+        );
+    }
+    f16::from_bits(d)
+}
+
+/// `a + b` computed in `f32`
+pub fn bf16_add(a: bf16, b: bf16) -> bf16 {
+    bf16::from_f32(a.to_f32() + b.to_f32())
+}
+
+/// `a - b` computed in `f32`
+pub fn bf16_sub(a: bf16, b: bf16) -> bf16 {
+    bf16::from_f32(a.to_f32() - b.to_f32())
+}
+
+/// `a * b` computed in `f32`, which is exact before rounding into `bf16`
+pub fn bf16_mul(a: bf16, b: bf16) -> bf16 {
+    bf16::from_f32(a.to_f32() * b.to_f32())
+}
+
+/// `a * b + c` computed in `f32`
+pub fn bf16_fma(a: bf16, b: bf16, c: bf16) -> bf16 {
+    bf16::from_f32(a.to_f32() * b.to_f32() + c.to_f32())
+}
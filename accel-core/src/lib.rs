@@ -5,12 +5,22 @@
 //!   i.e. You need to write `#![no_std]` Rust code.
 //! - `alloc` crate is supported by `accel_core::PTXAllocator` which utilizes CUDA malloc/free system-calls
 //!   - You can use `println!` and `assert_eq!` throught it.
+//! - `half` feature enables conversions and arithmetic of `half::f16` and `half::bf16`
 
 #![feature(stdsimd)]
+#![feature(llvm_asm)]
 #![no_std]
 
 extern crate alloc;
 
+#[cfg(feature = "half")]
+mod fp16;
+mod texture;
+
+#[cfg(feature = "half")]
+pub use fp16::*;
+pub use texture::*;
+
 use alloc::alloc::*;
 use core::arch::nvptx;
 
@@ -101,6 +111,36 @@ macro_rules! assert_ne {
     };
 }
 
+/// Declare variables in the global memory, which can be accessed from the host by `accel::Module::global`
+///
+/// - The variable is exported with its name in Rust, e.g. `COEF` in `static mut COEF: [f32; 16]`
+/// - It must be `static mut` since the host may rewrite it between kernel launches
+/// - Rust cannot put a variable in the `.const` state space. It is placed in `.global`.
+///
+/// ```ignore
+/// #[kernel]
+/// unsafe fn scale(a: *mut f32, n: usize) {
+///     accel_core::global! {
+///         static mut COEF: [f32; 4] = [0.0; 4];
+///     }
+///     let i = accel_core::index();
+///     if (i as usize) < n {
+///         *a.offset(i) *= COEF[i as usize % 4];
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! global {
+    ($($(#[$attr:meta])* static mut $name:ident: $ty:ty = $init:expr;)*) => {
+        $(
+            $(#[$attr])*
+            #[no_mangle]
+            #[allow(non_upper_case_globals)]
+            static mut $name: $ty = $init;
+        )*
+    };
+}
+
 /// Dimension specified in kernel launching
 pub struct Dim3 {
     pub x: i32,
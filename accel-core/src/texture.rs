@@ -0,0 +1,94 @@
+//! Fetch from texture objects and store into surface objects
+//!
+//! Handles are `accel::TextureObject` and `accel::SurfaceObject` sent as kernel arguments,
+//! i.e. `u64` in kernels.
+
+/// Element types which can be fetched from textures and stored into surfaces
+pub trait Texel: Copy {
+    /// Fetch 4 components at `x` of 1D texture by `tex.1d`
+    unsafe fn tex1d(tex: u64, x: f32) -> [Self; 4];
+    /// Fetch 4 components at `(x, y)` of 2D texture by `tex.2d`
+    unsafe fn tex2d(tex: u64, x: f32, y: f32) -> [Self; 4];
+    /// Store at `(x, y)` of 2D surface by `sust.b.2d`, where `x` is in bytes
+    unsafe fn surf2d_write(surf: u64, x: i32, y: i32, value: Self);
+}
+
+macro_rules! impl_texel {
+    ($t:ty, $reg:tt, $tex1d:tt, $tex2d:tt, $to_bits:expr) => {
+        impl Texel for $t {
+            unsafe fn tex1d(tex: u64, x: f32) -> [Self; 4] {
+                let (a, b, c, d): ($t, $t, $t, $t);
+                llvm_asm!($tex1d
+                    : $reg(a), $reg(b), $reg(c), $reg(d)
+                    : "l"(tex), "f"(x)
+                );
+                [a, b, c, d]
+            }
+
+            unsafe fn tex2d(tex: u64, x: f32, y: f32) -> [Self; 4] {
+                let (a, b, c, d): ($t, $t, $t, $t);
+                llvm_asm!($tex2d
+                    : $reg(a), $reg(b), $reg(c), $reg(d)
+                    : "l"(tex), "f"(x), "f"(y)
+                );
+                [a, b, c, d]
+            }
+
+            unsafe fn surf2d_write(surf: u64, x: i32, y: i32, value: Self) {
+                let bits: u32 = $to_bits(value);
+                llvm_asm!("sust.b.2d.b32.trap [$0, {$1, $2}], {$3};"
+                    :
+                    : "l"(surf), "r"(x), "r"(y), "r"(bits)
+                    : "memory"
+                    : "volatile"
+                );
+            }
+        }
+    };
+}
+
+impl_texel!(
+    f32,
+    "=f",
+    "tex.1d.v4.f32.f32 {$0, $1, $2, $3}, [$4, {$5}];",
+    "tex.2d.v4.f32.f32 {$0, $1, $2, $3}, [$4, {$5, $6}];",
+    f32::to_bits
+);
+impl_texel!(
+    i32,
+    "=r",
+    "tex.1d.v4.s32.f32 {$0, $1, $2, $3}, [$4, {$5}];",
+    "tex.2d.v4.s32.f32 {$0, $1, $2, $3}, [$4, {$5, $6}];",
+    |v: i32| v as u32
+);
+impl_texel!(
+    u32,
+    "=r",
+    "tex.1d.v4.u32.f32 {$0, $1, $2, $3}, [$4, {$5}];",
+    "tex.2d.v4.u32.f32 {$0, $1, $2, $3}, [$4, {$5, $6}];",
+    |v: u32| v
+);
+
+/// Fetch 4 components at `x` of 1D texture
+///
+/// Unused components of the texture format are filled by 0, and the alpha by 1.
+pub unsafe fn tex1d<T: Texel>(tex: u64, x: f32) -> [T; 4] {
+    T::tex1d(tex, x)
+}
+
+/// Fetch 4 components at `(x, y)` of 2D texture
+///
+/// Unused components of the texture format are filled by 0, and the alpha by 1.
+pub unsafe fn tex2d<T: Texel>(tex: u64, x: f32, y: f32) -> [T; 4] {
+    T::tex2d(tex, x, y)
+}
+
+/// Store `value` at `(x, y)` of 2D surface
+///
+/// `x` is the index of the element, not in bytes unlike `sust` instruction.
+/// The surface must be of a single-channel array of `T`, which `accel::SurfaceObject` asserts,
+/// since `x` is scaled by the size of `T`.
+/// Out-of-range stores trap.
+pub unsafe fn surf2d_write<T: Texel>(surf: u64, x: i32, y: i32, value: T) {
+    T::surf2d_write(surf, x * core::mem::size_of::<T>() as i32, y, value)
+}
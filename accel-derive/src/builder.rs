@@ -1,4 +1,4 @@
-use crate::parser::*;
+use crate::{cache::*, parser::*};
 use failure::*;
 use quote::quote;
 use std::{
@@ -11,6 +11,7 @@ use std::{
 };
 
 const NIGHTLY_VERSION: &str = "nightly-2020-09-20";
+const TARGET: &str = "nvptx64-nvidia-cuda";
 
 trait CheckRun {
     fn check_run(&mut self) -> Fallible<()>;
@@ -33,27 +34,27 @@ impl CheckRun for Command {
 }
 
 /// Generate Rust code for nvptx64-nvidia-cuda target from tokens
-fn ptx_kernel(func: &syn::ItemFn, content: Option<Vec<syn::Item>>) -> String {
-    let vis = &func.vis;
-    let ident = &func.sig.ident;
-    let unsafety = &func.sig.unsafety;
-    let block = &func.block;
-
-    let fn_token = &func.sig.fn_token;
-    let inputs = &mut func.sig.inputs.clone();
-    inputs.iter_mut().for_each(|i| {
-        if let syn::FnArg::Typed(arg) = i {
-            arg.attrs = arg.attrs.clone().into_iter().filter(|a| !a.path.is_ident("type_substitute")).collect();
-        }
-    });
+fn ptx_kernel(funcs: &[syn::ItemFn], content: &[syn::Item]) -> String {
+    let kernels = funcs.iter().map(|func| {
+        let vis = &func.vis;
+        let ident = &func.sig.ident;
+        let unsafety = &func.sig.unsafety;
+        let block = &func.block;
 
-    let output = &func.sig.output;
+        let fn_token = &func.sig.fn_token;
+        let inputs = &mut func.sig.inputs.clone();
+        inputs.iter_mut().for_each(|i| {
+            if let syn::FnArg::Typed(arg) = i {
+                arg.attrs = arg.attrs.clone().into_iter().filter(|a| !a.path.is_ident("type_substitute")).collect();
+            }
+        });
 
-    let content = if let Some(inner) = content {
-        inner
-    } else {
-        vec![]
-    };
+        let output = &func.sig.output;
+        quote! {
+            #[no_mangle]
+            #vis #unsafety extern "ptx-kernel" #fn_token #ident(#inputs) #output #block
+        }
+    });
 
     let kernel = quote! {
         #![feature(abi_ptx, stdsimd, alloc_error_handler)]
@@ -62,8 +63,7 @@ fn ptx_kernel(func: &syn::ItemFn, content: Option<Vec<syn::Item>>) -> String {
         #[global_allocator]
         static _GLOBAL_ALLOCATOR: accel_core::PTXAllocator = accel_core::PTXAllocator;
         #(#content)*
-        #[no_mangle]
-        #vis #unsafety extern "ptx-kernel" #fn_token #ident(#inputs) #output #block
+        #(#kernels)*
         #[panic_handler]
         fn panic(_info: &::core::panic::PanicInfo) -> ! {
             unsafe { ::core::arch::nvptx::trap() }
@@ -94,101 +94,142 @@ fn project_id() -> String {
     format!("{}-{:x}", stem, hash)
 }
 
-pub fn compile_tokens_mod(module: &syn::ItemMod) -> Fallible<(String, syn::ItemFn, Vec<syn::Item>)> {
+/// Compile all `#[kernel_func]` in the module into a PTX
+///
+/// Returns PTX, kernel functions, and the other items in the module
+pub fn compile_tokens_mod(
+    module: &syn::ItemMod,
+) -> Fallible<(String, Vec<syn::ItemFn>, Vec<syn::Item>)> {
     let meta = MetaData::from_module(module)?;
-    // Try to extract kernel function from rest of module.
-    let item_vec = &module.content.as_ref().expect("module must contain kernel").1;
+    let items = match &module.content {
+        Some((_, items)) => items,
+        None => bail!("#[kernel_mod] must be an inline module"),
+    };
 
-    let mut func_op = None;
-    for item in item_vec {
+    let mut funcs = Vec::new();
+    let mut content = Vec::new();
+    for item in items {
         match item {
-            syn::Item::Fn(f) => {
-                if f.attrs.iter().any(|a| a.path.is_ident("kernel_func")) {
-                    if func_op.is_none() {
-                        func_op = Some(f);
-                    } else {
-                        panic!("Cannot have more than one kernel_func");
-                    }
-                }
-            },
-            _ => (),
-        };
+            syn::Item::Fn(f) if f.attrs.iter().any(|a| a.path.is_ident("kernel_func")) => {
+                funcs.push(f.clone())
+            }
+            _ => content.push(item.clone()),
+        }
+    }
+    if funcs.is_empty() {
+        bail!("#[kernel_mod] must contain at least one #[kernel_func]");
     }
-    let func = func_op.unwrap();
-    let new_content: Vec<syn::Item> = module.content.clone().unwrap().1.into_iter()
-        .filter(|i| *i != syn::Item::Fn(func.clone()))
-        .collect();
-
-    // Create crate
-    let dir = dirs::cache_dir()
-        .unwrap()
-        .join("accel-derive")
-        .join(project_id())
-        .join(meta.name());
-    fs::create_dir_all(dir.join("src"))?;
-
-    // Generate lib.rs and write into a file
-    let mut lib_rs = fs::File::create(dir.join("src/lib.rs"))?;
-    lib_rs.write_all(ptx_kernel(&func, Some(new_content.clone())).as_bytes())?;
-    lib_rs.sync_data()?;
-
-    // Generate Cargo.toml
-    let mut cargo_toml = fs::File::create(dir.join("Cargo.toml"))?;
-    cargo_toml.write_all(toml::to_string(&meta)?.as_bytes())?;
-    cargo_toml.sync_data()?;
-
-    // Build
-    Command::new("cargo")
-        .args(&[&format!("+{}", NIGHTLY_VERSION), "fmt"])
-        .current_dir(&dir)
-        .check_run()?;
-    Command::new("cargo")
-        .args(&[
-            &format!("+{}", NIGHTLY_VERSION),
-            "build",
-            "--release",
-            "--target",
-            "nvptx64-nvidia-cuda",
-        ])
-        .current_dir(&dir)
-        .check_run()?;
 
-    // Read PTX file
-    let mut ptx = fs::File::open(dir.join(format!(
-        "target/nvptx64-nvidia-cuda/release/{}.ptx",
-        meta.name()
-    )))?;
-    let mut buf = String::new();
-    ptx.read_to_string(&mut buf)?;
-    Ok((buf, func.clone(), new_content))
+    let ptx = compile(&meta, &ptx_kernel(&funcs, &content))?;
+    Ok((ptx, funcs, content))
 }
 
-
 pub fn compile_tokens(func: &syn::ItemFn) -> Fallible<String> {
     let meta = MetaData::from_token(func)?;
+    compile(&meta, &ptx_kernel(std::slice::from_ref(func), &[]))
+}
 
-    // Create crate
+/// Compile generated `lib.rs` into PTX, or get it from the PTX cache
+///
+/// The PTX cache is looked up before touching the crate directory,
+/// i.e. a cache hit returns PTX without running cargo.
+/// Each generated crate is built in `<project>/<name>-<hash of lib.rs>`,
+/// and thus kernels of the same name in different modules do not share a directory.
+fn compile(meta: &MetaData, lib_rs: &str) -> Fallible<String> {
+    let cargo_toml = toml::to_string(meta)?;
+    let token_hash = KeyHasher::new().input("lib.rs", lib_rs).finish().hex();
     let dir = dirs::cache_dir()
         .unwrap()
         .join("accel-derive")
         .join(project_id())
-        .join(meta.name());
-    fs::create_dir_all(dir.join("src"))?;
+        .join(format!("{}-{}", meta.name(), &token_hash[..16]));
+
+    let cache = PtxCache::from_env();
+    if let Some(cache) = &cache {
+        // `Cargo.lock` resolved by the last build, if exists
+        let cargo_lock = fs::read_to_string(dir.join("Cargo.lock")).unwrap_or_default();
+        if let Some(ptx) = cache.get(cache_key(meta, &dir, lib_rs, &cargo_toml, &cargo_lock)) {
+            return Ok(ptx);
+        }
+    }
 
-    // Generate lib.rs and write into a file
-    let mut lib_rs = fs::File::create(dir.join("src/lib.rs"))?;
-    lib_rs.write_all(ptx_kernel(func, None).as_bytes())?;
-    lib_rs.sync_data()?;
+    // Serialize expansions using the same directory
+    let _lock = BuildLock::acquire(dir.with_extension("lock"))?;
+    let cargo_lock = prepare(&dir, lib_rs, &cargo_toml)?;
+    let cache = match cache {
+        Some(cache) => cache,
+        None => return build(&dir, meta.name()),
+    };
+    let key = cache_key(meta, &dir, lib_rs, &cargo_toml, &cargo_lock);
+    // Another expansion may have built it while waiting the lock
+    if let Some(ptx) = cache.get(key) {
+        return Ok(ptx);
+    }
+    let ptx = build(&dir, meta.name())?;
+    cache.put(key, &ptx)?;
+    Ok(ptx)
+}
+
+/// Key of the PTX cache for the crate in `dir` built with `cargo_lock`
+fn cache_key(
+    meta: &MetaData,
+    dir: &Path,
+    lib_rs: &str,
+    cargo_toml: &str,
+    cargo_lock: &str,
+) -> CacheKey {
+    let mut hasher = KeyHasher::new()
+        .input("lib.rs", lib_rs)
+        .input("Cargo.toml", cargo_toml)
+        .input("Cargo.lock", cargo_lock)
+        .input("toolchain", NIGHTLY_VERSION)
+        .input("target", TARGET);
+    // Relative paths of local dependencies are resolved from the crate directory
+    for path in meta.local_dependencies() {
+        hasher = hasher.directory(&dir.join(path));
+    }
+    hasher.finish()
+}
 
-    // Generate Cargo.toml
-    let mut cargo_toml = fs::File::create(dir.join("Cargo.toml"))?;
-    cargo_toml.write_all(toml::to_string(&meta)?.as_bytes())?;
-    cargo_toml.sync_data()?;
+/// Write a file if its contents differ, to keep the modified time for cargo
+fn write_if_changed(path: &Path, contents: &str) -> Fallible<bool> {
+    if fs::read_to_string(path).ok().as_deref() == Some(contents) {
+        return Ok(false);
+    }
+    let mut f = fs::File::create(path)?;
+    f.write_all(contents.as_bytes())?;
+    f.sync_data()?;
+    Ok(true)
+}
+
+/// Create a crate in `dir`, and return `Cargo.lock` of dependencies resolved by cargo
+///
+/// Dependencies are resolved only if `Cargo.toml` is changed or `Cargo.lock` does not exist,
+/// i.e. `cargo build` in `dir` uses exactly the returned `Cargo.lock`.
+fn prepare(dir: &Path, lib_rs: &str, cargo_toml: &str) -> Fallible<String> {
+    fs::create_dir_all(dir.join("src"))?;
+    write_if_changed(&dir.join("src/lib.rs"), lib_rs)?;
+    let manifest_changed = write_if_changed(&dir.join("Cargo.toml"), cargo_toml)?;
+    let lock_path = dir.join("Cargo.lock");
+    if manifest_changed || !lock_path.exists() {
+        Command::new("cargo")
+            .args(&[
+                &format!("+{}", NIGHTLY_VERSION),
+                "metadata",
+                "--format-version",
+                "1",
+            ])
+            .current_dir(dir)
+            .check_run()?;
+    }
+    Ok(fs::read_to_string(lock_path)?)
+}
 
-    // Build
+/// Build the crate in `dir` into PTX
+fn build(dir: &Path, name: &str) -> Fallible<String> {
     Command::new("cargo")
         .args(&[&format!("+{}", NIGHTLY_VERSION), "fmt"])
-        .current_dir(&dir)
+        .current_dir(dir)
         .check_run()?;
     Command::new("cargo")
         .args(&[
@@ -196,16 +237,13 @@ pub fn compile_tokens(func: &syn::ItemFn) -> Fallible<String> {
             "build",
             "--release",
             "--target",
-            "nvptx64-nvidia-cuda",
+            TARGET,
         ])
-        .current_dir(&dir)
+        .current_dir(dir)
         .check_run()?;
 
     // Read PTX file
-    let mut ptx = fs::File::open(dir.join(format!(
-        "target/nvptx64-nvidia-cuda/release/{}.ptx",
-        meta.name()
-    )))?;
+    let mut ptx = fs::File::open(dir.join(format!("target/{}/release/{}.ptx", TARGET, name)))?;
     let mut buf = String::new();
     ptx.read_to_string(&mut buf)?;
     Ok(buf)
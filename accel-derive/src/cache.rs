@@ -0,0 +1,437 @@
+//! Content-addressed cache of compiled PTX
+//!
+//! Each entry is stored in `<root>/<key>/` where the key is a SHA-256 digest of every input of the nvptx build,
+//! i.e. the generated `lib.rs`, `Cargo.toml`, the resolved `Cargo.lock`, contents of local dependencies,
+//! the toolchain and the target.
+//! A cache hit returns the stored PTX without building.
+//!
+//! The cache is configured by environment variables:
+//!
+//! |name                          | default                            | description                                   |
+//! |:-----------------------------|:-----------------------------------|:----------------------------------------------|
+//! |`ACCEL_PTX_CACHE_DIR`         | `$XDG_CACHE_HOME/accel-derive/ptx` | Root directory of the cache                   |
+//! |`ACCEL_PTX_CACHE_SIZE`        | `268435456` (256MB)                | Total size limit of cached PTX in bytes       |
+//! |`ACCEL_PTX_CACHE_MAX_ENTRIES` | `1024`                             | Limit of number of entries                    |
+//! |`ACCEL_PTX_CACHE_DISABLE`     | unset                              | Disable the cache if set, i.e. always rebuild |
+//!
+//! Least recently used entries are evicted when the limits are exceeded.
+
+use failure::*;
+use sha2::{Digest, Sha256};
+use std::{
+    env, fs,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const PTX_FILE: &str = "kernel.ptx";
+const LAST_USED_FILE: &str = "last_used";
+
+/// Lock files older than this are regarded as left by a crashed process
+const STALE_LOCK: Duration = Duration::from_secs(30 * 60);
+
+/// SHA-256 digest of inputs of the nvptx build
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CacheKey([u8; 32]);
+
+impl CacheKey {
+    pub fn hex(&self) -> String {
+        self.0.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// Builder of [CacheKey]
+#[derive(Debug, Default)]
+pub struct KeyHasher {
+    hasher: Sha256,
+}
+
+impl KeyHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a length-prefixed byte sequence
+    fn update(&mut self, bytes: &[u8]) {
+        self.hasher.update((bytes.len() as u64).to_le_bytes());
+        self.hasher.update(bytes);
+    }
+
+    /// Add a named input
+    pub fn input(mut self, name: &str, value: impl AsRef<[u8]>) -> Self {
+        self.update(name.as_bytes());
+        self.update(value.as_ref());
+        self
+    }
+
+    /// Add relative paths and contents of files under `dir` except `target/` and `.git/`
+    pub fn directory(mut self, dir: &Path) -> Self {
+        let mut files = Vec::new();
+        collect_files(dir, &mut files);
+        files.sort();
+        self.update(dir.to_string_lossy().as_bytes());
+        for path in files {
+            if let Ok(contents) = fs::read(&path) {
+                let relative = path.strip_prefix(dir).unwrap_or(&path);
+                self.update(relative.to_string_lossy().as_bytes());
+                self.update(&contents);
+            }
+        }
+        self
+    }
+
+    pub fn finish(self) -> CacheKey {
+        let mut key = [0; 32];
+        key.copy_from_slice(&self.hasher.finalize());
+        CacheKey(key)
+    }
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path.is_dir() {
+            if path
+                .file_name()
+                .map_or(false, |name| name != "target" && name != ".git")
+            {
+                collect_files(&path, files);
+            }
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Write a file atomically by renaming a temporal file
+fn write_atomic(path: &Path, contents: &[u8]) -> Fallible<()> {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let tmp = path.with_extension(format!(
+        "tmp{}-{}",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::SeqCst)
+    ));
+    let mut f = fs::File::create(&tmp)?;
+    f.write_all(contents)?;
+    f.sync_data()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Exclusive lock by a lock file, released on drop
+#[derive(Debug)]
+pub struct BuildLock {
+    path: PathBuf,
+}
+
+impl BuildLock {
+    /// Create the lock file `path`, waiting for other processes holding it
+    ///
+    /// Lock files older than 30 minutes are regarded as left by a crashed process, and removed.
+    pub fn acquire(path: PathBuf) -> Fallible<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(mut f) => {
+                    write!(f, "{}", std::process::id())?;
+                    return Ok(BuildLock { path });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    let stale = fs::metadata(&path)
+                        .and_then(|m| m.modified())
+                        .ok()
+                        .and_then(|t| t.elapsed().ok())
+                        .map_or(false, |elapsed| elapsed > STALE_LOCK);
+                    if stale {
+                        let _ = fs::remove_file(&path);
+                    } else {
+                        thread::sleep(Duration::from_millis(100));
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Drop for BuildLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Content-addressed PTX cache
+#[derive(Debug, Clone)]
+pub struct PtxCache {
+    root: PathBuf,
+    max_bytes: u64,
+    max_entries: usize,
+}
+
+impl PtxCache {
+    pub fn new(root: impl Into<PathBuf>, max_bytes: u64, max_entries: usize) -> Self {
+        PtxCache {
+            root: root.into(),
+            max_bytes,
+            max_entries,
+        }
+    }
+
+    /// Cache configured by `ACCEL_PTX_CACHE_*` environment variables, `None` if disabled
+    pub fn from_env() -> Option<Self> {
+        if env::var_os("ACCEL_PTX_CACHE_DISABLE").is_some() {
+            return None;
+        }
+        let root = match env::var_os("ACCEL_PTX_CACHE_DIR") {
+            Some(dir) => PathBuf::from(dir),
+            None => dirs::cache_dir()?.join("accel-derive").join("ptx"),
+        };
+        let max_bytes = env::var("ACCEL_PTX_CACHE_SIZE")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(256 * 1024 * 1024);
+        let max_entries = env::var("ACCEL_PTX_CACHE_MAX_ENTRIES")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(1024);
+        Some(Self::new(root, max_bytes, max_entries))
+    }
+
+    fn entry_dir(&self, key: CacheKey) -> PathBuf {
+        self.root.join(key.hex())
+    }
+
+    /// Get cached PTX, and mark it as recently used
+    pub fn get(&self, key: CacheKey) -> Option<String> {
+        let dir = self.entry_dir(key);
+        let ptx = fs::read_to_string(dir.join(PTX_FILE)).ok()?;
+        let _ = write_atomic(&dir.join(LAST_USED_FILE), now().to_string().as_bytes());
+        Some(ptx)
+    }
+
+    /// Store PTX, and evict old entries if the limits are exceeded
+    pub fn put(&self, key: CacheKey, ptx: &str) -> Fallible<()> {
+        let dir = self.entry_dir(key);
+        fs::create_dir_all(&dir)?;
+        write_atomic(&dir.join(PTX_FILE), ptx.as_bytes())?;
+        write_atomic(&dir.join(LAST_USED_FILE), now().to_string().as_bytes())?;
+        self.evict(Some(key))?;
+        Ok(())
+    }
+
+    /// List `(key directory, last used, size in bytes)` of entries
+    fn entries(&self) -> Vec<(PathBuf, u64, u64)> {
+        let read_dir = match fs::read_dir(&self.root) {
+            Ok(read_dir) => read_dir,
+            Err(_) => return Vec::new(),
+        };
+        read_dir
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|path| path.is_dir())
+            .filter_map(|dir| {
+                let size = fs::metadata(dir.join(PTX_FILE)).ok()?.len();
+                let last_used = fs::read_to_string(dir.join(LAST_USED_FILE))
+                    .ok()
+                    .and_then(|s| s.trim().parse().ok())
+                    .unwrap_or(0);
+                Some((dir, last_used, size))
+            })
+            .collect()
+    }
+
+    /// Remove least recently used entries until the limits are satisfied
+    ///
+    /// The entry of `keep` is not removed even if it exceeds the limits by itself.
+    pub fn evict(&self, keep: Option<CacheKey>) -> Fallible<()> {
+        let keep = keep.map(|key| self.entry_dir(key));
+        let mut entries = self.entries();
+        // the kept entry first, and then newest first
+        entries.sort_by_key(|(dir, last_used, _)| {
+            (
+                keep.as_ref() != Some(dir),
+                std::cmp::Reverse(*last_used),
+                dir.clone(),
+            )
+        });
+        let mut total = 0;
+        let mut count = 0;
+        for (dir, _, size) in entries {
+            let is_kept = keep.as_ref() == Some(&dir);
+            if is_kept || (total + size <= self.max_bytes && count < self.max_entries) {
+                total += size;
+                count += 1;
+            } else {
+                match fs::remove_dir_all(&dir) {
+                    // already evicted by another process
+                    Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{atomic::AtomicBool, Arc};
+
+    fn test_cache(max_bytes: u64, max_entries: usize) -> PtxCache {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let root = env::temp_dir().join(format!(
+            "accel-derive-cache-test-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&root);
+        PtxCache::new(root, max_bytes, max_entries)
+    }
+
+    #[test]
+    fn key() {
+        let key1 = KeyHasher::new().input("lib.rs", "fn a() {}").finish();
+        let key2 = KeyHasher::new().input("lib.rs", "fn a() {}").finish();
+        let key3 = KeyHasher::new().input("lib.rs", "fn b() {}").finish();
+        assert_eq!(key1, key2);
+        assert_ne!(key1, key3);
+        assert_eq!(key1.hex().len(), 64);
+
+        // Inputs are separated
+        let key4 = KeyHasher::new()
+            .input("lib.rs", "ab")
+            .input("", "")
+            .finish();
+        let key5 = KeyHasher::new()
+            .input("lib.rs", "a")
+            .input("b", "")
+            .finish();
+        assert_ne!(key4, key5);
+    }
+
+    #[test]
+    fn get_put() -> Fallible<()> {
+        let cache = test_cache(1024, 16);
+        let key = KeyHasher::new().input("ptx", "1").finish();
+        assert_eq!(cache.get(key), None);
+        cache.put(key, ".version 6.5")?;
+        assert_eq!(cache.get(key).as_deref(), Some(".version 6.5"));
+        fs::remove_dir_all(&cache.root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn evict_lru() -> Fallible<()> {
+        let cache = test_cache(1024, 2);
+        let keys: Vec<_> = (0..3)
+            .map(|i| KeyHasher::new().input("ptx", i.to_string()).finish())
+            .collect();
+        cache.put(keys[0], "a")?;
+        cache.put(keys[1], "b")?;
+        // make keys[0] older than keys[1]
+        write_atomic(&cache.entry_dir(keys[0]).join(LAST_USED_FILE), b"0")?;
+        cache.put(keys[2], "c")?;
+        assert_eq!(cache.get(keys[0]), None);
+        assert!(cache.get(keys[1]).is_some());
+        assert!(cache.get(keys[2]).is_some());
+
+        // Size limit
+        let cache_small = PtxCache::new(cache.root.clone(), 1, 16);
+        let key = KeyHasher::new().input("ptx", "4").finish();
+        cache_small.put(key, "too large")?;
+        assert!(cache_small.get(key).is_some());
+        assert!(cache_small.get(keys[2]).is_none());
+        fs::remove_dir_all(&cache.root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn write_atomic_threads() -> Fallible<()> {
+        let cache = test_cache(1024, 16);
+        fs::create_dir_all(&cache.root)?;
+        let path = cache.root.join(LAST_USED_FILE);
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                thread::spawn(move || -> Fallible<()> {
+                    for _ in 0..16 {
+                        write_atomic(&path, i.to_string().as_bytes())?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap()?;
+        }
+        let last: usize = fs::read_to_string(&path)?.parse()?;
+        assert!(last < 8);
+        // No temporal file is left
+        assert_eq!(fs::read_dir(&cache.root)?.count(), 1);
+        fs::remove_dir_all(&cache.root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn lock() -> Fallible<()> {
+        let cache = test_cache(1024, 16);
+        let lock_path = cache.root.join("build.lock");
+        let lock = BuildLock::acquire(lock_path.clone())?;
+        assert!(lock_path.exists());
+        let acquired = Arc::new(AtomicBool::new(false));
+        let waiter = {
+            let lock_path = lock_path.clone();
+            let acquired = acquired.clone();
+            thread::spawn(move || {
+                let _lock = BuildLock::acquire(lock_path).unwrap();
+                acquired.store(true, Ordering::SeqCst);
+            })
+        };
+        thread::sleep(Duration::from_millis(300));
+        assert!(!acquired.load(Ordering::SeqCst));
+        drop(lock);
+        waiter.join().unwrap();
+        assert!(acquired.load(Ordering::SeqCst));
+        assert!(!lock_path.exists());
+        fs::remove_dir_all(&cache.root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn directory_contents() -> Fallible<()> {
+        let cache = test_cache(1024, 16);
+        let dir = cache.root.join("dep");
+        fs::create_dir_all(dir.join("src"))?;
+        fs::create_dir_all(dir.join("target"))?;
+        fs::write(dir.join("src/lib.rs"), "fn a() {}")?;
+        let key1 = KeyHasher::new().directory(&dir).finish();
+        // build outputs are ignored
+        fs::write(dir.join("target/out"), "binary")?;
+        assert_eq!(key1, KeyHasher::new().directory(&dir).finish());
+        fs::write(dir.join("src/lib.rs"), "fn b() {}")?;
+        assert_ne!(key1, KeyHasher::new().directory(&dir).finish());
+        fs::remove_dir_all(&cache.root)?;
+        Ok(())
+    }
+}
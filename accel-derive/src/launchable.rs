@@ -40,44 +40,66 @@ pub fn generate(item: TokenStream) -> TokenStream {
                             #args_types: DeviceSend<Target = Self::#targets>
                         ),*
                     {
-                        let grid = grid.into();
-                        // println!("Launching kernel");
-                        // println!("Grid: {:?} {:?} {:?}",
-                        // grid.x,
-                        // grid.y,
-                        // grid.z,);
-                        let block = block.into();
-                        // println!("Block: {:?} {:?} {:?}",
-                        // block.x,
-                        // block.y,
-                        // block.z,);
+                        self.launch_with(&LaunchConfig::new(grid, block), (#(#args_value,)*))
+                    }
+
+                    fn launch_async<#(#args_types),*>(
+                        &self,
+                        grid: impl Into<Grid>,
+                        block: impl Into<Block>,
+                        (#(#args_value,)*): (#(#args_types,)*),
+                    ) -> ::futures::future::BoxFuture<'arg, Result<()>>
+                    where
+                        #(
+                            #args_types: DeviceSend<Target = Self::#targets> + 'arg
+                        ),*
+                    {
+                        self.launch_async_with(&LaunchConfig::new(grid, block), (#(#args_value,)*))
+                    }
+
+                    /// Launch kernel with dynamic shared memory and stream specified in [LaunchConfig]
+                    ///
+                    /// The configuration is validated before launch,
+                    /// and the dynamic shared memory limit of the kernel is raised if required.
+                    /// This blocks until the stream (or the context if no stream is specified) completes.
+                    fn launch_with<#(#args_types),*>(
+                        &self,
+                        config: &LaunchConfig,
+                        (#(#args_value,)*): (#(#args_types,)*),
+                    ) -> Result<()>
+                    where
+                        #(
+                            #args_types: DeviceSend<Target = Self::#targets>
+                        ),*
+                    {
                         let kernel = self.get_kernel()?;
+                        config.prepare(&kernel)?;
                         let mut args = [#(#args_value.as_kernel_parameter()),*];
                         unsafe {
                             contexted_call!(
                                 &kernel,
-                                cuLaunchKernel,
+                                launch_kernel,
                                 kernel.func,
-                                grid.x,
-                                grid.y,
-                                grid.z,
-                                block.x,
-                                block.y,
-                                block.z,
-                                0,          /* FIXME: no shared memory */
-                                null_mut(), /* use default stream */
-                                args.as_mut_ptr(),
-                                null_mut() /* no extra */
+                                config.grid,
+                                config.block,
+                                config.shared_mem_bytes,
+                                config.raw_stream(),
+                                args.as_mut_ptr()
                             )?;
                         }
-                        kernel.sync()?;
-                        Ok(())
+                        match config.stream {
+                            Some(stream) => stream.sync(),
+                            None => kernel.sync(),
+                        }
                     }
 
-                    fn launch_async<#(#args_types),*>(
+                    /// Asynchronous version of `launch_with`
+                    ///
+                    /// The returned future resolves when the kernel completes on the stream of `config`.
+                    /// A new stream is created if no stream is specified.
+                    fn launch_async_with<#(#args_types),*>(
                         &self,
-                        grid: impl Into<Grid>,
-                        block: impl Into<Block>,
+                        config: &LaunchConfig,
                         (#(#args_value,)*): (#(#args_types,)*),
                     ) -> ::futures::future::BoxFuture<'arg, Result<()>>
                     where
@@ -85,30 +107,44 @@ pub fn generate(item: TokenStream) -> TokenStream {
                             #args_types: DeviceSend<Target = Self::#targets> + 'arg
                         ),*
                     {
-                        let grid = grid.into();
-                        let block = block.into();
-                        let kernel = self.get_kernel().unwrap();
-                        let stream = stream::Stream::new(kernel.get_ref());
+                        let kernel = match self.get_kernel().and_then(|kernel| {
+                            config.prepare(&kernel)?;
+                            Ok(kernel)
+                        }) {
+                            Ok(kernel) => kernel,
+                            Err(e) => return Box::pin(async { Err(e) }),
+                        };
                         let mut args = [#(#args_value.as_kernel_parameter()),*];
-                        unsafe {
+                        let new_stream = match config.stream {
+                            Some(_) => None,
+                            None => Some(stream::Stream::new(kernel.get_ref())),
+                        };
+                        let raw_stream = new_stream
+                            .as_ref()
+                            .map_or(config.raw_stream(), |stream| stream.stream);
+                        if let Err(e) = unsafe {
                             contexted_call!(
                                 &kernel,
-                                cuLaunchKernel,
+                                launch_kernel,
                                 kernel.func,
-                                grid.x,
-                                grid.y,
-                                grid.z,
-                                block.x,
-                                block.y,
-                                block.z,
-                                0, /* FIXME: no shared memory */
-                                stream.stream,
-                                args.as_mut_ptr(),
-                                null_mut() /* no extra */
+                                config.grid,
+                                config.block,
+                                config.shared_mem_bytes,
+                                raw_stream,
+                                args.as_mut_ptr()
                             )
+                        } {
+                            return Box::pin(async { Err(e) });
+                        }
+                        match (new_stream, config.stream) {
+                            (Some(stream), _) => Box::pin(stream.into_future()),
+                            (None, Some(stream)) => {
+                                let mut event = stream::Event::new(kernel.get_ref());
+                                event.record(stream);
+                                Box::pin(event.into_future())
+                            }
+                            (None, None) => unreachable!(),
                         }
-                        .expect("Asynchronous kernel launch has been failed");
-                        Box::pin(stream.into_future())
                     }
                 }
             }
@@ -23,22 +23,31 @@
 //! ```
 
 mod builder;
+mod cache;
 mod contexted;
-mod host;
 mod launchable;
-mod parser;
 
+use accel_codegen::{host, parser};
 use proc_macro::TokenStream;
 
+/// Compile all `#[kernel_func]` in a module into a single PTX
+///
+/// The module is replaced by a module of the same name containing `PTX_STR`, a `Module` wrapper,
+/// and a typed launcher for each kernel, e.g. `Module::add()` returns `add::Launcher`
+/// which implements the corresponding `Launchable` trait.
+/// The caller function of each kernel is also generated in the module, e.g. `add(ctx, grid, block, args)`.
+/// The other items in the module are copied into the host code with `to_mod`,
+/// and only compiled into the device code with `transparent`.
 #[proc_macro_attribute]
 pub fn kernel_mod(attr: TokenStream, mod_in: TokenStream) -> TokenStream {
     let kernel_mod_type: String = attr.to_string();
     let module: syn::ItemMod = syn::parse(mod_in).expect("Not a module");
-    let (ptx_str, func, content) = builder::compile_tokens_mod(&module).expect("Failed to compile to PTX");
+    let (ptx_str, funcs, content) =
+        builder::compile_tokens_mod(&module).expect("Failed to compile to PTX");
     if &kernel_mod_type == "to_mod" {
-        host::mod2modcaller(&ptx_str, &func, content).into()
+        host::mod2module(&ptx_str, &module, &funcs, &content).into()
     } else if &kernel_mod_type == "transparent" {
-        host::mod2caller(&ptx_str, &func, content).into()
+        host::mod2module(&ptx_str, &module, &funcs, &[]).into()
     } else {
         panic!("Not an acceptable kernel_mod type. Allowed: to_mod, transparent.")
     }
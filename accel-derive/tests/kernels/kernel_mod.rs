@@ -0,0 +1,46 @@
+//! Testing multiple kernels in a module share a PTX and a module
+
+use accel::*;
+use anyhow::Result;
+
+#[kernel_mod(to_mod)]
+mod ops {
+    fn square(x: f32) -> f32 {
+        x * x
+    }
+
+    #[kernel_func]
+    pub unsafe fn add(a: *const f32, b: *const f32, c: *mut f32, n: usize) {
+        let i = accel_core::index();
+        if (i as usize) < n {
+            *c.offset(i) = *a.offset(i) + *b.offset(i);
+        }
+    }
+
+    #[kernel_func]
+    pub unsafe fn square_all(a: *mut f32, n: usize) {
+        let i = accel_core::index();
+        if (i as usize) < n {
+            *a.offset(i) = square(*a.offset(i));
+        }
+    }
+}
+
+fn test() -> Result<()> {
+    use accel::execution::{Launchable2, Launchable4};
+    let device = Device::nth(0)?;
+    let ctx = device.create_context();
+    let module = ops::Module::new(&ctx)?;
+    let n = 16;
+    let a = ManagedMemory::<f32>::zeros(&ctx, n);
+    let b = ManagedMemory::<f32>::zeros(&ctx, n);
+    let mut c = ManagedMemory::<f32>::zeros(&ctx, n);
+    module.add().launch(1, n, (&a as &[f32], &b as &[f32], &mut c as &mut [f32], n))?;
+    module.square_all().launch(1, n, (&mut c as &mut [f32], n))?;
+    // Caller functions load the same module
+    ops::square_all(&ctx, 1, n, (&mut c as &mut [f32], n))?;
+    Ok(())
+}
+
+// Only check `test` can be compiled. not run here
+fn main() {}
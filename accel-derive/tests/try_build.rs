@@ -6,4 +6,5 @@ fn kernel_generate() {
     t.pass("tests/kernels/dependencies_git.rs");
     t.pass("tests/kernels/dependencies_default.rs");
     t.pass("tests/kernels/arguments.rs");
+    t.pass("tests/kernels/kernel_mod.rs");
 }
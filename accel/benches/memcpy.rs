@@ -10,6 +10,7 @@ fn h2d(c: &mut Criterion) {
         ($host:expr, $id:expr) => {
             let host = $host;
             let n = host.len();
+            let mut managed = ManagedMemory::zeros(&context, n);
             let mut dev = DeviceMemory::zeros(&context, n);
             group.bench_with_input(
                 BenchmarkId::new(&format!("direct_{}", $id), n),
@@ -17,7 +18,7 @@ fn h2d(c: &mut Criterion) {
                 |b, _| {
                     b.iter(|| {
                         for i in 0..n {
-                            dev[i] = host[i];
+                            managed[i] = host[i];
                         }
                     })
                 },
@@ -51,6 +52,7 @@ fn d2h(c: &mut Criterion) {
         ($host:expr, $id:expr) => {
             let mut host = $host;
             let n = host.len();
+            let managed = ManagedMemory::zeros(&context, n);
             let dev = DeviceMemory::zeros(&context, n);
             group.bench_with_input(
                 BenchmarkId::new(&format!("direct_{}", $id), n),
@@ -58,7 +60,7 @@ fn d2h(c: &mut Criterion) {
                 |b, _| {
                     b.iter(|| {
                         for i in 0..n {
-                            host[i] = dev[i];
+                            host[i] = managed[i];
                         }
                     })
                 },
@@ -14,11 +14,11 @@ fn main() -> error::Result<()> {
 
     let _pf = Profiler::start(&ctx);
 
-    // Allocate memories on GPU
+    // Allocate memories accessible from both CPU and GPU
     let n = 1024;
-    let mut a = DeviceMemory::<f32>::zeros(&ctx, n);
-    let mut b = DeviceMemory::<f32>::zeros(&ctx, n);
-    let mut c = DeviceMemory::<f32>::zeros(&ctx, n);
+    let mut a = ManagedMemory::<f32>::zeros(&ctx, n);
+    let mut b = ManagedMemory::<f32>::zeros(&ctx, n);
+    let mut c = ManagedMemory::<f32>::zeros(&ctx, n);
 
     // Accessible from CPU as usual Rust slice (though this will be slow)
     for i in 0..n {
@@ -0,0 +1,509 @@
+use super::*;
+use crate::{ffi_call, ffi_new};
+use std::{ffi::CString, ptr::null_mut};
+
+/// Backend calling the CUDA Driver API through `cuda_driver_sys`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cuda;
+
+impl Backend for Cuda {
+    fn name(&self) -> &'static str {
+        "cuda"
+    }
+
+    fn init(&self) -> Result<()> {
+        unsafe { ffi_call!(cuInit, 0) }
+    }
+
+    fn driver_get_version(&self) -> Result<u32> {
+        let version: i32 = unsafe { ffi_new!(cuDriverGetVersion) }?;
+        Ok(version as u32)
+    }
+
+    fn device_get_count(&self) -> Result<usize> {
+        let mut count: i32 = 0;
+        unsafe { ffi_call!(cuDeviceGetCount, &mut count as *mut i32) }?;
+        Ok(count as usize)
+    }
+
+    fn device_get(&self, ordinal: usize) -> Result<CUdevice> {
+        unsafe { ffi_new!(cuDeviceGet, ordinal as i32) }
+    }
+
+    fn device_get_name(&self, device: CUdevice) -> Result<String> {
+        let mut bytes: Vec<u8> = vec![0_u8; 1024];
+        unsafe {
+            ffi_call!(
+                cuDeviceGetName,
+                bytes.as_mut_ptr() as *mut i8,
+                bytes.len() as i32,
+                device
+            )
+        }?;
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        bytes.truncate(len);
+        Ok(String::from_utf8(bytes).expect("GPU name is not UTF8"))
+    }
+
+    fn device_total_mem(&self, device: CUdevice) -> Result<usize> {
+        let mut mem = 0;
+        unsafe { ffi_call!(cuDeviceTotalMem_v2, &mut mem as *mut _, device) }?;
+        Ok(mem)
+    }
+
+    fn device_get_attribute(&self, attrib: CUdevice_attribute, device: CUdevice) -> Result<i32> {
+        unsafe { ffi_new!(cuDeviceGetAttribute, attrib, device) }
+    }
+
+    fn ctx_create(&self, flags: u32, device: CUdevice) -> Result<CUcontext> {
+        unsafe { ffi_new!(cuCtxCreate_v2, flags, device) }
+    }
+
+    unsafe fn ctx_destroy(&self, ctx: CUcontext) -> Result<()> {
+        ffi_call!(cuCtxDestroy_v2, ctx)
+    }
+
+    unsafe fn ctx_push_current(&self, ctx: CUcontext) -> Result<()> {
+        ffi_call!(cuCtxPushCurrent_v2, ctx)
+    }
+
+    fn ctx_pop_current(&self) -> Result<CUcontext> {
+        unsafe { ffi_new!(cuCtxPopCurrent_v2) }
+    }
+
+    unsafe fn ctx_get_api_version(&self, ctx: CUcontext) -> Result<u32> {
+        let mut version: u32 = 0;
+        ffi_call!(cuCtxGetApiVersion, ctx, &mut version as *mut _)?;
+        Ok(version)
+    }
+
+    fn ctx_synchronize(&self) -> Result<()> {
+        unsafe { ffi_call!(cuCtxSynchronize) }
+    }
+
+    fn ctx_get_device(&self) -> Result<CUdevice> {
+        unsafe { ffi_new!(cuCtxGetDevice) }
+    }
+
+    fn mem_get_info(&self) -> Result<(usize, usize)> {
+        let mut free = 0;
+        let mut total = 0;
+        unsafe {
+            ffi_call!(
+                cuMemGetInfo_v2,
+                &mut free as *mut usize,
+                &mut total as *mut usize
+            )
+        }?;
+        Ok((free, total))
+    }
+
+    unsafe fn mem_alloc(&self, bytes: usize) -> Result<CUdeviceptr> {
+        ffi_new!(cuMemAlloc_v2, bytes)
+    }
+
+    unsafe fn mem_alloc_pitch(
+        &self,
+        width_bytes: usize,
+        height: usize,
+        element_size: u32,
+    ) -> Result<(CUdeviceptr, usize)> {
+        let mut ptr = 0;
+        let mut pitch = 0;
+        ffi_call!(
+            cuMemAllocPitch_v2,
+            &mut ptr,
+            &mut pitch,
+            width_bytes,
+            height,
+            element_size
+        )?;
+        Ok((ptr, pitch))
+    }
+
+    unsafe fn mem_alloc_managed(&self, bytes: usize, flags: u32) -> Result<CUdeviceptr> {
+        ffi_new!(cuMemAllocManaged, bytes, flags)
+    }
+
+    unsafe fn mem_prefetch_async(
+        &self,
+        ptr: CUdeviceptr,
+        bytes: usize,
+        device: CUdevice,
+        stream: CUstream,
+    ) -> Result<()> {
+        ffi_call!(cuMemPrefetchAsync, ptr, bytes, device, stream)
+    }
+
+    unsafe fn mem_advise(
+        &self,
+        ptr: CUdeviceptr,
+        bytes: usize,
+        advice: CUmem_advise,
+        device: CUdevice,
+    ) -> Result<()> {
+        ffi_call!(cuMemAdvise, ptr, bytes, advice, device)
+    }
+
+    unsafe fn mem_free(&self, ptr: CUdeviceptr) -> Result<()> {
+        ffi_call!(cuMemFree_v2, ptr)
+    }
+
+    unsafe fn mem_alloc_host(&self, bytes: usize) -> Result<*mut c_void> {
+        ffi_new!(cuMemAllocHost_v2, bytes)
+    }
+
+    unsafe fn mem_free_host(&self, ptr: *mut c_void) -> Result<()> {
+        ffi_call!(cuMemFreeHost, ptr)
+    }
+
+    unsafe fn mem_host_register(&self, ptr: *mut c_void, bytes: usize, flags: u32) -> Result<()> {
+        ffi_call!(cuMemHostRegister_v2, ptr, bytes, flags)
+    }
+
+    unsafe fn mem_host_unregister(&self, ptr: *mut c_void) -> Result<()> {
+        ffi_call!(cuMemHostUnregister, ptr)
+    }
+
+    unsafe fn pointer_get_attribute(
+        &self,
+        data: *mut c_void,
+        attr: CUpointer_attribute,
+        ptr: CUdeviceptr,
+    ) -> Result<()> {
+        ffi_call!(cuPointerGetAttribute, data, attr, ptr)
+    }
+
+    unsafe fn memset_d8(&self, dst: CUdeviceptr, value: u8, n: usize) -> Result<()> {
+        ffi_call!(cuMemsetD8_v2, dst, value, n)
+    }
+
+    unsafe fn memset_d16(&self, dst: CUdeviceptr, value: u16, n: usize) -> Result<()> {
+        ffi_call!(cuMemsetD16_v2, dst, value, n)
+    }
+
+    unsafe fn memset_d32(&self, dst: CUdeviceptr, value: u32, n: usize) -> Result<()> {
+        ffi_call!(cuMemsetD32_v2, dst, value, n)
+    }
+
+    unsafe fn memset_d8_async(
+        &self,
+        dst: CUdeviceptr,
+        value: u8,
+        n: usize,
+        stream: CUstream,
+    ) -> Result<()> {
+        ffi_call!(cuMemsetD8Async, dst, value, n, stream)
+    }
+
+    unsafe fn memset_d16_async(
+        &self,
+        dst: CUdeviceptr,
+        value: u16,
+        n: usize,
+        stream: CUstream,
+    ) -> Result<()> {
+        ffi_call!(cuMemsetD16Async, dst, value, n, stream)
+    }
+
+    unsafe fn memset_d32_async(
+        &self,
+        dst: CUdeviceptr,
+        value: u32,
+        n: usize,
+        stream: CUstream,
+    ) -> Result<()> {
+        ffi_call!(cuMemsetD32Async, dst, value, n, stream)
+    }
+
+    unsafe fn memset_d2d32_async(
+        &self,
+        dst: CUdeviceptr,
+        pitch: usize,
+        value: u32,
+        width: usize,
+        height: usize,
+        stream: CUstream,
+    ) -> Result<()> {
+        ffi_call!(cuMemsetD2D32Async, dst, pitch, value, width, height, stream)
+    }
+
+    unsafe fn memcpy(&self, dst: CUdeviceptr, src: CUdeviceptr, bytes: usize) -> Result<()> {
+        ffi_call!(cuMemcpy, dst, src, bytes)
+    }
+
+    unsafe fn memcpy_async(
+        &self,
+        dst: CUdeviceptr,
+        src: CUdeviceptr,
+        bytes: usize,
+        stream: CUstream,
+    ) -> Result<()> {
+        ffi_call!(cuMemcpyAsync, dst, src, bytes, stream)
+    }
+
+    unsafe fn memcpy_2d(&self, param: &CUDA_MEMCPY2D) -> Result<()> {
+        ffi_call!(cuMemcpy2D_v2, param)
+    }
+
+    unsafe fn memcpy_2d_async(&self, param: &CUDA_MEMCPY2D, stream: CUstream) -> Result<()> {
+        ffi_call!(cuMemcpy2DAsync_v2, param, stream)
+    }
+
+    unsafe fn memcpy_3d(&self, param: &CUDA_MEMCPY3D) -> Result<()> {
+        ffi_call!(cuMemcpy3D_v2, param)
+    }
+
+    unsafe fn memcpy_3d_async(&self, param: &CUDA_MEMCPY3D, stream: CUstream) -> Result<()> {
+        ffi_call!(cuMemcpy3DAsync_v2, param, stream)
+    }
+
+    unsafe fn array_3d_create(&self, desc: &CUDA_ARRAY3D_DESCRIPTOR) -> Result<CUarray> {
+        ffi_new!(cuArray3DCreate_v2, desc)
+    }
+
+    unsafe fn array_destroy(&self, array: CUarray) -> Result<()> {
+        ffi_call!(cuArrayDestroy, array)
+    }
+
+    unsafe fn tex_object_create(
+        &self,
+        res_desc: &CUDA_RESOURCE_DESC,
+        tex_desc: &CUDA_TEXTURE_DESC,
+    ) -> Result<CUtexObject> {
+        ffi_new!(cuTexObjectCreate, res_desc, tex_desc, std::ptr::null())
+    }
+
+    unsafe fn tex_object_destroy(&self, tex: CUtexObject) -> Result<()> {
+        ffi_call!(cuTexObjectDestroy, tex)
+    }
+
+    unsafe fn surf_object_create(&self, res_desc: &CUDA_RESOURCE_DESC) -> Result<CUsurfObject> {
+        ffi_new!(cuSurfObjectCreate, res_desc)
+    }
+
+    unsafe fn surf_object_destroy(&self, surf: CUsurfObject) -> Result<()> {
+        ffi_call!(cuSurfObjectDestroy, surf)
+    }
+
+    fn stream_create(&self, flags: u32) -> Result<CUstream> {
+        unsafe { ffi_new!(cuStreamCreate, flags) }
+    }
+
+    unsafe fn stream_destroy(&self, stream: CUstream) -> Result<()> {
+        ffi_call!(cuStreamDestroy_v2, stream)
+    }
+
+    unsafe fn stream_query(&self, stream: CUstream) -> Result<()> {
+        ffi_call!(cuStreamQuery, stream)
+    }
+
+    unsafe fn stream_synchronize(&self, stream: CUstream) -> Result<()> {
+        ffi_call!(cuStreamSynchronize, stream)
+    }
+
+    unsafe fn stream_attach_mem_async(
+        &self,
+        stream: CUstream,
+        ptr: CUdeviceptr,
+        bytes: usize,
+        flags: u32,
+    ) -> Result<()> {
+        ffi_call!(cuStreamAttachMemAsync, stream, ptr, bytes, flags)
+    }
+
+    unsafe fn stream_wait_event(&self, stream: CUstream, event: CUevent, flags: u32) -> Result<()> {
+        ffi_call!(cuStreamWaitEvent, stream, event, flags)
+    }
+
+    fn event_create(&self, flags: u32) -> Result<CUevent> {
+        unsafe { ffi_new!(cuEventCreate, flags) }
+    }
+
+    unsafe fn event_destroy(&self, event: CUevent) -> Result<()> {
+        ffi_call!(cuEventDestroy_v2, event)
+    }
+
+    unsafe fn event_record(&self, event: CUevent, stream: CUstream) -> Result<()> {
+        ffi_call!(cuEventRecord, event, stream)
+    }
+
+    unsafe fn event_query(&self, event: CUevent) -> Result<()> {
+        ffi_call!(cuEventQuery, event)
+    }
+
+    unsafe fn event_synchronize(&self, event: CUevent) -> Result<()> {
+        ffi_call!(cuEventSynchronize, event)
+    }
+
+    fn module_load(&self, path: &Path) -> Result<CUmodule> {
+        let filename = CString::new(path.to_str().unwrap()).expect("Invalid Path");
+        unsafe { ffi_new!(cuModuleLoad, filename.as_ptr()) }
+    }
+
+    unsafe fn module_load_data(&self, image: *const c_void) -> Result<CUmodule> {
+        ffi_new!(cuModuleLoadData, image)
+    }
+
+    unsafe fn module_load_data_ex(
+        &self,
+        image: *const c_void,
+        num_opts: u32,
+        opts: *mut CUjit_option,
+        opt_values: *mut *mut c_void,
+    ) -> Result<CUmodule> {
+        ffi_new!(cuModuleLoadDataEx, image, num_opts, opts, opt_values)
+    }
+
+    unsafe fn module_unload(&self, module: CUmodule) -> Result<()> {
+        ffi_call!(cuModuleUnload, module)
+    }
+
+    unsafe fn module_get_function(&self, module: CUmodule, name: &CStr) -> Result<CUfunction> {
+        ffi_new!(cuModuleGetFunction, module, name.as_ptr())
+    }
+
+    unsafe fn module_get_global(
+        &self,
+        module: CUmodule,
+        name: &CStr,
+    ) -> Result<(CUdeviceptr, usize)> {
+        let mut dptr = 0;
+        let mut bytes = 0;
+        ffi_call!(
+            cuModuleGetGlobal_v2,
+            &mut dptr as *mut CUdeviceptr,
+            &mut bytes as *mut usize,
+            module,
+            name.as_ptr()
+        )?;
+        Ok((dptr, bytes))
+    }
+
+    unsafe fn func_get_attribute(
+        &self,
+        attrib: CUfunction_attribute,
+        func: CUfunction,
+    ) -> Result<i32> {
+        ffi_new!(cuFuncGetAttribute, attrib, func)
+    }
+
+    unsafe fn func_set_attribute(
+        &self,
+        func: CUfunction,
+        attrib: CUfunction_attribute,
+        value: i32,
+    ) -> Result<()> {
+        ffi_call!(cuFuncSetAttribute, func, attrib, value)
+    }
+
+    unsafe fn launch_kernel(
+        &self,
+        func: CUfunction,
+        grid: Grid,
+        block: Block,
+        shared_mem_bytes: u32,
+        stream: CUstream,
+        params: *mut *mut c_void,
+    ) -> Result<()> {
+        ffi_call!(
+            cuLaunchKernel,
+            func,
+            grid.x,
+            grid.y,
+            grid.z,
+            block.x,
+            block.y,
+            block.z,
+            shared_mem_bytes,
+            stream,
+            params,
+            null_mut() /* no extra */
+        )
+    }
+
+    unsafe fn link_create(
+        &self,
+        num_opts: u32,
+        opts: *mut CUjit_option,
+        opt_values: *mut *mut c_void,
+    ) -> Result<CUlinkState> {
+        let mut state = std::mem::MaybeUninit::uninit();
+        ffi_call!(
+            cuLinkCreate_v2,
+            num_opts,
+            opts,
+            opt_values,
+            state.as_mut_ptr()
+        )?;
+        Ok(state.assume_init())
+    }
+
+    unsafe fn link_add_data(
+        &self,
+        state: CUlinkState,
+        input_type: CUjitInputType,
+        data: &[u8],
+        name: &CStr,
+        num_opts: u32,
+        opts: *mut CUjit_option,
+        opt_values: *mut *mut c_void,
+    ) -> Result<()> {
+        ffi_call!(
+            cuLinkAddData_v2,
+            state,
+            input_type,
+            data.as_ptr() as *mut _,
+            data.len(),
+            name.as_ptr(),
+            num_opts,
+            opts,
+            opt_values
+        )
+    }
+
+    unsafe fn link_add_file(
+        &self,
+        state: CUlinkState,
+        input_type: CUjitInputType,
+        path: &Path,
+        num_opts: u32,
+        opts: *mut CUjit_option,
+        opt_values: *mut *mut c_void,
+    ) -> Result<()> {
+        let filename = CString::new(path.to_str().unwrap()).expect("Invalid file path");
+        ffi_call!(
+            cuLinkAddFile_v2,
+            state,
+            input_type,
+            filename.as_ptr(),
+            num_opts,
+            opts,
+            opt_values
+        )
+    }
+
+    unsafe fn link_complete(&self, state: CUlinkState) -> Result<(*mut c_void, usize)> {
+        let mut cubin = null_mut();
+        let mut size = 0;
+        ffi_call!(
+            cuLinkComplete,
+            state,
+            &mut cubin as *mut _,
+            &mut size as *mut _
+        )?;
+        Ok((cubin, size))
+    }
+
+    unsafe fn link_destroy(&self, state: CUlinkState) -> Result<()> {
+        ffi_call!(cuLinkDestroy, state)
+    }
+
+    fn profiler_start(&self) -> Result<()> {
+        unsafe { ffi_call!(cuProfilerStart) }
+    }
+
+    fn profiler_stop(&self) -> Result<()> {
+        unsafe { ffi_call!(cuProfilerStop) }
+    }
+}
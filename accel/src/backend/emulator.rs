@@ -0,0 +1,1791 @@
+use super::*;
+use crate::{
+    error::check,
+    fatbin::FATBIN_MAGIC,
+    ptx::{Linkage, PtxError, PtxModule, StateSpace},
+};
+use std::{
+    alloc::{alloc_zeroed, dealloc, Layout},
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs, ptr,
+    sync::Mutex,
+    time::Instant,
+};
+
+/// Alignment of allocations, same as guaranteed by `cuMemAlloc`
+const ALIGNMENT: usize = 256;
+
+/// Alignment of pitches returned by `cuMemAllocPitch`
+const PITCH_ALIGNMENT: usize = 512;
+
+/// API version reported by `cuCtxGetApiVersion`
+const API_VERSION: u32 = 3020;
+
+/// Driver version reported by `cuDriverGetVersion`, i.e. CUDA 10.2
+const DRIVER_VERSION: u32 = 10020;
+
+/// Device memory reserved by each context, as the CUDA driver does for its own use
+const CONTEXT_RESERVED_MEMORY: usize = 1 << 20;
+
+/// Default limit of the shared memory per block
+const MAX_SHARED_MEMORY_PER_BLOCK: i32 = 48 * 1024;
+
+/// Limit of the shared memory per block with opt-in by `cuFuncSetAttribute`
+const MAX_SHARED_MEMORY_PER_BLOCK_OPTIN: i32 = 96 * 1024;
+
+/// Device attributes of the emulated device, same as a compute capability 7.0 device
+fn device_attribute(attrib: CUdevice_attribute) -> Option<i32> {
+    use CUdevice_attribute::*;
+    Some(match attrib {
+        CU_DEVICE_ATTRIBUTE_MAX_THREADS_PER_BLOCK => 1024,
+        CU_DEVICE_ATTRIBUTE_MAX_BLOCK_DIM_X => 1024,
+        CU_DEVICE_ATTRIBUTE_MAX_BLOCK_DIM_Y => 1024,
+        CU_DEVICE_ATTRIBUTE_MAX_BLOCK_DIM_Z => 64,
+        CU_DEVICE_ATTRIBUTE_MAX_GRID_DIM_X => std::i32::MAX,
+        CU_DEVICE_ATTRIBUTE_MAX_GRID_DIM_Y => std::u16::MAX as i32,
+        CU_DEVICE_ATTRIBUTE_MAX_GRID_DIM_Z => std::u16::MAX as i32,
+        CU_DEVICE_ATTRIBUTE_MAX_SHARED_MEMORY_PER_BLOCK => MAX_SHARED_MEMORY_PER_BLOCK,
+        CU_DEVICE_ATTRIBUTE_MAX_SHARED_MEMORY_PER_BLOCK_OPTIN => MAX_SHARED_MEMORY_PER_BLOCK_OPTIN,
+        CU_DEVICE_ATTRIBUTE_MAX_SHARED_MEMORY_PER_MULTIPROCESSOR => {
+            MAX_SHARED_MEMORY_PER_BLOCK_OPTIN
+        }
+        CU_DEVICE_ATTRIBUTE_TOTAL_CONSTANT_MEMORY => 64 * 1024,
+        CU_DEVICE_ATTRIBUTE_WARP_SIZE => 32,
+        CU_DEVICE_ATTRIBUTE_MAX_REGISTERS_PER_BLOCK => 64 * 1024,
+        CU_DEVICE_ATTRIBUTE_MAX_REGISTERS_PER_MULTIPROCESSOR => 64 * 1024,
+        CU_DEVICE_ATTRIBUTE_MULTIPROCESSOR_COUNT => 80,
+        CU_DEVICE_ATTRIBUTE_MAX_THREADS_PER_MULTIPROCESSOR => 2048,
+        CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MAJOR => 7,
+        CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MINOR => 0,
+        _ => return None,
+    })
+}
+
+/// Registers used by each thread of emulated kernels
+const NUM_REGS: i32 = 32;
+
+thread_local! {
+    /// Context stack of the current thread
+    static CONTEXT_STACK: RefCell<Vec<usize>> = RefCell::new(Vec::new());
+}
+
+fn fail<T>(error: CUresult, api_name: &str) -> Result<T> {
+    check(error, api_name)?;
+    unreachable!("{} must fail", api_name)
+}
+
+fn current_context() -> Option<usize> {
+    CONTEXT_STACK.with(|stack| stack.borrow().last().cloned())
+}
+
+/// Size of each channel of the array element in bytes
+fn format_size(format: CUarray_format) -> usize {
+    match format {
+        CUarray_format::CU_AD_FORMAT_UNSIGNED_INT8 | CUarray_format::CU_AD_FORMAT_SIGNED_INT8 => 1,
+        CUarray_format::CU_AD_FORMAT_UNSIGNED_INT16
+        | CUarray_format::CU_AD_FORMAT_SIGNED_INT16
+        | CUarray_format::CU_AD_FORMAT_HALF => 2,
+        CUarray_format::CU_AD_FORMAT_UNSIGNED_INT32
+        | CUarray_format::CU_AD_FORMAT_SIGNED_INT32
+        | CUarray_format::CU_AD_FORMAT_FLOAT => 4,
+    }
+}
+
+/// Extent of array in `(pitch in bytes, height, depth)`
+fn array_extent(desc: &CUDA_ARRAY3D_DESCRIPTOR) -> (usize, usize, usize) {
+    (
+        desc.Width * desc.NumChannels as usize * format_size(desc.Format),
+        std::cmp::max(desc.Height, 1),
+        std::cmp::max(desc.Depth, 1),
+    )
+}
+
+/// `CUDA_MEMCPY2D` as a single layer of `CUDA_MEMCPY3D`
+fn memcpy_2d_as_3d(p: &CUDA_MEMCPY2D) -> CUDA_MEMCPY3D {
+    CUDA_MEMCPY3D {
+        srcXInBytes: p.srcXInBytes,
+        srcY: p.srcY,
+        srcMemoryType: p.srcMemoryType,
+        srcHost: p.srcHost,
+        srcDevice: p.srcDevice,
+        srcArray: p.srcArray,
+        srcPitch: p.srcPitch,
+        dstXInBytes: p.dstXInBytes,
+        dstY: p.dstY,
+        dstMemoryType: p.dstMemoryType,
+        dstHost: p.dstHost,
+        dstDevice: p.dstDevice,
+        dstArray: p.dstArray,
+        dstPitch: p.dstPitch,
+        WidthInBytes: p.WidthInBytes,
+        Height: p.Height,
+        Depth: 1,
+        ..crate::memory::memcpy3d_param_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AllocationKind {
+    /// Allocated by `cuMemAlloc`
+    Device,
+    /// Allocated by `cuMemAllocManaged`
+    Managed,
+    /// Allocated by `cuMemAllocHost`
+    PageLocked,
+    /// Registered by `cuMemHostRegister`, not owned by the emulator
+    Registered,
+    /// Allocated by `cuArray3DCreate`
+    Array(CUDA_ARRAY3D_DESCRIPTOR),
+    /// `.global` and `.const` variables of a loaded module
+    Global,
+}
+
+impl AllocationKind {
+    fn memory_type(&self) -> CUmemorytype {
+        match self {
+            AllocationKind::Device | AllocationKind::Managed | AllocationKind::Global => {
+                CUmemorytype::CU_MEMORYTYPE_DEVICE
+            }
+            AllocationKind::PageLocked | AllocationKind::Registered => {
+                CUmemorytype::CU_MEMORYTYPE_HOST
+            }
+            AllocationKind::Array(_) => CUmemorytype::CU_MEMORYTYPE_ARRAY,
+        }
+    }
+
+    fn is_owned(&self) -> bool {
+        *self != AllocationKind::Registered
+    }
+}
+
+#[derive(Debug)]
+struct Allocation {
+    size: usize,
+    kind: AllocationKind,
+    context: usize,
+}
+
+impl Allocation {
+    fn layout(&self) -> Layout {
+        Layout::from_size_align(self.size, ALIGNMENT).unwrap()
+    }
+}
+
+/// Log buffer given by `CU_JIT_*_LOG_BUFFER` and `CU_JIT_*_LOG_BUFFER_SIZE_BYTES` options
+///
+/// Addresses are kept as integers to keep `State` Send.
+#[derive(Debug, Clone, Copy)]
+struct LogOption {
+    buffer: usize,
+    size: usize,
+    /// Option value overwritten by the filled size
+    size_slot: usize,
+}
+
+impl LogOption {
+    /// Write a NUL-terminated message truncated to the buffer size
+    unsafe fn write(&self, message: &str) {
+        if self.buffer == 0 || self.size == 0 {
+            return;
+        }
+        let len = message.len().min(self.size - 1);
+        ptr::copy_nonoverlapping(message.as_ptr(), self.buffer as *mut u8, len);
+        *(self.buffer as *mut u8).add(len) = 0;
+        *(self.size_slot as *mut *mut c_void) = (len + 1) as *mut c_void;
+    }
+}
+
+/// Log buffers and the `CU_JIT_WALL_TIME` slot given by JIT options
+#[derive(Debug, Default, Clone, Copy)]
+struct JitLog {
+    info: Option<LogOption>,
+    error: Option<LogOption>,
+    /// Option value overwritten by the wall time as `f32`
+    wall_time: Option<usize>,
+}
+
+impl JitLog {
+    unsafe fn from_options(
+        num_opts: u32,
+        opts: *mut CUjit_option,
+        opt_values: *mut *mut c_void,
+    ) -> Self {
+        use CUjit_option::*;
+        let mut buffers = [(0, None), (0, None)];
+        let mut wall_time = None;
+        for i in 0..num_opts as usize {
+            let value = opt_values.add(i);
+            match *opts.add(i) {
+                CU_JIT_WALL_TIME => wall_time = Some(value as usize),
+                CU_JIT_INFO_LOG_BUFFER => buffers[0].0 = *value as usize,
+                CU_JIT_INFO_LOG_BUFFER_SIZE_BYTES => buffers[0].1 = Some(value),
+                CU_JIT_ERROR_LOG_BUFFER => buffers[1].0 = *value as usize,
+                CU_JIT_ERROR_LOG_BUFFER_SIZE_BYTES => buffers[1].1 = Some(value),
+                _ => {}
+            }
+        }
+        let [info, error] = buffers;
+        let log_option = |(buffer, slot): (usize, Option<*mut *mut c_void>)| {
+            slot.map(|slot| LogOption {
+                buffer,
+                size: *slot as usize,
+                size_slot: slot as usize,
+            })
+        };
+        JitLog {
+            info: log_option(info),
+            error: log_option(error),
+            wall_time,
+        }
+    }
+
+    /// Use buffers of `other` if not given
+    fn or(self, other: JitLog) -> JitLog {
+        JitLog {
+            info: self.info.or(other.info),
+            error: self.error.or(other.error),
+            wall_time: self.wall_time.or(other.wall_time),
+        }
+    }
+
+    /// Write the time elapsed since `start` in milliseconds
+    unsafe fn wall_time(&self, start: Instant) {
+        if let Some(slot) = self.wall_time {
+            *(slot as *mut *mut c_void) = ptr::null_mut();
+            *(slot as *mut f32) = start.elapsed().as_secs_f32() * 1e3;
+        }
+    }
+
+    unsafe fn info(&self, message: &str) {
+        if let Some(info) = self.info {
+            info.write(message);
+        }
+    }
+
+    unsafe fn error(&self, message: &str) {
+        if let Some(error) = self.error {
+            error.write(message);
+        }
+    }
+}
+
+/// Compile PTX as ptxas does, writing its messages into the log buffers
+fn compile_ptx(image: &[u8], log: &JitLog) -> Option<PtxModule> {
+    let start = Instant::now();
+    let result = PtxModule::parse(&String::from_utf8_lossy(image));
+    unsafe { log.wall_time(start) };
+    match result {
+        Ok(ptx) => {
+            let info: Vec<String> = ptx
+                .kernels()
+                .map(|f| {
+                    format!(
+                        "ptxas info    : Compiling entry function '{}' for 'sm_70'",
+                        f.name
+                    )
+                })
+                .collect();
+            unsafe { log.info(&info.join("\n")) };
+            Some(ptx)
+        }
+        Err(PtxError { line, message }) => {
+            unsafe {
+                log.error(&format!(
+                    "ptxas application ptx input, line {}; error   : {}\nptxas fatal   : Ptx assembly aborted due to errors",
+                    line, message
+                ))
+            };
+            None
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Link {
+    inputs: Vec<u8>,
+    output: Vec<u8>,
+    /// Log buffers given to `cuLinkCreate`
+    log: JitLog,
+}
+
+#[derive(Debug)]
+struct LoadedModule {
+    /// Names of `.entry`
+    entries: Vec<String>,
+    /// Size of module-scope `.shared` variables, regarded as static shared memory of every entry
+    shared_size: i32,
+    /// PTX ISA version, e.g. 65 for 6.5
+    ptx_version: i32,
+    /// Address and size of `.global` and `.const` variables defined in the module
+    globals: HashMap<String, (usize, usize)>,
+}
+
+#[derive(Debug)]
+struct Function {
+    module: usize,
+    name: String,
+    /// `CU_FUNC_ATTRIBUTE_MAX_DYNAMIC_SHARED_SIZE_BYTES`
+    max_dynamic_shared: i32,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    last_handle: usize,
+    contexts: HashMap<usize, CUdevice>,
+    allocations: BTreeMap<usize, Allocation>,
+    streams: HashSet<usize>,
+    events: HashSet<usize>,
+    modules: HashMap<usize, LoadedModule>,
+    functions: HashMap<usize, Function>,
+    links: HashMap<usize, Link>,
+    textures: HashSet<usize>,
+    surfaces: HashSet<usize>,
+}
+
+impl State {
+    fn new_handle(&mut self) -> usize {
+        self.last_handle += 1;
+        self.last_handle
+    }
+
+    fn require_context(&self, api_name: &str) -> Result<usize> {
+        match current_context() {
+            Some(ctx) if self.contexts.contains_key(&ctx) => Ok(ctx),
+            _ => fail(CUresult::CUDA_ERROR_INVALID_CONTEXT, api_name),
+        }
+    }
+
+    fn require_stream(&self, stream: CUstream, api_name: &str) -> Result<()> {
+        // null stream is the default stream of the current context
+        if stream.is_null() || self.streams.contains(&(stream as usize)) {
+            Ok(())
+        } else {
+            fail(CUresult::CUDA_ERROR_INVALID_HANDLE, api_name)
+        }
+    }
+
+    fn used_memory(&self) -> usize {
+        self.allocations
+            .values()
+            .filter(|a| a.kind.is_owned())
+            .map(|a| a.size)
+            .sum::<usize>()
+            + self.contexts.len() * CONTEXT_RESERVED_MEMORY
+    }
+
+    /// Find the allocation which contains `addr`
+    fn find(&self, addr: usize) -> Option<(usize, &Allocation)> {
+        self.allocations
+            .range(..=addr)
+            .next_back()
+            .filter(|(&base, a)| addr < base + a.size)
+            .map(|(&base, a)| (base, a))
+    }
+
+    fn allocate(
+        &mut self,
+        size: usize,
+        kind: AllocationKind,
+        total: usize,
+        api_name: &str,
+    ) -> Result<usize> {
+        let context = self.require_context(api_name)?;
+        if size == 0 {
+            return fail(CUresult::CUDA_ERROR_INVALID_VALUE, api_name);
+        }
+        if self.used_memory().saturating_add(size) > total {
+            return fail(CUresult::CUDA_ERROR_OUT_OF_MEMORY, api_name);
+        }
+        let allocation = Allocation {
+            size,
+            kind,
+            context,
+        };
+        let ptr = unsafe { alloc_zeroed(allocation.layout()) };
+        if ptr.is_null() {
+            return fail(CUresult::CUDA_ERROR_OUT_OF_MEMORY, api_name);
+        }
+        self.allocations.insert(ptr as usize, allocation);
+        Ok(ptr as usize)
+    }
+
+    fn free(&mut self, addr: usize, kind: AllocationKind, api_name: &str) -> Result<()> {
+        match self.allocations.get(&addr) {
+            Some(a) if a.kind == kind => {}
+            _ => return fail(CUresult::CUDA_ERROR_INVALID_VALUE, api_name),
+        }
+        let allocation = self.allocations.remove(&addr).unwrap();
+        unsafe { dealloc(addr as *mut u8, allocation.layout()) };
+        Ok(())
+    }
+
+    /// Check `[addr, addr + size)` is inside a managed memory
+    fn require_managed(&self, addr: usize, size: usize, api_name: &str) -> Result<()> {
+        match self.find(addr) {
+            Some((base, a))
+                if a.kind == AllocationKind::Managed && addr + size <= base + a.size =>
+            {
+                Ok(())
+            }
+            _ => fail(CUresult::CUDA_ERROR_INVALID_VALUE, api_name),
+        }
+    }
+
+    /// Descriptor of the array of `CU_RESOURCE_TYPE_ARRAY` resource
+    unsafe fn require_array_resource(
+        &self,
+        res_desc: &CUDA_RESOURCE_DESC,
+        api_name: &str,
+    ) -> Result<CUDA_ARRAY3D_DESCRIPTOR> {
+        if res_desc.resType != CUresourcetype::CU_RESOURCE_TYPE_ARRAY {
+            return fail(CUresult::CUDA_ERROR_NOT_SUPPORTED, api_name);
+        }
+        match self.allocations.get(&(res_desc.res.array.hArray as usize)) {
+            Some(Allocation {
+                kind: AllocationKind::Array(desc),
+                ..
+            }) => Ok(*desc),
+            _ => fail(CUresult::CUDA_ERROR_INVALID_HANDLE, api_name),
+        }
+    }
+
+    /// Check `[addr, addr + size)` is inside a linear memory
+    fn require_range(&self, addr: usize, size: usize, api_name: &str) -> Result<()> {
+        match self.find(addr) {
+            Some((base, a))
+                if !matches!(a.kind, AllocationKind::Array(_)) && addr + size <= base + a.size =>
+            {
+                Ok(())
+            }
+            _ => fail(CUresult::CUDA_ERROR_INVALID_VALUE, api_name),
+        }
+    }
+
+    /// Resolve one side of `CUDA_MEMCPY3D` into `(head pointer, pitch, height)`
+    #[allow(clippy::too_many_arguments)]
+    fn memcpy_operand(
+        &self,
+        memory_type: CUmemorytype,
+        host: *const c_void,
+        device: CUdeviceptr,
+        array: CUarray,
+        (x, y, z): (usize, usize, usize),
+        (pitch, height): (usize, usize),
+        param: &CUDA_MEMCPY3D,
+        api_name: &str,
+    ) -> Result<(*mut u8, usize, usize)> {
+        match memory_type {
+            CUmemorytype::CU_MEMORYTYPE_ARRAY => {
+                let desc = match self.allocations.get(&(array as usize)) {
+                    Some(Allocation {
+                        kind: AllocationKind::Array(desc),
+                        ..
+                    }) => desc,
+                    _ => return fail(CUresult::CUDA_ERROR_INVALID_VALUE, api_name),
+                };
+                let (pitch, height, depth) = array_extent(desc);
+                if x + param.WidthInBytes > pitch
+                    || y + std::cmp::max(param.Height, 1) > height
+                    || z + std::cmp::max(param.Depth, 1) > depth
+                {
+                    return fail(CUresult::CUDA_ERROR_INVALID_VALUE, api_name);
+                }
+                Ok((array as *mut u8, pitch, height))
+            }
+            _ => {
+                let head = if memory_type == CUmemorytype::CU_MEMORYTYPE_HOST {
+                    host as *mut u8
+                } else {
+                    device as *mut u8
+                };
+                let pitch = if pitch == 0 {
+                    param.WidthInBytes
+                } else {
+                    pitch
+                };
+                let height = if height == 0 { param.Height } else { height };
+                Ok((head, pitch, height))
+            }
+        }
+    }
+
+    unsafe fn memcpy_3d(&self, p: &CUDA_MEMCPY3D, api_name: &str) -> Result<()> {
+        self.require_context(api_name)?;
+        let (src, src_pitch, src_height) = self.memcpy_operand(
+            p.srcMemoryType,
+            p.srcHost,
+            p.srcDevice,
+            p.srcArray,
+            (p.srcXInBytes, p.srcY, p.srcZ),
+            (p.srcPitch, p.srcHeight),
+            p,
+            api_name,
+        )?;
+        let (dst, dst_pitch, dst_height) = self.memcpy_operand(
+            p.dstMemoryType,
+            p.dstHost,
+            p.dstDevice,
+            p.dstArray,
+            (p.dstXInBytes, p.dstY, p.dstZ),
+            (p.dstPitch, p.dstHeight),
+            p,
+            api_name,
+        )?;
+        for z in 0..std::cmp::max(p.Depth, 1) {
+            for y in 0..std::cmp::max(p.Height, 1) {
+                let src_offset =
+                    ((p.srcZ + z) * src_height + p.srcY + y) * src_pitch + p.srcXInBytes;
+                let dst_offset =
+                    ((p.dstZ + z) * dst_height + p.dstY + y) * dst_pitch + p.dstXInBytes;
+                ptr::copy(src.add(src_offset), dst.add(dst_offset), p.WidthInBytes);
+            }
+        }
+        Ok(())
+    }
+
+    fn load_module(
+        &mut self,
+        image: &[u8],
+        log: &JitLog,
+        total: usize,
+        api_name: &str,
+    ) -> Result<CUmodule> {
+        self.require_context(api_name)?;
+        if image.starts_with(b"\x7fELF") || image.starts_with(&FATBIN_MAGIC) {
+            // cubin and fatbin cannot be executed on host
+            return fail(CUresult::CUDA_ERROR_NO_BINARY_FOR_GPU, api_name);
+        }
+        let ptx = match compile_ptx(image, log) {
+            Some(ptx) => ptx,
+            None => return fail(CUresult::CUDA_ERROR_INVALID_PTX, api_name),
+        };
+        let module = self.new_handle();
+        let entries = ptx.kernels().map(|f| f.name.clone()).collect();
+        let shared_size = ptx
+            .variables
+            .iter()
+            .filter(|v| v.space == StateSpace::Shared)
+            .map(|v| v.size().unwrap_or(0))
+            .sum::<usize>() as i32;
+        let ptx_version = (ptx.version.0 * 10 + ptx.version.1) as i32;
+        // Initializers are not parsed, i.e. variables are always zero-initialized
+        let mut globals = HashMap::new();
+        for v in &ptx.variables {
+            if v.space == StateSpace::Shared || v.linkage == Linkage::Extern {
+                continue;
+            }
+            let size = match v.size() {
+                Some(size) if size > 0 => size,
+                _ => continue,
+            };
+            match self.allocate(size, AllocationKind::Global, total, api_name) {
+                Ok(addr) => {
+                    globals.insert(v.name.clone(), (addr, size));
+                }
+                Err(e) => {
+                    for (addr, _) in globals.values() {
+                        self.free(*addr, AllocationKind::Global, api_name)?;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        self.modules.insert(
+            module,
+            LoadedModule {
+                entries,
+                shared_size,
+                ptx_version,
+                globals,
+            },
+        );
+        Ok(module as CUmodule)
+    }
+
+    fn add_link_input(
+        &mut self,
+        state: CUlinkState,
+        input_type: CUjitInputType,
+        data: &[u8],
+        name: &str,
+        log: JitLog,
+        api_name: &str,
+    ) -> Result<()> {
+        let link = match self.links.get_mut(&(state as usize)) {
+            Some(link) => link,
+            None => return fail(CUresult::CUDA_ERROR_INVALID_HANDLE, api_name),
+        };
+        let log = log.or(link.log);
+        if input_type != CUjitInputType::CU_JIT_INPUT_PTX {
+            // Device code in binaries cannot be emulated
+            unsafe {
+                log.error(&format!(
+                    "nvlink fatal   : Could not link '{}' on the emulator",
+                    name
+                ))
+            };
+            return fail(CUresult::CUDA_ERROR_NOT_SUPPORTED, api_name);
+        }
+        let len = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+        if compile_ptx(&data[..len], &log).is_none() {
+            return fail(CUresult::CUDA_ERROR_INVALID_PTX, api_name);
+        }
+        link.inputs.extend_from_slice(&data[..len]);
+        link.inputs.push(b'\n');
+        Ok(())
+    }
+}
+
+/// Host-only backend for testing accel without GPU
+///
+/// - Device and page-locked memories are allocated on the heap, and can be accessed from the host
+/// - Every operation completes synchronously, i.e. streams and events are always ready
+/// - Contexts are tracked in a per-thread stack as the CUDA driver does
+/// - PTX modules are parsed by [PtxModule](../ptx/struct.PtxModule.html) to find `.entry` names.
+///   Kernel launches are validated, but nothing is executed except the built-in fill kernel used by `Memory::set`.
+/// - The linker concatenates the PTX inputs
+/// - Invalid PTX is reported into the error log buffer of JIT options as ptxas does
+#[derive(Debug)]
+pub struct Emulator {
+    num_devices: usize,
+    total_memory: usize,
+    state: Mutex<State>,
+}
+
+impl Default for Emulator {
+    /// Single device with 4GB memory
+    fn default() -> Self {
+        Emulator::new(1, 4 << 30)
+    }
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        let state = self.state.get_mut().unwrap();
+        for (&addr, allocation) in &state.allocations {
+            if allocation.kind.is_owned() {
+                unsafe { dealloc(addr as *mut u8, allocation.layout()) };
+            }
+        }
+    }
+}
+
+impl Emulator {
+    /// Emulator with `num_devices` devices, each has `total_memory` bytes
+    pub fn new(num_devices: usize, total_memory: usize) -> Self {
+        Emulator {
+            num_devices,
+            total_memory,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+impl Backend for Emulator {
+    fn name(&self) -> &'static str {
+        "emulator"
+    }
+
+    fn init(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn driver_get_version(&self) -> Result<u32> {
+        Ok(DRIVER_VERSION)
+    }
+
+    fn device_get_count(&self) -> Result<usize> {
+        Ok(self.num_devices)
+    }
+
+    fn device_get(&self, ordinal: usize) -> Result<CUdevice> {
+        if ordinal >= self.num_devices {
+            return fail(CUresult::CUDA_ERROR_INVALID_DEVICE, "cuDeviceGet");
+        }
+        Ok(ordinal as CUdevice)
+    }
+
+    fn device_get_name(&self, device: CUdevice) -> Result<String> {
+        Ok(format!("accel emulator #{}", device))
+    }
+
+    fn device_total_mem(&self, _device: CUdevice) -> Result<usize> {
+        Ok(self.total_memory)
+    }
+
+    fn device_get_attribute(&self, attrib: CUdevice_attribute, device: CUdevice) -> Result<i32> {
+        let api_name = "cuDeviceGetAttribute";
+        if device < 0 || device as usize >= self.num_devices {
+            return fail(CUresult::CUDA_ERROR_INVALID_DEVICE, api_name);
+        }
+        match device_attribute(attrib) {
+            Some(value) => Ok(value),
+            None => fail(CUresult::CUDA_ERROR_INVALID_VALUE, api_name),
+        }
+    }
+
+    fn ctx_create(&self, _flags: u32, device: CUdevice) -> Result<CUcontext> {
+        if device < 0 || device as usize >= self.num_devices {
+            return fail(CUresult::CUDA_ERROR_INVALID_DEVICE, "cuCtxCreate_v2");
+        }
+        let mut state = self.state();
+        let ctx = state.new_handle();
+        state.contexts.insert(ctx, device);
+        CONTEXT_STACK.with(|stack| stack.borrow_mut().push(ctx));
+        Ok(ctx as CUcontext)
+    }
+
+    unsafe fn ctx_destroy(&self, ctx: CUcontext) -> Result<()> {
+        let ctx = ctx as usize;
+        let mut state = self.state();
+        if state.contexts.remove(&ctx).is_none() {
+            return fail(CUresult::CUDA_ERROR_INVALID_CONTEXT, "cuCtxDestroy_v2");
+        }
+        CONTEXT_STACK.with(|stack| stack.borrow_mut().retain(|&c| c != ctx));
+        // Resources in the context are released with it
+        let owned: Vec<usize> = state
+            .allocations
+            .iter()
+            .filter(|(_, a)| a.context == ctx)
+            .map(|(&addr, _)| addr)
+            .collect();
+        for addr in owned {
+            let allocation = state.allocations.remove(&addr).unwrap();
+            if allocation.kind.is_owned() {
+                dealloc(addr as *mut u8, allocation.layout());
+            }
+        }
+        Ok(())
+    }
+
+    unsafe fn ctx_push_current(&self, ctx: CUcontext) -> Result<()> {
+        let ctx = ctx as usize;
+        if !self.state().contexts.contains_key(&ctx) {
+            return fail(CUresult::CUDA_ERROR_INVALID_CONTEXT, "cuCtxPushCurrent_v2");
+        }
+        CONTEXT_STACK.with(|stack| stack.borrow_mut().push(ctx));
+        Ok(())
+    }
+
+    fn ctx_pop_current(&self) -> Result<CUcontext> {
+        match CONTEXT_STACK.with(|stack| stack.borrow_mut().pop()) {
+            Some(ctx) => Ok(ctx as CUcontext),
+            None => fail(CUresult::CUDA_ERROR_INVALID_CONTEXT, "cuCtxPopCurrent_v2"),
+        }
+    }
+
+    unsafe fn ctx_get_api_version(&self, ctx: CUcontext) -> Result<u32> {
+        if !self.state().contexts.contains_key(&(ctx as usize)) {
+            return fail(CUresult::CUDA_ERROR_INVALID_CONTEXT, "cuCtxGetApiVersion");
+        }
+        Ok(API_VERSION)
+    }
+
+    fn ctx_synchronize(&self) -> Result<()> {
+        self.state().require_context("cuCtxSynchronize")?;
+        Ok(())
+    }
+
+    fn ctx_get_device(&self) -> Result<CUdevice> {
+        let state = self.state();
+        let ctx = state.require_context("cuCtxGetDevice")?;
+        Ok(state.contexts[&ctx])
+    }
+
+    fn mem_get_info(&self) -> Result<(usize, usize)> {
+        let state = self.state();
+        state.require_context("cuMemGetInfo_v2")?;
+        Ok((
+            self.total_memory.saturating_sub(state.used_memory()),
+            self.total_memory,
+        ))
+    }
+
+    unsafe fn mem_alloc(&self, bytes: usize) -> Result<CUdeviceptr> {
+        let addr = self.state().allocate(
+            bytes,
+            AllocationKind::Device,
+            self.total_memory,
+            "cuMemAlloc_v2",
+        )?;
+        Ok(addr as CUdeviceptr)
+    }
+
+    unsafe fn mem_alloc_pitch(
+        &self,
+        width_bytes: usize,
+        height: usize,
+        element_size: u32,
+    ) -> Result<(CUdeviceptr, usize)> {
+        let api_name = "cuMemAllocPitch_v2";
+        if ![4, 8, 16].contains(&element_size) || width_bytes == 0 || height == 0 {
+            return fail(CUresult::CUDA_ERROR_INVALID_VALUE, api_name);
+        }
+        let pitch = (width_bytes + PITCH_ALIGNMENT - 1) / PITCH_ALIGNMENT * PITCH_ALIGNMENT;
+        let addr = self.state().allocate(
+            pitch * height,
+            AllocationKind::Device,
+            self.total_memory,
+            api_name,
+        )?;
+        Ok((addr as CUdeviceptr, pitch))
+    }
+
+    unsafe fn mem_alloc_managed(&self, bytes: usize, _flags: u32) -> Result<CUdeviceptr> {
+        let addr = self.state().allocate(
+            bytes,
+            AllocationKind::Managed,
+            self.total_memory,
+            "cuMemAllocManaged",
+        )?;
+        Ok(addr as CUdeviceptr)
+    }
+
+    unsafe fn mem_prefetch_async(
+        &self,
+        ptr: CUdeviceptr,
+        bytes: usize,
+        device: CUdevice,
+        stream: CUstream,
+    ) -> Result<()> {
+        let api_name = "cuMemPrefetchAsync";
+        let state = self.state();
+        state.require_context(api_name)?;
+        state.require_stream(stream, api_name)?;
+        state.require_managed(ptr as usize, bytes, api_name)?;
+        if device < CU_DEVICE_CPU || device >= self.num_devices as CUdevice {
+            return fail(CUresult::CUDA_ERROR_INVALID_DEVICE, api_name);
+        }
+        // Host and device share the heap, nothing to migrate
+        Ok(())
+    }
+
+    unsafe fn mem_advise(
+        &self,
+        ptr: CUdeviceptr,
+        bytes: usize,
+        _advice: CUmem_advise,
+        device: CUdevice,
+    ) -> Result<()> {
+        let api_name = "cuMemAdvise";
+        let state = self.state();
+        state.require_context(api_name)?;
+        state.require_managed(ptr as usize, bytes, api_name)?;
+        if device < CU_DEVICE_CPU || device >= self.num_devices as CUdevice {
+            return fail(CUresult::CUDA_ERROR_INVALID_DEVICE, api_name);
+        }
+        Ok(())
+    }
+
+    unsafe fn mem_free(&self, ptr: CUdeviceptr) -> Result<()> {
+        let mut state = self.state();
+        let kind = match state.allocations.get(&(ptr as usize)) {
+            Some(a) if a.kind == AllocationKind::Device => AllocationKind::Device,
+            _ => AllocationKind::Managed,
+        };
+        state.free(ptr as usize, kind, "cuMemFree_v2")
+    }
+
+    unsafe fn mem_alloc_host(&self, bytes: usize) -> Result<*mut c_void> {
+        let addr = self.state().allocate(
+            bytes,
+            AllocationKind::PageLocked,
+            self.total_memory,
+            "cuMemAllocHost_v2",
+        )?;
+        Ok(addr as *mut c_void)
+    }
+
+    unsafe fn mem_free_host(&self, ptr: *mut c_void) -> Result<()> {
+        self.state()
+            .free(ptr as usize, AllocationKind::PageLocked, "cuMemFreeHost")
+    }
+
+    unsafe fn mem_host_register(&self, ptr: *mut c_void, bytes: usize, _flags: u32) -> Result<()> {
+        let api_name = "cuMemHostRegister_v2";
+        let mut state = self.state();
+        let context = state.require_context(api_name)?;
+        if ptr.is_null() || bytes == 0 {
+            return fail(CUresult::CUDA_ERROR_INVALID_VALUE, api_name);
+        }
+        let addr = ptr as usize;
+        let overlapped = state.find(addr).is_some()
+            || state.allocations.range(addr..addr + bytes).next().is_some();
+        if overlapped {
+            return fail(
+                CUresult::CUDA_ERROR_HOST_MEMORY_ALREADY_REGISTERED,
+                api_name,
+            );
+        }
+        state.allocations.insert(
+            addr,
+            Allocation {
+                size: bytes,
+                kind: AllocationKind::Registered,
+                context,
+            },
+        );
+        Ok(())
+    }
+
+    unsafe fn mem_host_unregister(&self, ptr: *mut c_void) -> Result<()> {
+        let mut state = self.state();
+        match state.allocations.get(&(ptr as usize)) {
+            Some(a) if a.kind == AllocationKind::Registered => {
+                state.allocations.remove(&(ptr as usize));
+                Ok(())
+            }
+            _ => fail(
+                CUresult::CUDA_ERROR_HOST_MEMORY_NOT_REGISTERED,
+                "cuMemHostUnregister",
+            ),
+        }
+    }
+
+    unsafe fn pointer_get_attribute(
+        &self,
+        data: *mut c_void,
+        attr: CUpointer_attribute,
+        ptr: CUdeviceptr,
+    ) -> Result<()> {
+        let api_name = "cuPointerGetAttribute";
+        let state = self.state();
+        let allocation = match state.find(ptr as usize) {
+            Some((_, a)) => a,
+            None => return fail(CUresult::CUDA_ERROR_INVALID_VALUE, api_name),
+        };
+        match attr {
+            CUpointer_attribute::CU_POINTER_ATTRIBUTE_MEMORY_TYPE => {
+                *(data as *mut CUmemorytype) = allocation.kind.memory_type();
+            }
+            CUpointer_attribute::CU_POINTER_ATTRIBUTE_CONTEXT => {
+                *(data as *mut CUcontext) = allocation.context as CUcontext;
+            }
+            CUpointer_attribute::CU_POINTER_ATTRIBUTE_IS_MANAGED => {
+                *(data as *mut u32) = (allocation.kind == AllocationKind::Managed) as u32;
+            }
+            _ => return fail(CUresult::CUDA_ERROR_NOT_SUPPORTED, api_name),
+        }
+        Ok(())
+    }
+
+    unsafe fn memset_d8(&self, dst: CUdeviceptr, value: u8, n: usize) -> Result<()> {
+        let state = self.state();
+        state.require_context("cuMemsetD8_v2")?;
+        state.require_range(dst as usize, n, "cuMemsetD8_v2")?;
+        ptr::write_bytes(dst as *mut u8, value, n);
+        Ok(())
+    }
+
+    unsafe fn memset_d16(&self, dst: CUdeviceptr, value: u16, n: usize) -> Result<()> {
+        let state = self.state();
+        state.require_context("cuMemsetD16_v2")?;
+        state.require_range(dst as usize, n * 2, "cuMemsetD16_v2")?;
+        if dst & 1 != 0 {
+            return fail(CUresult::CUDA_ERROR_INVALID_VALUE, "cuMemsetD16_v2");
+        }
+        std::slice::from_raw_parts_mut(dst as *mut u16, n)
+            .iter_mut()
+            .for_each(|v| *v = value);
+        Ok(())
+    }
+
+    unsafe fn memset_d32(&self, dst: CUdeviceptr, value: u32, n: usize) -> Result<()> {
+        let state = self.state();
+        state.require_context("cuMemsetD32_v2")?;
+        state.require_range(dst as usize, n * 4, "cuMemsetD32_v2")?;
+        if dst & 3 != 0 {
+            return fail(CUresult::CUDA_ERROR_INVALID_VALUE, "cuMemsetD32_v2");
+        }
+        std::slice::from_raw_parts_mut(dst as *mut u32, n)
+            .iter_mut()
+            .for_each(|v| *v = value);
+        Ok(())
+    }
+
+    unsafe fn memset_d8_async(
+        &self,
+        dst: CUdeviceptr,
+        value: u8,
+        n: usize,
+        stream: CUstream,
+    ) -> Result<()> {
+        self.state().require_stream(stream, "cuMemsetD8Async")?;
+        self.memset_d8(dst, value, n)
+    }
+
+    unsafe fn memset_d16_async(
+        &self,
+        dst: CUdeviceptr,
+        value: u16,
+        n: usize,
+        stream: CUstream,
+    ) -> Result<()> {
+        self.state().require_stream(stream, "cuMemsetD16Async")?;
+        self.memset_d16(dst, value, n)
+    }
+
+    unsafe fn memset_d32_async(
+        &self,
+        dst: CUdeviceptr,
+        value: u32,
+        n: usize,
+        stream: CUstream,
+    ) -> Result<()> {
+        self.state().require_stream(stream, "cuMemsetD32Async")?;
+        self.memset_d32(dst, value, n)
+    }
+
+    unsafe fn memset_d2d32_async(
+        &self,
+        dst: CUdeviceptr,
+        pitch: usize,
+        value: u32,
+        width: usize,
+        height: usize,
+        stream: CUstream,
+    ) -> Result<()> {
+        let api_name = "cuMemsetD2D32Async";
+        let state = self.state();
+        state.require_context(api_name)?;
+        state.require_stream(stream, api_name)?;
+        if dst & 3 != 0 || pitch & 3 != 0 || pitch < width * 4 {
+            return fail(CUresult::CUDA_ERROR_INVALID_VALUE, api_name);
+        }
+        if height == 0 {
+            return Ok(());
+        }
+        state.require_range(dst as usize, pitch * (height - 1) + width * 4, api_name)?;
+        for y in 0..height {
+            std::slice::from_raw_parts_mut((dst as usize + y * pitch) as *mut u32, width)
+                .iter_mut()
+                .for_each(|v| *v = value);
+        }
+        Ok(())
+    }
+
+    unsafe fn memcpy(&self, dst: CUdeviceptr, src: CUdeviceptr, bytes: usize) -> Result<()> {
+        self.state().require_context("cuMemcpy")?;
+        ptr::copy(src as *const u8, dst as *mut u8, bytes);
+        Ok(())
+    }
+
+    unsafe fn memcpy_async(
+        &self,
+        dst: CUdeviceptr,
+        src: CUdeviceptr,
+        bytes: usize,
+        stream: CUstream,
+    ) -> Result<()> {
+        let state = self.state();
+        state.require_context("cuMemcpyAsync")?;
+        state.require_stream(stream, "cuMemcpyAsync")?;
+        ptr::copy(src as *const u8, dst as *mut u8, bytes);
+        Ok(())
+    }
+
+    unsafe fn memcpy_2d(&self, param: &CUDA_MEMCPY2D) -> Result<()> {
+        self.state()
+            .memcpy_3d(&memcpy_2d_as_3d(param), "cuMemcpy2D_v2")
+    }
+
+    unsafe fn memcpy_2d_async(&self, param: &CUDA_MEMCPY2D, stream: CUstream) -> Result<()> {
+        let state = self.state();
+        state.require_stream(stream, "cuMemcpy2DAsync_v2")?;
+        state.memcpy_3d(&memcpy_2d_as_3d(param), "cuMemcpy2DAsync_v2")
+    }
+
+    unsafe fn memcpy_3d(&self, param: &CUDA_MEMCPY3D) -> Result<()> {
+        self.state().memcpy_3d(param, "cuMemcpy3D_v2")
+    }
+
+    unsafe fn memcpy_3d_async(&self, param: &CUDA_MEMCPY3D, stream: CUstream) -> Result<()> {
+        let state = self.state();
+        state.require_stream(stream, "cuMemcpy3DAsync_v2")?;
+        state.memcpy_3d(param, "cuMemcpy3DAsync_v2")
+    }
+
+    unsafe fn array_3d_create(&self, desc: &CUDA_ARRAY3D_DESCRIPTOR) -> Result<CUarray> {
+        let (pitch, height, depth) = array_extent(desc);
+        let addr = self.state().allocate(
+            pitch * height * depth,
+            AllocationKind::Array(*desc),
+            self.total_memory,
+            "cuArray3DCreate_v2",
+        )?;
+        Ok(addr as CUarray)
+    }
+
+    unsafe fn array_destroy(&self, array: CUarray) -> Result<()> {
+        let mut state = self.state();
+        match state.allocations.get(&(array as usize)) {
+            Some(Allocation {
+                kind: AllocationKind::Array(desc),
+                ..
+            }) => {
+                let kind = AllocationKind::Array(*desc);
+                state.free(array as usize, kind, "cuArrayDestroy")
+            }
+            _ => fail(CUresult::CUDA_ERROR_INVALID_HANDLE, "cuArrayDestroy"),
+        }
+    }
+
+    unsafe fn tex_object_create(
+        &self,
+        res_desc: &CUDA_RESOURCE_DESC,
+        tex_desc: &CUDA_TEXTURE_DESC,
+    ) -> Result<CUtexObject> {
+        let api_name = "cuTexObjectCreate";
+        let mut state = self.state();
+        state.require_context(api_name)?;
+        let desc = state.require_array_resource(res_desc, api_name)?;
+        let float = matches!(
+            desc.Format,
+            CUarray_format::CU_AD_FORMAT_FLOAT | CUarray_format::CU_AD_FORMAT_HALF
+        );
+        let read_as_integer = tex_desc.flags & CU_TRSF_READ_AS_INTEGER != 0;
+        if tex_desc.filterMode == CUfilter_mode::CU_TR_FILTER_MODE_LINEAR
+            && read_as_integer
+            && !float
+        {
+            return fail(CUresult::CUDA_ERROR_INVALID_VALUE, api_name);
+        }
+        let tex = state.new_handle();
+        state.textures.insert(tex);
+        Ok(tex as CUtexObject)
+    }
+
+    unsafe fn tex_object_destroy(&self, tex: CUtexObject) -> Result<()> {
+        if self.state().textures.remove(&(tex as usize)) {
+            Ok(())
+        } else {
+            fail(CUresult::CUDA_ERROR_INVALID_HANDLE, "cuTexObjectDestroy")
+        }
+    }
+
+    unsafe fn surf_object_create(&self, res_desc: &CUDA_RESOURCE_DESC) -> Result<CUsurfObject> {
+        let api_name = "cuSurfObjectCreate";
+        let mut state = self.state();
+        state.require_context(api_name)?;
+        let desc = state.require_array_resource(res_desc, api_name)?;
+        if desc.Flags & CUDA_ARRAY3D_SURFACE_LDST == 0 {
+            return fail(CUresult::CUDA_ERROR_INVALID_VALUE, api_name);
+        }
+        let surf = state.new_handle();
+        state.surfaces.insert(surf);
+        Ok(surf as CUsurfObject)
+    }
+
+    unsafe fn surf_object_destroy(&self, surf: CUsurfObject) -> Result<()> {
+        if self.state().surfaces.remove(&(surf as usize)) {
+            Ok(())
+        } else {
+            fail(CUresult::CUDA_ERROR_INVALID_HANDLE, "cuSurfObjectDestroy")
+        }
+    }
+
+    fn stream_create(&self, _flags: u32) -> Result<CUstream> {
+        let mut state = self.state();
+        state.require_context("cuStreamCreate")?;
+        let stream = state.new_handle();
+        state.streams.insert(stream);
+        Ok(stream as CUstream)
+    }
+
+    unsafe fn stream_destroy(&self, stream: CUstream) -> Result<()> {
+        if !self.state().streams.remove(&(stream as usize)) {
+            return fail(CUresult::CUDA_ERROR_INVALID_HANDLE, "cuStreamDestroy_v2");
+        }
+        Ok(())
+    }
+
+    unsafe fn stream_query(&self, stream: CUstream) -> Result<()> {
+        self.state().require_stream(stream, "cuStreamQuery")
+    }
+
+    unsafe fn stream_synchronize(&self, stream: CUstream) -> Result<()> {
+        self.state().require_stream(stream, "cuStreamSynchronize")
+    }
+
+    unsafe fn stream_attach_mem_async(
+        &self,
+        stream: CUstream,
+        ptr: CUdeviceptr,
+        bytes: usize,
+        _flags: u32,
+    ) -> Result<()> {
+        let api_name = "cuStreamAttachMemAsync";
+        let state = self.state();
+        state.require_stream(stream, api_name)?;
+        // zero length attaches the whole allocation
+        state.require_managed(ptr as usize, bytes, api_name)
+    }
+
+    unsafe fn stream_wait_event(
+        &self,
+        stream: CUstream,
+        event: CUevent,
+        _flags: u32,
+    ) -> Result<()> {
+        let state = self.state();
+        state.require_stream(stream, "cuStreamWaitEvent")?;
+        if !state.events.contains(&(event as usize)) {
+            return fail(CUresult::CUDA_ERROR_INVALID_HANDLE, "cuStreamWaitEvent");
+        }
+        Ok(())
+    }
+
+    fn event_create(&self, _flags: u32) -> Result<CUevent> {
+        let mut state = self.state();
+        state.require_context("cuEventCreate")?;
+        let event = state.new_handle();
+        state.events.insert(event);
+        Ok(event as CUevent)
+    }
+
+    unsafe fn event_destroy(&self, event: CUevent) -> Result<()> {
+        if !self.state().events.remove(&(event as usize)) {
+            return fail(CUresult::CUDA_ERROR_INVALID_HANDLE, "cuEventDestroy_v2");
+        }
+        Ok(())
+    }
+
+    unsafe fn event_record(&self, event: CUevent, stream: CUstream) -> Result<()> {
+        let state = self.state();
+        state.require_stream(stream, "cuEventRecord")?;
+        if !state.events.contains(&(event as usize)) {
+            return fail(CUresult::CUDA_ERROR_INVALID_HANDLE, "cuEventRecord");
+        }
+        Ok(())
+    }
+
+    unsafe fn event_query(&self, event: CUevent) -> Result<()> {
+        if !self.state().events.contains(&(event as usize)) {
+            return fail(CUresult::CUDA_ERROR_INVALID_HANDLE, "cuEventQuery");
+        }
+        Ok(())
+    }
+
+    unsafe fn event_synchronize(&self, event: CUevent) -> Result<()> {
+        if !self.state().events.contains(&(event as usize)) {
+            return fail(CUresult::CUDA_ERROR_INVALID_HANDLE, "cuEventSynchronize");
+        }
+        Ok(())
+    }
+
+    fn module_load(&self, path: &Path) -> Result<CUmodule> {
+        let image = match fs::read(path) {
+            Ok(image) => image,
+            Err(_) => return fail(CUresult::CUDA_ERROR_FILE_NOT_FOUND, "cuModuleLoad"),
+        };
+        self.state().load_module(
+            &image,
+            &JitLog::default(),
+            self.total_memory,
+            "cuModuleLoad",
+        )
+    }
+
+    unsafe fn module_load_data(&self, image: *const c_void) -> Result<CUmodule> {
+        self.module_load_data_ex(image, 0, ptr::null_mut(), ptr::null_mut())
+    }
+
+    unsafe fn module_load_data_ex(
+        &self,
+        image: *const c_void,
+        num_opts: u32,
+        opts: *mut CUjit_option,
+        opt_values: *mut *mut c_void,
+    ) -> Result<CUmodule> {
+        let head = std::slice::from_raw_parts(image as *const u8, 4);
+        let image = if head == b"\x7fELF" || head == FATBIN_MAGIC {
+            head
+        } else {
+            CStr::from_ptr(image as *const _).to_bytes()
+        };
+        let log = JitLog::from_options(num_opts, opts, opt_values);
+        self.state()
+            .load_module(image, &log, self.total_memory, "cuModuleLoadDataEx")
+    }
+
+    unsafe fn module_unload(&self, module: CUmodule) -> Result<()> {
+        let module = module as usize;
+        let mut state = self.state();
+        let loaded = match state.modules.remove(&module) {
+            Some(loaded) => loaded,
+            None => return fail(CUresult::CUDA_ERROR_INVALID_HANDLE, "cuModuleUnload"),
+        };
+        state.functions.retain(|_, f| f.module != module);
+        for (addr, _) in loaded.globals.values() {
+            // already released if the context has been destroyed
+            if state.allocations.contains_key(addr) {
+                state.free(*addr, AllocationKind::Global, "cuModuleUnload")?;
+            }
+        }
+        Ok(())
+    }
+
+    unsafe fn module_get_global(
+        &self,
+        module: CUmodule,
+        name: &CStr,
+    ) -> Result<(CUdeviceptr, usize)> {
+        let api_name = "cuModuleGetGlobal_v2";
+        let name = name.to_string_lossy();
+        match self.state().modules.get(&(module as usize)) {
+            Some(m) => match m.globals.get(name.as_ref()) {
+                Some(&(addr, size)) => Ok((addr as CUdeviceptr, size)),
+                None => fail(CUresult::CUDA_ERROR_NOT_FOUND, api_name),
+            },
+            None => fail(CUresult::CUDA_ERROR_INVALID_HANDLE, api_name),
+        }
+    }
+
+    unsafe fn module_get_function(&self, module: CUmodule, name: &CStr) -> Result<CUfunction> {
+        let api_name = "cuModuleGetFunction";
+        let module = module as usize;
+        let name = name.to_string_lossy().into_owned();
+        let mut state = self.state();
+        let shared_size = match state.modules.get(&module) {
+            Some(m) if m.entries.contains(&name) => m.shared_size,
+            Some(_) => return fail(CUresult::CUDA_ERROR_NOT_FOUND, api_name),
+            None => return fail(CUresult::CUDA_ERROR_INVALID_HANDLE, api_name),
+        };
+        let found = state
+            .functions
+            .iter()
+            .find(|(_, f)| f.module == module && f.name == name)
+            .map(|(&func, _)| func);
+        let func = match found {
+            Some(func) => func,
+            None => {
+                let func = state.new_handle();
+                let max_dynamic_shared = MAX_SHARED_MEMORY_PER_BLOCK - shared_size;
+                state.functions.insert(
+                    func,
+                    Function {
+                        module,
+                        name,
+                        max_dynamic_shared,
+                    },
+                );
+                func
+            }
+        };
+        Ok(func as CUfunction)
+    }
+
+    unsafe fn func_get_attribute(
+        &self,
+        attrib: CUfunction_attribute,
+        func: CUfunction,
+    ) -> Result<i32> {
+        let api_name = "cuFuncGetAttribute";
+        let state = self.state();
+        let function = match state.functions.get(&(func as usize)) {
+            Some(function) => function,
+            None => return fail(CUresult::CUDA_ERROR_INVALID_HANDLE, api_name),
+        };
+        let module = &state.modules[&function.module];
+        use CUfunction_attribute::*;
+        Ok(match attrib {
+            CU_FUNC_ATTRIBUTE_MAX_THREADS_PER_BLOCK => 1024,
+            CU_FUNC_ATTRIBUTE_SHARED_SIZE_BYTES => module.shared_size,
+            CU_FUNC_ATTRIBUTE_CONST_SIZE_BYTES | CU_FUNC_ATTRIBUTE_LOCAL_SIZE_BYTES => 0,
+            CU_FUNC_ATTRIBUTE_NUM_REGS => NUM_REGS,
+            CU_FUNC_ATTRIBUTE_PTX_VERSION => module.ptx_version,
+            CU_FUNC_ATTRIBUTE_BINARY_VERSION => 70,
+            CU_FUNC_ATTRIBUTE_MAX_DYNAMIC_SHARED_SIZE_BYTES => function.max_dynamic_shared,
+            _ => return fail(CUresult::CUDA_ERROR_INVALID_VALUE, api_name),
+        })
+    }
+
+    unsafe fn func_set_attribute(
+        &self,
+        func: CUfunction,
+        attrib: CUfunction_attribute,
+        value: i32,
+    ) -> Result<()> {
+        let api_name = "cuFuncSetAttribute";
+        let mut state = self.state();
+        let shared_size = match state.functions.get(&(func as usize)) {
+            Some(function) => state.modules[&function.module].shared_size,
+            None => return fail(CUresult::CUDA_ERROR_INVALID_HANDLE, api_name),
+        };
+        match attrib {
+            CUfunction_attribute::CU_FUNC_ATTRIBUTE_MAX_DYNAMIC_SHARED_SIZE_BYTES
+                if value >= 0 && value + shared_size <= MAX_SHARED_MEMORY_PER_BLOCK_OPTIN =>
+            {
+                state
+                    .functions
+                    .get_mut(&(func as usize))
+                    .unwrap()
+                    .max_dynamic_shared = value;
+                Ok(())
+            }
+            _ => fail(CUresult::CUDA_ERROR_INVALID_VALUE, api_name),
+        }
+    }
+
+    unsafe fn launch_kernel(
+        &self,
+        func: CUfunction,
+        grid: Grid,
+        block: Block,
+        shared_mem_bytes: u32,
+        stream: CUstream,
+        params: *mut *mut c_void,
+    ) -> Result<()> {
+        let api_name = "cuLaunchKernel";
+        let state = self.state();
+        state.require_context(api_name)?;
+        state.require_stream(stream, api_name)?;
+        let function = match state.functions.get(&(func as usize)) {
+            Some(function) => function,
+            None => return fail(CUresult::CUDA_ERROR_INVALID_HANDLE, api_name),
+        };
+        let valid_grid = grid.x > 0
+            && grid.y > 0
+            && grid.z > 0
+            && grid.y <= std::u16::MAX as u32
+            && grid.z <= std::u16::MAX as u32;
+        let valid_block = block.x > 0
+            && block.y > 0
+            && block.z > 0
+            && block.z <= 64
+            && block.x as u64 * block.y as u64 * block.z as u64 <= 1024;
+        if !valid_grid
+            || !valid_block
+            || shared_mem_bytes as i64 > function.max_dynamic_shared as i64
+        {
+            return fail(CUresult::CUDA_ERROR_INVALID_VALUE, api_name);
+        }
+        if function.name == crate::memory::FILL_KERNEL {
+            // (dst, size, total): dst[i] = dst[i % size] for size <= i < total
+            let param = |i: usize| *(*params.add(i) as *const u64) as usize;
+            let (dst, size, total) = (param(0), param(1), param(2));
+            state.require_range(dst, total, api_name)?;
+            for i in size..total {
+                *((dst + i) as *mut u8) = *((dst + i % size) as *const u8);
+            }
+            return Ok(());
+        }
+        log::debug!(
+            "Emulator skips kernel launch: {}, {:?}, {:?}",
+            function.name,
+            grid,
+            block
+        );
+        Ok(())
+    }
+
+    unsafe fn link_create(
+        &self,
+        num_opts: u32,
+        opts: *mut CUjit_option,
+        opt_values: *mut *mut c_void,
+    ) -> Result<CUlinkState> {
+        let mut state = self.state();
+        state.require_context("cuLinkCreate_v2")?;
+        let link = state.new_handle();
+        let log = JitLog::from_options(num_opts, opts, opt_values);
+        state.links.insert(
+            link,
+            Link {
+                log,
+                ..Default::default()
+            },
+        );
+        Ok(link as CUlinkState)
+    }
+
+    unsafe fn link_add_data(
+        &self,
+        state: CUlinkState,
+        input_type: CUjitInputType,
+        data: &[u8],
+        name: &CStr,
+        num_opts: u32,
+        opts: *mut CUjit_option,
+        opt_values: *mut *mut c_void,
+    ) -> Result<()> {
+        let log = JitLog::from_options(num_opts, opts, opt_values);
+        self.state().add_link_input(
+            state,
+            input_type,
+            data,
+            &name.to_string_lossy(),
+            log,
+            "cuLinkAddData_v2",
+        )
+    }
+
+    unsafe fn link_add_file(
+        &self,
+        state: CUlinkState,
+        input_type: CUjitInputType,
+        path: &Path,
+        num_opts: u32,
+        opts: *mut CUjit_option,
+        opt_values: *mut *mut c_void,
+    ) -> Result<()> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(_) => return fail(CUresult::CUDA_ERROR_FILE_NOT_FOUND, "cuLinkAddFile_v2"),
+        };
+        let log = JitLog::from_options(num_opts, opts, opt_values);
+        self.state().add_link_input(
+            state,
+            input_type,
+            &data,
+            &path.display().to_string(),
+            log,
+            "cuLinkAddFile_v2",
+        )
+    }
+
+    unsafe fn link_complete(&self, state: CUlinkState) -> Result<(*mut c_void, usize)> {
+        let api_name = "cuLinkComplete";
+        let start = Instant::now();
+        let mut links = self.state();
+        let link = match links.links.get_mut(&(state as usize)) {
+            Some(link) => link,
+            None => return fail(CUresult::CUDA_ERROR_INVALID_HANDLE, api_name),
+        };
+        if link.inputs.is_empty() {
+            return fail(CUresult::CUDA_ERROR_INVALID_VALUE, api_name);
+        }
+        link.output = link.inputs.clone();
+        link.output.push(0);
+        link.log.wall_time(start);
+        Ok((link.output.as_mut_ptr() as *mut c_void, link.output.len()))
+    }
+
+    unsafe fn link_destroy(&self, state: CUlinkState) -> Result<()> {
+        if self.state().links.remove(&(state as usize)).is_none() {
+            return fail(CUresult::CUDA_ERROR_INVALID_HANDLE, "cuLinkDestroy");
+        }
+        Ok(())
+    }
+
+    fn profiler_start(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn profiler_stop(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ffi::CString, ptr::null_mut};
+
+    const PTX: &str = r#"
+    .version 3.2
+    .target sm_30
+    .address_size 64
+    .visible .entry do_nothing()
+    {
+      ret;
+    }
+    "#;
+
+    #[test]
+    fn context_stack() -> Result<()> {
+        let emu = Emulator::default();
+        let ctx = emu.ctx_create(0, 0)?;
+        assert_eq!(emu.ctx_pop_current()?, ctx);
+        assert!(emu.ctx_pop_current().is_err());
+        unsafe {
+            emu.ctx_push_current(ctx)?;
+            emu.ctx_synchronize()?;
+            assert_eq!(emu.ctx_pop_current()?, ctx);
+            emu.ctx_destroy(ctx)?;
+            assert!(emu.ctx_push_current(ctx).is_err());
+            assert!(emu.ctx_get_api_version(ctx).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn alloc_memset_free() -> Result<()> {
+        let total = CONTEXT_RESERVED_MEMORY + 1024;
+        let emu = Emulator::new(1, total);
+        let ctx = emu.ctx_create(0, 0)?;
+        unsafe {
+            let ptr = emu.mem_alloc_managed(16 * 4, 0)?;
+            assert_eq!(emu.mem_get_info()?, (1024 - 64, total));
+            assert!(emu.mem_alloc_managed(1024, 0).is_err());
+
+            emu.memset_d32(ptr, 0x1234_5678, 16)?;
+            let sl = std::slice::from_raw_parts(ptr as *const u32, 16);
+            assert!(sl.iter().all(|&v| v == 0x1234_5678));
+            assert!(emu.memset_d32(ptr, 0, 17).is_err());
+
+            let mut ty = CUmemorytype::CU_MEMORYTYPE_HOST;
+            emu.pointer_get_attribute(
+                &mut ty as *mut _ as *mut c_void,
+                CUpointer_attribute::CU_POINTER_ATTRIBUTE_MEMORY_TYPE,
+                ptr + 8,
+            )?;
+            assert_eq!(ty, CUmemorytype::CU_MEMORYTYPE_DEVICE);
+
+            emu.mem_free(ptr)?;
+            assert!(emu.mem_free(ptr).is_err());
+        }
+        assert_eq!(emu.ctx_pop_current()?, ctx);
+        Ok(())
+    }
+
+    #[test]
+    fn device_and_managed() -> Result<()> {
+        let emu = Emulator::default();
+        let ctx = emu.ctx_create(0, 0)?;
+        unsafe {
+            let dev = emu.mem_alloc(64)?;
+            let managed = emu.mem_alloc_managed(64, 0)?;
+            let is_managed = |ptr| -> Result<u32> {
+                let mut flag = 2_u32;
+                emu.pointer_get_attribute(
+                    &mut flag as *mut _ as *mut c_void,
+                    CUpointer_attribute::CU_POINTER_ATTRIBUTE_IS_MANAGED,
+                    ptr,
+                )?;
+                Ok(flag)
+            };
+            assert_eq!(is_managed(dev)?, 0);
+            assert_eq!(is_managed(managed)?, 1);
+
+            let stream = emu.stream_create(0)?;
+            emu.mem_prefetch_async(managed, 64, 0, stream)?;
+            emu.mem_prefetch_async(managed, 64, CU_DEVICE_CPU, stream)?;
+            assert!(emu.mem_prefetch_async(managed, 65, 0, stream).is_err());
+            assert!(emu.mem_prefetch_async(managed, 64, 1, stream).is_err());
+            assert!(emu.mem_prefetch_async(dev, 64, 0, stream).is_err());
+            emu.mem_advise(managed, 64, CUmem_advise::CU_MEM_ADVISE_SET_READ_MOSTLY, 0)?;
+            assert!(emu
+                .mem_advise(dev, 64, CUmem_advise::CU_MEM_ADVISE_SET_READ_MOSTLY, 0)
+                .is_err());
+            emu.stream_attach_mem_async(stream, managed, 0, 0)?;
+            assert!(emu.stream_attach_mem_async(stream, dev, 0, 0).is_err());
+            emu.stream_destroy(stream)?;
+
+            emu.mem_free(dev)?;
+            emu.mem_free(managed)?;
+            assert!(emu.mem_free(dev).is_err());
+        }
+        assert_eq!(emu.ctx_pop_current()?, ctx);
+        Ok(())
+    }
+
+    #[test]
+    fn memcpy_array() -> Result<()> {
+        let emu = Emulator::default();
+        let _ctx = emu.ctx_create(0, 0)?;
+        let desc = CUDA_ARRAY3D_DESCRIPTOR {
+            Width: 3,
+            Height: 2,
+            Depth: 0,
+            Format: CUarray_format::CU_AD_FORMAT_UNSIGNED_INT32,
+            NumChannels: 1,
+            Flags: 0,
+        };
+        let src: Vec<u32> = (0..6).collect();
+        let mut dst = vec![0_u32; 6];
+        unsafe {
+            let array = emu.array_3d_create(&desc)?;
+            emu.memcpy_3d(&CUDA_MEMCPY3D {
+                srcMemoryType: CUmemorytype::CU_MEMORYTYPE_UNIFIED,
+                srcDevice: src.as_ptr() as CUdeviceptr,
+                dstMemoryType: CUmemorytype::CU_MEMORYTYPE_ARRAY,
+                dstArray: array,
+                WidthInBytes: 3 * 4,
+                Height: 2,
+                Depth: 1,
+                ..crate::memory::memcpy3d_param_empty()
+            })?;
+            emu.memcpy_3d(&CUDA_MEMCPY3D {
+                srcMemoryType: CUmemorytype::CU_MEMORYTYPE_ARRAY,
+                srcArray: array,
+                dstMemoryType: CUmemorytype::CU_MEMORYTYPE_UNIFIED,
+                dstDevice: dst.as_mut_ptr() as CUdeviceptr,
+                WidthInBytes: 3 * 4,
+                Height: 2,
+                Depth: 1,
+                ..crate::memory::memcpy3d_param_empty()
+            })?;
+            emu.array_destroy(array)?;
+        }
+        assert_eq!(src, dst);
+        Ok(())
+    }
+
+    #[test]
+    fn module_launch() -> Result<()> {
+        let emu = Emulator::default();
+        let _ctx = emu.ctx_create(0, 0)?;
+        let ptx = CString::new(PTX).unwrap();
+        unsafe {
+            let module = emu.module_load_data(ptx.as_ptr() as *const c_void)?;
+            let name = CString::new("do_nothing").unwrap();
+            let func = emu.module_get_function(module, &name)?;
+            let unknown = CString::new("unknown").unwrap();
+            assert!(emu.module_get_function(module, &unknown).is_err());
+
+            let stream = emu.stream_create(0)?;
+            emu.launch_kernel(func, Grid::x(1), Block::x(32), 0, stream, null_mut())?;
+            assert!(emu
+                .launch_kernel(func, Grid::x(1), Block::x(2048), 0, stream, null_mut())
+                .is_err());
+            emu.stream_synchronize(stream)?;
+            emu.stream_destroy(stream)?;
+            emu.module_unload(module)?;
+            assert!(emu
+                .launch_kernel(func, Grid::x(1), Block::x(32), 0, null_mut(), null_mut())
+                .is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn link_ptx() -> Result<()> {
+        let emu = Emulator::default();
+        let _ctx = emu.ctx_create(0, 0)?;
+        let name = CString::new("").unwrap();
+        unsafe {
+            let state = emu.link_create(0, null_mut(), null_mut())?;
+            emu.link_add_data(
+                state,
+                CUjitInputType::CU_JIT_INPUT_PTX,
+                PTX.as_bytes(),
+                &name,
+                0,
+                null_mut(),
+                null_mut(),
+            )?;
+            emu.link_add_file(
+                state,
+                CUjitInputType::CU_JIT_INPUT_PTX,
+                Path::new("tests/data/add.ptx"),
+                0,
+                null_mut(),
+                null_mut(),
+            )?;
+            let (cubin, _size) = emu.link_complete(state)?;
+            let module = emu.module_load_data(cubin)?;
+            emu.link_destroy(state)?;
+            for name in &["do_nothing", "_Z3addPKiS0_Pi"] {
+                let name = CString::new(*name).unwrap();
+                emu.module_get_function(module, &name)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,387 @@
+//! Pluggable driver backends
+//!
+//! Every wrapper in this crate, e.g. [Device], [DeviceMemory] or [Stream],
+//! calls the CUDA Driver API through the [Backend] trait instead of `cuda_driver_sys` directly.
+//!
+//! |name       | Description                                                                     |
+//! |:----------|:--------------------------------------------------------------------------------|
+//! |[Cuda]     | Calls the CUDA Driver API (default)                                             |
+//! |[Emulator] | Host-only backend. Memories are allocated on heap, and streams complete at once |
+//!
+//! The backend is selected once per process, when the first driver call is issued.
+//! It can be chosen by the `ACCEL_BACKEND` environment variable (`cuda` or `emulator`),
+//! or explicitly by [set] before any other API of accel is used:
+//!
+//! ```
+//! use accel::{*, backend::Emulator};
+//! backend::set(Emulator::default()).unwrap();
+//!
+//! let device = Device::nth(0).unwrap();
+//! let ctx = device.create_context();
+//! let mem = DeviceMemory::<f32>::zeros(&ctx, 12);
+//! assert_eq!(mem.to_vec(), vec![0.0; 12]);
+//! ```
+//!
+//! [Device]: ../device/struct.Device.html
+//! [DeviceMemory]: ../memory/struct.DeviceMemory.html
+//! [Stream]: ../stream/struct.Stream.html
+//! [Backend]: trait.Backend.html
+//! [Cuda]: struct.Cuda.html
+//! [Emulator]: struct.Emulator.html
+//! [set]: fn.set.html
+
+mod driver;
+mod emulator;
+
+pub use driver::*;
+pub use emulator::*;
+
+use crate::{error::*, *};
+use cuda::*;
+use std::{ffi::CStr, os::raw::c_void, path::Path, sync::Once};
+
+/// Environment variable for selecting the backend
+pub const BACKEND_ENV: &str = "ACCEL_BACKEND";
+
+/// Device ordinal of the host for `cuMemPrefetchAsync` and `cuMemAdvise`, `CU_DEVICE_CPU` in `cuda.h`
+pub const CU_DEVICE_CPU: CUdevice = -1;
+
+/// Driver calls used by accel
+///
+/// Each method corresponds to a CUDA Driver API, e.g. `mem_alloc_managed` to `cuMemAllocManaged`.
+/// The methods which require a current context assume that it has been pushed by [ContextGuard].
+///
+/// Safety
+/// -------
+/// Methods taking handles or pointers are `unsafe` because the driver does not check their validity.
+/// Callers must pass handles created by the same backend and not yet destroyed,
+/// and pointers valid for the given size.
+///
+/// [ContextGuard]: ../device/struct.ContextGuard.html
+/// [CU_DEVICE_CPU]: constant.CU_DEVICE_CPU.html
+#[allow(clippy::missing_safety_doc)]
+pub trait Backend: Send + Sync {
+    /// Name of this backend for logging
+    fn name(&self) -> &'static str;
+
+    /// Wrapper of `cuInit`
+    fn init(&self) -> Result<()>;
+    /// Wrapper of `cuDriverGetVersion`, e.g. `10020` for CUDA 10.2
+    fn driver_get_version(&self) -> Result<u32>;
+
+    /// Wrapper of `cuDeviceGetCount`
+    fn device_get_count(&self) -> Result<usize>;
+    /// Wrapper of `cuDeviceGet`
+    fn device_get(&self, ordinal: usize) -> Result<CUdevice>;
+    /// Wrapper of `cuDeviceGetName`
+    fn device_get_name(&self, device: CUdevice) -> Result<String>;
+    /// Wrapper of `cuDeviceTotalMem`
+    fn device_total_mem(&self, device: CUdevice) -> Result<usize>;
+    /// Wrapper of `cuDeviceGetAttribute`
+    fn device_get_attribute(&self, attrib: CUdevice_attribute, device: CUdevice) -> Result<i32>;
+
+    /// Wrapper of `cuCtxCreate`. The created context becomes current
+    fn ctx_create(&self, flags: u32, device: CUdevice) -> Result<CUcontext>;
+    /// Wrapper of `cuCtxDestroy`
+    unsafe fn ctx_destroy(&self, ctx: CUcontext) -> Result<()>;
+    /// Wrapper of `cuCtxPushCurrent`
+    unsafe fn ctx_push_current(&self, ctx: CUcontext) -> Result<()>;
+    /// Wrapper of `cuCtxPopCurrent`
+    fn ctx_pop_current(&self) -> Result<CUcontext>;
+    /// Wrapper of `cuCtxGetApiVersion`
+    unsafe fn ctx_get_api_version(&self, ctx: CUcontext) -> Result<u32>;
+    /// Wrapper of `cuCtxSynchronize`
+    fn ctx_synchronize(&self) -> Result<()>;
+    /// Wrapper of `cuCtxGetDevice`, returns the device of the current context
+    fn ctx_get_device(&self) -> Result<CUdevice>;
+
+    /// Wrapper of `cuMemGetInfo`, returns `(free, total)` in bytes
+    fn mem_get_info(&self) -> Result<(usize, usize)>;
+    /// Wrapper of `cuMemAlloc`
+    unsafe fn mem_alloc(&self, bytes: usize) -> Result<CUdeviceptr>;
+    /// Wrapper of `cuMemAllocPitch`, which returns the pointer and the pitch in bytes
+    unsafe fn mem_alloc_pitch(
+        &self,
+        width_bytes: usize,
+        height: usize,
+        element_size: u32,
+    ) -> Result<(CUdeviceptr, usize)>;
+    /// Wrapper of `cuMemAllocManaged`
+    unsafe fn mem_alloc_managed(&self, bytes: usize, flags: u32) -> Result<CUdeviceptr>;
+    /// Wrapper of `cuMemPrefetchAsync`. `device` is [CU_DEVICE_CPU] for the host
+    unsafe fn mem_prefetch_async(
+        &self,
+        ptr: CUdeviceptr,
+        bytes: usize,
+        device: CUdevice,
+        stream: CUstream,
+    ) -> Result<()>;
+    /// Wrapper of `cuMemAdvise`
+    unsafe fn mem_advise(
+        &self,
+        ptr: CUdeviceptr,
+        bytes: usize,
+        advice: CUmem_advise,
+        device: CUdevice,
+    ) -> Result<()>;
+    /// Wrapper of `cuMemFree`
+    unsafe fn mem_free(&self, ptr: CUdeviceptr) -> Result<()>;
+    /// Wrapper of `cuMemAllocHost`
+    unsafe fn mem_alloc_host(&self, bytes: usize) -> Result<*mut c_void>;
+    /// Wrapper of `cuMemFreeHost`
+    unsafe fn mem_free_host(&self, ptr: *mut c_void) -> Result<()>;
+    /// Wrapper of `cuMemHostRegister`
+    unsafe fn mem_host_register(&self, ptr: *mut c_void, bytes: usize, flags: u32) -> Result<()>;
+    /// Wrapper of `cuMemHostUnregister`
+    unsafe fn mem_host_unregister(&self, ptr: *mut c_void) -> Result<()>;
+    /// Wrapper of `cuPointerGetAttribute`
+    unsafe fn pointer_get_attribute(
+        &self,
+        data: *mut c_void,
+        attr: CUpointer_attribute,
+        ptr: CUdeviceptr,
+    ) -> Result<()>;
+
+    /// Wrapper of `cuMemsetD8`
+    unsafe fn memset_d8(&self, dst: CUdeviceptr, value: u8, n: usize) -> Result<()>;
+    /// Wrapper of `cuMemsetD16`
+    unsafe fn memset_d16(&self, dst: CUdeviceptr, value: u16, n: usize) -> Result<()>;
+    /// Wrapper of `cuMemsetD32`
+    unsafe fn memset_d32(&self, dst: CUdeviceptr, value: u32, n: usize) -> Result<()>;
+    /// Wrapper of `cuMemsetD8Async`
+    unsafe fn memset_d8_async(
+        &self,
+        dst: CUdeviceptr,
+        value: u8,
+        n: usize,
+        stream: CUstream,
+    ) -> Result<()>;
+    /// Wrapper of `cuMemsetD16Async`
+    unsafe fn memset_d16_async(
+        &self,
+        dst: CUdeviceptr,
+        value: u16,
+        n: usize,
+        stream: CUstream,
+    ) -> Result<()>;
+    /// Wrapper of `cuMemsetD32Async`
+    unsafe fn memset_d32_async(
+        &self,
+        dst: CUdeviceptr,
+        value: u32,
+        n: usize,
+        stream: CUstream,
+    ) -> Result<()>;
+    /// Wrapper of `cuMemsetD2D32Async`
+    ///
+    /// `width` is the number of 32-bit values in each row, and `pitch` is in bytes.
+    unsafe fn memset_d2d32_async(
+        &self,
+        dst: CUdeviceptr,
+        pitch: usize,
+        value: u32,
+        width: usize,
+        height: usize,
+        stream: CUstream,
+    ) -> Result<()>;
+    /// Wrapper of `cuMemcpy`
+    unsafe fn memcpy(&self, dst: CUdeviceptr, src: CUdeviceptr, bytes: usize) -> Result<()>;
+    /// Wrapper of `cuMemcpyAsync`
+    unsafe fn memcpy_async(
+        &self,
+        dst: CUdeviceptr,
+        src: CUdeviceptr,
+        bytes: usize,
+        stream: CUstream,
+    ) -> Result<()>;
+    /// Wrapper of `cuMemcpy2D`
+    unsafe fn memcpy_2d(&self, param: &CUDA_MEMCPY2D) -> Result<()>;
+    /// Wrapper of `cuMemcpy2DAsync`
+    unsafe fn memcpy_2d_async(&self, param: &CUDA_MEMCPY2D, stream: CUstream) -> Result<()>;
+    /// Wrapper of `cuMemcpy3D`
+    unsafe fn memcpy_3d(&self, param: &CUDA_MEMCPY3D) -> Result<()>;
+    /// Wrapper of `cuMemcpy3DAsync`
+    unsafe fn memcpy_3d_async(&self, param: &CUDA_MEMCPY3D, stream: CUstream) -> Result<()>;
+
+    /// Wrapper of `cuArray3DCreate`
+    unsafe fn array_3d_create(&self, desc: &CUDA_ARRAY3D_DESCRIPTOR) -> Result<CUarray>;
+    /// Wrapper of `cuArrayDestroy`
+    unsafe fn array_destroy(&self, array: CUarray) -> Result<()>;
+
+    /// Wrapper of `cuTexObjectCreate` without resource view
+    unsafe fn tex_object_create(
+        &self,
+        res_desc: &CUDA_RESOURCE_DESC,
+        tex_desc: &CUDA_TEXTURE_DESC,
+    ) -> Result<CUtexObject>;
+    /// Wrapper of `cuTexObjectDestroy`
+    unsafe fn tex_object_destroy(&self, tex: CUtexObject) -> Result<()>;
+    /// Wrapper of `cuSurfObjectCreate`
+    unsafe fn surf_object_create(&self, res_desc: &CUDA_RESOURCE_DESC) -> Result<CUsurfObject>;
+    /// Wrapper of `cuSurfObjectDestroy`
+    unsafe fn surf_object_destroy(&self, surf: CUsurfObject) -> Result<()>;
+
+    /// Wrapper of `cuStreamCreate`
+    fn stream_create(&self, flags: u32) -> Result<CUstream>;
+    /// Wrapper of `cuStreamDestroy`
+    unsafe fn stream_destroy(&self, stream: CUstream) -> Result<()>;
+    /// Wrapper of `cuStreamQuery`
+    unsafe fn stream_query(&self, stream: CUstream) -> Result<()>;
+    /// Wrapper of `cuStreamSynchronize`
+    unsafe fn stream_synchronize(&self, stream: CUstream) -> Result<()>;
+    /// Wrapper of `cuStreamAttachMemAsync`
+    unsafe fn stream_attach_mem_async(
+        &self,
+        stream: CUstream,
+        ptr: CUdeviceptr,
+        bytes: usize,
+        flags: u32,
+    ) -> Result<()>;
+    /// Wrapper of `cuStreamWaitEvent`
+    unsafe fn stream_wait_event(&self, stream: CUstream, event: CUevent, flags: u32) -> Result<()>;
+
+    /// Wrapper of `cuEventCreate`
+    fn event_create(&self, flags: u32) -> Result<CUevent>;
+    /// Wrapper of `cuEventDestroy`
+    unsafe fn event_destroy(&self, event: CUevent) -> Result<()>;
+    /// Wrapper of `cuEventRecord`
+    unsafe fn event_record(&self, event: CUevent, stream: CUstream) -> Result<()>;
+    /// Wrapper of `cuEventQuery`
+    unsafe fn event_query(&self, event: CUevent) -> Result<()>;
+    /// Wrapper of `cuEventSynchronize`
+    unsafe fn event_synchronize(&self, event: CUevent) -> Result<()>;
+
+    /// Wrapper of `cuModuleLoad`
+    fn module_load(&self, path: &Path) -> Result<CUmodule>;
+    /// Wrapper of `cuModuleLoadData`
+    ///
+    /// `image` is a NUL-terminated PTX string or a cubin
+    unsafe fn module_load_data(&self, image: *const c_void) -> Result<CUmodule>;
+    /// Wrapper of `cuModuleLoadDataEx`, which takes JIT options as `cuLink*` APIs
+    unsafe fn module_load_data_ex(
+        &self,
+        image: *const c_void,
+        num_opts: u32,
+        opts: *mut CUjit_option,
+        opt_values: *mut *mut c_void,
+    ) -> Result<CUmodule>;
+    /// Wrapper of `cuModuleUnload`
+    unsafe fn module_unload(&self, module: CUmodule) -> Result<()>;
+    /// Wrapper of `cuModuleGetFunction`
+    unsafe fn module_get_function(&self, module: CUmodule, name: &CStr) -> Result<CUfunction>;
+    /// Wrapper of `cuModuleGetGlobal_v2`, returns the address and the size in bytes
+    unsafe fn module_get_global(
+        &self,
+        module: CUmodule,
+        name: &CStr,
+    ) -> Result<(CUdeviceptr, usize)>;
+    /// Wrapper of `cuFuncGetAttribute`
+    unsafe fn func_get_attribute(
+        &self,
+        attrib: CUfunction_attribute,
+        func: CUfunction,
+    ) -> Result<i32>;
+    /// Wrapper of `cuFuncSetAttribute`
+    unsafe fn func_set_attribute(
+        &self,
+        func: CUfunction,
+        attrib: CUfunction_attribute,
+        value: i32,
+    ) -> Result<()>;
+
+    /// Wrapper of `cuLaunchKernel`
+    unsafe fn launch_kernel(
+        &self,
+        func: CUfunction,
+        grid: Grid,
+        block: Block,
+        shared_mem_bytes: u32,
+        stream: CUstream,
+        params: *mut *mut c_void,
+    ) -> Result<()>;
+
+    /// Wrapper of `cuLinkCreate`
+    unsafe fn link_create(
+        &self,
+        num_opts: u32,
+        opts: *mut CUjit_option,
+        opt_values: *mut *mut c_void,
+    ) -> Result<CUlinkState>;
+    /// Wrapper of `cuLinkAddData`
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn link_add_data(
+        &self,
+        state: CUlinkState,
+        input_type: CUjitInputType,
+        data: &[u8],
+        name: &CStr,
+        num_opts: u32,
+        opts: *mut CUjit_option,
+        opt_values: *mut *mut c_void,
+    ) -> Result<()>;
+    /// Wrapper of `cuLinkAddFile`
+    unsafe fn link_add_file(
+        &self,
+        state: CUlinkState,
+        input_type: CUjitInputType,
+        path: &Path,
+        num_opts: u32,
+        opts: *mut CUjit_option,
+        opt_values: *mut *mut c_void,
+    ) -> Result<()>;
+    /// Wrapper of `cuLinkComplete`, returns a pointer to the cubin owned by `state` and its size
+    unsafe fn link_complete(&self, state: CUlinkState) -> Result<(*mut c_void, usize)>;
+    /// Wrapper of `cuLinkDestroy`
+    unsafe fn link_destroy(&self, state: CUlinkState) -> Result<()>;
+
+    /// Wrapper of `cuProfilerStart`
+    fn profiler_start(&self) -> Result<()>;
+    /// Wrapper of `cuProfilerStop`
+    fn profiler_stop(&self) -> Result<()>;
+}
+
+static SELECT: Once = Once::new();
+static mut BACKEND: Option<&'static dyn Backend> = None;
+
+/// Select the backend of this process explicitly
+///
+/// Returns `AccelError::BackendAlreadySelected` if a backend has been selected,
+/// i.e. `set` has been called or a driver call has been issued.
+pub fn set(backend: impl Backend + 'static) -> Result<()> {
+    let mut backend = Some(backend);
+    SELECT.call_once(|| {
+        let backend = backend.take().unwrap();
+        // Written only once in `SELECT`, and read after `SELECT` completes
+        unsafe { BACKEND = Some(Box::leak(Box::new(backend))) };
+    });
+    if backend.is_some() {
+        return Err(AccelError::BackendAlreadySelected {
+            name: get().name().into(),
+        });
+    }
+    Ok(())
+}
+
+/// Get the backend of this process
+///
+/// If no backend has been selected by [set](fn.set.html), it is selected by `ACCEL_BACKEND` environment variable.
+/// The backend is selected only once, and this does not lock after that.
+///
+/// Panic
+/// ------
+/// - if `ACCEL_BACKEND` is neither `cuda` nor `emulator`
+pub fn get() -> &'static dyn Backend {
+    SELECT.call_once(|| {
+        let backend: &'static dyn Backend = match std::env::var(BACKEND_ENV) {
+            Ok(name) => match name.as_str() {
+                "cuda" => &Cuda,
+                "emulator" => Box::leak(Box::new(Emulator::default())),
+                _ => panic!("Unknown backend in {}: {}", BACKEND_ENV, name),
+            },
+            Err(_) => &Cuda,
+        };
+        log::info!("Use {} backend", backend.name());
+        unsafe { BACKEND = Some(backend) };
+    });
+    unsafe { BACKEND }.expect("Backend selection has panicked")
+}
@@ -0,0 +1,417 @@
+//! Inspect cubin, i.e. ELF for CUDA devices
+//!
+//! The resource usage of each kernel is recorded by ptxas in the `.nv.info` sections,
+//! and available without loading the cubin to a device.
+//!
+//! ```
+//! use accel::cubin::*;
+//! use std::path::Path;
+//!
+//! let cubin = Cubin::from_path(Path::new("tests/data/add.cubin")).unwrap();
+//! assert_eq!(cubin.arch, 30);
+//! let add = cubin.kernel("_Z3addPKiS0_Pi").unwrap();
+//! assert_eq!(add.registers, Some(8));
+//! assert_eq!(add.params.len(), 3);
+//! ```
+
+use crate::{error::*, *};
+use std::{convert::TryInto, fs, path::Path};
+
+/// `e_machine` for CUDA
+const EM_CUDA: u16 = 190;
+/// `EI_ABIVERSION` of the format until CUDA 12.x, where the architecture is in the lowest byte of `e_flags`
+const ABI_VERSION_V1: u8 = 7;
+/// `EI_ABIVERSION` of the format where the architecture is in the second byte of `e_flags`
+const ABI_VERSION_V2: u8 = 8;
+/// `e_flags` bit for 64-bit address in the V1 format
+const EF_CUDA_64BIT_ADDRESS: u32 = 0x400;
+
+/// `st_other` bit for kernels
+const STO_CUDA_ENTRY: u8 = 0x10;
+/// `STT_FUNC` in `st_info`
+const STT_FUNC: u8 = 2;
+/// `SHT_NOBITS`, e.g. `.nv.shared.*`
+const SHT_NOBITS: u32 = 8;
+/// Size of a section header of ELF64
+const SECTION_HEADER_SIZE: usize = 64;
+
+// Formats of `.nv.info` attributes
+const EIFMT_SVAL: u8 = 0x04;
+
+// `.nv.info` attributes
+const EIATTR_MAX_THREADS: u8 = 0x05;
+const EIATTR_REQNTID: u8 = 0x10;
+const EIATTR_FRAME_SIZE: u8 = 0x11;
+const EIATTR_MIN_STACK_SIZE: u8 = 0x12;
+const EIATTR_KPARAM_INFO: u8 = 0x17;
+const EIATTR_CBANK_PARAM_SIZE: u8 = 0x19;
+const EIATTR_MAXREG_COUNT: u8 = 0x1b;
+const EIATTR_MAX_STACK_SIZE: u8 = 0x23;
+const EIATTR_REGCOUNT: u8 = 0x2f;
+
+/// Kernel parameter recorded in `EIATTR_KPARAM_INFO`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParamInfo {
+    /// Position in the parameter list
+    pub ordinal: u32,
+    /// Offset in the parameter buffer in bytes
+    pub offset: u32,
+    /// Size in bytes
+    pub size: u32,
+}
+
+/// Resource usage of a kernel
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct KernelInfo {
+    pub name: String,
+    /// Registers per thread
+    pub registers: Option<u32>,
+    /// Upper bound of registers given by `.maxnreg`
+    pub max_registers: Option<u32>,
+    /// Statically allocated shared memory in bytes, i.e. size of `.nv.shared.<kernel>`
+    pub static_shared_mem_bytes: u32,
+    /// Local memory per thread in bytes, i.e. size of the stack frame
+    pub local_mem_bytes: u32,
+    pub min_stack_size: Option<u32>,
+    pub max_stack_size: Option<u32>,
+    /// Parameters sorted by ordinal
+    pub params: Vec<ParamInfo>,
+    /// Total size of parameters in bytes
+    pub param_bytes: u32,
+    /// Maximum block size given by `.maxntid`
+    pub max_threads: Option<[u32; 3]>,
+    /// Required block size given by `.reqntid`
+    pub required_threads: Option<[u32; 3]>,
+}
+
+/// Header and kernels of a cubin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cubin {
+    /// SM architecture, e.g. `70` for sm_70
+    pub arch: u32,
+    /// 32 or 64
+    pub address_size: u32,
+    /// Kernels in the order of the symbol table
+    pub kernels: Vec<KernelInfo>,
+}
+
+fn invalid(message: &str) -> AccelError {
+    AccelError::InvalidCubin {
+        message: message.into(),
+    }
+}
+
+/// Bounds-checked little endian reader
+#[derive(Clone, Copy)]
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn slice(&self, offset: usize, len: usize) -> Result<&'a [u8]> {
+        offset
+            .checked_add(len)
+            .and_then(|end| self.0.get(offset..end))
+            .ok_or_else(|| invalid("Unexpected end of data"))
+    }
+
+    fn u8(&self, offset: usize) -> Result<u8> {
+        Ok(self.slice(offset, 1)?[0])
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16> {
+        Ok(u16::from_le_bytes(
+            self.slice(offset, 2)?.try_into().unwrap(),
+        ))
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32> {
+        Ok(u32::from_le_bytes(
+            self.slice(offset, 4)?.try_into().unwrap(),
+        ))
+    }
+
+    fn u64(&self, offset: usize) -> Result<usize> {
+        Ok(u64::from_le_bytes(self.slice(offset, 8)?.try_into().unwrap()) as usize)
+    }
+
+    /// NUL-terminated string
+    fn str(&self, offset: usize) -> Result<&'a str> {
+        let rest = self
+            .0
+            .get(offset..)
+            .ok_or_else(|| invalid("String out of range"))?;
+        let len = rest
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| invalid("String is not terminated"))?;
+        std::str::from_utf8(&rest[..len]).map_err(|_| invalid("String is not UTF-8"))
+    }
+}
+
+#[derive(Debug)]
+struct Section<'a> {
+    name: &'a str,
+    ty: u32,
+    offset: usize,
+    size: usize,
+}
+
+/// Attribute in `.nv.info` sections
+struct Attribute<'a> {
+    attr: u8,
+    /// Inline value of non-`EIFMT_SVAL` formats
+    value: u16,
+    /// Data of `EIFMT_SVAL` format
+    data: Bytes<'a>,
+}
+
+fn attributes(data: Bytes) -> Result<Vec<Attribute>> {
+    let mut attrs = Vec::new();
+    let mut offset = 0;
+    while offset < data.0.len() {
+        let format = data.u8(offset)?;
+        let attr = data.u8(offset + 1)?;
+        let value = data.u16(offset + 2)?;
+        offset += 4;
+        let data = if format == EIFMT_SVAL {
+            let sval = Bytes(data.slice(offset, value as usize)?);
+            offset += value as usize;
+            sval
+        } else {
+            Bytes(&[])
+        };
+        attrs.push(Attribute { attr, value, data });
+    }
+    Ok(attrs)
+}
+
+fn dim3(data: Bytes) -> Result<[u32; 3]> {
+    Ok([data.u32(0)?, data.u32(4)?, data.u32(8)?])
+}
+
+impl Cubin {
+    /// Parse ELF64 cubin
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let elf = Bytes(bytes);
+        if elf.slice(0, 4)? != b"\x7fELF" {
+            return Err(invalid("Not an ELF"));
+        }
+        if elf.u8(4)? != 2 || elf.u8(5)? != 1 {
+            return Err(invalid("Only 64-bit little endian ELF is supported"));
+        }
+        if elf.u16(18)? != EM_CUDA {
+            return Err(invalid("Not an ELF for CUDA devices"));
+        }
+        let flags = elf.u32(48)?;
+        let (arch, address_size) = match elf.u8(8)? {
+            ABI_VERSION_V1 => (
+                flags & 0xff,
+                if flags & EF_CUDA_64BIT_ADDRESS != 0 {
+                    64
+                } else {
+                    32
+                },
+            ),
+            ABI_VERSION_V2 => ((flags >> 8) & 0xff, 64),
+            _ => return Err(invalid("Unknown ABI version")),
+        };
+
+        // Section headers
+        let shoff = elf.u64(40)?;
+        let shentsize = elf.u16(58)? as usize;
+        let shnum = elf.u16(60)? as usize;
+        let shstrndx = elf.u16(62)? as usize;
+        if shstrndx >= shnum {
+            return Err(invalid("Index of section name table out of range"));
+        }
+        let header = |i: usize| -> Result<Bytes> {
+            let offset = i
+                .checked_mul(shentsize)
+                .and_then(|offset| offset.checked_add(shoff))
+                .ok_or_else(|| invalid("Section header out of range"))?;
+            Ok(Bytes(elf.slice(offset, SECTION_HEADER_SIZE)?))
+        };
+        let shstrtab = header(shstrndx)?.u64(24)?;
+        let names = Bytes(
+            elf.0
+                .get(shstrtab..)
+                .ok_or_else(|| invalid("Section name table out of range"))?,
+        );
+        let sections = (0..shnum)
+            .map(|i| {
+                let h = header(i)?;
+                Ok(Section {
+                    name: names.str(h.u32(0)? as usize)?,
+                    ty: h.u32(4)?,
+                    offset: h.u64(24)?,
+                    size: h.u64(32)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let find = |name: &str| sections.iter().find(|s| s.name == name);
+        let content = |s: &Section| -> Result<Bytes> {
+            if s.ty == SHT_NOBITS {
+                return Ok(Bytes(&[]));
+            }
+            Ok(Bytes(elf.slice(s.offset, s.size)?))
+        };
+
+        // Kernels in the symbol table
+        let symtab = find(".symtab").ok_or_else(|| invalid(".symtab not found"))?;
+        let strtab = find(".strtab").ok_or_else(|| invalid(".strtab not found"))?;
+        let symtab_data = content(symtab)?;
+        let strtab_data = content(strtab)?;
+        let mut symbols = Vec::new(); // (index, name) of kernels
+        for (index, offset) in (0..symtab.size).step_by(24).enumerate() {
+            let info = symtab_data.u8(offset + 4)?;
+            let other = symtab_data.u8(offset + 5)?;
+            if info & 0xf == STT_FUNC && other & STO_CUDA_ENTRY != 0 {
+                let name = strtab_data.str(symtab_data.u32(offset)? as usize)?;
+                symbols.push((index as u32, name));
+            }
+        }
+
+        let global_attrs = match find(".nv.info") {
+            Some(s) => attributes(content(s)?)?,
+            None => Vec::new(),
+        };
+        let mut kernels = Vec::new();
+        for (index, name) in symbols {
+            let mut info = KernelInfo {
+                name: name.to_string(),
+                ..Default::default()
+            };
+            // Attributes of functions are keyed by the symbol index
+            for a in &global_attrs {
+                if a.data.0.len() < 8 || a.data.u32(0)? != index {
+                    continue;
+                }
+                let value = a.data.u32(4)?;
+                match a.attr {
+                    EIATTR_REGCOUNT => info.registers = Some(value),
+                    EIATTR_FRAME_SIZE => info.local_mem_bytes = value,
+                    EIATTR_MIN_STACK_SIZE => info.min_stack_size = Some(value),
+                    EIATTR_MAX_STACK_SIZE => info.max_stack_size = Some(value),
+                    _ => {}
+                }
+            }
+            if let Some(s) = find(&format!(".nv.info.{}", name)) {
+                for a in attributes(content(s)?)? {
+                    match a.attr {
+                        EIATTR_MAX_THREADS => info.max_threads = Some(dim3(a.data)?),
+                        EIATTR_REQNTID => info.required_threads = Some(dim3(a.data)?),
+                        EIATTR_MAXREG_COUNT => info.max_registers = Some(a.value as u32),
+                        EIATTR_CBANK_PARAM_SIZE => info.param_bytes = a.value as u32,
+                        EIATTR_KPARAM_INFO => info.params.push(ParamInfo {
+                            ordinal: a.data.u16(4)? as u32,
+                            offset: a.data.u16(6)? as u32,
+                            size: (a.data.u32(8)? >> 18) & 0x3fff,
+                        }),
+                        _ => {}
+                    }
+                }
+            }
+            info.params.sort_by_key(|p| p.ordinal);
+            if let Some(s) = find(&format!(".nv.shared.{}", name)) {
+                info.static_shared_mem_bytes = s.size as u32;
+            }
+            kernels.push(info);
+        }
+
+        Ok(Cubin {
+            arch,
+            address_size,
+            kernels,
+        })
+    }
+
+    /// Read a cubin file
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path).map_err(|e| file_error(path, e))?;
+        Self::parse(&bytes)
+    }
+
+    /// Read `Instruction::Cubin` or `Instruction::CubinFile`
+    pub fn from_instruction(data: &Instruction) -> Result<Self> {
+        match data {
+            Instruction::Cubin(bin) => Self::parse(bin),
+            Instruction::CubinFile(path) => Self::from_path(path),
+            _ => Err(invalid(&format!("{:?} is not a cubin", data.input_type()))),
+        }
+    }
+
+    /// Compute capability of the architecture, e.g. `(7, 0)` for sm_70
+    pub fn compute_capability(&self) -> (u32, u32) {
+        (self.arch / 10, self.arch % 10)
+    }
+
+    /// Find a kernel by name
+    pub fn kernel(&self, name: &str) -> Option<&KernelInfo> {
+        self.kernels.iter().find(|k| k.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_cubin() -> Result<()> {
+        let cubin = Cubin::from_path(Path::new("tests/data/add.cubin"))?;
+        assert_eq!(cubin.arch, 30);
+        assert_eq!(cubin.compute_capability(), (3, 0));
+        assert_eq!(cubin.address_size, 64);
+        assert_eq!(cubin.kernels.len(), 1);
+
+        let add = &cubin.kernels[0];
+        assert_eq!(add.name, "_Z3addPKiS0_Pi");
+        assert_eq!(add.registers, Some(8));
+        assert_eq!(add.max_registers, Some(63));
+        assert_eq!(add.static_shared_mem_bytes, 0);
+        assert_eq!(add.local_mem_bytes, 0);
+        assert_eq!(add.min_stack_size, Some(0));
+        assert_eq!(add.max_stack_size, Some(0));
+        assert_eq!(add.param_bytes, 24);
+        let params: Vec<_> = add
+            .params
+            .iter()
+            .map(|p| (p.ordinal, p.offset, p.size))
+            .collect();
+        assert_eq!(params, vec![(0, 0, 8), (1, 8, 8), (2, 16, 8)]);
+        assert_eq!(add.max_threads, None);
+
+        let resources = crate::occupancy::KernelResources::from_cubin(add);
+        assert_eq!(resources.registers_per_thread, Some(8));
+        assert_eq!(resources.max_threads_per_block, 1024);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_cubin() {
+        let bytes = fs::read("tests/data/add.cubin").unwrap();
+        assert!(Cubin::parse(&bytes[..100]).is_err());
+        assert!(Cubin::parse(b"\x7fELF").is_err());
+        let mut host = bytes;
+        host[18] = 62; // x86_64
+        assert!(matches!(
+            Cubin::parse(&host),
+            Err(AccelError::InvalidCubin { .. })
+        ));
+        assert!(Cubin::from_instruction(&Instruction::ptx(".version 6.5")).is_err());
+
+        // e_shstrndx out of e_shnum
+        let mut broken = fs::read("tests/data/add.cubin").unwrap();
+        let shnum = [broken[60], broken[61]];
+        broken[62..64].copy_from_slice(&shnum);
+        assert!(matches!(
+            Cubin::parse(&broken),
+            Err(AccelError::InvalidCubin { .. })
+        ));
+        // e_shoff overflows
+        let mut broken = fs::read("tests/data/add.cubin").unwrap();
+        broken[40..48].copy_from_slice(&std::u64::MAX.to_le_bytes());
+        assert!(matches!(
+            Cubin::parse(&broken),
+            Err(AccelError::InvalidCubin { .. })
+        ));
+    }
+}
@@ -4,7 +4,7 @@
 //! [Context]: https://docs.nvidia.com/cuda/cuda-driver-api/group__CUDA__CTX.html
 
 use crate::*;
-use crate::{error::*};
+use crate::{contexted_call, error::*};
 use cuda::*;
 use std::sync::{Arc, Once, Mutex};
 use lazy_static::lazy_static;
@@ -25,14 +25,9 @@ impl Device {
     pub fn init() -> bool {
         static DRIVER_API_INIT: Once = Once::new();
         let mut inner = CUDA_INIT_SUCCESS.lock().unwrap();
-        unsafe {
-            DRIVER_API_INIT.call_once(|| {
-                *inner = match ffi_call!(cuda::cuInit, 0) {
-                    Ok(()) => true,
-                    _ => false,
-                }
-            });
-        }
+        DRIVER_API_INIT.call_once(|| {
+            *inner = backend::get().init().is_ok();
+        });
         *inner
     }
 
@@ -42,11 +37,15 @@ impl Device {
             true => Ok(()),
             false => Err(AccelError::InitFailed),
         })?;
-        let mut count: i32 = 0;
-        unsafe {
-            ffi_call!(cuDeviceGetCount, &mut count as *mut i32)?;
+        backend::get().device_get_count()
+    }
+
+    /// Version of the CUDA driver, e.g. `10020` for CUDA 10.2
+    pub fn driver_version() -> Result<u32> {
+        if !Self::init() {
+            return Err(AccelError::InitFailed);
         }
-        Ok(count as usize)
+        backend::get().driver_get_version()
     }
 
     pub fn nth(id: usize) -> Result<Self> {
@@ -54,31 +53,42 @@ impl Device {
         if id >= count {
             return Err(AccelError::DeviceNotFound { id, count });
         }
-        let device = unsafe { ffi_new!(cuDeviceGet, id as i32)? };
+        let device = backend::get().device_get(id)?;
         Ok(Device { device })
     }
 
+    /// Raw handle of the device
+    pub(crate) fn raw(&self) -> CUdevice {
+        self.device
+    }
+
     /// Get total memory of GPU
     pub fn total_memory(&self) -> Result<usize> {
-        let mut mem = 0;
-        unsafe {
-            ffi_call!(cuDeviceTotalMem_v2, &mut mem as *mut _, self.device)?;
-        }
-        Ok(mem)
+        backend::get().device_total_mem(self.device)
     }
 
     /// Get name of GPU
     pub fn get_name(&self) -> Result<String> {
-        let mut bytes: Vec<u8> = vec![0_u8; 1024];
-        unsafe {
-            ffi_call!(
-                cuDeviceGetName,
-                bytes.as_mut_ptr() as *mut i8,
-                1024,
-                self.device
-            )?;
-        }
-        Ok(String::from_utf8(bytes).expect("GPU name is not UTF8"))
+        backend::get().device_get_name(self.device)
+    }
+
+    /// Get the device of the context
+    pub fn from_context(ctx: &impl Contexted) -> Result<Self> {
+        let device = contexted_call!(ctx, ctx_get_device)?;
+        Ok(Device { device })
+    }
+
+    /// Wrapper of `cuDeviceGetAttribute`
+    pub fn get_attribute(&self, attrib: CUdevice_attribute) -> Result<i32> {
+        backend::get().device_get_attribute(attrib, self.device)
+    }
+
+    /// Compute capability as `(major, minor)`, e.g. `(7, 0)` for sm_70
+    pub fn compute_capability(&self) -> Result<(u32, u32)> {
+        use CUdevice_attribute::*;
+        let major = self.get_attribute(CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MAJOR)?;
+        let minor = self.get_attribute(CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MINOR)?;
+        Ok((major as u32, minor as u32))
     }
 
     /// Create a new CUDA context on this device.
@@ -89,14 +99,9 @@ impl Device {
     /// let ctx = device.create_context();
     /// ```
     pub fn create_context(&self) -> Context {
-        let ptr = unsafe {
-            ffi_new!(
-                cuCtxCreate_v2,
-                CUctx_flags_enum::CU_CTX_SCHED_AUTO as u32,
-                self.device
-            )
-        }
-        .expect("Failed to create a new context");
+        let ptr = backend::get()
+            .ctx_create(CUctx_flags_enum::CU_CTX_SCHED_AUTO as u32, self.device)
+            .expect("Failed to create a new context");
         if ptr.is_null() {
             panic!("Cannot crate a new context");
         }
@@ -108,13 +113,13 @@ impl Device {
 
 /// Push to the context stack of this thread
 fn ctx_push(ptr: CUcontext) -> Result<()> {
-    unsafe { ffi_call!(cuCtxPushCurrent_v2, ptr) }?;
+    unsafe { backend::get().ctx_push_current(ptr) }?;
     Ok(())
 }
 
 /// Pop from the context stack of this thread
 fn ctx_pop() -> Result<CUcontext> {
-    let ptr = unsafe { ffi_new!(cuCtxPopCurrent_v2) }?;
+    let ptr = backend::get().ctx_pop_current()?;
     if ptr.is_null() {
         panic!("No current context");
     }
@@ -123,15 +128,13 @@ fn ctx_pop() -> Result<CUcontext> {
 
 /// Get API version
 fn ctx_version(ptr: CUcontext) -> Result<u32> {
-    let mut version: u32 = 0;
-    unsafe { ffi_call!(cuCtxGetApiVersion, ptr, &mut version as *mut _) }?;
-    Ok(version)
+    unsafe { backend::get().ctx_get_api_version(ptr) }
 }
 
 /// Block until all tasks in this context to be complete.
 fn ctx_sync(ptr: CUcontext) -> Result<()> {
     ctx_push(ptr)?;
-    unsafe { ffi_call!(cuCtxSynchronize) }?;
+    backend::get().ctx_synchronize()?;
     let ptr_new = ctx_pop()?;
     assert_eq!(ptr, ptr_new);
     Ok(())
@@ -160,7 +163,9 @@ pub type Context = Arc<ContextOwned>;
 
 impl Drop for ContextOwned {
     fn drop(&mut self) {
-        if let Err(e) = unsafe { ffi_call!(cuCtxDestroy_v2, self.ptr) } {
+        module::evict_registry(ContextRef { ptr: self.ptr });
+        execution::evict_device_limits(ContextRef { ptr: self.ptr });
+        if let Err(e) = unsafe { backend::get().ctx_destroy(self.ptr) } {
             log::error!("Context remove failed: {:?}", e);
         }
     }
@@ -196,7 +201,7 @@ impl Contexted for Context {
 ///
 /// [cuPointerGetAttribute]: https://docs.nvidia.com/cuda/cuda-driver-api/group__CUDA__UNIFIED.html#group__CUDA__UNIFIED_1g0c28ed0aff848042bc0533110e45820c
 ///
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub struct ContextRef {
     ptr: CUcontext,
 }
@@ -314,6 +319,6 @@ mod tests {
         let ctx = device.create_context();
         let ctx_ref = ctx.get_ref();
         drop(ctx);
-        unsafe { contexted_call!(&ctx_ref, cuCtxSynchronize) }.unwrap();
+        contexted_call!(&ctx_ref, ctx_synchronize).unwrap();
     }
 }
@@ -1,5 +1,8 @@
 use cuda::cudaError_enum as DeviceError;
-use std::path::PathBuf;
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
 
 pub type Result<T> = ::std::result::Result<T, AccelError>;
 
@@ -14,6 +17,27 @@ pub enum AccelError {
         error: DeviceError,
     },
 
+    /// Failure of the JIT compiler or linker with its error log, e.g. diagnostics of ptxas
+    #[error("JIT compilation failed: {api_name}, {error:?}\n{log}")]
+    JitFailed {
+        api_name: String,
+        error: DeviceError,
+        log: String,
+    },
+
+    #[error("Global symbol `{name}` is bound twice")]
+    DuplicateGlobalSymbol { name: String },
+
+    #[error("Global symbol `{name}` is not an unresolved `.extern .global` variable")]
+    UnknownGlobalSymbol { name: String },
+
+    #[error("Symbol `{name}` of {size} bytes does not match the host type of {elem_size} bytes")]
+    SymbolSizeMismatch {
+        name: String,
+        size: usize,
+        elem_size: usize,
+    },
+
     // This is not an error potentially, but it should be a bug if not captured by accel
     #[error("Async operations issues previously have not completed yet")]
     AsyncOperationNotReady,
@@ -28,10 +52,70 @@ pub enum AccelError {
     #[error("File not found: {path:?}")]
     FileNotFound { path: PathBuf },
 
+    #[error("Failed to read {path:?}: {error}")]
+    IoError {
+        path: PathBuf,
+        #[source]
+        error: io::Error,
+    },
+
+    #[error("Invalid instruction: {message}")]
+    InvalidInstruction { message: String },
+
+    #[error("Invalid JIT configuration: {message}")]
+    InvalidJitConfig { message: String },
+
+    #[error("Invalid cubin: {message}")]
+    InvalidCubin { message: String },
+
+    #[error("Invalid fatbin: {message}")]
+    InvalidFatbin { message: String },
+
+    #[error("Invalid PTX at line {line}: {message}")]
+    InvalidPtx { line: usize, message: String },
+
+    #[error("Backend has already been selected: {name}")]
+    BackendAlreadySelected { name: String },
+
+    #[error("Invalid launch configuration: {message}")]
+    InvalidLaunchConfig { message: String },
+
+    #[error("Invalid texture descriptor: {message}")]
+    InvalidTextureDesc { message: String },
+
+    #[error("Compute capability {major}.{minor} is not supported")]
+    UnsupportedArchitecture { major: i32, minor: i32 },
+
+    /// Module shared by `Module::cached` is used after its context is dropped
+    #[error("Module has been unloaded since its context is dropped")]
+    ModuleExpired,
+
     #[error(transparent)]
     AsyncTaskFailed(#[from] tokio::task::JoinError),
 }
 
+impl From<accel_codegen::ptx::PtxError> for AccelError {
+    fn from(e: accel_codegen::ptx::PtxError) -> Self {
+        AccelError::InvalidPtx {
+            line: e.line,
+            message: e.message,
+        }
+    }
+}
+
+/// Convert an I/O error on the file into `FileNotFound` if it does not exist, or `IoError` otherwise
+pub(crate) fn file_error(path: &Path, error: io::Error) -> AccelError {
+    match error.kind() {
+        io::ErrorKind::NotFound => AccelError::FileNotFound {
+            path: path.to_owned(),
+        },
+        _ => AccelError::IoError {
+            path: path.to_owned(),
+            error,
+        },
+    }
+}
+
 /// Convert return code of CUDA Driver/Runtime API into Result
 pub(crate) fn check(error: DeviceError, api_name: &str) -> Result<()> {
     match error {
@@ -64,13 +148,17 @@ macro_rules! ffi_new {
     };
 }
 
+/// Call a method of the [Backend](backend/trait.Backend.html) in the context
 #[macro_export]
 macro_rules! contexted_call {
-    ($ctx:expr, $ffi:path $(,$args:expr)*) => {
-        $crate::Contexted::guard($ctx).and_then(|_g| { $crate::ffi_call!($ffi $(,$args)*) })
+    ($ctx:expr, $method:ident $(,$args:expr)*) => {
+        $crate::Contexted::guard($ctx).and_then(|_g| { $crate::backend::get().$method($($args),*) })
     };
 }
 
+/// Call a CUDA Driver API creating a new value in the context
+///
+/// This calls `cuda_driver_sys` directly, i.e. it does not go through the [Backend](backend/trait.Backend.html).
 #[macro_export]
 macro_rules! contexted_new {
     ($ctx:expr, $ffi:path $(,$args:expr)*) => {
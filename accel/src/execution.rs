@@ -58,8 +58,9 @@
 //!
 //!     pub const PTX_STR: &str = "{{ PTX string generated by rustc/nvptx64-nvidia-cuda }}";
 //!
-//!     // wrapper for implement one of Launchable traits
-//!     pub struct Module(::accel::Module);
+//!     // wrapper for implement one of Launchable traits,
+//!     // which shares the module loaded by `accel::Module::cached`
+//!     pub struct Module(std::sync::Arc<::accel::Module>);
 //!
 //!     // impl Launchable1 because number of arugment is 1
 //!     impl ::accel::execution::Launchable1<'_> for Module {
@@ -83,7 +84,8 @@
 
 use crate::{contexted_call, device::*, error::*, *};
 use cuda::*;
-use std::{ffi::*, ptr::null_mut};
+use lazy_static::lazy_static;
+use std::{collections::HashMap, ffi::*, ptr::null_mut, sync::Mutex};
 
 /// Type which can be sent to device
 pub trait DeviceSend {
@@ -141,5 +143,288 @@ impl_device_send!(u128);
 impl_device_send!(usize);
 impl_device_send!(f32);
 impl_device_send!(f64);
+#[cfg(feature = "half")]
+impl_device_send!(half::f16);
+#[cfg(feature = "half")]
+impl_device_send!(half::bf16);
+
+/// Configuration of kernel launch used by `launch_with` and `launch_async_with` of Launchable traits
+///
+/// ```
+/// # use accel::*;
+/// # let device = Device::nth(0).unwrap();
+/// # let ctx = device.create_context();
+/// let stream = Stream::new(ctx.get_ref());
+/// let config = LaunchConfig::new(Grid::x(64), Block::x(256))
+///     .shared_mem_bytes(256 * std::mem::size_of::<f32>() as u32)
+///     .stream(&stream);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct LaunchConfig<'stream> {
+    pub grid: Grid,
+    pub block: Block,
+    /// Size of dynamic shared memory per block in bytes
+    pub shared_mem_bytes: u32,
+    /// Stream where the kernel is launched. The default stream is used if `None`
+    pub stream: Option<&'stream Stream>,
+}
+
+impl<'stream> LaunchConfig<'stream> {
+    /// Launch on the default stream without dynamic shared memory
+    pub fn new(grid: impl Into<Grid>, block: impl Into<Block>) -> Self {
+        LaunchConfig {
+            grid: grid.into(),
+            block: block.into(),
+            shared_mem_bytes: 0,
+            stream: None,
+        }
+    }
+
+    /// Set the size of dynamic shared memory per block in bytes
+    pub fn shared_mem_bytes(mut self, bytes: u32) -> Self {
+        self.shared_mem_bytes = bytes;
+        self
+    }
+
+    /// Set the stream where the kernel is launched
+    pub fn stream(mut self, stream: &'stream Stream) -> Self {
+        self.stream = Some(stream);
+        self
+    }
+
+    /// Check the configuration against the limits of the kernel and its device
+    ///
+    /// - Each dimension of grid and block is positive and within the device limit
+    /// - Number of threads per block is within the limit of the kernel, which depends on its register usage
+    /// - Sum of static and dynamic shared memory is within the device limit
+    /// - Stream belongs to the context of the kernel
+    ///
+    /// Limits of the device are cached per context, and those of the kernel are cached in its module.
+    pub fn validate(&self, kernel: &Kernel) -> Result<()> {
+        let invalid = |message: String| Err(AccelError::InvalidLaunchConfig { message });
+        let limits = DeviceLimits::get(kernel)?;
+        let attributes = kernel.attributes()?;
+
+        let grid = [self.grid.x, self.grid.y, self.grid.z];
+        let axes = ["x", "y", "z"].iter();
+        for (axis, (&n, &max)) in axes.zip(grid.iter().zip(limits.max_grid.iter())) {
+            if n == 0 || n as i64 > max as i64 {
+                return invalid(format!("grid.{} = {} must be in 1..={}", axis, n, max));
+            }
+        }
+
+        let block = [self.block.x, self.block.y, self.block.z];
+        let axes = ["x", "y", "z"].iter();
+        for (axis, (&n, &max)) in axes.zip(block.iter().zip(limits.max_block.iter())) {
+            if n == 0 || n as i64 > max as i64 {
+                return invalid(format!("block.{} = {} must be in 1..={}", axis, n, max));
+            }
+        }
+        let threads = block.iter().map(|&n| n as u64).product::<u64>();
+        let max_threads = attributes.max_threads_per_block as u64;
+        if threads > max_threads {
+            return invalid(format!(
+                "{} threads per block exceeds the limit {} of the kernel",
+                threads, max_threads
+            ));
+        }
+
+        let static_shared = attributes.static_shared_mem_bytes as u64;
+        let max_shared = limits.max_shared_mem as u64;
+        if static_shared + self.shared_mem_bytes as u64 > max_shared {
+            return invalid(format!(
+                "Shared memory {} bytes (static {} + dynamic {}) exceeds the limit {} bytes per block",
+                static_shared + self.shared_mem_bytes as u64,
+                static_shared,
+                self.shared_mem_bytes,
+                max_shared
+            ));
+        }
+
+        if let Some(stream) = self.stream {
+            if stream.get_ref() != kernel.get_ref() {
+                return invalid("Stream belongs to a different context from the kernel".into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate, and opt-in to dynamic shared memory larger than the default limit
+    pub(crate) fn prepare(&self, kernel: &Kernel) -> Result<()> {
+        self.validate(kernel)?;
+        let max_dynamic_shared = kernel.attributes()?.max_dynamic_shared_mem_bytes;
+        if self.shared_mem_bytes as i64 > max_dynamic_shared as i64 {
+            let attrib = CUfunction_attribute::CU_FUNC_ATTRIBUTE_MAX_DYNAMIC_SHARED_SIZE_BYTES;
+            kernel.set_attribute(attrib, self.shared_mem_bytes as i32)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn raw_stream(&self) -> CUstream {
+        self.stream.map_or(null_mut(), |stream| stream.stream)
+    }
+}
+
+/// Limits of a device checked by [LaunchConfig::validate]
+#[derive(Debug, Clone, Copy)]
+struct DeviceLimits {
+    max_grid: [i32; 3],
+    max_block: [i32; 3],
+    max_shared_mem: i32,
+}
+
+lazy_static! {
+    /// Limits of the device of each context, queried at the first validation in the context
+    static ref DEVICE_LIMITS: Mutex<HashMap<ContextRef, DeviceLimits>> = Mutex::new(HashMap::new());
+}
+
+impl DeviceLimits {
+    fn get(ctx: &impl Contexted) -> Result<Self> {
+        let key = ctx.get_ref();
+        if let Some(limits) = DEVICE_LIMITS.lock().unwrap().get(&key) {
+            return Ok(*limits);
+        }
+        let limits = Self::query(&Device::from_context(ctx)?)?;
+        DEVICE_LIMITS.lock().unwrap().insert(key, limits);
+        Ok(limits)
+    }
+
+    fn query(device: &Device) -> Result<Self> {
+        use CUdevice_attribute::*;
+        Ok(DeviceLimits {
+            max_grid: [
+                device.get_attribute(CU_DEVICE_ATTRIBUTE_MAX_GRID_DIM_X)?,
+                device.get_attribute(CU_DEVICE_ATTRIBUTE_MAX_GRID_DIM_Y)?,
+                device.get_attribute(CU_DEVICE_ATTRIBUTE_MAX_GRID_DIM_Z)?,
+            ],
+            max_block: [
+                device.get_attribute(CU_DEVICE_ATTRIBUTE_MAX_BLOCK_DIM_X)?,
+                device.get_attribute(CU_DEVICE_ATTRIBUTE_MAX_BLOCK_DIM_Y)?,
+                device.get_attribute(CU_DEVICE_ATTRIBUTE_MAX_BLOCK_DIM_Z)?,
+            ],
+            max_shared_mem: shared_mem_limit(device)?,
+        })
+    }
+}
+
+/// Drop the cached limits of the context, called when the context is dropped
+pub(crate) fn evict_device_limits(ctx: ContextRef) {
+    DEVICE_LIMITS.lock().unwrap().remove(&ctx);
+}
+
+/// Limit of shared memory per block including the opt-in region
+fn shared_mem_limit(device: &Device) -> Result<i32> {
+    use CUdevice_attribute::*;
+    let limit = device.get_attribute(CU_DEVICE_ATTRIBUTE_MAX_SHARED_MEMORY_PER_BLOCK)?;
+    // Opt-in is not supported before Volta
+    let optin = device
+        .get_attribute(CU_DEVICE_ATTRIBUTE_MAX_SHARED_MEMORY_PER_BLOCK_OPTIN)
+        .unwrap_or(0);
+    Ok(limit.max(optin))
+}
 
 accel_derive::define_launchable!(12 /* 0..=12 */);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PTX: &str = r#"
+    .version 3.2
+    .target sm_30
+    .address_size 64
+    .visible .entry do_nothing()
+    {
+      ret;
+    }
+    "#;
+
+    struct DoNothing(Module);
+
+    impl Launchable0<'_> for DoNothing {
+        fn get_kernel(&self) -> Result<Kernel> {
+            self.0.get_kernel("do_nothing")
+        }
+    }
+
+    #[test]
+    fn validate_dims() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let module = Module::from_str(&ctx, PTX)?;
+        let kernel = module.get_kernel("do_nothing")?;
+        LaunchConfig::new(Grid::x(64), Block::x(256)).validate(&kernel)?;
+        let threads = kernel.max_threads_per_block()?;
+        assert!(LaunchConfig::new(Grid::x(1), Block::x(threads + 1))
+            .validate(&kernel)
+            .is_err());
+        assert!(LaunchConfig::new(Grid::x(0), Block::x(1))
+            .validate(&kernel)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn device_limits_cached() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let key = ctx.get_ref();
+        let module = Module::from_str(&ctx, PTX)?;
+        let kernel = module.get_kernel("do_nothing")?;
+        LaunchConfig::new(Grid::x(1), Block::x(1)).validate(&kernel)?;
+        assert!(DEVICE_LIMITS.lock().unwrap().contains_key(&key));
+        drop(kernel);
+        drop(module);
+        drop(ctx);
+        assert!(!DEVICE_LIMITS.lock().unwrap().contains_key(&key));
+        Ok(())
+    }
+
+    #[test]
+    fn shared_mem_optin() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let module = DoNothing(Module::from_str(&ctx, PTX)?);
+        let limit = shared_mem_limit(&device)? as u32;
+        let config = LaunchConfig::new(Grid::x(1), Block::x(1)).shared_mem_bytes(limit);
+        module.launch_with(&config, ())?;
+        let kernel = module.get_kernel()?;
+        let attrib = CUfunction_attribute::CU_FUNC_ATTRIBUTE_MAX_DYNAMIC_SHARED_SIZE_BYTES;
+        assert_eq!(kernel.get_attribute(attrib)? as u32, limit);
+        let config = config.shared_mem_bytes(limit + 1);
+        assert!(config.validate(&kernel).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn stream_mismatch() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx1 = device.create_context();
+        let ctx2 = device.create_context();
+        let module = DoNothing(Module::from_str(&ctx1, PTX)?);
+        let stream = Stream::new(ctx1.get_ref());
+        let config = LaunchConfig::new(Grid::x(1), Block::x(1)).stream(&stream);
+        module.launch_with(&config, ())?;
+        let stream = Stream::new(ctx2.get_ref());
+        let config = config.stream(&stream);
+        assert!(matches!(
+            module.launch_with(&config, ()),
+            Err(AccelError::InvalidLaunchConfig { .. })
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn launch_async_with() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let module = DoNothing(Module::from_str(&ctx, PTX)?);
+        let stream = Stream::new(ctx.get_ref());
+        let config = LaunchConfig::new(Grid::x(1), Block::x(1));
+        module.launch_async_with(&config, ()).await?;
+        module
+            .launch_async_with(&config.stream(&stream), ())
+            .await?;
+        Ok(())
+    }
+}
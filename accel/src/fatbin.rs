@@ -0,0 +1,345 @@
+//! Fat binary containing cubins and PTX for several architectures
+//!
+//! A fatbin consists of a header and a sequence of images.
+//! Each image is a cubin or PTX for an SM architecture, e.g. `sm_70`:
+//!
+//! ```text
+//! +-----------------------------------+
+//! | magic (0xBA55ED50), version, size |  fatbin header, 16 bytes
+//! +-----------------------------------+
+//! | kind, arch, size, flags, ...      |  image header, 64 bytes
+//! | payload (padded to 8 bytes)       |
+//! +-----------------------------------+
+//! | ...                               |
+//! ```
+//!
+//! The CUDA driver chooses an image by itself when a fatbin is loaded.
+//! [Module::load](../module/struct.Module.html#method.load) chooses it in Rust using [Fatbin::select],
+//! i.e. the choice is also available for the emulator backend.
+//!
+//! ```
+//! use accel::fatbin::*;
+//!
+//! let ptx = ".version 6.5\n.target sm_30\n.address_size 64\n";
+//! let mut fatbin = Fatbin::new();
+//! fatbin.add_ptx(30, ptx).add_cubin(70, b"\x7fELF...".to_vec());
+//!
+//! let bytes = fatbin.to_bytes();
+//! let fatbin = Fatbin::from_bytes(&bytes).unwrap();
+//! assert_eq!(fatbin.select(7, 5).unwrap().kind, ImageKind::Cubin); // binary compatible
+//! assert_eq!(fatbin.select(8, 0).unwrap().kind, ImageKind::Ptx); // JIT compiled
+//! assert!(fatbin.select(2, 0).is_none());
+//! ```
+//!
+//! [Fatbin::select]: struct.Fatbin.html#method.select
+
+use crate::error::*;
+use std::convert::TryInto;
+
+/// Magic number at the head of a fatbin, `0xBA55ED50` in little endian
+pub const FATBIN_MAGIC: [u8; 4] = [0x50, 0xed, 0x55, 0xba];
+
+const FATBIN_VERSION: u16 = 1;
+const FATBIN_HEADER_SIZE: usize = 16;
+const IMAGE_VERSION: u16 = 0x0101;
+const IMAGE_HEADER_SIZE: usize = 64;
+
+const KIND_PTX: u16 = 1;
+const KIND_CUBIN: u16 = 2;
+
+/// Image is for 64-bit address
+const FLAG_64BIT: u64 = 0x1;
+/// Image is built on Linux
+const FLAG_LINUX: u64 = 0x10;
+/// Payload is compressed
+const FLAG_COMPRESSED: u64 = 0x2000;
+
+/// Kind of an image in a fatbin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageKind {
+    /// PTX compiled by the JIT compiler when loaded
+    Ptx,
+    /// cubin, i.e. SASS for the architecture
+    Cubin,
+}
+
+/// cubin or PTX in a fatbin
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub kind: ImageKind,
+    /// SM architecture, e.g. `70` for sm_70
+    pub arch: u32,
+    /// cubin, or PTX without NUL terminator
+    ///
+    /// cubin read from a fatbin may have trailing zeros padded to 8 bytes.
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    /// Compute capability of the architecture, e.g. `(7, 0)` for sm_70
+    pub fn compute_capability(&self) -> (u32, u32) {
+        (self.arch / 10, self.arch % 10)
+    }
+
+    /// Check if the device of compute capability `major.minor` can run this image
+    ///
+    /// - cubin runs on devices of the same major version, and the same or newer minor version
+    /// - PTX is compiled for devices of the same or newer architecture
+    pub fn is_compatible(&self, major: u32, minor: u32) -> bool {
+        let (image_major, image_minor) = self.compute_capability();
+        match self.kind {
+            ImageKind::Cubin => image_major == major && image_minor <= minor,
+            ImageKind::Ptx => (image_major, image_minor) <= (major, minor),
+        }
+    }
+}
+
+/// Reader and writer of fatbin
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Fatbin {
+    pub images: Vec<Image>,
+}
+
+fn invalid(message: &str) -> AccelError {
+    AccelError::InvalidFatbin {
+        message: message.into(),
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+impl Fatbin {
+    /// Empty fatbin
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add cubin for `sm_{arch}`
+    pub fn add_cubin(&mut self, arch: u32, cubin: Vec<u8>) -> &mut Self {
+        self.images.push(Image {
+            kind: ImageKind::Cubin,
+            arch,
+            data: cubin,
+        });
+        self
+    }
+
+    /// Add PTX for `sm_{arch}`
+    pub fn add_ptx(&mut self, arch: u32, ptx: &str) -> &mut Self {
+        self.images.push(Image {
+            kind: ImageKind::Ptx,
+            arch,
+            data: ptx.as_bytes().to_vec(),
+        });
+        self
+    }
+
+    /// Choose the best image for the device of compute capability `major.minor`
+    ///
+    /// cubin of the newest compatible architecture is preferred,
+    /// and PTX of the newest compatible architecture is used if there is no such cubin.
+    pub fn select(&self, major: u32, minor: u32) -> Option<&Image> {
+        let newest = |kind| {
+            self.images
+                .iter()
+                .filter(|image| image.kind == kind && image.is_compatible(major, minor))
+                .max_by_key(|image| image.arch)
+        };
+        newest(ImageKind::Cubin).or_else(|| newest(ImageKind::Ptx))
+    }
+
+    /// Serialize into fatbin
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        for image in &self.images {
+            let mut payload = image.data.clone();
+            if image.kind == ImageKind::Ptx {
+                payload.push(0);
+            }
+            let padded = (payload.len() + 7) / 8 * 8;
+            payload.resize(padded, 0);
+
+            let kind = match image.kind {
+                ImageKind::Ptx => KIND_PTX,
+                ImageKind::Cubin => KIND_CUBIN,
+            };
+            body.extend_from_slice(&kind.to_le_bytes());
+            body.extend_from_slice(&IMAGE_VERSION.to_le_bytes());
+            body.extend_from_slice(&(IMAGE_HEADER_SIZE as u32).to_le_bytes());
+            body.extend_from_slice(&(padded as u64).to_le_bytes());
+            body.extend_from_slice(&0_u32.to_le_bytes()); // compressed size
+            body.extend_from_slice(&0_u32.to_le_bytes());
+            body.extend_from_slice(&0_u32.to_le_bytes()); // minor and major version of the image
+            body.extend_from_slice(&image.arch.to_le_bytes());
+            body.extend_from_slice(&0_u64.to_le_bytes()); // offset and size of the object name
+            body.extend_from_slice(&(FLAG_64BIT | FLAG_LINUX).to_le_bytes());
+            body.extend_from_slice(&0_u64.to_le_bytes());
+            body.extend_from_slice(&0_u64.to_le_bytes()); // decompressed size
+            body.extend_from_slice(&payload);
+        }
+
+        let mut bytes = Vec::with_capacity(FATBIN_HEADER_SIZE + body.len());
+        bytes.extend_from_slice(&FATBIN_MAGIC);
+        bytes.extend_from_slice(&FATBIN_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(FATBIN_HEADER_SIZE as u16).to_le_bytes());
+        bytes.extend_from_slice(&(body.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    /// Parse fatbin
+    ///
+    /// Compressed images are not supported, and returns `AccelError::InvalidFatbin`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < FATBIN_HEADER_SIZE || bytes[..4] != FATBIN_MAGIC {
+            return Err(invalid("Magic number mismatch"));
+        }
+        let header_size = read_u16(bytes, 6) as usize;
+        let body_size = read_u64(bytes, 8) as usize;
+        let end = header_size
+            .checked_add(body_size)
+            .filter(|&end| header_size >= FATBIN_HEADER_SIZE && end <= bytes.len())
+            .ok_or_else(|| invalid("Fatbin is truncated"))?;
+
+        let mut images = Vec::new();
+        let mut offset = header_size;
+        while offset < end {
+            if end - offset < IMAGE_HEADER_SIZE {
+                return Err(invalid("Image header is truncated"));
+            }
+            let kind = match read_u16(bytes, offset) {
+                KIND_PTX => ImageKind::Ptx,
+                KIND_CUBIN => ImageKind::Cubin,
+                _ => return Err(invalid("Unknown image kind")),
+            };
+            let image_header_size = read_u32(bytes, offset + 4) as usize;
+            let size = read_u64(bytes, offset + 8) as usize;
+            let compressed_size = read_u32(bytes, offset + 16);
+            let arch = read_u32(bytes, offset + 28);
+            let flags = read_u64(bytes, offset + 40);
+            if compressed_size != 0 || flags & FLAG_COMPRESSED != 0 {
+                return Err(invalid("Compressed image is not supported"));
+            }
+            let head = offset + image_header_size;
+            let tail = head
+                .checked_add(size)
+                .filter(|&tail| image_header_size >= IMAGE_HEADER_SIZE && tail <= end)
+                .ok_or_else(|| invalid("Image is truncated"))?;
+            let mut data = bytes[head..tail].to_vec();
+            if kind == ImageKind::Ptx {
+                let len = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+                data.truncate(len);
+            }
+            images.push(Image { kind, arch, data });
+            offset = tail;
+        }
+        Ok(Fatbin { images })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    const PTX: &str = ".version 6.5\n.target sm_30\n.address_size 64\n";
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let cubin = std::fs::read("tests/data/add.cubin").unwrap();
+        let mut fatbin = Fatbin::new();
+        fatbin
+            .add_cubin(30, cubin.clone())
+            .add_cubin(70, vec![1; 8])
+            .add_ptx(30, PTX);
+        let bytes = fatbin.to_bytes();
+        assert!(matches!(
+            crate::Instruction::from_bytes(&bytes)?,
+            crate::Instruction::Fatbin(_)
+        ));
+
+        let read = Fatbin::from_bytes(&bytes)?;
+        assert_eq!(read.images.len(), 3);
+        let sm30 = &read.images[0];
+        assert_eq!(sm30.kind, ImageKind::Cubin);
+        assert_eq!(sm30.arch, 30);
+        assert_eq!(&sm30.data[..cubin.len()], cubin.as_slice());
+        assert!(sm30.data[cubin.len()..].iter().all(|&b| b == 0));
+        assert_eq!(read.images[1], fatbin.images[1]);
+        assert_eq!(read.images[2], fatbin.images[2]);
+        assert_eq!(read.to_bytes(), bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn select() {
+        let mut fatbin = Fatbin::new();
+        fatbin
+            .add_cubin(60, vec![])
+            .add_cubin(70, vec![])
+            .add_cubin(72, vec![])
+            .add_ptx(50, PTX)
+            .add_ptx(70, PTX);
+        let selected = |major, minor| {
+            fatbin
+                .select(major, minor)
+                .map(|image| (image.kind, image.arch))
+        };
+        assert_eq!(selected(7, 0), Some((ImageKind::Cubin, 70)));
+        assert_eq!(selected(7, 5), Some((ImageKind::Cubin, 72)));
+        assert_eq!(selected(6, 1), Some((ImageKind::Cubin, 60)));
+        assert_eq!(selected(8, 6), Some((ImageKind::Ptx, 70)));
+        assert_eq!(selected(5, 2), Some((ImageKind::Ptx, 50)));
+        assert_eq!(selected(3, 5), None);
+    }
+
+    #[test]
+    fn load() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let (major, _minor) = device.compute_capability()?;
+        let ptx = format!("{}.visible .entry f()\n{{\n  ret;\n}}\n", PTX);
+
+        // cubin for the next generation cannot run on the device
+        let mut fatbin = Fatbin::new();
+        fatbin.add_cubin((major + 1) * 10, b"\x7fELF".to_vec());
+        let data = Instruction::fatbin(&fatbin.to_bytes());
+        assert!(matches!(
+            Module::load(&ctx, &data),
+            Err(AccelError::UnsupportedArchitecture { .. })
+        ));
+
+        // fallback to PTX
+        fatbin.add_ptx(30, &ptx);
+        let data = Instruction::fatbin(&fatbin.to_bytes());
+        let module = Module::load(&ctx, &data)?;
+        module.get_kernel("f")?;
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_fatbin() {
+        let mut fatbin = Fatbin::new();
+        fatbin.add_ptx(30, PTX);
+        let bytes = fatbin.to_bytes();
+        assert!(Fatbin::from_bytes(&bytes[..bytes.len() - 8]).is_err());
+        assert!(Fatbin::from_bytes(b"\x7fELF").is_err());
+
+        let mut compressed = bytes;
+        compressed[FATBIN_HEADER_SIZE + 41] |= 0x20; // FLAG_COMPRESSED
+        assert!(matches!(
+            Fatbin::from_bytes(&compressed),
+            Err(AccelError::InvalidFatbin { .. })
+        ));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::{error::Result, occupancy::*, Block, Device, Kernel};
 use num_traits::ToPrimitive;
 
 /// Size of Grid (grid of blocks) in [CUDA thread hierarchy]( http://docs.nvidia.com/cuda/cuda-c-programming-guide/index.html#programming-model )
@@ -100,6 +101,26 @@ impl Grid {
             z: z.to_u32().expect("Cannot convert to u32"),
         }
     }
+
+    /// 1D Grid and Block covering `n` elements, with the block size maximizing the occupancy of the kernel
+    ///
+    /// The occupancy is computed by [accel::occupancy](occupancy/index.html) for the device of the kernel
+    /// without dynamic shared memory. Each thread is assumed to process one element.
+    ///
+    /// ```
+    /// # use accel::*;
+    /// # let device = Device::nth(0).unwrap();
+    /// # let ctx = device.create_context();
+    /// # let ptx = ".version 3.2\n.target sm_30\n.address_size 64\n.visible .entry f() { ret; }";
+    /// let module = Module::from_str(&ctx, ptx).unwrap();
+    /// let kernel = module.get_kernel("f").unwrap();
+    /// let (grid, block) = Grid::for_elements(1000, &kernel).unwrap();
+    /// assert!(grid.x * block.x >= 1000);
+    /// ```
+    pub fn for_elements(n: usize, kernel: &Kernel) -> Result<(Grid, Block)> {
+        let limits = SmLimits::from_device(&Device::from_context(kernel)?)?;
+        limits.for_elements(&KernelResources::from_kernel(kernel)?, n)
+    }
 }
 
 impl<I: ToPrimitive> Into<Grid> for (I,) {
@@ -1,14 +1,71 @@
-use crate::{error::*, *};
+use crate::{error::*, fatbin::FATBIN_MAGIC, *};
 use cuda::*;
-use std::{ffi::*, path::*};
+use std::{ffi::*, fs, io::Read, path::*};
 
-/// Represent the resource of CUDA middle-IR (PTX/cubin)
+/// Magic number at the head of an ELF file
+const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+/// `e_machine` of ELF for CUDA devices
+const EM_CUDA: u16 = 190;
+/// Magic number at the head of an `ar` archive
+const AR_MAGIC: [u8; 8] = *b"!<arch>\n";
+
+/// Bytes read by [Instruction::from_path] to detect the format
+const HEADER_SIZE: u64 = 1024;
+
+/// Represent the resource of CUDA middle-IR (PTX/cubin), and the other inputs of the linker
 #[derive(Debug)]
 pub enum Instruction {
     PTX(CString),
     PTXFile(PathBuf),
     Cubin(Vec<u8>),
     CubinFile(PathBuf),
+    /// Fat binary containing cubins and PTX for several architectures
+    Fatbin(Vec<u8>),
+    FatbinFile(PathBuf),
+    /// Host object with embedded relocatable device code, e.g. compiled by `nvcc -dc`
+    Object(Vec<u8>),
+    ObjectFile(PathBuf),
+    /// Archive of host objects with embedded relocatable device code, e.g. `libcudadevrt.a`
+    Library(Vec<u8>),
+    LibraryFile(PathBuf),
+}
+
+/// Detect the input type from the head of data
+fn detect(head: &[u8]) -> Option<CUjitInputType> {
+    if head.starts_with(&ELF_MAGIC) {
+        // `e_machine` at offset 18, in the byte order given by `EI_DATA` at offset 5
+        let machine = match (head.get(5), head.get(18..20)) {
+            (Some(1), Some(m)) => u16::from_le_bytes([m[0], m[1]]),
+            (Some(2), Some(m)) => u16::from_be_bytes([m[0], m[1]]),
+            _ => return None,
+        };
+        return Some(if machine == EM_CUDA {
+            CUjitInputType::CU_JIT_INPUT_CUBIN
+        } else {
+            CUjitInputType::CU_JIT_INPUT_OBJECT
+        });
+    }
+    if head.starts_with(&FATBIN_MAGIC) {
+        return Some(CUjitInputType::CU_JIT_INPUT_FATBINARY);
+    }
+    if head.starts_with(&AR_MAGIC) {
+        return Some(CUjitInputType::CU_JIT_INPUT_LIBRARY);
+    }
+    // PTX is a text, which may be terminated by NUL
+    let len = head.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    if len > 0 && !head[..len].contains(&0) {
+        return Some(CUjitInputType::CU_JIT_INPUT_PTX);
+    }
+    None
+}
+
+fn check_exists(path: &Path) -> Result<PathBuf> {
+    if !path.exists() {
+        return Err(AccelError::FileNotFound {
+            path: path.to_owned(),
+        });
+    }
+    Ok(path.to_owned())
 }
 
 impl Instruction {
@@ -23,24 +80,95 @@ impl Instruction {
         Instruction::Cubin(sl.to_vec())
     }
 
+    /// Constructor for `Instruction::Fatbin`
+    pub fn fatbin(sl: &[u8]) -> Instruction {
+        Instruction::Fatbin(sl.to_vec())
+    }
+
+    /// Constructor for `Instruction::Object`
+    pub fn object(sl: &[u8]) -> Instruction {
+        Instruction::Object(sl.to_vec())
+    }
+
+    /// Constructor for `Instruction::Library`
+    pub fn library(sl: &[u8]) -> Instruction {
+        Instruction::Library(sl.to_vec())
+    }
+
     /// Constructor for `Instruction::PTXFile`
     pub fn ptx_file(path: &Path) -> Result<Self> {
-        if !path.exists() {
-            return Err(AccelError::FileNotFound {
-                path: path.to_owned(),
-            });
-        }
-        Ok(Instruction::PTXFile(path.to_owned()))
+        Ok(Instruction::PTXFile(check_exists(path)?))
     }
 
     /// Constructor for `Instruction::CubinFile`
     pub fn cubin_file(path: &Path) -> Result<Self> {
-        if !path.exists() {
-            return Err(AccelError::FileNotFound {
-                path: path.to_owned(),
-            });
-        }
-        Ok(Instruction::CubinFile(path.to_owned()))
+        Ok(Instruction::CubinFile(check_exists(path)?))
+    }
+
+    /// Constructor for `Instruction::FatbinFile`
+    pub fn fatbin_file(path: &Path) -> Result<Self> {
+        Ok(Instruction::FatbinFile(check_exists(path)?))
+    }
+
+    /// Constructor for `Instruction::ObjectFile`
+    pub fn object_file(path: &Path) -> Result<Self> {
+        Ok(Instruction::ObjectFile(check_exists(path)?))
+    }
+
+    /// Constructor for `Instruction::LibraryFile`
+    pub fn library_file(path: &Path) -> Result<Self> {
+        Ok(Instruction::LibraryFile(check_exists(path)?))
+    }
+
+    /// Detect the format by its magic number
+    ///
+    /// - ELF for CUDA devices is a cubin, and the other ELF is a host object
+    /// - fatbin starts with `0xBA55ED50`
+    /// - `ar` archive is a library
+    /// - The others are regarded as PTX if they are texts
+    ///
+    /// ```
+    /// # use accel::*;
+    /// let data = Instruction::from_bytes(b".version 6.5\n.target sm_30\n").unwrap();
+    /// assert!(matches!(data, Instruction::PTX(_)));
+    /// let data = Instruction::from_bytes(b"!<arch>\n").unwrap();
+    /// assert!(matches!(data, Instruction::Library(_)));
+    /// ```
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let input_type = detect(data).ok_or_else(|| AccelError::InvalidInstruction {
+            message: "Unknown format".into(),
+        })?;
+        Ok(match input_type {
+            CUjitInputType::CU_JIT_INPUT_PTX => {
+                let len = data.iter().rposition(|&b| b != 0).unwrap() + 1;
+                Instruction::PTX(CString::new(&data[..len]).unwrap())
+            }
+            CUjitInputType::CU_JIT_INPUT_CUBIN => Instruction::cubin(data),
+            CUjitInputType::CU_JIT_INPUT_FATBINARY => Instruction::fatbin(data),
+            CUjitInputType::CU_JIT_INPUT_OBJECT => Instruction::object(data),
+            CUjitInputType::CU_JIT_INPUT_LIBRARY => Instruction::library(data),
+            _ => unreachable!(),
+        })
+    }
+
+    /// Detect the format of a file by its magic number, see [from_bytes](#method.from_bytes)
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let mut head = Vec::new();
+        fs::File::open(path)
+            .and_then(|f| f.take(HEADER_SIZE).read_to_end(&mut head))
+            .map_err(|e| file_error(path, e))?;
+        let input_type = detect(&head).ok_or_else(|| AccelError::InvalidInstruction {
+            message: format!("Unknown format: {}", path.display()),
+        })?;
+        let path = path.to_owned();
+        Ok(match input_type {
+            CUjitInputType::CU_JIT_INPUT_PTX => Instruction::PTXFile(path),
+            CUjitInputType::CU_JIT_INPUT_CUBIN => Instruction::CubinFile(path),
+            CUjitInputType::CU_JIT_INPUT_FATBINARY => Instruction::FatbinFile(path),
+            CUjitInputType::CU_JIT_INPUT_OBJECT => Instruction::ObjectFile(path),
+            CUjitInputType::CU_JIT_INPUT_LIBRARY => Instruction::LibraryFile(path),
+            _ => unreachable!(),
+        })
     }
 }
 
@@ -52,6 +180,97 @@ impl Instruction {
             Instruction::Cubin(_) | Instruction::CubinFile(_) => {
                 CUjitInputType_enum::CU_JIT_INPUT_CUBIN
             }
+            Instruction::Fatbin(_) | Instruction::FatbinFile(_) => {
+                CUjitInputType_enum::CU_JIT_INPUT_FATBINARY
+            }
+            Instruction::Object(_) | Instruction::ObjectFile(_) => {
+                CUjitInputType_enum::CU_JIT_INPUT_OBJECT
+            }
+            Instruction::Library(_) | Instruction::LibraryFile(_) => {
+                CUjitInputType_enum::CU_JIT_INPUT_LIBRARY
+            }
+        }
+    }
+
+    /// Path of the file, `None` for in-memory data
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Instruction::PTXFile(path)
+            | Instruction::CubinFile(path)
+            | Instruction::FatbinFile(path)
+            | Instruction::ObjectFile(path)
+            | Instruction::LibraryFile(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// File extension usually used for the format
+    pub(crate) fn extension(&self) -> &'static str {
+        match self.input_type() {
+            CUjitInputType_enum::CU_JIT_INPUT_PTX => "ptx",
+            CUjitInputType_enum::CU_JIT_INPUT_CUBIN => "cubin",
+            CUjitInputType_enum::CU_JIT_INPUT_FATBINARY => "fatbin",
+            CUjitInputType_enum::CU_JIT_INPUT_OBJECT => "o",
+            _ => "a",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_files() -> Result<()> {
+        let data = Instruction::from_path(Path::new("tests/data/add.ptx"))?;
+        assert!(matches!(data, Instruction::PTXFile(_)));
+        let data = Instruction::from_path(Path::new("tests/data/add.cubin"))?;
+        assert!(matches!(data, Instruction::CubinFile(_)));
+        assert!(matches!(
+            Instruction::from_path(Path::new("tests/data/not_found.ptx")),
+            Err(AccelError::FileNotFound { .. })
+        ));
+        // Other I/O errors are reported as they are
+        assert!(matches!(
+            Instruction::from_path(Path::new("tests/data")),
+            Err(AccelError::IoError { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn detect_bytes() -> Result<()> {
+        let cubin = fs::read("tests/data/add.cubin").unwrap();
+        assert!(matches!(
+            Instruction::from_bytes(&cubin)?,
+            Instruction::Cubin(_)
+        ));
+
+        // x86_64 ELF is a host object
+        let mut object = cubin.clone();
+        object[18] = 62;
+        assert!(matches!(
+            Instruction::from_bytes(&object)?,
+            Instruction::Object(_)
+        ));
+
+        let mut fatbin = FATBIN_MAGIC.to_vec();
+        fatbin.extend_from_slice(&[1, 0, 0x10, 0]);
+        assert!(matches!(
+            Instruction::from_bytes(&fatbin)?,
+            Instruction::Fatbin(_)
+        ));
+
+        // NUL terminated PTX
+        match Instruction::from_bytes(b".version 6.5\n\0")? {
+            Instruction::PTX(ptx) => assert_eq!(ptx.to_bytes(), b".version 6.5\n"),
+            _ => panic!("must be PTX"),
         }
+        assert!(matches!(
+            Instruction::from_bytes(b"\x01\0\x02"),
+            Err(AccelError::InvalidInstruction { .. })
+        ));
+        assert!(Instruction::from_bytes(b"").is_err());
+        Ok(())
     }
 }
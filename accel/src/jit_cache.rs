@@ -0,0 +1,463 @@
+//! Persistent on-disk cache of cubins produced by the JIT compiler and linker
+//!
+//! PTX is compiled by ptxas in the driver every time it is loaded.
+//! [JitCache] stores the cubin compiled from PTX and the linked cubin of [Linker],
+//! and loads it on the next run without invoking the JIT compiler.
+//!
+//! Each entry is stored in `<root>/<key>/` where the key is the SHA-256 digest of
+//!
+//! - the contents of input [Instruction]s,
+//! - options of [JITConfig] affecting the generated code, i.e. log buffers are ignored,
+//! - the compute capability of the device, the driver version and the backend.
+//!
+//! The cache is opt-in, i.e. only used through [JitCache::load] and [JitCache::link].
+//! It is configured by environment variables in [JitCache::from_env]:
+//!
+//! |name                          | default                      | description                                 |
+//! |:-----------------------------|:-----------------------------|:--------------------------------------------|
+//! |`ACCEL_JIT_CACHE_DIR`         | `$XDG_CACHE_HOME/accel/jit`  | Root directory of the cache                 |
+//! |`ACCEL_JIT_CACHE_SIZE`        | `268435456` (256MB)          | Total size limit of cached cubins in bytes  |
+//! |`ACCEL_JIT_CACHE_MAX_ENTRIES` | `1024`                       | Limit of number of entries                  |
+//! |`ACCEL_JIT_CACHE_DISABLE`     | unset                        | Disable the cache if set, i.e. always JIT   |
+//!
+//! Least recently used entries are evicted when the limits are exceeded.
+//! Files are written to temporary files and renamed,
+//! and thus concurrent processes never read a partially written entry.
+//!
+//! ```
+//! use accel::{*, jit_cache::JitCache};
+//! # let ptx = ".version 6.5\n.target sm_30\n.address_size 64\n.visible .entry f()\n{\n  ret;\n}";
+//! # let root = std::env::temp_dir().join(format!("accel-jit-cache-doc-{}", std::process::id()));
+//! let device = Device::nth(0).unwrap();
+//! let ctx = device.create_context();
+//! let cache = JitCache::new(&root, 256 * 1024 * 1024, 1024);
+//! let data = Instruction::ptx(ptx);
+//! let module = cache.load(&ctx, &data, JITConfig::default()).unwrap(); // compiled and stored
+//! let module = cache.load(&ctx, &data, JITConfig::default()).unwrap(); // loaded from the cache
+//! # std::fs::remove_dir_all(&root).unwrap();
+//! ```
+//!
+//! [JitCache]: struct.JitCache.html
+//! [JitCache::load]: struct.JitCache.html#method.load
+//! [JitCache::link]: struct.JitCache.html#method.link
+//! [JitCache::from_env]: struct.JitCache.html#method.from_env
+//! [Linker]: ../linker/struct.Linker.html
+//! [Instruction]: ../enum.Instruction.html
+//! [JITConfig]: ../linker/struct.JITConfig.html
+
+use crate::{error::*, *};
+use cuda::*;
+use sha2::{Digest, Sha256};
+use std::{
+    env, fs,
+    hash::{Hash, Hasher},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const CUBIN_FILE: &str = "module.cubin";
+const LAST_USED_FILE: &str = "last_used";
+
+/// Bumped when the layout of the cache or the key is changed
+const CACHE_VERSION: u32 = 1;
+
+/// SHA-256 digest of inputs of the JIT compiler and linker
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CacheKey([u8; 32]);
+
+impl CacheKey {
+    pub fn hex(&self) -> String {
+        self.0.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// Feed values of `Hash` into SHA-256
+#[derive(Default)]
+struct KeyHasher(Sha256);
+
+impl KeyHasher {
+    fn digest(self) -> CacheKey {
+        let mut key = [0; 32];
+        key.copy_from_slice(&self.0.finalize());
+        CacheKey(key)
+    }
+}
+
+impl Hasher for KeyHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        unreachable!("Use KeyHasher::digest")
+    }
+}
+
+fn hash_instruction(data: &Instruction, hasher: &mut KeyHasher) -> Result<()> {
+    (data.input_type() as u32).hash(hasher);
+    match data.path() {
+        Some(path) => {
+            let bytes = fs::read(path).map_err(|e| file_error(path, e))?;
+            bytes.hash(hasher);
+        }
+        None => match data {
+            Instruction::PTX(ptx) => ptx.as_bytes().hash(hasher),
+            Instruction::Cubin(bin)
+            | Instruction::Fatbin(bin)
+            | Instruction::Object(bin)
+            | Instruction::Library(bin) => bin.hash(hasher),
+            _ => unreachable!("in-memory instruction"),
+        },
+    }
+    Ok(())
+}
+
+/// Hash options affecting the generated code
+fn hash_config(cfg: &JITConfig, hasher: &mut KeyHasher) {
+    cfg.max_registers.hash(hasher);
+    cfg.threads_per_block.hash(hasher);
+    cfg.optimization_level.hash(hasher);
+    cfg.target.hash(hasher);
+    cfg.fallback_strategy.hash(hasher);
+    cfg.generate_debug_info.hash(hasher);
+    cfg.generate_line_info.hash(hasher);
+    cfg.cache_mode.hash(hasher);
+    cfg.new_sm3x_opt.hash(hasher);
+    cfg.fast_compile.hash(hasher);
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Write a file atomically by renaming a temporal file
+///
+/// The temporal file is unique for each call, and thus threads and processes do not race.
+fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let tmp = path.with_extension(format!(
+        "tmp{}-{}",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::SeqCst)
+    ));
+    let mut f = fs::File::create(&tmp)?;
+    f.write_all(contents)?;
+    f.sync_data()?;
+    fs::rename(&tmp, path)
+}
+
+/// Content-addressed cache of cubins
+#[derive(Debug, Clone)]
+pub struct JitCache {
+    root: PathBuf,
+    max_bytes: u64,
+    max_entries: usize,
+}
+
+impl JitCache {
+    pub fn new(root: impl Into<PathBuf>, max_bytes: u64, max_entries: usize) -> Self {
+        JitCache {
+            root: root.into(),
+            max_bytes,
+            max_entries,
+        }
+    }
+
+    /// Cache configured by `ACCEL_JIT_CACHE_*` environment variables, `None` if disabled
+    pub fn from_env() -> Option<Self> {
+        if env::var_os("ACCEL_JIT_CACHE_DISABLE").is_some() {
+            return None;
+        }
+        let root = match env::var_os("ACCEL_JIT_CACHE_DIR") {
+            Some(dir) => PathBuf::from(dir),
+            None => dirs::cache_dir()?.join("accel").join("jit"),
+        };
+        let max_bytes = env::var("ACCEL_JIT_CACHE_SIZE")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(256 * 1024 * 1024);
+        let max_entries = env::var("ACCEL_JIT_CACHE_MAX_ENTRIES")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(1024);
+        Some(Self::new(root, max_bytes, max_entries))
+    }
+
+    /// Key of the cubin linked from `data` with `cfg` for the device of `ctx`
+    pub fn key(&self, ctx: &Context, data: &[Instruction], cfg: &JITConfig) -> Result<CacheKey> {
+        let mut hasher = KeyHasher::default();
+        CACHE_VERSION.hash(&mut hasher);
+        backend::get().name().hash(&mut hasher);
+        Device::driver_version()?.hash(&mut hasher);
+        Device::from_context(ctx)?
+            .compute_capability()?
+            .hash(&mut hasher);
+        hash_config(cfg, &mut hasher);
+        data.len().hash(&mut hasher);
+        for d in data {
+            hash_instruction(d, &mut hasher)?;
+        }
+        Ok(hasher.digest())
+    }
+
+    fn entry_dir(&self, key: CacheKey) -> PathBuf {
+        self.root.join(key.hex())
+    }
+
+    /// Get cached cubin, and mark it as recently used
+    pub fn get(&self, key: CacheKey) -> Option<Vec<u8>> {
+        let dir = self.entry_dir(key);
+        let cubin = fs::read(dir.join(CUBIN_FILE)).ok()?;
+        let _ = write_atomic(&dir.join(LAST_USED_FILE), now().to_string().as_bytes());
+        Some(cubin)
+    }
+
+    /// Store cubin, and evict old entries if the limits are exceeded
+    pub fn put(&self, key: CacheKey, cubin: &[u8]) -> io::Result<()> {
+        let dir = self.entry_dir(key);
+        fs::create_dir_all(&dir)?;
+        write_atomic(&dir.join(CUBIN_FILE), cubin)?;
+        write_atomic(&dir.join(LAST_USED_FILE), now().to_string().as_bytes())?;
+        self.evict(Some(key))
+    }
+
+    /// List `(key directory, last used, size in bytes)` of entries
+    fn entries(&self) -> Vec<(PathBuf, u64, u64)> {
+        let read_dir = match fs::read_dir(&self.root) {
+            Ok(read_dir) => read_dir,
+            Err(_) => return Vec::new(),
+        };
+        read_dir
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|path| path.is_dir())
+            .filter_map(|dir| {
+                let size = fs::metadata(dir.join(CUBIN_FILE)).ok()?.len();
+                let last_used = fs::read_to_string(dir.join(LAST_USED_FILE))
+                    .ok()
+                    .and_then(|s| s.trim().parse().ok())
+                    .unwrap_or(0);
+                Some((dir, last_used, size))
+            })
+            .collect()
+    }
+
+    /// Remove least recently used entries until the limits are satisfied
+    ///
+    /// The entry of `keep` is not removed even if it exceeds the limits by itself.
+    pub fn evict(&self, keep: Option<CacheKey>) -> io::Result<()> {
+        let keep = keep.map(|key| self.entry_dir(key));
+        let mut entries = self.entries();
+        // the kept entry first, and then newest first
+        entries.sort_by_key(|(dir, last_used, _)| {
+            (
+                keep.as_ref() != Some(dir),
+                std::cmp::Reverse(*last_used),
+                dir.clone(),
+            )
+        });
+        let mut total = 0;
+        let mut count = 0;
+        for (dir, _, size) in entries {
+            let is_kept = keep.as_ref() == Some(&dir);
+            if is_kept || (total + size <= self.max_bytes && count < self.max_entries) {
+                total += size;
+                count += 1;
+            } else {
+                match fs::remove_dir_all(&dir) {
+                    // already evicted by another process
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Link `data` into a module, or load the cubin linked before
+    ///
+    /// Configurations with global symbols are not cached,
+    /// since the symbols are relocated to host addresses of the current process.
+    /// Failures of reading or writing the cache are logged, and do not fail the link.
+    pub fn link(&self, ctx: &Context, data: &[Instruction], cfg: JITConfig) -> Result<Module> {
+        if !cfg.global_symbol.is_empty() {
+            return linker::link(ctx, data, cfg);
+        }
+        let key = self.key(ctx, data, &cfg)?;
+        if let Some(cubin) = self.get(key) {
+            match Module::load(ctx, &Instruction::Cubin(cubin)) {
+                Ok(module) => return Ok(module),
+                Err(e) => log::warn!("Cached cubin {} is not loaded: {}", key.hex(), e),
+            }
+        }
+        let mut linker = Linker::create(ctx, cfg)?;
+        for d in data {
+            linker = linker.add(d)?;
+        }
+        let output = linker.complete()?;
+        if let Err(e) = self.put(key, &output.cubin) {
+            log::warn!("Failed to store cubin {} in JIT cache: {}", key.hex(), e);
+        }
+        Module::load(ctx, &output.into_instruction())
+    }
+
+    /// Load `data` as [Module::load_with] does, and cache the cubin compiled from PTX
+    ///
+    /// Inputs other than PTX are loaded directly since they are not compiled by ptxas.
+    ///
+    /// [Module::load_with]: ../module/struct.Module.html#method.load_with
+    pub fn load(&self, ctx: &Context, data: &Instruction, mut cfg: JITConfig) -> Result<Module> {
+        match data.input_type() {
+            CUjitInputType::CU_JIT_INPUT_PTX => self.link(ctx, std::slice::from_ref(data), cfg),
+            _ => Module::load_with(ctx, data, &mut cfg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_cache(max_bytes: u64, max_entries: usize) -> JitCache {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let root = env::temp_dir().join(format!(
+            "accel-jit-cache-test-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&root);
+        JitCache::new(root, max_bytes, max_entries)
+    }
+
+    #[test]
+    fn key() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let cache = test_cache(1024, 16);
+        let add = [Instruction::ptx_file(Path::new("tests/data/add.ptx"))?];
+        let sub = [Instruction::ptx_file(Path::new("tests/data/sub.ptx"))?];
+        let key = cache.key(&ctx, &add, &JITConfig::default())?;
+        assert_eq!(key.hex().len(), 64);
+
+        // In-memory PTX of the same contents
+        let ptx = fs::read_to_string("tests/data/add.ptx").unwrap();
+        assert_eq!(
+            cache.key(&ctx, &[Instruction::ptx(&ptx)], &JITConfig::default())?,
+            key
+        );
+        // Log buffers do not affect the key
+        let cfg = JITConfig {
+            info_log_buffer: Some(LogBuffer::default()),
+            ..Default::default()
+        };
+        assert_eq!(cache.key(&ctx, &add, &cfg)?, key);
+
+        let cfg = JITConfig {
+            optimization_level: Some(1),
+            ..Default::default()
+        };
+        assert_ne!(cache.key(&ctx, &add, &cfg)?, key);
+        assert_ne!(cache.key(&ctx, &sub, &JITConfig::default())?, key);
+        Ok(())
+    }
+
+    #[test]
+    fn link() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let cache = test_cache(1024 * 1024, 16);
+        let data = [
+            Instruction::ptx_file(Path::new("tests/data/add.ptx"))?,
+            Instruction::ptx_file(Path::new("tests/data/sub.ptx"))?,
+        ];
+        let key = cache.key(&ctx, &data, &JITConfig::default())?;
+        assert!(cache.get(key).is_none());
+        let module = cache.link(&ctx, &data, JITConfig::default())?;
+        module.get_kernel("_Z3addPKiS0_Pi")?;
+        let cubin = cache.get(key).unwrap();
+
+        // Loaded from the cache
+        let module = cache.link(&ctx, &data, JITConfig::default())?;
+        module.get_kernel("_Z3subPKiS0_Pi")?;
+        assert_eq!(cache.get(key).unwrap(), cubin);
+        assert_eq!(cache.entries().len(), 1);
+
+        // Only PTX is cached
+        cache.load(&ctx, &data[0], JITConfig::default())?;
+        assert_eq!(cache.entries().len(), 2);
+        fs::remove_dir_all(&cache.root).unwrap();
+        Ok(())
+    }
+
+    static TABLE: [u32; 4] = [1, 2, 4, 8];
+
+    #[test]
+    fn global_symbol_not_cached() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let cache = test_cache(1024 * 1024, 16);
+        let data = Instruction::ptx(
+            ".version 6.5\n.target sm_30\n.address_size 64\n.extern .global .align 4 .u32 table[4];",
+        );
+        let mut cfg = JITConfig::default();
+        cfg.bind_global("table", &TABLE)?;
+        cache.load(&ctx, &data, cfg)?;
+        assert!(cache.entries().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn write_atomic_threads() -> io::Result<()> {
+        let cache = test_cache(1024, 16);
+        fs::create_dir_all(&cache.root)?;
+        let path = cache.root.join(LAST_USED_FILE);
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || -> io::Result<()> {
+                    for _ in 0..16 {
+                        write_atomic(&path, i.to_string().as_bytes())?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap()?;
+        }
+        let last: usize = fs::read_to_string(&path)?.parse().unwrap();
+        assert!(last < 8);
+        // No temporal file is left
+        assert_eq!(fs::read_dir(&cache.root)?.count(), 1);
+        fs::remove_dir_all(&cache.root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn evict_lru() -> io::Result<()> {
+        let cache = test_cache(1024, 2);
+        let keys: Vec<_> = (0..3).map(|i| CacheKey([i; 32])).collect();
+        cache.put(keys[0], b"a")?;
+        cache.put(keys[1], b"b")?;
+        // make keys[0] older than keys[1]
+        write_atomic(&cache.entry_dir(keys[0]).join(LAST_USED_FILE), b"0")?;
+        cache.put(keys[2], b"c")?;
+        assert_eq!(cache.get(keys[0]), None);
+        assert!(cache.get(keys[1]).is_some());
+        assert!(cache.get(keys[2]).is_some());
+
+        // Size limit
+        let cache_small = JitCache::new(cache.root.clone(), 1, 16);
+        let key = CacheKey([4; 32]);
+        cache_small.put(key, b"too large")?;
+        assert!(cache_small.get(key).is_some());
+        assert!(cache_small.get(keys[2]).is_none());
+        fs::remove_dir_all(&cache.root)?;
+        Ok(())
+    }
+}
@@ -43,11 +43,11 @@
 //!     let device = Device::nth(0)?;
 //!     let ctx = device.create_context();
 //!
-//!     // Allocate memories on GPU
+//!     // Allocate managed memories shared by CPU and GPU
 //!     let n = 32;
-//!     let mut a = DeviceMemory::<f32>::zeros(&ctx, n);
-//!     let mut b = DeviceMemory::<f32>::zeros(&ctx, n);
-//!     let mut c = DeviceMemory::<f32>::zeros(&ctx, n);
+//!     let mut a = ManagedMemory::<f32>::zeros(&ctx, n);
+//!     let mut b = ManagedMemory::<f32>::zeros(&ctx, n);
+//!     let mut c = ManagedMemory::<f32>::zeros(&ctx, n);
 //!
 //!     // Accessible from CPU as usual Rust slice (though this will be slow)
 //!     for i in 0..n {
@@ -106,18 +106,58 @@
 //!     Ok(())
 //! }
 //! ```
+//!
+//! ### Global variables
+//!
+//! Variables declared by `accel_core::global!` can be read and written from the host
+//! through the module shared with the kernel launcher.
+//!
+//! ```
+//! use accel::*;
+//!
+//! #[kernel]
+//! unsafe fn scale(a: *mut f32, n: usize) {
+//!     accel_core::global! {
+//!         static mut COEF: [f32; 4] = [0.0; 4];
+//!     }
+//!     let i = accel_core::index();
+//!     if (i as usize) < n {
+//!         *a.offset(i) *= COEF[i as usize % 4];
+//!     }
+//! }
+//!
+//! fn main() -> error::Result<()> {
+//!     let device = Device::nth(0)?;
+//!     let ctx = device.create_context();
+//!     let module = Module::cached(&ctx, scale::PTX_STR)?;
+//!     let mut coef = module.global::<f32>("COEF")?;
+//!     coef.copy_from(&[1.0, 2.0, 3.0, 4.0][..]);
+//!
+//!     let n = 16;
+//!     let mut a = ManagedMemory::<f32>::from_elem(&ctx, n, 1.0);
+//!     scale(&ctx, 1, n, (a.as_mut_ptr(), n))?;
+//!     assert_eq!(a[5], 2.0);
+//!     Ok(())
+//! }
+//! ```
 
 extern crate cuda_driver_sys as cuda;
 
 pub use accel_derive::{kernel, kernel_mod, kernel_func, type_substitute};
 
+pub mod backend;
+pub mod cubin;
 pub mod device;
 pub mod error;
 pub mod execution;
+pub mod fatbin;
+pub mod jit_cache;
 pub mod linker;
 pub mod memory;
 pub mod module;
+pub mod occupancy;
 pub mod profiler;
+pub mod ptx;
 pub mod stream;
 
 mod block;
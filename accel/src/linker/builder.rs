@@ -0,0 +1,494 @@
+//! Validated builder of [JITConfig] with environment and file overrides
+
+use super::*;
+use serde::{
+    de::{value::StrDeserializer, IntoDeserializer},
+    Deserialize, Serialize,
+};
+use std::{env, fs, str::FromStr};
+
+/// Fallback strategy of the JIT compiler, i.e. `CUjit_fallback`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JitFallback {
+    /// Prefer to compile PTX if exact binary match not found
+    PreferPtx,
+    /// Prefer to fall back to compatible binary code if exact match not found
+    PreferBinary,
+}
+
+impl From<JitFallback> for CUjit_fallback {
+    fn from(fallback: JitFallback) -> Self {
+        match fallback {
+            JitFallback::PreferPtx => CUjit_fallback::CU_PREFER_PTX,
+            JitFallback::PreferBinary => CUjit_fallback::CU_PREFER_BINARY,
+        }
+    }
+}
+
+/// Caching mode of global memory loads (`-dlcm`), i.e. `CUjit_cacheMode`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JitCacheMode {
+    /// Compile with no `-dlcm` flag specified
+    None,
+    /// Compile with L1 cache disabled
+    Cg,
+    /// Compile with L1 cache enabled
+    Ca,
+}
+
+impl From<JitCacheMode> for CUjit_cacheMode_enum {
+    fn from(mode: JitCacheMode) -> Self {
+        match mode {
+            JitCacheMode::None => CUjit_cacheMode_enum::CU_JIT_CACHE_OPTION_NONE,
+            JitCacheMode::Cg => CUjit_cacheMode_enum::CU_JIT_CACHE_OPTION_CG,
+            JitCacheMode::Ca => CUjit_cacheMode_enum::CU_JIT_CACHE_OPTION_CA,
+        }
+    }
+}
+
+/// `CUjit_target` of the SM architecture, e.g. `70` for `compute_70`
+fn jit_target(arch: u32) -> Option<CUjit_target> {
+    use CUjit_target::*;
+    Some(match arch {
+        20 => CU_TARGET_COMPUTE_20,
+        21 => CU_TARGET_COMPUTE_21,
+        30 => CU_TARGET_COMPUTE_30,
+        32 => CU_TARGET_COMPUTE_32,
+        35 => CU_TARGET_COMPUTE_35,
+        37 => CU_TARGET_COMPUTE_37,
+        50 => CU_TARGET_COMPUTE_50,
+        52 => CU_TARGET_COMPUTE_52,
+        53 => CU_TARGET_COMPUTE_53,
+        60 => CU_TARGET_COMPUTE_60,
+        61 => CU_TARGET_COMPUTE_61,
+        62 => CU_TARGET_COMPUTE_62,
+        70 => CU_TARGET_COMPUTE_70,
+        72 => CU_TARGET_COMPUTE_72,
+        75 => CU_TARGET_COMPUTE_75,
+        _ => return None,
+    })
+}
+
+fn invalid<T>(message: String) -> Result<T> {
+    Err(AccelError::InvalidJitConfig { message })
+}
+
+/// Parse a value of an environment variable
+fn parse_var<T: FromStr>(name: &str, value: &str) -> Result<T> {
+    value
+        .trim()
+        .parse()
+        .or_else(|_| invalid(format!("Invalid value of {}: {}", name, value)))
+}
+
+/// Parse a flag of an environment variable, `1`/`0` or `true`/`false`
+fn parse_flag(name: &str, value: &str) -> Result<bool> {
+    match value.trim() {
+        "1" | "true" => Ok(true),
+        "0" | "false" => Ok(false),
+        _ => invalid(format!("Invalid value of {}: {}", name, value)),
+    }
+}
+
+/// Parse an enum of an environment variable by its serde name, e.g. `prefer_ptx`
+fn parse_enum<'de, T: Deserialize<'de>>(name: &str, value: &'de str) -> Result<T> {
+    let de: StrDeserializer<serde::de::value::Error> = value.trim().into_deserializer();
+    T::deserialize(de).or_else(|_| invalid(format!("Invalid value of {}: {}", name, value)))
+}
+
+/// Builder of [JITConfig] rejecting invalid values and combinations of options
+///
+/// Unset options are not passed to the driver, i.e. its default is used.
+/// The builder is (de)serializable, e.g. from a TOML file:
+///
+/// ```toml
+/// optimization_level = 3
+/// line_info = true
+/// cache_mode = "ca"
+/// ```
+///
+/// ```
+/// # use accel::*;
+/// let cfg = JITConfig::builder()
+///     .optimization_level(3)
+///     .line_info(true)
+///     .info_log_size(4096)
+///     .build()
+///     .unwrap();
+/// assert_eq!(cfg.optimization_level, Some(3));
+/// assert!(cfg.generate_line_info);
+///
+/// // mutually exclusive
+/// assert!(JITConfig::builder().threads_per_block(128).target(70).build().is_err());
+/// ```
+///
+/// [JITConfig]: struct.JITConfig.html
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JITConfigBuilder {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_registers: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    threads_per_block: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    optimization_level: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fallback: Option<JitFallback>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_mode: Option<JitCacheMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    debug_info: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line_info: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    log_verbose: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fast_compile: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    info_log_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_log_size: Option<usize>,
+}
+
+impl JITConfig {
+    /// Start building a configuration, see [JITConfigBuilder](struct.JITConfigBuilder.html)
+    pub fn builder() -> JITConfigBuilder {
+        JITConfigBuilder::default()
+    }
+}
+
+impl JITConfigBuilder {
+    /// Maximum number of registers per thread (`CU_JIT_MAX_REGISTERS`)
+    pub fn max_registers(mut self, registers: u32) -> Self {
+        self.max_registers = Some(registers);
+        self
+    }
+
+    /// Minimum number of threads per block to target (`CU_JIT_THREADS_PER_BLOCK`). Cannot be combined with `target`.
+    pub fn threads_per_block(mut self, threads: u32) -> Self {
+        self.threads_per_block = Some(threads);
+        self
+    }
+
+    /// Optimization level in `0..=4` (`CU_JIT_OPTIMIZATION_LEVEL`)
+    pub fn optimization_level(mut self, level: u32) -> Self {
+        self.optimization_level = Some(level);
+        self
+    }
+
+    /// Target architecture, e.g. `70` for `compute_70` (`CU_JIT_TARGET`). Cannot be combined with `threads_per_block`.
+    pub fn target(mut self, arch: u32) -> Self {
+        self.target = Some(arch);
+        self
+    }
+
+    /// Fallback strategy (`CU_JIT_FALLBACK_STRATEGY`). Cannot be used with [Linker](struct.Linker.html).
+    pub fn fallback(mut self, fallback: JitFallback) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+
+    /// Caching mode of global memory loads (`CU_JIT_CACHE_MODE`)
+    pub fn cache_mode(mut self, mode: JitCacheMode) -> Self {
+        self.cache_mode = Some(mode);
+        self
+    }
+
+    /// Generate debug information, `-g` (`CU_JIT_GENERATE_DEBUG_INFO`)
+    pub fn debug_info(mut self, enabled: bool) -> Self {
+        self.debug_info = Some(enabled);
+        self
+    }
+
+    /// Generate line number information, `-lineinfo` (`CU_JIT_GENERATE_LINE_INFO`)
+    pub fn line_info(mut self, enabled: bool) -> Self {
+        self.line_info = Some(enabled);
+        self
+    }
+
+    /// Generate verbose log messages (`CU_JIT_LOG_VERBOSE`)
+    pub fn log_verbose(mut self, enabled: bool) -> Self {
+        self.log_verbose = Some(enabled);
+        self
+    }
+
+    /// `CU_JIT_FAST_COMPILE`
+    pub fn fast_compile(mut self, enabled: bool) -> Self {
+        self.fast_compile = Some(enabled);
+        self
+    }
+
+    /// Collect informational messages into a [LogBuffer](struct.LogBuffer.html) of `size` bytes
+    pub fn info_log_size(mut self, size: usize) -> Self {
+        self.info_log_size = Some(size);
+        self
+    }
+
+    /// Collect error messages into a [LogBuffer](struct.LogBuffer.html) of `size` bytes
+    pub fn error_log_size(mut self, size: usize) -> Self {
+        self.error_log_size = Some(size);
+        self
+    }
+
+    /// Parse options from TOML, e.g. `optimization_level = 3`
+    pub fn from_toml(s: &str) -> Result<Self> {
+        toml::from_str(s).or_else(|e| invalid(format!("Invalid TOML: {}", e)))
+    }
+
+    /// Overwrite options set in `other`
+    pub fn merge(mut self, other: JITConfigBuilder) -> Self {
+        macro_rules! merge {
+            ($($field:ident),*) => {
+                $(
+                    if other.$field.is_some() {
+                        self.$field = other.$field;
+                    }
+                )*
+            };
+        }
+        merge!(
+            max_registers,
+            threads_per_block,
+            optimization_level,
+            target,
+            fallback,
+            cache_mode,
+            debug_info,
+            line_info,
+            log_verbose,
+            fast_compile,
+            info_log_size,
+            error_log_size
+        );
+        self
+    }
+
+    /// Overwrite options by environment variables
+    ///
+    /// Options in the TOML file of `ACCEL_JIT_CONFIG` are applied first,
+    /// and then the following variables:
+    ///
+    /// |name                           | value                                  |
+    /// |:------------------------------|:---------------------------------------|
+    /// |`ACCEL_JIT_MAX_REGISTERS`      | integer                                |
+    /// |`ACCEL_JIT_THREADS_PER_BLOCK`  | integer                                |
+    /// |`ACCEL_JIT_OPTIMIZATION_LEVEL` | integer in `0..=4`                     |
+    /// |`ACCEL_JIT_TARGET`             | architecture, e.g. `70`                |
+    /// |`ACCEL_JIT_FALLBACK`           | `prefer_ptx` or `prefer_binary`        |
+    /// |`ACCEL_JIT_DLCM`               | cache mode, `none`, `cg` or `ca`       |
+    /// |`ACCEL_JIT_DEBUG_INFO`         | `1`/`0` or `true`/`false`              |
+    /// |`ACCEL_JIT_LINE_INFO`          | `1`/`0` or `true`/`false`              |
+    /// |`ACCEL_JIT_LOG_VERBOSE`        | `1`/`0` or `true`/`false`              |
+    /// |`ACCEL_JIT_FAST_COMPILE`       | `1`/`0` or `true`/`false`              |
+    /// |`ACCEL_JIT_INFO_LOG_SIZE`      | size of the info log buffer in bytes   |
+    /// |`ACCEL_JIT_ERROR_LOG_SIZE`     | size of the error log buffer in bytes  |
+    ///
+    /// These are also applied to [Module::load](../module/struct.Module.html#method.load)
+    /// and the kernels generated by `#[kernel]`, e.g. to turn on line info in production without recompiling.
+    pub fn env_overrides(self) -> Result<Self> {
+        self.overrides(|name| env::var(name).ok())
+    }
+
+    /// Overwrite options by variables given by `var`
+    fn overrides(mut self, var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        if let Some(path) = var("ACCEL_JIT_CONFIG") {
+            let file = fs::read_to_string(&path).map_err(|_| AccelError::FileNotFound {
+                path: path.clone().into(),
+            })?;
+            self = self.merge(Self::from_toml(&file)?);
+        }
+        macro_rules! apply {
+            ($name:expr, $field:ident, $parse:ident) => {
+                if let Some(value) = var($name) {
+                    self.$field = Some($parse($name, &value)?);
+                }
+            };
+        }
+        apply!("ACCEL_JIT_MAX_REGISTERS", max_registers, parse_var);
+        apply!("ACCEL_JIT_THREADS_PER_BLOCK", threads_per_block, parse_var);
+        apply!(
+            "ACCEL_JIT_OPTIMIZATION_LEVEL",
+            optimization_level,
+            parse_var
+        );
+        apply!("ACCEL_JIT_TARGET", target, parse_var);
+        apply!("ACCEL_JIT_FALLBACK", fallback, parse_enum);
+        apply!("ACCEL_JIT_DLCM", cache_mode, parse_enum);
+        apply!("ACCEL_JIT_DEBUG_INFO", debug_info, parse_flag);
+        apply!("ACCEL_JIT_LINE_INFO", line_info, parse_flag);
+        apply!("ACCEL_JIT_LOG_VERBOSE", log_verbose, parse_flag);
+        apply!("ACCEL_JIT_FAST_COMPILE", fast_compile, parse_flag);
+        apply!("ACCEL_JIT_INFO_LOG_SIZE", info_log_size, parse_var);
+        apply!("ACCEL_JIT_ERROR_LOG_SIZE", error_log_size, parse_var);
+        Ok(self)
+    }
+
+    /// Validate options, and build [JITConfig](struct.JITConfig.html)
+    pub fn build(self) -> Result<JITConfig> {
+        let target = match self.target {
+            Some(arch) => Some(
+                jit_target(arch)
+                    .map_or_else(|| invalid(format!("Unknown target: compute_{}", arch)), Ok)?,
+            ),
+            None => None,
+        };
+        let log_buffer = |name: &str, size: Option<usize>| match size {
+            Some(0) => invalid(format!("{} must be positive", name)),
+            Some(size) => Ok(Some(LogBuffer::new(size))),
+            None => Ok(None),
+        };
+        let cfg = JITConfig {
+            max_registers: self.max_registers,
+            threads_per_block: self.threads_per_block,
+            optimization_level: self.optimization_level,
+            target,
+            fallback_strategy: self.fallback.map(Into::into),
+            cache_mode: self.cache_mode.map(Into::into),
+            generate_debug_info: self.debug_info.unwrap_or(false),
+            generate_line_info: self.line_info.unwrap_or(false),
+            log_verbose: self.log_verbose.unwrap_or(false),
+            fast_compile: self.fast_compile.unwrap_or(false),
+            info_log_buffer: log_buffer("info_log_size", self.info_log_size)?,
+            error_log_buffer: log_buffer("error_log_size", self.error_log_size)?,
+            ..Default::default()
+        };
+        cfg.validate()?;
+        Ok(cfg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn build() -> Result<()> {
+        let cfg = JITConfig::builder()
+            .max_registers(32)
+            .target(70)
+            .fallback(JitFallback::PreferBinary)
+            .cache_mode(JitCacheMode::Cg)
+            .debug_info(true)
+            .error_log_size(1024)
+            .build()?;
+        assert_eq!(cfg.max_registers, Some(32));
+        assert_eq!(cfg.target, Some(CUjit_target::CU_TARGET_COMPUTE_70));
+        assert_eq!(
+            cfg.fallback_strategy,
+            Some(CUjit_fallback::CU_PREFER_BINARY)
+        );
+        assert_eq!(
+            cfg.cache_mode,
+            Some(CUjit_cacheMode_enum::CU_JIT_CACHE_OPTION_CG)
+        );
+        assert!(cfg.generate_debug_info);
+        assert!(!cfg.generate_line_info);
+        assert_eq!(cfg.error_log_buffer.as_ref().unwrap().size(), 1024);
+        assert!(cfg.info_log_buffer.is_none());
+        assert!(cfg.validate_for_linker().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_options() {
+        let is_invalid = |builder: JITConfigBuilder| {
+            matches!(builder.build(), Err(AccelError::InvalidJitConfig { .. }))
+        };
+        assert!(is_invalid(
+            JITConfig::builder().threads_per_block(128).target(70)
+        ));
+        assert!(is_invalid(JITConfig::builder().threads_per_block(0)));
+        assert!(is_invalid(JITConfig::builder().threads_per_block(2048)));
+        assert!(is_invalid(JITConfig::builder().optimization_level(5)));
+        assert!(is_invalid(JITConfig::builder().max_registers(256)));
+        assert!(is_invalid(JITConfig::builder().target(71)));
+        assert!(is_invalid(JITConfig::builder().info_log_size(0)));
+    }
+
+    #[test]
+    fn linker_rejects_fallback() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let cfg = JITConfig::builder()
+            .fallback(JitFallback::PreferPtx)
+            .build()?;
+        assert!(matches!(
+            Linker::create(&ctx, cfg),
+            Err(AccelError::InvalidJitConfig { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn serde() -> Result<()> {
+        let builder = JITConfigBuilder::from_toml(
+            r#"
+            optimization_level = 3
+            line_info = true
+            fallback = "prefer_ptx"
+            cache_mode = "ca"
+            "#,
+        )?;
+        assert_eq!(
+            builder,
+            JITConfig::builder()
+                .optimization_level(3)
+                .line_info(true)
+                .fallback(JitFallback::PreferPtx)
+                .cache_mode(JitCacheMode::Ca)
+        );
+        let s = toml::to_string(&builder).unwrap();
+        assert_eq!(JITConfigBuilder::from_toml(&s)?, builder);
+        assert!(JITConfigBuilder::from_toml("optimisation_level = 3").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn overrides() -> Result<()> {
+        let path = env::temp_dir().join(format!("accel-jit-config-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "optimization_level = 1\nline_info = false\nmax_registers = 64\n",
+        )
+        .unwrap();
+        let vars: HashMap<&str, String> = vec![
+            ("ACCEL_JIT_CONFIG", path.display().to_string()),
+            ("ACCEL_JIT_OPTIMIZATION_LEVEL", "2".to_string()),
+            ("ACCEL_JIT_LINE_INFO", "1".to_string()),
+            ("ACCEL_JIT_DLCM", "cg".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let builder = JITConfig::builder()
+            .optimization_level(4)
+            .max_registers(32)
+            .threads_per_block(128)
+            .overrides(|name| vars.get(name).cloned())?;
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            builder,
+            JITConfig::builder()
+                .optimization_level(2)
+                .max_registers(64)
+                .threads_per_block(128)
+                .line_info(true)
+                .cache_mode(JitCacheMode::Cg)
+        );
+
+        let invalid = |name: &'static str, value: &'static str| {
+            JITConfig::builder()
+                .overrides(|n| if n == name { Some(value.into()) } else { None })
+                .is_err()
+        };
+        assert!(invalid("ACCEL_JIT_OPTIMIZATION_LEVEL", "high"));
+        assert!(invalid("ACCEL_JIT_LINE_INFO", "yes"));
+        assert!(invalid("ACCEL_JIT_FALLBACK", "binary"));
+        assert!(invalid("ACCEL_JIT_CONFIG", "/not/found.toml"));
+        Ok(())
+    }
+}
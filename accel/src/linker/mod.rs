@@ -0,0 +1,939 @@
+//! CUDA JIT compiler and Linkers
+
+use crate::{contexted_call, device::*, error::*, module::*, ptx::*, *};
+use cuda::*;
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::CString,
+    os::raw::{c_char, c_void},
+    path::Path,
+};
+
+mod builder;
+pub use builder::*;
+
+/// Upper bound of `CU_JIT_MAX_REGISTERS`
+const MAX_REGISTERS: u32 = 255;
+/// Upper bound of `CU_JIT_THREADS_PER_BLOCK`
+const MAX_THREADS_PER_BLOCK: u32 = 1024;
+/// Upper bound of `CU_JIT_OPTIMIZATION_LEVEL`
+const MAX_OPTIMIZATION_LEVEL: u32 = 4;
+
+/// Default size of [LogBuffer] in bytes
+///
+/// [LogBuffer]: struct.LogBuffer.html
+pub const DEFAULT_LOG_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Owned buffer receiving log messages of the JIT compiler and linker
+///
+/// The driver writes a NUL-terminated message into the buffer on each `cuLink*` or `cuModuleLoadDataEx` call.
+/// It is decoded after the call, and accumulated into [log](#method.log).
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogBuffer {
+    buffer: Vec<u8>,
+    log: String,
+}
+
+impl Default for LogBuffer {
+    fn default() -> Self {
+        LogBuffer::new(DEFAULT_LOG_BUFFER_SIZE)
+    }
+}
+
+impl LogBuffer {
+    /// Buffer of `size` bytes. Messages longer than `size - 1` bytes are truncated by the driver.
+    ///
+    /// Panic
+    /// -----
+    /// - If `size` is zero
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0, "LogBuffer must have space for NUL terminator");
+        LogBuffer {
+            buffer: vec![0; size],
+            log: String::new(),
+        }
+    }
+
+    /// Size of the buffer in bytes
+    pub fn size(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Messages decoded so far
+    pub fn log(&self) -> &str {
+        &self.log
+    }
+
+    /// Decode the message written by the driver and clear the buffer, returns the new message
+    fn collect(&mut self) -> String {
+        let len = self
+            .buffer
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(self.buffer.len());
+        let message = String::from_utf8_lossy(&self.buffer[..len])
+            .trim_end()
+            .to_string();
+        self.buffer[0] = 0;
+        if !message.is_empty() {
+            if !self.log.is_empty() {
+                self.log.push('\n');
+            }
+            self.log.push_str(&message);
+        }
+        message
+    }
+}
+
+/// Configure generator for [CUjit_option] required in `cuLink*` APIs
+///
+/// [CUjit_option]: https://docs.nvidia.com/cuda/cuda-driver-api/group__CUDA__TYPES.html#group__CUDA__TYPES_1g5527fa8030d5cabedc781a04dbd1997d
+#[derive(Debug, Clone, Default)]
+pub struct JITConfig {
+    /// CU_JIT_MAX_REGISTERS, Applies to compiler only
+    ///
+    /// - Max number of registers that a thread may use.
+    pub max_registers: Option<u32>,
+
+    /// CU_JIT_THREADS_PER_BLOCK, Applies to compiler only
+    ///
+    /// - **IN**: Specifies minimum number of threads per block to target compilation for
+    /// - **OUT**: Returns the number of threads the compiler actually targeted.
+    ///   This restricts the resource utilization fo the compiler (e.g. max registers) such that a block with the given number of threads should be able to launch based on register limitations.
+    ///
+    /// Note
+    /// ----
+    /// This option does not currently take into account any other resource limitations, such as shared memory utilization. Cannot be combined with CU_JIT_TARGET.
+    pub threads_per_block: Option<u32>,
+
+    /// CU_JIT_WALL_TIME, Applies to compiler and linker
+    ///
+    /// - Overwrites the option value with the total wall clock time, in milliseconds, spent in the compiler and linker
+    /// - Option type: float
+    ///
+    /// Always requested, and overwritten by the time of the last call of the JIT compiler or linker.
+    pub wall_time: Option<f32>,
+
+    /// CU_JIT_INFO_LOG_BUFFER, Applies to compiler and linker
+    ///
+    /// - Pointer to a buffer in which to print any log messages that are informational in nature (the buffer size is specified via option CU_JIT_INFO_LOG_BUFFER_SIZE_BYTES)
+    ///
+    /// CU_JIT_INFO_LOG_BUFFER_SIZE_BYTES, Applies to compiler and linker
+    ///
+    /// - **IN**: Log buffer size in bytes. Log messages will be capped at this size (including null terminator)
+    /// - **OUT**: Amount of log buffer filled with messages
+    pub info_log_buffer: Option<LogBuffer>,
+
+    /// CU_JIT_ERROR_LOG_BUFFER, Applies to compiler and linker
+    ///
+    /// - Pointer to a buffer in which to print any log messages that reflect errors (the buffer size is specified via option CU_JIT_ERROR_LOG_BUFFER_SIZE_BYTES)
+    ///
+    /// CU_JIT_ERROR_LOG_BUFFER_SIZE_BYTES, Applies to compiler and linker
+    ///
+    /// - **IN**: Log buffer size in bytes. Log messages will be capped at this size (including null terminator)
+    /// - **OUT**: Amount of log buffer filled with messages
+    pub error_log_buffer: Option<LogBuffer>,
+
+    /// CU_JIT_OPTIMIZATION_LEVEL, Applies to compiler only
+    ///
+    /// - Level of optimizations to apply to generated code (0 - 4), with 4 being the default and highest level of optimizations.
+    pub optimization_level: Option<u32>,
+
+    /// CU_JIT_TARGET_FROM_CUCONTEXT, Applies to compiler and linker
+    ///
+    /// - No option value required. Determines the target based on the current attached context (default)
+    pub target_from_cucontext: Option<()>,
+
+    /// CU_JIT_TARGET, Applies to compiler and linker
+    ///
+    /// - Target is chosen based on supplied CUjit_target. Cannot be combined with CU_JIT_THREADS_PER_BLOCK.
+    pub target: Option<CUjit_target>,
+
+    /// CU_JIT_FALLBACK_STRATEGY, Applies to compiler only
+    ///
+    /// - Specifies choice of fallback strategy if matching cubin is not found. Choice is based on supplied CUjit_fallback.
+    ///   This option cannot be used with cuLink* APIs as the linker requires exact matches.
+    pub fallback_strategy: Option<CUjit_fallback>,
+
+    /// CU_JIT_GENERATE_DEBUG_INFO, Applies to compiler and linker
+    ///
+    /// - Specifies whether to create debug information in output (-g) (false, default)
+    pub generate_debug_info: bool,
+
+    /// CU_JIT_LOG_VERBOSE, Applies to compiler and linker
+    ///
+    /// - Generate verbose log messages (false, default)
+    pub log_verbose: bool,
+
+    /// CU_JIT_GENERATE_LINE_INFO, Applies to compiler only
+    ///
+    /// - Generate line number information (-lineinfo) (false, default)
+    pub generate_line_info: bool,
+
+    /// CU_JIT_CACHE_MODE, Applies to compiler only
+    ///
+    /// - Specifies whether to enable caching explicitly (-dlcm) Choice is based on supplied CUjit_cacheMode_enum.
+    pub cache_mode: Option<CUjit_cacheMode_enum>,
+
+    /// CU_JIT_NEW_SM3X_OPT
+    ///
+    /// - The below jit options are used for internal purposes only, in this version of CUDA
+    pub new_sm3x_opt: Option<u32>,
+
+    /// CU_JIT_FAST_COMPILE
+    pub fast_compile: bool,
+
+    /// CU_JIT_GLOBAL_SYMBOL_NAMES, Applies to dynamic linker only
+    ///
+    /// - Array of device symbol names that will be relocated to the corresponing host addresses stored in CU_JIT_GLOBAL_SYMBOL_ADDRESSES.
+    ///   Must contain CU_JIT_GLOBAL_SYMBOL_COUNT entries. When loding a device module, driver will relocate all encountered unresolved symbols to the host addresses.
+    ///   It is only allowed to register symbols that correspond to unresolved global variables. It is illegal to register the same device symbol at multiple addresses.
+    ///
+    /// CU_JIT_GLOBAL_SYMBOL_ADDRESSES, Applies to dynamic linker only
+    ///
+    /// - Array of host addresses that will be used to relocate corresponding device symbols stored in CU_JIT_GLOBAL_SYMBOL_NAMES.
+    ///   Must contain CU_JIT_GLOBAL_SYMBOL_COUNT entries.
+    ///
+    /// CU_JIT_GLOBAL_SYMBOL_COUNT, Applies to dynamic linker only
+    ///
+    /// - Number of entries in CU_JIT_GLOBAL_SYMBOL_NAMES and CU_JIT_GLOBAL_SYMBOL_ADDRESSES arrays.
+    pub global_symbol: HashMap<CString, *mut c_void>,
+
+    /// Size in bytes of the host value of each symbol bound by [JITConfig::bind_global]
+    ///
+    /// It is checked against the size of `.extern .global` variable in PTX.
+    /// Symbols inserted into `global_symbol` directly are not checked.
+    pub global_symbol_size: HashMap<CString, usize>,
+}
+
+/// JIT options packed by `JITConfig::pack`, owning the arrays referred by option values
+pub(crate) struct JitOptions {
+    keys: Vec<CUjit_option>,
+    values: Vec<*mut c_void>,
+    /// Arrays of `CU_JIT_GLOBAL_SYMBOL_NAMES` and `CU_JIT_GLOBAL_SYMBOL_ADDRESSES`
+    _symbol_names: Vec<*const c_char>,
+    _symbol_addresses: Vec<*mut c_void>,
+}
+
+impl JitOptions {
+    pub(crate) fn count(&self) -> u32 {
+        self.keys.len() as u32
+    }
+
+    pub(crate) fn keys(&mut self) -> *mut CUjit_option {
+        self.keys.as_mut_ptr()
+    }
+
+    pub(crate) fn values(&mut self) -> *mut *mut c_void {
+        self.values.as_mut_ptr()
+    }
+
+    /// Option value of `key`, which may be overwritten by the driver
+    fn value(&self, key: CUjit_option) -> Option<&*mut c_void> {
+        let i = self.keys.iter().position(|k| *k == key)?;
+        Some(&self.values[i])
+    }
+
+    /// Drop options for the dynamic linker, which are given once to `cuLinkCreate`
+    fn without_global_symbols(mut self) -> Self {
+        let (keys, values) = self
+            .keys
+            .iter()
+            .zip(self.values.iter())
+            .filter(|(key, _)| {
+                !matches!(
+                    key,
+                    CUjit_option::CU_JIT_GLOBAL_SYMBOL_NAMES
+                        | CUjit_option::CU_JIT_GLOBAL_SYMBOL_ADDRESSES
+                        | CUjit_option::CU_JIT_GLOBAL_SYMBOL_COUNT
+                )
+            })
+            .unzip();
+        self.keys = keys;
+        self.values = values;
+        self
+    }
+}
+
+impl JITConfig {
+    /// Relocate an unresolved `.extern .global` variable `name` in PTX to `value` on the host
+    ///
+    /// The device accesses `value` at its host address,
+    /// i.e. it must be accessible from the device, e.g. registered as page-locked memory.
+    /// Names and sizes are checked against the PTX when loaded by [Module::load_with] or linked by [Linker],
+    /// i.e. `size_of::<T>()` must be equal to the size of the `.extern .global` variable.
+    ///
+    /// ```
+    /// # use accel::{*, error::AccelError};
+    /// static TABLE: [u32; 4] = [1, 2, 4, 8];
+    /// let mut cfg = JITConfig::default();
+    /// cfg.bind_global("table", &TABLE).unwrap();
+    /// assert!(matches!(
+    ///     cfg.bind_global("table", &TABLE),
+    ///     Err(AccelError::DuplicateGlobalSymbol { .. })
+    /// ));
+    /// ```
+    ///
+    /// [Module::load_with]: ../module/struct.Module.html#method.load_with
+    /// [Linker]: struct.Linker.html
+    pub fn bind_global<T>(&mut self, name: &str, value: &'static T) -> Result<&mut Self> {
+        let symbol = CString::new(name).expect("Invalid symbol name");
+        if self.global_symbol.contains_key(&symbol) {
+            return Err(AccelError::DuplicateGlobalSymbol { name: name.into() });
+        }
+        let size = std::mem::size_of::<T>();
+        self.global_symbol_size.insert(symbol.clone(), size);
+        self.global_symbol
+            .insert(symbol, value as *const T as *mut c_void);
+        Ok(self)
+    }
+
+    /// Check bound symbols against unresolved `.extern .global` variables in PTX modules linked together
+    pub(crate) fn check_global_symbols(&self, modules: &[PtxModule]) -> Result<()> {
+        let globals = || {
+            modules
+                .iter()
+                .flat_map(|m| m.variables.iter())
+                .filter(|v| v.space == StateSpace::Global)
+        };
+        let defined: HashSet<&str> = globals()
+            .filter(|v| v.linkage != Linkage::Extern)
+            .map(|v| v.name.as_str())
+            .collect();
+        let unresolved: HashMap<&str, Option<usize>> = globals()
+            .filter(|v| v.linkage == Linkage::Extern && !defined.contains(v.name.as_str()))
+            .map(|v| (v.name.as_str(), v.size()))
+            .collect();
+        let mut symbols: Vec<_> = self.global_symbol.keys().collect();
+        symbols.sort();
+        for symbol in symbols {
+            let name = symbol.to_string_lossy().into_owned();
+            let size = match unresolved.get(name.as_str()) {
+                Some(size) => *size,
+                None => return Err(AccelError::UnknownGlobalSymbol { name }),
+            };
+            if let (Some(size), Some(&elem_size)) = (size, self.global_symbol_size.get(symbol)) {
+                if size != elem_size {
+                    return Err(AccelError::SymbolSizeMismatch {
+                        name,
+                        size,
+                        elem_size,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Pack configure into C API compatible format
+    pub(crate) fn pack(&mut self) -> JitOptions {
+        let mut opt_keys = Vec::new();
+        let mut opt_values = Vec::new();
+
+        // Integer and enum options are passed as the option value itself, not a pointer to it.
+        // OUT values are also written into the option values.
+        macro_rules! check_option {
+            ( $tag:ident, $opt_name:ident) => {
+                if let Some(value) = self.$opt_name {
+                    opt_keys.push(CUjit_option::$tag);
+                    opt_values.push(value as usize as *mut c_void);
+                }
+            };
+        }
+        macro_rules! check_flag {
+            ( $tag:ident, $opt_name:ident) => {
+                if self.$opt_name {
+                    opt_keys.push(CUjit_option::$tag);
+                    opt_values.push(self.$opt_name as usize as *mut c_void);
+                }
+            };
+        }
+        check_option!(CU_JIT_MAX_REGISTERS, max_registers);
+        check_option!(CU_JIT_THREADS_PER_BLOCK, threads_per_block);
+        check_option!(CU_JIT_OPTIMIZATION_LEVEL, optimization_level);
+        check_option!(CU_JIT_TARGET, target);
+        check_option!(CU_JIT_FALLBACK_STRATEGY, fallback_strategy);
+        check_option!(CU_JIT_CACHE_MODE, cache_mode);
+        check_option!(CU_JIT_NEW_SM3X_OPT, new_sm3x_opt);
+        check_flag!(CU_JIT_GENERATE_DEBUG_INFO, generate_debug_info);
+        check_flag!(CU_JIT_LOG_VERBOSE, log_verbose);
+        check_flag!(CU_JIT_GENERATE_LINE_INFO, generate_line_info);
+        check_flag!(CU_JIT_FAST_COMPILE, fast_compile);
+
+        opt_keys.push(CUjit_option::CU_JIT_WALL_TIME);
+        opt_values.push(std::ptr::null_mut());
+
+        // The size is passed as the option value itself, and overwritten by the filled size
+        if let Some(buffer) = self.info_log_buffer.as_mut() {
+            opt_keys.push(CUjit_option::CU_JIT_INFO_LOG_BUFFER);
+            opt_values.push(buffer.buffer.as_mut_ptr() as *mut c_void);
+            opt_keys.push(CUjit_option::CU_JIT_INFO_LOG_BUFFER_SIZE_BYTES);
+            opt_values.push(buffer.size() as *mut c_void);
+        }
+
+        if let Some(buffer) = self.error_log_buffer.as_mut() {
+            opt_keys.push(CUjit_option::CU_JIT_ERROR_LOG_BUFFER);
+            opt_values.push(buffer.buffer.as_mut_ptr() as *mut c_void);
+            opt_keys.push(CUjit_option::CU_JIT_ERROR_LOG_BUFFER_SIZE_BYTES);
+            opt_values.push(buffer.size() as *mut c_void);
+        }
+
+        let mut symbol_names = Vec::new();
+        let mut symbol_addresses = Vec::new();
+        if !self.global_symbol.is_empty() {
+            for (name, address) in &self.global_symbol {
+                symbol_names.push(name.as_ptr());
+                symbol_addresses.push(*address);
+            }
+            opt_keys.push(CUjit_option::CU_JIT_GLOBAL_SYMBOL_NAMES);
+            opt_values.push(symbol_names.as_mut_ptr() as *mut c_void);
+            opt_keys.push(CUjit_option::CU_JIT_GLOBAL_SYMBOL_ADDRESSES);
+            opt_values.push(symbol_addresses.as_mut_ptr() as *mut c_void);
+            opt_keys.push(CUjit_option::CU_JIT_GLOBAL_SYMBOL_COUNT);
+            opt_values.push(symbol_names.len() as *mut c_void);
+        }
+        assert_eq!(opt_keys.len(), opt_values.len());
+        JitOptions {
+            keys: opt_keys,
+            values: opt_values,
+            _symbol_names: symbol_names,
+            _symbol_addresses: symbol_addresses,
+        }
+    }
+
+    /// Check values and combinations of options
+    ///
+    /// ```
+    /// # use accel::*;
+    /// let cfg = JITConfig {
+    ///     threads_per_block: Some(128),
+    ///     target: Some(cuda_driver_sys::CUjit_target::CU_TARGET_COMPUTE_70),
+    ///     ..Default::default()
+    /// };
+    /// assert!(cfg.validate().is_err());
+    /// ```
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: &str| {
+            Err(AccelError::InvalidJitConfig {
+                message: message.into(),
+            })
+        };
+        if self.threads_per_block.is_some() && self.target.is_some() {
+            return invalid("threads_per_block cannot be combined with target");
+        }
+        if let Some(threads) = self.threads_per_block {
+            if threads == 0 || threads > MAX_THREADS_PER_BLOCK {
+                return invalid(&format!(
+                    "threads_per_block must be in 1..={}, but {}",
+                    MAX_THREADS_PER_BLOCK, threads
+                ));
+            }
+        }
+        if let Some(level) = self.optimization_level {
+            if level > MAX_OPTIMIZATION_LEVEL {
+                return invalid(&format!(
+                    "optimization_level must be in 0..={}, but {}",
+                    MAX_OPTIMIZATION_LEVEL, level
+                ));
+            }
+        }
+        if let Some(registers) = self.max_registers {
+            if registers == 0 || registers > MAX_REGISTERS {
+                return invalid(&format!(
+                    "max_registers must be in 1..={}, but {}",
+                    MAX_REGISTERS, registers
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check options for `cuLink*` APIs, which reject `CU_JIT_FALLBACK_STRATEGY`
+    pub(crate) fn validate_for_linker(&self) -> Result<()> {
+        self.validate()?;
+        if self.fallback_strategy.is_some() {
+            return Err(AccelError::InvalidJitConfig {
+                message: "fallback_strategy cannot be used with the linker".into(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Configuration with the error log and `ACCEL_JIT_*` overrides, used when no configuration is given
+    ///
+    /// Invalid overrides are logged and ignored.
+    pub(crate) fn error_log() -> Self {
+        JITConfig::builder()
+            .error_log_size(DEFAULT_LOG_BUFFER_SIZE)
+            .env_overrides()
+            .and_then(|builder| builder.build())
+            .unwrap_or_else(|e| {
+                log::warn!("ACCEL_JIT_* overrides are ignored: {}", e);
+                JITConfig {
+                    error_log_buffer: Some(LogBuffer::default()),
+                    ..Default::default()
+                }
+            })
+    }
+
+    /// Read OUT values from `opts` and decode log buffers after a JIT call,
+    /// and convert its failure into `AccelError::JitFailed`
+    pub(crate) fn collect_outputs<T>(&mut self, opts: &JitOptions, result: Result<T>) -> Result<T> {
+        if let Some(value) = opts.value(CUjit_option::CU_JIT_WALL_TIME) {
+            self.wall_time = Some(unsafe { *(value as *const *mut c_void as *const f32) });
+        }
+        if let Some(value) = opts.value(CUjit_option::CU_JIT_THREADS_PER_BLOCK) {
+            self.threads_per_block = Some(*value as usize as u32);
+        }
+        if let Some(buffer) = self.info_log_buffer.as_mut() {
+            buffer.collect();
+        }
+        let log = match self.error_log_buffer.as_mut() {
+            Some(buffer) => buffer.collect(),
+            None => String::new(),
+        };
+        match result {
+            Err(AccelError::CUDAError { api_name, error }) if !log.is_empty() => {
+                Err(AccelError::JitFailed {
+                    api_name,
+                    error,
+                    log,
+                })
+            }
+            _ => result,
+        }
+    }
+
+    /// Lengths of the logs collected so far, to report only the messages after it by [report](#method.report)
+    pub(crate) fn log_marks(&self) -> (usize, usize) {
+        let len = |buffer: &Option<LogBuffer>| buffer.as_ref().map_or(0, |b| b.log().len());
+        (len(&self.info_log_buffer), len(&self.error_log_buffer))
+    }
+
+    /// Report of the JIT compiler and linker with the messages collected after `marks`
+    pub(crate) fn report(&self, marks: (usize, usize)) -> JitReport {
+        let since = |buffer: &Option<LogBuffer>, mark: usize| {
+            buffer.as_ref().map_or(String::new(), |b| {
+                b.log()[mark..].trim_start_matches('\n').to_string()
+            })
+        };
+        JitReport {
+            wall_time: self.wall_time.unwrap_or(0.0),
+            threads_per_block: self.threads_per_block,
+            info_log: since(&self.info_log_buffer, marks.0),
+            error_log: since(&self.error_log_buffer, marks.1),
+        }
+    }
+}
+
+/// OUT values and messages of the JIT compiler and linker
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct JitReport {
+    /// Wall clock time spent in the JIT compiler and linker in milliseconds
+    pub wall_time: f32,
+    /// Number of threads per block the compiler actually targeted, if `JITConfig::threads_per_block` is given
+    pub threads_per_block: Option<u32>,
+    /// Informational messages, empty if the info log buffer is not given
+    pub info_log: String,
+    /// Error messages
+    pub error_log: String,
+}
+
+/// Output of [Linker::complete](struct.Linker.html#method.complete)
+#[derive(Debug, Clone)]
+pub struct LinkOutput {
+    /// Linked cubin
+    pub cubin: Vec<u8>,
+    pub report: JitReport,
+}
+
+impl LinkOutput {
+    /// Take the cubin as `Instruction::Cubin`
+    pub fn into_instruction(self) -> Instruction {
+        Instruction::Cubin(self.cubin)
+    }
+}
+
+/// Consuming builder for cubin from PTX and cubins
+///
+/// Messages of the JIT compiler and linker are collected into the log buffers of [JITConfig].
+/// An error log buffer is added if not configured,
+/// and failures of the JIT compiler and linker are reported as `AccelError::JitFailed` with its contents.
+///
+/// [JITConfig]: struct.JITConfig.html
+#[derive(accel_derive::Contexted)]
+pub struct Linker {
+    state: CUlinkState,
+    cfg: JITConfig,
+    /// Options given to `cuLinkCreate`, which must be alive while the state is used.
+    /// OUT values of `cuLinkComplete` are written into it.
+    opts: JitOptions,
+    /// PTX inputs to check global symbols. `None` if an input is not a PTX
+    ptx_inputs: Option<Vec<PtxModule>>,
+    /// Number of inputs added so far, used to name in-memory inputs
+    num_inputs: usize,
+    /// Total wall time of the JIT compiler and linker in milliseconds
+    wall_time: f32,
+    ctx: Context,
+}
+
+impl Drop for Linker {
+    fn drop(&mut self) {
+        if let Err(e) = unsafe { contexted_call!(self, link_destroy, self.state) } {
+            log::error!("Failed to release Linker: {:?}", e)
+        }
+    }
+}
+
+impl Linker {
+    /// Create a new Linker
+    ///
+    /// `cfg` is validated by [JITConfig::validate](struct.JITConfig.html#method.validate),
+    /// and `fallback_strategy` is rejected since the linker requires exact matches.
+    pub fn create(ctx: &Context, mut cfg: JITConfig) -> Result<Self> {
+        cfg.validate_for_linker()?;
+        if cfg.error_log_buffer.is_none() {
+            cfg.error_log_buffer = Some(LogBuffer::default());
+        }
+        let mut opts = cfg.pack();
+        let state =
+            unsafe { contexted_call!(ctx, link_create, opts.count(), opts.keys(), opts.values()) };
+        let state = cfg.collect_outputs(&opts, state)?;
+        Ok(Linker {
+            state,
+            wall_time: cfg.wall_time.unwrap_or(0.0),
+            cfg,
+            opts,
+            ptx_inputs: Some(Vec::new()),
+            num_inputs: 0,
+            ctx: ctx.clone(),
+        })
+    }
+
+    /// Informational messages of the JIT compiler and linker collected so far
+    pub fn info_log(&self) -> &str {
+        self.cfg
+            .info_log_buffer
+            .as_ref()
+            .map_or("", |buffer| buffer.log())
+    }
+
+    /// Error messages of the JIT compiler and linker collected so far
+    pub fn error_log(&self) -> &str {
+        self.cfg
+            .error_log_buffer
+            .as_ref()
+            .map_or("", |buffer| buffer.log())
+    }
+
+    /// Wrapper of cuLinkAddData
+    ///
+    /// `name` appears in the messages of the linker
+    unsafe fn add_data(
+        mut self,
+        input_type: CUjitInputType,
+        data: &[u8],
+        name: &str,
+    ) -> Result<Self> {
+        let mut opts = self.cfg.pack().without_global_symbols();
+        let name = CString::new(name).expect("Invalid input name");
+        let result = contexted_call!(
+            &self,
+            link_add_data,
+            self.state,
+            input_type,
+            data,
+            &name,
+            opts.count(),
+            opts.keys(),
+            opts.values()
+        );
+        self.cfg.collect_outputs(&opts, result)?;
+        self.wall_time += self.cfg.wall_time.unwrap_or(0.0);
+        Ok(self)
+    }
+
+    /// Wrapper of cuLinkAddFile
+    unsafe fn add_file(mut self, input_type: CUjitInputType, path: &Path) -> Result<Self> {
+        let mut opts = self.cfg.pack().without_global_symbols();
+        let result = contexted_call!(
+            &self,
+            link_add_file,
+            self.state,
+            input_type,
+            path,
+            opts.count(),
+            opts.keys(),
+            opts.values()
+        );
+        self.cfg.collect_outputs(&opts, result)?;
+        self.wall_time += self.cfg.wall_time.unwrap_or(0.0);
+        Ok(self)
+    }
+
+    /// Add a resouce into the linker stack.
+    ///
+    /// In-memory inputs are named by its order and format, e.g. `input0.ptx`, in the messages of the linker.
+    /// Host objects and libraries, e.g. `libcudadevrt.a`, are linked with their embedded relocatable device code.
+    pub fn add(mut self, data: &Instruction) -> Result<Self> {
+        if !self.cfg.global_symbol.is_empty() {
+            self.ptx_inputs = match (self.ptx_inputs.take(), PtxModule::from_instruction(data)) {
+                (Some(mut inputs), Ok(ptx)) => {
+                    inputs.push(ptx);
+                    Some(inputs)
+                }
+                _ => None,
+            };
+        }
+        let name = format!("input{}.{}", self.num_inputs, data.extension());
+        self.num_inputs += 1;
+        Ok(match *data {
+            Instruction::PTX(ref ptx) => unsafe {
+                self.add_data(data.input_type(), ptx.as_bytes_with_nul(), &name)?
+            },
+            Instruction::Cubin(ref bin)
+            | Instruction::Fatbin(ref bin)
+            | Instruction::Object(ref bin)
+            | Instruction::Library(ref bin) => unsafe {
+                self.add_data(data.input_type(), bin, &name)?
+            },
+            Instruction::PTXFile(ref path)
+            | Instruction::CubinFile(ref path)
+            | Instruction::FatbinFile(ref path)
+            | Instruction::ObjectFile(ref path)
+            | Instruction::LibraryFile(ref path) => unsafe {
+                self.add_file(data.input_type(), path)?
+            },
+        })
+    }
+
+    /// Wrapper of cuLinkComplete
+    ///
+    /// LinkComplete returns a reference to cubin,
+    /// which is managed by LinkState.
+    /// Use owned strategy to avoid considering lifetime.
+    ///
+    /// The report contains the total wall time and all messages of the JIT compiler and linker.
+    /// Bound global symbols are checked against the PTX inputs if all inputs are PTX.
+    pub fn complete(mut self) -> Result<LinkOutput> {
+        if let Some(inputs) = &self.ptx_inputs {
+            if !self.cfg.global_symbol.is_empty() {
+                self.cfg.check_global_symbols(inputs)?;
+            }
+        }
+        let result = unsafe { contexted_call!(&self, link_complete, self.state) };
+        let (cb, size) = self.cfg.collect_outputs(&self.opts, result)?;
+        // cubin is a binary which may contain NUL, and thus the size must be used
+        let cubin = unsafe { std::slice::from_raw_parts(cb as *const u8, size) }.to_vec();
+        self.wall_time += self.cfg.wall_time.unwrap_or(0.0);
+        let mut report = self.cfg.report((0, 0));
+        report.wall_time = self.wall_time;
+        Ok(LinkOutput { cubin, report })
+    }
+}
+
+/// Link PTX/cubin into a module
+pub fn link(ctx: &Context, data: &[Instruction], opt: JITConfig) -> Result<Module> {
+    let mut l = Linker::create(&ctx, opt)?;
+    for d in data {
+        l = l.add(d)?;
+    }
+    let output = l.complete()?;
+    Module::load(ctx, &output.into_instruction())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let _linker = Linker::create(&ctx, JITConfig::default())?;
+        Ok(())
+    }
+
+    #[test]
+    fn ptx_file() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let linker = Linker::create(&ctx, JITConfig::default())?;
+        let data = Instruction::ptx_file(Path::new("tests/data/add.ptx"))?;
+        linker.add(&data)?;
+        Ok(())
+    }
+
+    #[test]
+    fn linking() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+
+        let data_add = Instruction::ptx_file(Path::new("tests/data/add.ptx"))?;
+        let data_sub = Instruction::ptx_file(Path::new("tests/data/sub.ptx"))?;
+        let _module = Linker::create(&ctx, JITConfig::default())?
+            .add(&data_add)?
+            .add(&data_sub)?
+            .complete()?;
+        Ok(())
+    }
+
+    #[test]
+    fn report() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let cfg = JITConfig {
+            info_log_buffer: Some(LogBuffer::default()),
+            threads_per_block: Some(128),
+            ..Default::default()
+        };
+        let data_add = Instruction::ptx_file(Path::new("tests/data/add.ptx"))?;
+        let data_sub = Instruction::ptx_file(Path::new("tests/data/sub.ptx"))?;
+        let output = Linker::create(&ctx, cfg)?
+            .add(&data_add)?
+            .add(&data_sub)?
+            .complete()?;
+        assert!(!output.cubin.is_empty());
+        let report = &output.report;
+        assert!(report.wall_time >= 0.0);
+        assert_eq!(report.threads_per_block, Some(128));
+        assert!(report.info_log.contains("_Z3addPKiS0_Pi"));
+        assert!(report.info_log.contains("_Z3subPKiS0_Pi"));
+        assert!(report.error_log.is_empty());
+
+        let module = Module::load(&ctx, &output.into_instruction())?;
+        assert!(module.jit_report().is_none());
+        let module = Module::load(&ctx, &data_add)?;
+        assert!(module.jit_report().unwrap().info_log.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn jit_failed() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let invalid = Instruction::ptx(".version 6.5\n.target sm_30\n.visible .entry f(");
+        match Linker::create(&ctx, JITConfig::default())?.add(&invalid) {
+            Err(AccelError::JitFailed { log, .. }) => assert!(log.contains("line 3")),
+            _ => panic!("must fail with the error log"),
+        }
+        match Module::load(&ctx, &invalid) {
+            Err(AccelError::JitFailed { log, .. }) => assert!(log.contains("line 3")),
+            _ => panic!("must fail with the error log"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn info_log() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let cfg = JITConfig {
+            info_log_buffer: Some(LogBuffer::default()),
+            ..Default::default()
+        };
+        let data = Instruction::ptx_file(Path::new("tests/data/add.ptx"))?;
+        let linker = Linker::create(&ctx, cfg)?.add(&data)?;
+        assert!(linker.info_log().contains("_Z3addPKiS0_Pi"));
+        assert!(linker.error_log().is_empty());
+
+        // messages are truncated to the buffer size
+        let mut cfg = JITConfig {
+            info_log_buffer: Some(LogBuffer::new(16)),
+            ..Default::default()
+        };
+        Module::load_with(&ctx, &data, &mut cfg)?;
+        assert_eq!(cfg.info_log_buffer.unwrap().log().len(), 15);
+        Ok(())
+    }
+
+    const LOOKUP_PTX: &str = r#"
+    .version 6.5
+    .target sm_30
+    .address_size 64
+    .extern .global .align 4 .u32 table[4];
+    .visible .entry lookup()
+    {
+        ret;
+    }
+    "#;
+
+    static TABLE: [u32; 4] = [1, 2, 4, 8];
+
+    #[test]
+    fn global_symbol() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let data = Instruction::ptx(LOOKUP_PTX);
+
+        let mut cfg = JITConfig::default();
+        cfg.bind_global("table", &TABLE)?;
+        let opts = cfg.pack();
+        assert_eq!(opts.count(), 4); // with CU_JIT_WALL_TIME
+        assert_eq!(opts._symbol_names.len(), 1);
+        assert_eq!(opts._symbol_addresses[0], TABLE.as_ptr() as *mut c_void);
+        Module::load_with(&ctx, &data, &mut cfg)?;
+        Linker::create(&ctx, cfg)?.add(&data)?.complete()?;
+
+        let mut cfg = JITConfig::default();
+        cfg.bind_global("not_found", &TABLE)?;
+        assert!(matches!(
+            Module::load_with(&ctx, &data, &mut cfg),
+            Err(AccelError::UnknownGlobalSymbol { .. })
+        ));
+
+        // `.u32 table[4]` cannot be relocated to a single `u32`
+        static ENTRY: u32 = 1;
+        let mut cfg = JITConfig::default();
+        cfg.bind_global("table", &ENTRY)?;
+        assert!(matches!(
+            Module::load_with(&ctx, &data, &mut cfg),
+            Err(AccelError::SymbolSizeMismatch {
+                size: 16,
+                elem_size: 4,
+                ..
+            })
+        ));
+        assert!(matches!(
+            Linker::create(&ctx, cfg)?.add(&data)?.complete(),
+            Err(AccelError::SymbolSizeMismatch { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn global_symbol_resolved() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        // `table` is defined in another input, and cannot be relocated to the host
+        let definition = Instruction::ptx(
+            ".version 6.5\n.target sm_30\n.address_size 64\n.visible .global .align 4 .u32 table[4];",
+        );
+        let mut cfg = JITConfig::default();
+        cfg.bind_global("table", &TABLE)?;
+        let linker = Linker::create(&ctx, cfg)?
+            .add(&Instruction::ptx(LOOKUP_PTX))?
+            .add(&definition)?;
+        assert!(matches!(
+            linker.complete(),
+            Err(AccelError::UnknownGlobalSymbol { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn cubin_file() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let linker = Linker::create(&ctx, JITConfig::default())?;
+        let data = Instruction::cubin_file(Path::new("tests/data/add.cubin"))?;
+        // cubin can be linked only for the devices of the same major version
+        let (major, _) = crate::cubin::Cubin::from_instruction(&data)?.compute_capability();
+        if device.compute_capability()?.0 == major {
+            linker.add(&data)?;
+        } else {
+            assert!(linker.add(&data).is_err());
+        }
+        Ok(())
+    }
+}
@@ -5,7 +5,7 @@
 //! [Surface]: https://docs.nvidia.com/cuda/cuda-driver-api/group__CUDA__SURFOBJECT.html#group__CUDA__SURFOBJECT
 
 use crate::*;
-use crate::{contexted_call, contexted_new, device::Contexted, error::Result};
+use crate::{contexted_call, device::Contexted, error::Result};
 use cuda::*;
 use futures::future::BoxFuture;
 use num_traits::ToPrimitive;
@@ -27,7 +27,7 @@ unsafe impl<T, Dim> Sync for Array<T, Dim> {}
 
 impl<T, Dim> Drop for Array<T, Dim> {
     fn drop(&mut self) {
-        if let Err(e) = unsafe { contexted_call!(self, cuArrayDestroy, self.array) } {
+        if let Err(e) = unsafe { contexted_call!(self, array_destroy, self.array) } {
             error!("Failed to cleanup array: {:?}", e);
         }
     }
@@ -38,16 +38,38 @@ impl<T: Scalar, Dim: Dimension> Array<T, Dim> {
     pub fn dim(&self) -> &Dim {
         &self.dim
     }
+
+    /// Allocate an array with additional flags, e.g. [ArrayFlag::SURFACE_LDST] for [SurfaceObject]
+    ///
+    /// Safety
+    /// ------
+    /// - Cause undefined behavior when read before write
+    ///
+    /// [ArrayFlag::SURFACE_LDST]: ./struct.ArrayFlag.html#associatedconstant.SURFACE_LDST
+    /// [SurfaceObject]: ./struct.SurfaceObject.html
+    pub unsafe fn uninitialized_with_flags(context: &Context, dim: Dim, flags: ArrayFlag) -> Self {
+        let mut desc = dim.as_descriptor::<T>();
+        desc.Flags |= flags.bits();
+        let array =
+            contexted_call!(context, array_3d_create, &desc).expect("Cannot create a new array");
+        Array {
+            array,
+            dim,
+            context: context.clone(),
+            phantom: PhantomData,
+        }
+    }
+
+    pub(super) fn raw(&self) -> CUarray {
+        self.array
+    }
 }
 
-impl<T: Scalar, Dim: Dimension> Memory for Array<T, Dim> {
+impl<T: Scalar, Dim: Dimension> MemoryRef for Array<T, Dim> {
     type Elem = T;
     fn head_addr(&self) -> *const T {
         self.array as _
     }
-    fn head_addr_mut(&mut self) -> *mut T {
-        self.array as _
-    }
 
     fn num_elem(&self) -> usize {
         self.dim.len()
@@ -56,10 +78,16 @@ impl<T: Scalar, Dim: Dimension> Memory for Array<T, Dim> {
     fn memory_type(&self) -> MemoryType {
         MemoryType::Array
     }
+}
+
+impl<T: Scalar, Dim: Dimension> Memory for Array<T, Dim> {
+    fn head_addr_mut(&mut self) -> *mut T {
+        self.array as _
+    }
 
     fn set(&mut self, value: Self::Elem) {
-        // FIXME CUDA does not have memcpy for array. This is easy but too expensive alternative way
-        let src = PageLockedMemory::from_elem(&self.context, self.dim.len(), value);
+        // CUDA does not have memset for array. Set a device memory and copy it into the array.
+        let src = DeviceMemory::from_elem(&self.context, self.dim.len(), value);
         self.copy_from(&src);
     }
 
@@ -70,14 +98,48 @@ impl<T: Scalar, Dim: Dimension> Memory for Array<T, Dim> {
     }
 }
 
+/// `CUDA_MEMCPY3D` with zero offsets, pitches and extents
+///
+/// `CUDA_MEMCPY3D::default()` cannot be used since it zero-initializes `CUmemorytype`,
+/// which has no variant for zero.
+pub(crate) fn memcpy3d_param_empty() -> CUDA_MEMCPY3D {
+    CUDA_MEMCPY3D {
+        srcXInBytes: 0,
+        srcY: 0,
+        srcZ: 0,
+        srcLOD: 0,
+        srcMemoryType: CUmemorytype_enum::CU_MEMORYTYPE_HOST,
+        srcHost: std::ptr::null(),
+        srcDevice: 0,
+        srcArray: std::ptr::null_mut(),
+        reserved0: std::ptr::null_mut(),
+        srcPitch: 0,
+        srcHeight: 0,
+        dstXInBytes: 0,
+        dstY: 0,
+        dstZ: 0,
+        dstLOD: 0,
+        dstMemoryType: CUmemorytype_enum::CU_MEMORYTYPE_HOST,
+        dstHost: std::ptr::null_mut(),
+        dstDevice: 0,
+        dstArray: std::ptr::null_mut(),
+        reserved1: std::ptr::null_mut(),
+        dstPitch: 0,
+        dstHeight: 0,
+        WidthInBytes: 0,
+        Height: 0,
+        Depth: 0,
+    }
+}
+
 fn memcpy3d_param_h2a<T: Scalar, Dim: Dimension>(
-    src: &[T],
+    src: *const T,
     dst: &mut Array<T, Dim>,
 ) -> CUDA_MEMCPY3D {
     let dim = dst.dim;
     CUDA_MEMCPY3D {
         srcMemoryType: CUmemorytype_enum::CU_MEMORYTYPE_UNIFIED,
-        srcDevice: src.as_ptr() as CUdeviceptr,
+        srcDevice: src as CUdeviceptr,
 
         dstMemoryType: CUmemorytype_enum::CU_MEMORYTYPE_ARRAY,
         dstArray: dst.array,
@@ -86,7 +148,7 @@ fn memcpy3d_param_h2a<T: Scalar, Dim: Dimension>(
         Height: dim.height(),
         Depth: dim.depth(),
 
-        ..Default::default()
+        ..memcpy3d_param_empty()
     }
 }
 
@@ -94,7 +156,7 @@ impl<T: Scalar, Dim: Dimension> Memcpy<[T]> for Array<T, Dim> {
     fn copy_from(&mut self, src: &[T]) {
         assert_ne!(self.head_addr(), src.head_addr());
         assert_eq!(self.num_elem(), src.num_elem());
-        unsafe { contexted_call!(self, cuMemcpy3D_v2, &memcpy3d_param_h2a(src, self)) }
+        unsafe { contexted_call!(self, memcpy_3d, &memcpy3d_param_h2a(src.as_ptr(), self)) }
             .expect("memcpy into array failed");
     }
 
@@ -105,8 +167,8 @@ impl<T: Scalar, Dim: Dimension> Memcpy<[T]> for Array<T, Dim> {
         unsafe {
             contexted_call!(
                 self,
-                cuMemcpy3DAsync_v2,
-                &memcpy3d_param_h2a(src, self),
+                memcpy_3d_async,
+                &memcpy3d_param_h2a(src.as_ptr(), self),
                 stream.stream
             )
         }
@@ -117,7 +179,7 @@ impl<T: Scalar, Dim: Dimension> Memcpy<[T]> for Array<T, Dim> {
 
 fn memcpy3d_param_a2h<T: Scalar, Dim: Dimension>(
     src: &Array<T, Dim>,
-    dst: &mut [T],
+    dst: *mut T,
 ) -> CUDA_MEMCPY3D {
     let dim = src.dim;
     CUDA_MEMCPY3D {
@@ -125,13 +187,13 @@ fn memcpy3d_param_a2h<T: Scalar, Dim: Dimension>(
         srcArray: src.array,
 
         dstMemoryType: CUmemorytype_enum::CU_MEMORYTYPE_UNIFIED,
-        dstDevice: dst.as_mut_ptr() as CUdeviceptr,
+        dstDevice: dst as CUdeviceptr,
 
         WidthInBytes: dim.width() * T::size_of() * dim.num_channels().to_usize().unwrap(),
         Height: dim.height(),
         Depth: dim.depth(),
 
-        ..Default::default()
+        ..memcpy3d_param_empty()
     }
 }
 
@@ -139,7 +201,7 @@ impl<T: Scalar, Dim: Dimension> Memcpy<Array<T, Dim>> for [T] {
     fn copy_from(&mut self, src: &Array<T, Dim>) {
         assert_ne!(self.head_addr(), src.head_addr());
         assert_eq!(self.num_elem(), src.num_elem());
-        unsafe { contexted_call!(src, cuMemcpy3D_v2, &memcpy3d_param_a2h(src, self)) }
+        unsafe { contexted_call!(src, memcpy_3d, &memcpy3d_param_a2h(src, self.as_mut_ptr())) }
             .expect("memcpy from array failed");
     }
 
@@ -150,8 +212,8 @@ impl<T: Scalar, Dim: Dimension> Memcpy<Array<T, Dim>> for [T] {
         unsafe {
             contexted_call!(
                 src,
-                cuMemcpy3DAsync_v2,
-                &memcpy3d_param_a2h(src, self),
+                memcpy_3d_async,
+                &memcpy3d_param_a2h(src, self.as_mut_ptr()),
                 stream.stream
             )
         }
@@ -182,22 +244,72 @@ macro_rules! impl_memcpy_array {
     };
 }
 
-impl_memcpy_array!(DeviceMemory::<T>);
+impl_memcpy_array!(ManagedMemory::<T>);
 impl_memcpy_array!(PageLockedMemory::<T>);
 impl_memcpy_array!(RegisteredMemory::<'_, T>);
 
+impl<T: Scalar, Dim: Dimension> Memcpy<DeviceMemory<T>> for Array<T, Dim> {
+    fn copy_from(&mut self, src: &DeviceMemory<T>) {
+        assert_eq!(self.num_elem(), src.num_elem());
+        unsafe {
+            contexted_call!(
+                self,
+                memcpy_3d,
+                &memcpy3d_param_h2a(src.head_addr(), self)
+            )
+        }
+        .expect("memcpy into array failed");
+    }
+
+    fn copy_from_async<'a>(&'a mut self, src: &'a DeviceMemory<T>) -> BoxFuture<'a, ()> {
+        assert_eq!(self.num_elem(), src.num_elem());
+        let stream = stream::Stream::new(self.context.get_ref());
+        unsafe {
+            contexted_call!(
+                self,
+                memcpy_3d_async,
+                &memcpy3d_param_h2a(src.head_addr(), self),
+                stream.stream
+            )
+        }
+        .expect("memcpy into array failed");
+        Box::pin(async { stream.into_future().await.expect("async memcpy failed") })
+    }
+}
+
+impl<T: Scalar, Dim: Dimension> Memcpy<Array<T, Dim>> for DeviceMemory<T> {
+    fn copy_from(&mut self, src: &Array<T, Dim>) {
+        assert_eq!(self.num_elem(), src.num_elem());
+        unsafe {
+            contexted_call!(
+                src,
+                memcpy_3d,
+                &memcpy3d_param_a2h(src, self.head_addr_mut())
+            )
+        }
+        .expect("memcpy from array failed");
+    }
+
+    fn copy_from_async<'a>(&'a mut self, src: &'a Array<T, Dim>) -> BoxFuture<'a, ()> {
+        assert_eq!(self.num_elem(), src.num_elem());
+        let stream = stream::Stream::new(src.context.get_ref());
+        unsafe {
+            contexted_call!(
+                src,
+                memcpy_3d_async,
+                &memcpy3d_param_a2h(src, self.head_addr_mut()),
+                stream.stream
+            )
+        }
+        .expect("memcpy from array failed");
+        Box::pin(async { stream.into_future().await.expect("async memcpy failed") })
+    }
+}
+
 impl<T: Scalar, Dim: Dimension> Allocatable for Array<T, Dim> {
     type Shape = Dim;
     unsafe fn uninitialized(context: &Context, dim: Dim) -> Self {
-        let desc = dim.as_descriptor::<T>();
-        let array =
-            contexted_new!(context, cuArray3DCreate_v2, &desc).expect("Cannot create a new array");
-        Array {
-            array,
-            dim,
-            context: context.clone(),
-            phantom: PhantomData,
-        }
+        Self::uninitialized_with_flags(context, dim, ArrayFlag::empty())
     }
 }
 
@@ -275,7 +387,7 @@ mod tests {
         let mut array = unsafe { Array::<u32, Ix2>::uninitialized(&context, (n, m).into()) };
         array.copy_from(&src);
         dst.copy_from(&array);
-        dbg!(dst.as_slice());
+        let dst = dst.to_vec();
         for i in 0..n * m {
             assert_eq!(dst[i], 2_u32);
         }
@@ -311,7 +423,7 @@ mod tests {
         let mut array = unsafe { Array::<u32, Ix2>::uninitialized(&context, (n, m).into()) };
         array.copy_from(&src);
         dst.copy_from(&array);
-        dbg!(dst.as_slice());
+        let dst = dst.to_vec();
         for i in 0..n {
             assert_eq!(dst[i], 2_u32);
         }
@@ -348,7 +460,7 @@ mod tests {
         let mut array = unsafe { Array::<u32, Ix3>::uninitialized(&context, (n, m, l).into()) };
         array.copy_from(&src);
         dst.copy_from(&array);
-        dbg!(dst.as_slice());
+        let dst = dst.to_vec();
         for i in 0..n {
             assert_eq!(dst[i], 2_u32);
         }
@@ -384,7 +496,7 @@ mod tests {
         let mut array = unsafe { Array::<u32, Ix1Layered>::uninitialized(&context, (n, m).into()) };
         array.copy_from(&src);
         dst.copy_from(&array);
-        dbg!(dst.as_slice());
+        let dst = dst.to_vec();
         for i in 0..n {
             assert_eq!(dst[i], 2_u32);
         }
@@ -424,7 +536,7 @@ mod tests {
             unsafe { Array::<u32, Ix2Layered>::uninitialized(&context, (n, m, l).into()) };
         array.copy_from(&src);
         dst.copy_from(&array);
-        dbg!(dst.as_slice());
+        let dst = dst.to_vec();
         for i in 0..n {
             assert_eq!(dst[i], 2_u32);
         }
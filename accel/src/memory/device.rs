@@ -1,19 +1,31 @@
 //! Device and Host memory handlers
 
-use super::*;
+use super::{fill::*, *};
 use crate::*;
-use crate::{error::*};
+use crate::{error::*, stream::Stream};
 use cuda::*;
 use std::{
     fmt,
     marker::PhantomData,
-    ops::{Deref, DerefMut},
 };
 use log::error;
 
-use cuda::CUmemAttach_flags_enum as AttachFlag;
-
-/// Memory allocated on the device.
+/// Memory allocated on the device by `cuMemAlloc`
+///
+/// Unlike [ManagedMemory], this is not accessible from the host.
+/// Read and write it by [Memcpy] or in kernels:
+///
+/// ```
+/// # use accel::*;
+/// # let device = Device::nth(0).unwrap();
+/// # let ctx = device.create_context();
+/// let mut mem = DeviceMemory::<i32>::zeros(&ctx, 4);
+/// mem.copy_from(&[1, 2, 3, 4][..]);
+/// assert_eq!(mem.to_vec(), vec![1, 2, 3, 4]);
+/// ```
+///
+/// [ManagedMemory]: ./struct.ManagedMemory.html
+/// [Memcpy]: ./trait.Memcpy.html
 #[derive(Contexted)]
 pub struct DeviceMemory<T> {
     ptr: CUdeviceptr,
@@ -27,58 +39,55 @@ unsafe impl<T> Send for DeviceMemory<T> {}
 
 impl<T> Drop for DeviceMemory<T> {
     fn drop(&mut self) {
-        if let Err(e) = unsafe { contexted_call!(self, cuMemFree_v2, self.ptr) } {
+        if let Err(e) = unsafe { contexted_call!(self, mem_free, self.ptr) } {
             error!("Failed to free device memory: {:?}", e);
         }
     }
 }
 
-impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> fmt::Debug for DeviceMemory<T> {
+impl<T> fmt::Debug for DeviceMemory<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("DeviceMemory")
             .field("context", &self.context)
-            .field("data", &self.as_slice())
+            .field("ptr", &self.ptr)
+            .field("size", &self.size)
             .finish()
     }
 }
 
-impl<T> Deref for DeviceMemory<T> {
-    type Target = [T];
-    fn deref(&self) -> &[T] {
-        unsafe { std::slice::from_raw_parts(self.ptr as _, self.size) }
-    }
-}
-
-impl<T> DerefMut for DeviceMemory<T> {
-    fn deref_mut(&mut self) -> &mut [T] {
-        unsafe { std::slice::from_raw_parts_mut(self.ptr as _, self.size) }
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> DeviceMemory<T> {
+    /// Copy into a new `Vec` on the host
+    pub fn to_vec(&self) -> Vec<T> {
+        let mut v = vec![T::default(); self.size];
+        v.as_mut_slice().copy_from(self);
+        v
+    }
+
+    /// Enqueue setting all elements by `value` into `stream`
+    ///
+    /// This returns immediately, and the memory is set when `stream` completes:
+    ///
+    /// ```
+    /// # use accel::{*, stream::Stream};
+    /// # let device = Device::nth(0).unwrap();
+    /// # let ctx = device.create_context();
+    /// let stream = Stream::new(ctx.get_ref());
+    /// let mut mem = DeviceMemory::<f64>::zeros(&ctx, 12);
+    /// mem.set_async(1.0, &stream).unwrap();
+    /// stream.sync().unwrap();
+    /// assert_eq!(mem.to_vec(), vec![1.0; 12]);
+    /// ```
+    pub fn set_async(&mut self, value: T, stream: &Stream) -> Result<()> {
+        unsafe { memset_async(self, self.ptr, value, self.size, stream.stream) }
     }
 }
 
-impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> PartialEq for DeviceMemory<T> {
-    fn eq(&self, other: &Self) -> bool {
-        // FIXME should be tested on device
-        self.as_slice().eq(other.as_slice())
-    }
-}
-
-impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> PartialEq<[T]> for DeviceMemory<T> {
-    fn eq(&self, other: &[T]) -> bool {
-        // FIXME should be tested on device
-        self.as_slice().eq(other)
-    }
-}
-
-impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memory for DeviceMemory<T> {
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> MemoryRef for DeviceMemory<T> {
     type Elem = T;
     fn head_addr(&self) -> *const T {
         self.ptr as _
     }
 
-    fn head_addr_mut(&mut self) -> *mut T {
-        self.ptr as _
-    }
-
     fn num_elem(&self) -> usize {
         self.size
     }
@@ -86,57 +95,22 @@ impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memo
     fn memory_type(&self) -> MemoryType {
         MemoryType::Device
     }
+}
 
-    // Ignore endianness
-    fn set(&mut self, _value: T) {
-        match core::mem::size_of::<T>() {
-            1 => {
-                unsafe {
-                    contexted_call!(
-                        self,
-                        cuMemsetD8_v2,
-                        self.head_addr_mut() as CUdeviceptr,
-                        core::mem::transmute_copy::<T, u8>(&_value),
-                        self.num_elem()
-                    )
-                }
-                .expect("memset failed for 8-bit scalar")
-            },
-            2 => {
-                unsafe {
-                    contexted_call!(
-                        self,
-                        cuMemsetD16_v2,
-                        self.head_addr_mut() as CUdeviceptr,
-                        core::mem::transmute_copy::<T, u16>(&_value),
-                        self.num_elem()
-                    )
-                }
-                .expect("memset failed for 16-bit scalar")
-            },
-            4 => {
-                unsafe {
-                    contexted_call!(
-                        self,
-                        cuMemsetD32_v2,
-                        self.head_addr_mut() as CUdeviceptr,
-                        core::mem::transmute_copy::<T, u32>(&_value),
-                        self.num_elem()
-                    )
-                }
-                .expect("memset failed for 32-bit scalar")
-            },
-            _ => {
-                unimplemented!("Memset is not implemented for this data type")
-            }
-        };
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memory for DeviceMemory<T> {
+    fn head_addr_mut(&mut self) -> *mut T {
+        self.ptr as _
+    }
+
+    fn set(&mut self, value: T) {
+        memset(self, self.ptr, value, self.size);
     }
 
     fn set_zero_u8(&mut self) {
         unsafe {
             contexted_call!(
                 self,
-                cuMemsetD8_v2,
+                memset_d8,
                 self.head_addr_mut() as CUdeviceptr,
                 0u8,
                 self.num_elem() * core::mem::size_of::<T>()
@@ -146,26 +120,12 @@ impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memo
     }
 }
 
-impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Continuous for DeviceMemory<T> {
-    fn as_slice(&self) -> &[T] {
-        self
-    }
-    fn as_mut_slice(&mut self) -> &mut [T] {
-        self
-    }
-}
-
 impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Allocatable for DeviceMemory<T> {
     type Shape = usize;
     unsafe fn uninitialized(context: &Context, size: usize) -> Self {
         assert!(size > 0, "Zero-sized malloc is forbidden");
-        let ptr = contexted_new!(
-            context,
-            cuMemAllocManaged,
-            size * std::mem::size_of::<T>(),
-            AttachFlag::CU_MEM_ATTACH_GLOBAL as u32
-        )
-        .expect("Cannot allocate device memory");
+        let ptr = contexted_call!(context, mem_alloc, size * std::mem::size_of::<T>())
+            .expect("Cannot allocate device memory");
         DeviceMemory {
             ptr,
             size,
@@ -175,14 +135,76 @@ impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Allo
     }
 }
 
-impl<'arg, T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> DeviceSend for &'arg DeviceMemory<T> {
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memcpy<[T]> for DeviceMemory<T> {
+    fn copy_from(&mut self, src: &[T]) {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy(self, self.ptr as *mut T, src.as_ptr(), self.size);
+    }
+
+    fn copy_from_async<'a>(&'a mut self, src: &'a [T]) -> BoxFuture<'a, ()> {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy_async(self, self.ptr as *mut T, src.as_ptr(), self.size)
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memcpy<DeviceMemory<T>> for [T] {
+    fn copy_from(&mut self, src: &DeviceMemory<T>) {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy(src, self.as_mut_ptr(), src.head_addr(), src.size);
+    }
+
+    fn copy_from_async<'a>(&'a mut self, src: &'a DeviceMemory<T>) -> BoxFuture<'a, ()> {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy_async(src, self.as_mut_ptr(), src.head_addr(), src.size)
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memcpy<DeviceMemory<T>> for DeviceMemory<T> {
+    fn copy_from(&mut self, src: &DeviceMemory<T>) {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy(self, self.ptr as *mut T, src.head_addr(), self.size);
+    }
+
+    fn copy_from_async<'a>(&'a mut self, src: &'a DeviceMemory<T>) -> BoxFuture<'a, ()> {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy_async(self, self.ptr as *mut T, src.head_addr(), self.size)
+    }
+}
+
+macro_rules! impl_memcpy_device {
+    ($t:path) => {
+        impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memcpy<$t> for DeviceMemory<T> {
+            fn copy_from(&mut self, src: &$t) {
+                self.copy_from(src.as_slice());
+            }
+            fn copy_from_async<'a>(&'a mut self, src: &'a $t) -> BoxFuture<'a, ()> {
+                self.copy_from_async(src.as_slice())
+            }
+        }
+
+        impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memcpy<DeviceMemory<T>> for $t {
+            fn copy_from(&mut self, src: &DeviceMemory<T>) {
+                self.as_mut_slice().copy_from(src);
+            }
+            fn copy_from_async<'a>(&'a mut self, src: &'a DeviceMemory<T>) -> BoxFuture<'a, ()> {
+                self.as_mut_slice().copy_from_async(src)
+            }
+        }
+    };
+}
+
+impl_memcpy_device!(ManagedMemory::<T>);
+impl_memcpy_device!(PageLockedMemory::<T>);
+impl_memcpy_device!(RegisteredMemory::<'_, T>);
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> DeviceSend for &DeviceMemory<T> {
     type Target = *const T;
     fn as_kernel_parameter(&self) -> *mut c_void {
         &self.ptr as *const CUdeviceptr as *mut c_void
     }
 }
 
-impl<'arg, T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> DeviceSend for &'arg mut DeviceMemory<T> {
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> DeviceSend for &mut DeviceMemory<T> {
     type Target = *mut T;
     fn as_kernel_parameter(&self) -> *mut c_void {
         &self.ptr as *const CUdeviceptr as *mut c_void
@@ -194,13 +216,29 @@ mod tests {
     use super::*;
 
     #[test]
-    fn as_mut_slice() -> Result<()> {
+    fn copy_roundtrip() -> Result<()> {
         let device = Device::nth(0)?;
         let context = device.create_context();
         let mut mem = DeviceMemory::<i32>::zeros(&context, 12);
-        let sl = mem.as_mut_slice();
-        sl[0] = 3; // test if accessible from host
-        assert_eq!(sl.num_elem(), 12);
+        assert_eq!(mem.to_vec(), vec![0; 12]);
+        let src: Vec<i32> = (0..12).collect();
+        mem.copy_from(src.as_slice());
+        let mut dst = PageLockedMemory::<i32>::zeros(&context, 12);
+        dst.copy_from(&mem);
+        assert_eq!(dst.as_slice(), src.as_slice());
+        let mut other = DeviceMemory::<i32>::zeros(&context, 12);
+        other.copy_from(&mem);
+        assert_eq!(other.to_vec(), src);
+        Ok(())
+    }
+
+    #[test]
+    fn memory_type() -> Result<()> {
+        let device = Device::nth(0)?;
+        let context = device.create_context();
+        let mem = DeviceMemory::<i32>::from_elem(&context, 12, 3);
+        assert_eq!(mem.memory_type(), MemoryType::Device);
+        assert_eq!(mem.to_vec(), vec![3; 12]);
         Ok(())
     }
 
@@ -0,0 +1,272 @@
+//! Fill device memory by a value of any element size
+
+use super::*;
+use crate::error::*;
+use std::ptr::null_mut;
+
+/// Name of the built-in fill kernel, which the emulator backend also executes
+pub(crate) const FILL_KERNEL: &str = "accel_fill_bytes";
+
+/// Number of threads per block of the fill kernel
+const FILL_BLOCK: u32 = 256;
+
+/// Upper limit of the grid size of the fill kernel. Larger memories are filled by grid-stride loop.
+const FILL_MAX_GRID: u64 = 1024;
+
+/// `dst[i] = dst[i % size]` for `size <= i < total` bytes, i.e. broadcast the first element
+const FILL_PTX: &str = r#"
+.version 3.2
+.target sm_30
+.address_size 64
+
+.visible .entry accel_fill_bytes(
+    .param .u64 dst,
+    .param .u64 size,
+    .param .u64 total
+)
+{
+    .reg .pred %p<2>;
+    .reg .b16 %rs<2>;
+    .reg .b32 %r<5>;
+    .reg .b64 %rd<12>;
+
+    ld.param.u64 %rd1, [dst];
+    ld.param.u64 %rd2, [size];
+    ld.param.u64 %rd3, [total];
+    cvta.to.global.u64 %rd4, %rd1;
+    mov.u32 %r1, %ctaid.x;
+    mov.u32 %r2, %ntid.x;
+    mov.u32 %r3, %tid.x;
+    mad.wide.u32 %rd5, %r1, %r2, %rd2;
+    cvt.u64.u32 %rd6, %r3;
+    add.u64 %rd7, %rd5, %rd6;
+    mov.u32 %r4, %nctaid.x;
+    mul.wide.u32 %rd8, %r4, %r2;
+
+LOOP:
+    setp.ge.u64 %p1, %rd7, %rd3;
+    @%p1 bra DONE;
+    rem.u64 %rd9, %rd7, %rd2;
+    add.u64 %rd10, %rd4, %rd9;
+    ld.global.u8 %rs1, [%rd10];
+    add.u64 %rd11, %rd4, %rd7;
+    st.global.u8 [%rd11], %rs1;
+    add.u64 %rd7, %rd7, %rd8;
+    bra.uni LOOP;
+
+DONE:
+    ret;
+}
+"#;
+
+/// Set `n` elements from `ptr` by `value`, and wait until completed
+///
+/// Panic
+/// ------
+/// - if memset fails, e.g. `ptr` is not a device-accessible memory
+pub(super) fn memset<T>(ctx: &impl Contexted, ptr: CUdeviceptr, value: T, n: usize) {
+    unsafe { memset_async(ctx, ptr, value, n, null_mut()) }
+        .and_then(|_| ctx.sync())
+        .expect("memset failed");
+}
+
+/// Enqueue setting `n` elements from `ptr` by `value` into `stream`
+///
+/// - 1, 2 and 4-byte elements are set by `cuMemsetD8Async`, `cuMemsetD16Async` and `cuMemsetD32Async`
+/// - 8 and 16-byte elements are set by `cuMemsetD2D32Async` for each 32-bit word,
+///   regarding the memory as a 2D array whose pitch is the element size
+/// - Others are set by copying `value` into the first element and broadcasting it by the built-in fill kernel
+///
+/// Safety
+/// ------
+/// - `ptr` must be valid for `n` elements until the operation completes
+pub(super) unsafe fn memset_async<T>(
+    ctx: &impl Contexted,
+    ptr: CUdeviceptr,
+    value: T,
+    n: usize,
+    stream: CUstream,
+) -> Result<()> {
+    let size = std::mem::size_of::<T>();
+    if n == 0 || size == 0 {
+        return Ok(());
+    }
+    match size {
+        1 => contexted_call!(
+            ctx,
+            memset_d8_async,
+            ptr,
+            std::mem::transmute_copy::<T, u8>(&value),
+            n,
+            stream
+        ),
+        2 if ptr % 2 == 0 => contexted_call!(
+            ctx,
+            memset_d16_async,
+            ptr,
+            std::mem::transmute_copy::<T, u16>(&value),
+            n,
+            stream
+        ),
+        4 if ptr % 4 == 0 => contexted_call!(
+            ctx,
+            memset_d32_async,
+            ptr,
+            std::mem::transmute_copy::<T, u32>(&value),
+            n,
+            stream
+        ),
+        8 if ptr % 4 == 0 => {
+            let words = std::mem::transmute_copy::<T, [u32; 2]>(&value);
+            memset_words(ctx, ptr, &words, n, stream)
+        }
+        16 if ptr % 4 == 0 => {
+            let words = std::mem::transmute_copy::<T, [u32; 4]>(&value);
+            memset_words(ctx, ptr, &words, n, stream)
+        }
+        _ => fill(ctx, ptr, &value, n, stream),
+    }
+}
+
+/// Set each 32-bit word of the elements as a column of 2D memory
+unsafe fn memset_words(
+    ctx: &impl Contexted,
+    ptr: CUdeviceptr,
+    words: &[u32],
+    n: usize,
+    stream: CUstream,
+) -> Result<()> {
+    let pitch = 4 * words.len();
+    for (i, &word) in words.iter().enumerate() {
+        contexted_call!(
+            ctx,
+            memset_d2d32_async,
+            ptr + 4 * i as CUdeviceptr,
+            pitch,
+            word,
+            1,
+            n,
+            stream
+        )?;
+    }
+    Ok(())
+}
+
+/// Copy `value` into the first element, and broadcast it by the fill kernel
+unsafe fn fill<T>(
+    ctx: &impl Contexted,
+    ptr: CUdeviceptr,
+    value: &T,
+    n: usize,
+    stream: CUstream,
+) -> Result<()> {
+    let size = std::mem::size_of::<T>();
+    // `cuMemcpyAsync` from pageable host memory returns after `value` is staged,
+    // i.e. `value` may be dropped before the stream completes.
+    contexted_call!(
+        ctx,
+        memcpy_async,
+        ptr,
+        value as *const T as CUdeviceptr,
+        size,
+        stream
+    )?;
+    if n == 1 {
+        return Ok(());
+    }
+    let module = Module::cached(ctx, FILL_PTX)?;
+    let kernel = module.get_kernel(FILL_KERNEL)?;
+    let (size, total) = (size as u64, (n * size) as u64);
+    let block_size = FILL_BLOCK as u64;
+    let grid = ((total - size + block_size - 1) / block_size).min(FILL_MAX_GRID);
+    let mut args = [
+        ptr.as_kernel_parameter(),
+        size.as_kernel_parameter(),
+        total.as_kernel_parameter(),
+    ];
+    contexted_call!(
+        ctx,
+        launch_kernel,
+        kernel.func,
+        Grid::x(grid),
+        Block::x(FILL_BLOCK),
+        0,
+        stream,
+        args.as_mut_ptr()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq)]
+    struct Rgb([u8; 3]);
+
+    #[test]
+    fn set_f64() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let mut mem = DeviceMemory::<f64>::zeros(&ctx, 12);
+        mem.set(1.5);
+        assert_eq!(mem.to_vec(), vec![1.5; 12]);
+        Ok(())
+    }
+
+    #[test]
+    fn set_16bytes() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let value = (1u32, 2u32, 3u64);
+        let mut mem = ManagedMemory::<(u32, u32, u64)>::zeros(&ctx, 12);
+        mem.set(value);
+        assert_eq!(mem.as_slice(), &[value; 12]);
+        Ok(())
+    }
+
+    #[test]
+    fn set_odd_size() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let value = Rgb([1, 2, 3]);
+        let mut mem = DeviceMemory::<Rgb>::zeros(&ctx, 1000);
+        mem.set(value);
+        assert_eq!(mem.to_vec(), vec![value; 1000]);
+
+        // single element does not launch the kernel
+        let mut mem = DeviceMemory::<Rgb>::zeros(&ctx, 1);
+        mem.set(value);
+        assert_eq!(mem.to_vec(), vec![value]);
+        Ok(())
+    }
+
+    #[cfg(feature = "half")]
+    #[test]
+    fn set_half() -> Result<()> {
+        use half::{bf16, f16};
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let mut a = DeviceMemory::<f16>::zeros(&ctx, 12);
+        let mut b = ManagedMemory::<bf16>::zeros(&ctx, 12);
+        a.set(f16::from_f32(1.5));
+        b.set(bf16::from_f32(-2.0));
+        assert_eq!(a.to_vec(), vec![f16::from_f32(1.5); 12]);
+        assert_eq!(b.as_slice(), &[bf16::from_f32(-2.0); 12]);
+        Ok(())
+    }
+
+    #[test]
+    fn set_async() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let stream = stream::Stream::new(ctx.get_ref());
+        let mut a = DeviceMemory::<u64>::zeros(&ctx, 12);
+        let mut b = ManagedMemory::<[u16; 5]>::zeros(&ctx, 12);
+        a.set_async(std::u64::MAX - 1, &stream)?;
+        b.set_async([1, 2, 3, 4, 5], &stream)?;
+        stream.sync()?;
+        assert_eq!(a.to_vec(), vec![std::u64::MAX - 1; 12]);
+        assert_eq!(b.as_slice(), &[[1, 2, 3, 4, 5]; 12]);
+        Ok(())
+    }
+}
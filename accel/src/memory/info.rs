@@ -1,5 +1,4 @@
 use crate::{contexted_call, device::*};
-use cuda::*;
 
 /// Total and Free memory size of the device (in bytes)
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -10,17 +9,7 @@ struct MemoryInfo {
 
 impl MemoryInfo {
     fn get(ctx: Context) -> Self {
-        let mut free = 0;
-        let mut total = 0;
-        unsafe {
-            contexted_call!(
-                &ctx,
-                cuMemGetInfo_v2,
-                &mut free as *mut usize,
-                &mut total as *mut usize
-            )
-        }
-        .expect("Cannot get memory info");
+        let (free, total) = contexted_call!(&ctx, mem_get_info).expect("Cannot get memory info");
         MemoryInfo { free, total }
     }
 }
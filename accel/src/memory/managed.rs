@@ -0,0 +1,307 @@
+//! Managed memory shared by host and device
+
+use super::{fill::*, *};
+use crate::{error::*, stream::Stream};
+use cuda::*;
+use log::error;
+use std::{
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
+
+pub use cuda::CUmemAttach_flags_enum as AttachFlag;
+pub use cuda::CUmem_advise_enum as Advice;
+
+/// Where managed memory is prefetched to, or advised for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Location<'a> {
+    Host,
+    Device(&'a Device),
+}
+
+impl Location<'_> {
+    fn raw(&self) -> CUdevice {
+        match self {
+            Location::Host => backend::CU_DEVICE_CPU,
+            Location::Device(device) => device.raw(),
+        }
+    }
+}
+
+/// Memory allocated by `cuMemAllocManaged`, which is accessible from both host and device
+///
+/// Pages are migrated between host and device on page faults.
+/// [prefetch] and [advise] hint the driver to reduce these faults:
+///
+/// ```
+/// # use accel::{*, memory::*, stream::Stream};
+/// let device = Device::nth(0).unwrap();
+/// let ctx = device.create_context();
+/// let mut mem = ManagedMemory::<f32>::zeros(&ctx, 12);
+/// mem[0] = 1.0; // accessible from host
+///
+/// let stream = Stream::new(ctx.get_ref());
+/// mem.advise(Advice::CU_MEM_ADVISE_SET_READ_MOSTLY, Location::Device(&device)).unwrap();
+/// mem.prefetch(Location::Device(&device), &stream).unwrap();
+/// stream.sync().unwrap();
+/// ```
+///
+/// [prefetch]: #method.prefetch
+/// [advise]: #method.advise
+#[derive(Contexted)]
+pub struct ManagedMemory<T> {
+    ptr: CUdeviceptr,
+    size: usize,
+    context: Context,
+    phantom: PhantomData<T>,
+}
+
+unsafe impl<T> Sync for ManagedMemory<T> {}
+unsafe impl<T> Send for ManagedMemory<T> {}
+
+impl<T> Drop for ManagedMemory<T> {
+    fn drop(&mut self) {
+        if let Err(e) = unsafe { contexted_call!(self, mem_free, self.ptr) } {
+            error!("Failed to free managed memory: {:?}", e);
+        }
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> fmt::Debug
+    for ManagedMemory<T>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ManagedMemory")
+            .field("context", &self.context)
+            .field("data", &self.as_slice())
+            .finish()
+    }
+}
+
+impl<T> Deref for ManagedMemory<T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.ptr as _, self.size) }
+    }
+}
+
+impl<T> DerefMut for ManagedMemory<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr as _, self.size) }
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> PartialEq
+    for ManagedMemory<T>
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice().eq(other.as_slice())
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> PartialEq<[T]>
+    for ManagedMemory<T>
+{
+    fn eq(&self, other: &[T]) -> bool {
+        self.as_slice().eq(other)
+    }
+}
+
+impl<T> ManagedMemory<T> {
+    fn bytes(&self) -> usize {
+        self.size * std::mem::size_of::<T>()
+    }
+
+    /// Enqueue setting all elements by `value` into `stream`
+    ///
+    /// This returns immediately, and the memory is set when `stream` completes.
+    pub fn set_async(&mut self, value: T, stream: &Stream) -> Result<()> {
+        unsafe { memset_async(self, self.ptr, value, self.size, stream.stream) }
+    }
+
+    /// Migrate the memory to `location` asynchronously on `stream` by `cuMemPrefetchAsync`
+    pub fn prefetch(&self, location: Location, stream: &Stream) -> Result<()> {
+        unsafe {
+            contexted_call!(
+                self,
+                mem_prefetch_async,
+                self.ptr,
+                self.bytes(),
+                location.raw(),
+                stream.stream
+            )
+        }
+    }
+
+    /// Hint the usage of the memory by `cuMemAdvise`
+    ///
+    /// `location` is ignored for `CU_MEM_ADVISE_SET_READ_MOSTLY` and `CU_MEM_ADVISE_UNSET_READ_MOSTLY`.
+    pub fn advise(&self, advice: Advice, location: Location) -> Result<()> {
+        unsafe {
+            contexted_call!(
+                self,
+                mem_advise,
+                self.ptr,
+                self.bytes(),
+                advice,
+                location.raw()
+            )
+        }
+    }
+
+    /// Attach the memory to `stream` by `cuStreamAttachMemAsync`
+    ///
+    /// With `CU_MEM_ATTACH_SINGLE`, the memory is accessed only by `stream`,
+    /// and the host can access it while other streams are running.
+    pub fn attach(&self, stream: &Stream, flag: AttachFlag) -> Result<()> {
+        unsafe {
+            contexted_call!(
+                self,
+                stream_attach_mem_async,
+                stream.stream,
+                self.ptr,
+                0, // whole allocation
+                flag as u32
+            )
+        }
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> MemoryRef
+    for ManagedMemory<T>
+{
+    type Elem = T;
+
+    fn head_addr(&self) -> *const T {
+        self.ptr as _
+    }
+
+    fn num_elem(&self) -> usize {
+        self.size
+    }
+
+    fn memory_type(&self) -> MemoryType {
+        MemoryType::Managed
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memory
+    for ManagedMemory<T>
+{
+    fn head_addr_mut(&mut self) -> *mut T {
+        self.ptr as _
+    }
+
+    fn set(&mut self, value: T) {
+        memset(self, self.ptr, value, self.size);
+    }
+
+    fn set_zero_u8(&mut self) {
+        unsafe { contexted_call!(self, memset_d8, self.ptr, 0u8, self.bytes()) }
+            .expect("zero memset failed for managed memory");
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Continuous
+    for ManagedMemory<T>
+{
+    fn as_slice(&self) -> &[T] {
+        self
+    }
+    fn as_mut_slice(&mut self) -> &mut [T] {
+        self
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Allocatable
+    for ManagedMemory<T>
+{
+    type Shape = usize;
+    unsafe fn uninitialized(context: &Context, size: usize) -> Self {
+        assert!(size > 0, "Zero-sized malloc is forbidden");
+        let ptr = contexted_call!(
+            context,
+            mem_alloc_managed,
+            size * std::mem::size_of::<T>(),
+            AttachFlag::CU_MEM_ATTACH_GLOBAL as u32
+        )
+        .expect("Cannot allocate managed memory");
+        ManagedMemory {
+            ptr,
+            size,
+            context: context.clone(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> DeviceSend
+    for &ManagedMemory<T>
+{
+    type Target = *const T;
+    fn as_kernel_parameter(&self) -> *mut c_void {
+        &self.ptr as *const CUdeviceptr as *mut c_void
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> DeviceSend
+    for &mut ManagedMemory<T>
+{
+    type Target = *mut T;
+    fn as_kernel_parameter(&self) -> *mut c_void {
+        &self.ptr as *const CUdeviceptr as *mut c_void
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn as_mut_slice() -> Result<()> {
+        let device = Device::nth(0)?;
+        let context = device.create_context();
+        let mut mem = ManagedMemory::<i32>::zeros(&context, 12);
+        let sl = mem.as_mut_slice();
+        sl[0] = 3; // test if accessible from host
+        assert_eq!(sl.num_elem(), 12);
+        assert_eq!(sl.memory_type(), MemoryType::Managed);
+        Ok(())
+    }
+
+    #[should_panic(expected = "Zero-sized malloc is forbidden")]
+    #[test]
+    fn managed_new_zero() {
+        let device = Device::nth(0).unwrap();
+        let context = device.create_context();
+        let _a = ManagedMemory::<i32>::zeros(&context, 0);
+    }
+
+    #[test]
+    fn hints() -> Result<()> {
+        let device = Device::nth(0)?;
+        let context = device.create_context();
+        let mem = ManagedMemory::<i32>::from_elem(&context, 12, 3);
+        let stream = Stream::new(context.get_ref());
+        mem.attach(&stream, AttachFlag::CU_MEM_ATTACH_SINGLE)?;
+        mem.advise(
+            Advice::CU_MEM_ADVISE_SET_PREFERRED_LOCATION,
+            Location::Device(&device),
+        )?;
+        mem.advise(Advice::CU_MEM_ADVISE_SET_ACCESSED_BY, Location::Host)?;
+        mem.prefetch(Location::Device(&device), &stream)?;
+        mem.prefetch(Location::Host, &stream)?;
+        stream.sync()?;
+        assert_eq!(mem.as_slice(), &[3; 12]);
+
+        // only managed memory can be prefetched
+        let dev = DeviceMemory::<i32>::zeros(&context, 12);
+        let head = dev.head_addr() as CUdeviceptr;
+        assert!(unsafe {
+            contexted_call!(&context, mem_prefetch_async, head, 48, -1, stream.stream)
+        }
+        .is_err());
+        Ok(())
+    }
+}
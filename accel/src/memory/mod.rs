@@ -14,24 +14,37 @@
 //! | (usual) Host memory | Host         | ✓         |  -          |  ✓       | allocated by usual manner, e.g. `vec![0; n]`                           |
 //! | [RegisteredMemory]  | Host         | ✓         |  ✓          |  ✓       | A host memory registered into CUDA memory management system            |
 //! | [PageLockedMemory]  | Host         | ✓         |  ✓          |  ✓       | OS memory paging is disabled for accelerating memory transfer          |
-//! | [DeviceMemory]      | Device       | ✓         |  ✓          |  ✓       | allocated on device as a single span                                   |
+//! | [DeviceMemory]      | Device       | -         |  ✓          |  -       | allocated on device as a single span, accessed by explicit copies      |
+//! | [ManagedMemory]     | Both         | ✓         |  ✓          |  ✓       | migrated between host and device on page faults                        |
+//! | [PitchedMemory]     | Device       | -         |  ✓          |  -       | 2D or 3D memory on device whose rows are padded to be aligned          |
 //! | [Array]             | Device       | ✓         |  ✓          |  -       | properly aligned memory on device for using Texture and Surface memory |
+//! | [DeviceSymbol]      | Device       | -         |  ✓          |  -       | `.global` or `.const` variable declared in a module                    |
+//! | [PooledMemory]      | Both         | ✓         |  ✓          |  ✓       | allocated by [MemoryPool] and reused after dropped                     |
+//! | [DeviceSlice]       | Any          | -         |  ✓          |  -       | borrowed sub-range of device-accessible memories, e.g. [DeviceMemory]  |
 //!
 //! Traits
 //! -------
 //!
-//! |traits       |`[T]`|[RegisteredMemory]|[PageLockedMemory]|[DeviceMemory]|[Array]| Description                                |
-//! |:------------|:---:|:----------------:|:----------------:|:------------:|:-----:|:-------------------------------------------|
-//! |[Memory]     | ✓   | ✓                | ✓                | ✓            | ✓     | Has Unified address and element size       |
-//! |[Contexted]  | -   | ✓                | ✓                | ✓            | ✓     | with CUDA Context                          |
-//! |[Continuous] | ✓   | ✓                | ✓                | ✓            | -     | Can be treated as a Rust slice             |
-//! |[Allocatable]| -   | -                | ✓                | ✓            | ✓     | Newly allocatable with its shape and value |
+//! |traits       |`[T]`|[RegisteredMemory]|[PageLockedMemory]|[DeviceMemory]|[ManagedMemory]|[Array]| Description                                |
+//! |:------------|:---:|:----------------:|:----------------:|:------------:|:-------------:|:-----:|:-------------------------------------------|
+//! |[MemoryRef]  | ✓   | ✓                | ✓                | ✓            | ✓             | ✓     | Has Unified address and element size       |
+//! |[Memory]     | ✓   | ✓                | ✓                | ✓            | ✓             | ✓     | Writable [MemoryRef]                       |
+//! |[Contexted]  | -   | ✓                | ✓                | ✓            | ✓             | ✓     | with CUDA Context                          |
+//! |[Continuous] | ✓   | ✓                | ✓                | -            | ✓             | -     | Can be treated as a Rust slice             |
+//! |[Allocatable]| -   | -                | ✓                | ✓            | ✓             | ✓     | Newly allocatable with its shape and value |
 //!
 //! [RegisteredMemory]: ./struct.RegisteredMemory.html
 //! [PageLockedMemory]: ./struct.PageLockedMemory.html
 //! [DeviceMemory]: ./struct.DeviceMemory.html
+//! [ManagedMemory]: ./struct.ManagedMemory.html
+//! [PitchedMemory]: ./struct.PitchedMemory.html
 //! [Array]: ./struct.Array.html
+//! [DeviceSymbol]: ./struct.DeviceSymbol.html
+//! [PooledMemory]: ./pool/struct.PooledMemory.html
+//! [MemoryPool]: ./pool/struct.MemoryPool.html
+//! [DeviceSlice]: ./struct.DeviceSlice.html
 //!
+//! [MemoryRef]: ./trait.MemoryRef.html
 //! [Memory]: ./trait.Memory.html
 //! [Memset]: ./trait.Memset.html
 //! [Contexted]: ../device/trait.Contexted.html
@@ -41,19 +54,33 @@
 mod array;
 mod device;
 mod dimension;
+mod fill;
 mod info;
+mod managed;
 mod page_locked;
+mod pitched;
+pub mod pool;
 mod registered;
 mod scalar;
 mod slice;
+mod symbol;
+mod texture;
+mod view;
 
 pub use array::*;
 pub use device::*;
 pub use dimension::*;
 pub use info::*;
+pub use managed::*;
 pub use page_locked::*;
+pub use pitched::*;
 pub use registered::*;
 pub use scalar::*;
+pub use symbol::*;
+pub use texture::*;
+pub use view::*;
+
+pub(crate) use fill::FILL_KERNEL;
 
 use crate::*;
 use cuda::*;
@@ -65,15 +92,15 @@ use std::{ffi::c_void, mem::MaybeUninit};
 ///
 /// Because of [unified addressing], we can get the memory type after casted into slice:
 ///
-/// - [DeviceMemory]
+/// - [ManagedMemory]
 ///
 /// ```
 /// # use accel::{*, memory::*};
 /// # let device = Device::nth(0).unwrap();
 /// # let ctx = device.create_context();
-/// let mem = DeviceMemory::<i32>::zeros(&ctx, 12);
+/// let mem = ManagedMemory::<i32>::zeros(&ctx, 12);
 /// let sl = mem.as_slice();
-/// assert_eq!(sl.memory_type(), MemoryType::Device);
+/// assert_eq!(sl.memory_type(), MemoryType::Managed);
 /// ```
 ///
 /// - [PageLockedMemory]
@@ -100,11 +127,12 @@ use std::{ffi::c_void, mem::MaybeUninit};
 /// assert_eq!(sl.memory_type(), MemoryType::PageLocked);
 /// ```
 ///
-/// - [Array] cannot be casted into a slice
+/// - [DeviceMemory] and [Array] cannot be casted into a slice
 ///
 /// [unified addressing]: https://docs.nvidia.com/cuda/cuda-driver-api/group__CUDA__UNIFIED.html#group__CUDA__UNIFIED
 /// [Array]: ./struct.Array.html
 /// [DeviceMemory]: ./struct.DeviceMemory.html
+/// [ManagedMemory]: ./struct.ManagedMemory.html
 /// [RegisteredMemory]: ./struct.RegisteredMemory.html
 /// [PageLockedMemory]: ./struct.PageLockedMemory.html
 ///
@@ -116,28 +144,36 @@ pub enum MemoryType {
     /// [RegisteredMemory](./struct.RegisteredMemory.html), and
     /// [PageLockedMemory](./struct.PageLockedMemory.html)
     PageLocked,
-    /// Device memory
+    /// Device memory, i.e. [DeviceMemory](./struct.DeviceMemory.html)
     Device,
+    /// Memory accessible from both host and device, i.e. [ManagedMemory](./struct.ManagedMemory.html)
+    Managed,
     /// Array memory
     Array,
 }
 
-/// Has unique head address and allocated size.
-pub trait Memory {
+/// Has unique head address and allocated size, but may be read-only.
+///
+/// Read-only views, e.g. [DeviceSlice](./struct.DeviceSlice.html), implement only this trait,
+/// and other memories implement [Memory](./trait.Memory.html) too.
+pub trait MemoryRef {
     /// Scalar type of each element
     type Elem: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized;
 
     /// Get head address of the memory as a const pointer
     fn head_addr(&self) -> *const Self::Elem;
 
-    /// Get head address of the memory as a mutable pointer
-    fn head_addr_mut(&mut self) -> *mut Self::Elem;
-
     /// Number of elements
     fn num_elem(&self) -> usize;
 
     /// Get memory type, See [MemoryType](./enum.MemoryType.html) for detail.
     fn memory_type(&self) -> MemoryType;
+}
+
+/// Has unique head address and allocated size.
+pub trait Memory: MemoryRef {
+    /// Get head address of the memory as a mutable pointer
+    fn head_addr_mut(&mut self) -> *mut Self::Elem;
 
     /// Set all elements by `value`
     ///
@@ -152,21 +188,21 @@ pub trait Memory {
     /// # let ctx = device.create_context();
     /// let mut mem = DeviceMemory::<i32>::zeros(&ctx, 12);
     /// mem.set(1234);
-    /// for &val in mem.as_slice() {
+    /// for val in mem.to_vec() {
     ///   assert_eq!(val, 1234);
     /// }
     /// ```
     ///
-    /// - Set `f32`
-    ///   - Be sure that `f64` is not supported yet because CUDA does not support 64-bit memset.
+    /// - Set `f64`
+    ///   - Elements of any size can be set, e.g. `f64` or `[u8; 3]`
     ///
     /// ```
     /// # use accel::*;
     /// # let device = Device::nth(0).unwrap();
     /// # let ctx = device.create_context();
-    /// let mut mem = DeviceMemory::<f32>::zeros(&ctx, 12);
+    /// let mut mem = DeviceMemory::<f64>::zeros(&ctx, 12);
     /// mem.set(1.0);
-    /// for &val in mem.as_slice() {
+    /// for val in mem.to_vec() {
     ///   assert_eq!(val, 1.0);
     /// }
     /// ```
@@ -190,7 +226,7 @@ pub trait Memory {
 }
 
 /// Copy data from one to another
-pub trait Memcpy<Target: Memory<Elem = Self::Elem> + ?Sized>: Memory {
+pub trait Memcpy<Target: MemoryRef<Elem = Self::Elem> + ?Sized>: Memory {
     /// Examples
     /// ---------
     ///
@@ -317,7 +353,7 @@ pub trait Memcpy<Target: Memory<Elem = Self::Elem> + ?Sized>: Memory {
     /// # async fn main() {
     /// # let device = Device::nth(0).unwrap();
     /// # let ctx = device.create_context();
-    /// # let mut dest = DeviceMemory::<f32>::zeros(&ctx, 12);
+    /// # let mut dest = ManagedMemory::<f32>::zeros(&ctx, 12);
     /// # let src = PageLockedMemory::<f32>::zeros(&ctx, 12);
     /// let future = dest.copy_from_async(&src);
     /// println!("dest[0] = {}", dest[0]);  // Destination is not accessible until .await
@@ -371,3 +407,46 @@ pub trait Continuous: Memory {
     fn as_slice(&self) -> &[Self::Elem];
     fn as_mut_slice(&mut self) -> &mut [Self::Elem];
 }
+
+/// Copy `n` elements between memories in the unified address space by `cuMemcpy`
+fn copy<T>(ctx: &impl Contexted, dst: *mut T, src: *const T, n: usize) {
+    assert_ne!(dst as *const T, src);
+    unsafe {
+        contexted_call!(
+            ctx,
+            memcpy,
+            dst as CUdeviceptr,
+            src as CUdeviceptr,
+            n * std::mem::size_of::<T>()
+        )
+    }
+    .expect("memcpy failed");
+}
+
+/// Copy `n` elements by `cuMemcpyAsync`, which completes when the returned future is awaited
+fn copy_async<'a, T>(
+    ctx: &impl Contexted,
+    dst: *mut T,
+    src: *const T,
+    n: usize,
+) -> BoxFuture<'a, ()> {
+    assert_ne!(dst as *const T, src);
+    let stream = stream::Stream::new(ctx.get_ref());
+    unsafe {
+        contexted_call!(
+            ctx,
+            memcpy_async,
+            dst as CUdeviceptr,
+            src as CUdeviceptr,
+            n * std::mem::size_of::<T>(),
+            stream.stream
+        )
+    }
+    .expect("Failed to start async memcpy");
+    Box::pin(async {
+        stream
+            .into_future()
+            .await
+            .expect("Async memcpy thread failed")
+    })
+}
@@ -3,7 +3,6 @@
 use super::*;
 use crate::*;
 use crate::error::Result;
-use cuda::*;
 use std::{
     fmt,
     ops::{Deref, DerefMut},
@@ -31,7 +30,7 @@ unsafe impl<T> Send for PageLockedMemory<T> {}
 
 impl<T> Drop for PageLockedMemory<T> {
     fn drop(&mut self) {
-        if let Err(e) = unsafe { contexted_call!(self, cuMemFreeHost, self.ptr as *mut _) } {
+        if let Err(e) = unsafe { contexted_call!(self, mem_free_host, self.ptr as *mut _) } {
             error!("Cannot free page-locked memory: {:?}", e);
         }
     }
@@ -71,13 +70,10 @@ impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Part
     }
 }
 
-impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memory for PageLockedMemory<T> {
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> MemoryRef for PageLockedMemory<T> {
     type Elem = T;
-    fn head_addr(&self) -> *const T {
-        self.ptr as _
-    }
 
-    fn head_addr_mut(&mut self) -> *mut T {
+    fn head_addr(&self) -> *const T {
         self.ptr as _
     }
 
@@ -88,6 +84,12 @@ impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memo
     fn memory_type(&self) -> MemoryType {
         MemoryType::PageLocked
     }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memory for PageLockedMemory<T> {
+    fn head_addr_mut(&mut self) -> *mut T {
+        self.ptr as _
+    }
 
     fn set(&mut self, value: Self::Elem) {
         self.iter_mut().for_each(|v| *v = value);
@@ -116,7 +118,7 @@ impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Allo
     type Shape = usize;
     unsafe fn uninitialized(context: &Context, size: usize) -> Self {
         assert!(size > 0, "Zero-sized malloc is forbidden");
-        let ptr = contexted_new!(context, cuMemAllocHost_v2, size * std::mem::size_of::<T>())
+        let ptr = contexted_call!(context, mem_alloc_host, size * std::mem::size_of::<T>())
             .expect("Cannot allocate page-locked memory");
         Self {
             ptr: ptr as *mut T,
@@ -0,0 +1,769 @@
+//! Pitched 2D/3D linear memory on device
+
+use super::{fill::memset, *};
+use crate::error::*;
+use futures::future::BoxFuture;
+use log::error;
+use num_traits::ToPrimitive;
+use std::fmt;
+
+/// Position or extent in a 2D or 3D memory as `(x, y, z)`
+///
+/// `x` counts "CUDA array elements", i.e. `[T; num_channels]`, as the width of [Dimension].
+/// `z` must be `0` for offsets and `1` for extents of 2D memories.
+///
+/// [Dimension]: ./trait.Dimension.html
+pub type Position = (usize, usize, usize);
+
+/// Read-only pointer with the pitch of rows, which is sent to kernels for `&PitchedMemory`
+///
+/// The element at `(x, y, z)` exists at `ptr + (z * height + y) * pitch` bytes, plus `x` elements.
+/// This has the same layout as [PitchedPtrMut].
+///
+/// [PitchedPtrMut]: ./struct.PitchedPtrMut.html
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PitchedPtr<T> {
+    /// Head of the first row
+    pub ptr: *const T,
+    /// Size of each row in bytes including padding
+    pub pitch: usize,
+    /// Number of rows in each 2D slice
+    pub height: usize,
+}
+
+impl<T> DeviceSend for PitchedPtr<T> {
+    type Target = Self;
+}
+
+/// Mutable pointer with the pitch of rows, which is sent to kernels for `&mut PitchedMemory`
+///
+/// See [PitchedPtr] for the layout of elements.
+///
+/// [PitchedPtr]: ./struct.PitchedPtr.html
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PitchedPtrMut<T> {
+    /// Head of the first row
+    pub ptr: *mut T,
+    /// Size of each row in bytes including padding
+    pub pitch: usize,
+    /// Number of rows in each 2D slice
+    pub height: usize,
+}
+
+impl<T> DeviceSend for PitchedPtrMut<T> {
+    type Target = Self;
+}
+
+/// 2D or 3D memory on device allocated by `cuMemAllocPitch`
+///
+/// Each row is padded to be aligned properly for coalesced access.
+/// Unlike [Array], this is a linear memory which can be accessed from kernels
+/// through [PitchedPtr] by `&PitchedMemory`, or [PitchedPtrMut] by `&mut PitchedMemory`:
+///
+/// ```
+/// # use accel::*;
+/// # let device = Device::nth(0).unwrap();
+/// # let ctx = device.create_context();
+/// let mut image = PitchedMemory::<f32, Ix2>::zeros(&ctx, (640, 480).into());
+/// assert!(image.pitch() >= 640 * 4);
+///
+/// let src = vec![1.0; 640 * 480];
+/// image.copy_from(src.as_slice());
+///
+/// let mut dst = vec![0.0; 640 * 480];
+/// dst.as_mut_slice().copy_from(&image);
+/// assert_eq!(dst, src);
+/// ```
+///
+/// [Array]: ./struct.Array.html
+/// [PitchedPtr]: ./struct.PitchedPtr.html
+/// [PitchedPtrMut]: ./struct.PitchedPtrMut.html
+#[derive(Contexted)]
+pub struct PitchedMemory<T, Dim> {
+    raw: PitchedPtrMut<T>,
+    dim: Dim,
+    context: Context,
+}
+
+unsafe impl<T, Dim> Sync for PitchedMemory<T, Dim> {}
+unsafe impl<T, Dim> Send for PitchedMemory<T, Dim> {}
+
+impl<T, Dim> Drop for PitchedMemory<T, Dim> {
+    fn drop(&mut self) {
+        if let Err(e) = unsafe { contexted_call!(self, mem_free, self.raw.ptr as CUdeviceptr) } {
+            error!("Failed to free pitched memory: {:?}", e);
+        }
+    }
+}
+
+impl<T, Dim: Dimension> fmt::Debug for PitchedMemory<T, Dim> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PitchedMemory")
+            .field("context", &self.context)
+            .field("ptr", &self.raw.ptr)
+            .field("pitch", &self.raw.pitch)
+            .field("dim", &self.dim)
+            .finish()
+    }
+}
+
+impl<T: Scalar, Dim: Dimension> PitchedMemory<T, Dim> {
+    /// Get dimension
+    pub fn dim(&self) -> &Dim {
+        &self.dim
+    }
+
+    /// Size of each row in bytes including padding
+    pub fn pitch(&self) -> usize {
+        self.raw.pitch
+    }
+
+    /// Read-only pointer and pitch
+    pub fn as_pitched_ptr(&self) -> PitchedPtr<T> {
+        PitchedPtr {
+            ptr: self.raw.ptr,
+            pitch: self.raw.pitch,
+            height: self.raw.height,
+        }
+    }
+
+    /// Mutable pointer and pitch
+    pub fn as_pitched_ptr_mut(&mut self) -> PitchedPtrMut<T> {
+        self.raw
+    }
+
+    /// Size of "CUDA array element" in bytes
+    fn elem_bytes(&self) -> usize {
+        T::size_of() * self.dim.num_channels().to_usize().unwrap()
+    }
+
+    /// Size of each row in bytes without padding
+    fn row_bytes(&self) -> usize {
+        self.dim.width() * self.elem_bytes()
+    }
+
+    fn extent(&self) -> Position {
+        (self.dim.width(), self.dim.height(), self.dim.depth())
+    }
+
+    fn side(&self) -> Side {
+        Side::linear(self.raw.ptr, self.raw.pitch, self.dim.height())
+    }
+
+    /// Copy `extent` from `src` at `src_offset` into `self` at `dst_offset`
+    ///
+    /// ```
+    /// # use accel::*;
+    /// # let device = Device::nth(0).unwrap();
+    /// # let ctx = device.create_context();
+    /// let src = PitchedMemory::<u32, Ix2>::from_elem(&ctx, (4, 4).into(), 1);
+    /// let mut dst = PitchedMemory::<u32, Ix2>::zeros(&ctx, (4, 4).into());
+    /// dst.copy_region_from((2, 2, 0), &src, (0, 0, 0), (2, 2, 1));
+    ///
+    /// let mut host = vec![0; 16];
+    /// host.as_mut_slice().copy_from(&dst);
+    /// assert_eq!(&host[8..], &[0, 0, 1, 1, 0, 0, 1, 1]);
+    /// ```
+    ///
+    /// Panic
+    /// ------
+    /// - if the regions are out of memories
+    /// - if the numbers of channels mismatch
+    pub fn copy_region_from<D: Dimension>(
+        &mut self,
+        dst_offset: Position,
+        src: &PitchedMemory<T, D>,
+        src_offset: Position,
+        extent: Position,
+    ) {
+        assert_eq!(self.dim.num_channels(), src.dim.num_channels());
+        assert_region(self.extent(), dst_offset, extent);
+        assert_region(src.extent(), src_offset, extent);
+        let param = memcpy3d_param(
+            (&src.side(), src_offset),
+            (&self.side(), dst_offset),
+            extent,
+            self.elem_bytes(),
+        );
+        unsafe { memcpy_pitched(self, &param) }.expect("memcpy between pitched memories failed");
+    }
+
+    /// Copy `src` into `self` at `dst_offset`, regarding `src` as a dense memory of `extent`
+    ///
+    /// Panic
+    /// ------
+    /// - if the region is out of `self`
+    /// - if the size of `src` mismatches to `extent`
+    pub fn copy_region_from_slice(&mut self, dst_offset: Position, src: &[T], extent: Position) {
+        assert_region(self.extent(), dst_offset, extent);
+        let side = dense_side(src.as_ptr(), extent, self.elem_bytes(), src.len());
+        let param = memcpy3d_param(
+            (&side, (0, 0, 0)),
+            (&self.side(), dst_offset),
+            extent,
+            self.elem_bytes(),
+        );
+        unsafe { memcpy_pitched(self, &param) }.expect("memcpy into pitched memory failed");
+    }
+
+    /// Copy `extent` of `self` at `src_offset` into `dst`, regarding `dst` as a dense memory of `extent`
+    ///
+    /// Panic
+    /// ------
+    /// - if the region is out of `self`
+    /// - if the size of `dst` mismatches to `extent`
+    pub fn copy_region_to_slice(&self, src_offset: Position, dst: &mut [T], extent: Position) {
+        assert_region(self.extent(), src_offset, extent);
+        let side = dense_side(dst.as_ptr(), extent, self.elem_bytes(), dst.len());
+        let param = memcpy3d_param(
+            (&self.side(), src_offset),
+            (&side, (0, 0, 0)),
+            extent,
+            self.elem_bytes(),
+        );
+        unsafe { memcpy_pitched(self, &param) }.expect("memcpy from pitched memory failed");
+    }
+}
+
+fn assert_region((width, height, depth): Position, (x, y, z): Position, (w, h, d): Position) {
+    assert!(
+        x + w <= width && y + h <= height && z + d <= depth,
+        "Region is out of memory"
+    );
+}
+
+/// One side of 2D/3D copy
+struct Side {
+    memory_type: CUmemorytype,
+    ptr: CUdeviceptr,
+    array: CUarray,
+    pitch: usize,
+    height: usize,
+}
+
+impl Side {
+    /// Linear memory in the unified address space
+    fn linear<T>(ptr: *const T, pitch: usize, height: usize) -> Self {
+        Side {
+            memory_type: CUmemorytype_enum::CU_MEMORYTYPE_UNIFIED,
+            ptr: ptr as CUdeviceptr,
+            array: std::ptr::null_mut(),
+            pitch,
+            height,
+        }
+    }
+
+    fn array<T: Scalar, Dim: Dimension>(array: &Array<T, Dim>) -> Self {
+        Side {
+            memory_type: CUmemorytype_enum::CU_MEMORYTYPE_ARRAY,
+            ptr: 0,
+            array: array.raw(),
+            pitch: 0,
+            height: 0,
+        }
+    }
+}
+
+/// Densely packed memory of `extent`, e.g. a slice
+fn dense_side<T>(ptr: *const T, (w, h, d): Position, elem_bytes: usize, len: usize) -> Side {
+    assert_eq!(
+        w * h * d * elem_bytes,
+        len * std::mem::size_of::<T>(),
+        "Size of memory mismatches to the region"
+    );
+    Side::linear(ptr, w * elem_bytes, h)
+}
+
+/// `CUDA_MEMCPY3D` copying `extent` from `src` at its offset into `dst` at its offset
+fn memcpy3d_param(
+    (src, (sx, sy, sz)): (&Side, Position),
+    (dst, (dx, dy, dz)): (&Side, Position),
+    (w, h, d): Position,
+    elem_bytes: usize,
+) -> CUDA_MEMCPY3D {
+    CUDA_MEMCPY3D {
+        srcXInBytes: sx * elem_bytes,
+        srcY: sy,
+        srcZ: sz,
+        srcMemoryType: src.memory_type,
+        srcDevice: src.ptr,
+        srcArray: src.array,
+        srcPitch: src.pitch,
+        srcHeight: src.height,
+
+        dstXInBytes: dx * elem_bytes,
+        dstY: dy,
+        dstZ: dz,
+        dstMemoryType: dst.memory_type,
+        dstDevice: dst.ptr,
+        dstArray: dst.array,
+        dstPitch: dst.pitch,
+        dstHeight: dst.height,
+
+        WidthInBytes: w * elem_bytes,
+        Height: h,
+        Depth: d,
+
+        ..memcpy3d_param_empty()
+    }
+}
+
+/// `cuMemcpy2D` is used if possible, i.e. a single layer between linear memories
+fn as_memcpy2d_param(p: &CUDA_MEMCPY3D) -> Option<CUDA_MEMCPY2D> {
+    let array = CUmemorytype_enum::CU_MEMORYTYPE_ARRAY;
+    if p.Depth != 1
+        || p.srcZ != 0
+        || p.dstZ != 0
+        || p.srcMemoryType == array
+        || p.dstMemoryType == array
+    {
+        return None;
+    }
+    Some(CUDA_MEMCPY2D {
+        srcXInBytes: p.srcXInBytes,
+        srcY: p.srcY,
+        srcMemoryType: p.srcMemoryType,
+        srcHost: p.srcHost,
+        srcDevice: p.srcDevice,
+        srcArray: p.srcArray,
+        srcPitch: p.srcPitch,
+        dstXInBytes: p.dstXInBytes,
+        dstY: p.dstY,
+        dstMemoryType: p.dstMemoryType,
+        dstHost: p.dstHost,
+        dstDevice: p.dstDevice,
+        dstArray: p.dstArray,
+        dstPitch: p.dstPitch,
+        WidthInBytes: p.WidthInBytes,
+        Height: p.Height,
+    })
+}
+
+unsafe fn memcpy_pitched(ctx: &impl Contexted, p: &CUDA_MEMCPY3D) -> Result<()> {
+    match as_memcpy2d_param(p) {
+        Some(p) => contexted_call!(ctx, memcpy_2d, &p),
+        None => contexted_call!(ctx, memcpy_3d, p),
+    }
+}
+
+fn memcpy_pitched_async<'a>(ctx: &impl Contexted, p: &CUDA_MEMCPY3D) -> BoxFuture<'a, ()> {
+    let stream = stream::Stream::new(ctx.get_ref());
+    unsafe {
+        match as_memcpy2d_param(p) {
+            Some(p) => contexted_call!(ctx, memcpy_2d_async, &p, stream.stream),
+            None => contexted_call!(ctx, memcpy_3d_async, p, stream.stream),
+        }
+    }
+    .expect("Failed to start async memcpy");
+    Box::pin(async { stream.into_future().await.expect("async memcpy failed") })
+}
+
+impl<T: Scalar, Dim: Dimension> MemoryRef for PitchedMemory<T, Dim> {
+    type Elem = T;
+
+    fn head_addr(&self) -> *const T {
+        self.raw.ptr
+    }
+
+    fn num_elem(&self) -> usize {
+        self.dim.len()
+    }
+
+    fn memory_type(&self) -> MemoryType {
+        MemoryType::Device
+    }
+}
+
+impl<T: Scalar, Dim: Dimension> Memory for PitchedMemory<T, Dim> {
+    fn head_addr_mut(&mut self) -> *mut T {
+        self.raw.ptr
+    }
+
+    fn set(&mut self, value: T) {
+        // padding is also set since pitch is a multiple of element size
+        let n = self.raw.pitch * self.dim.height() * self.dim.depth() / T::size_of();
+        memset(self, self.raw.ptr as CUdeviceptr, value, n);
+    }
+
+    fn set_zero_u8(&mut self) {
+        let bytes = self.raw.pitch * self.dim.height() * self.dim.depth();
+        unsafe { contexted_call!(self, memset_d8, self.raw.ptr as CUdeviceptr, 0u8, bytes) }
+            .expect("zero memset failed for pitched memory");
+    }
+}
+
+impl<T: Scalar, Dim: Dimension> Allocatable for PitchedMemory<T, Dim> {
+    type Shape = Dim;
+    unsafe fn uninitialized(context: &Context, dim: Dim) -> Self {
+        assert!(!dim.is_zero(), "Zero-sized malloc is forbidden");
+        let elem_bytes = T::size_of() * dim.num_channels().to_usize().unwrap();
+        // cuMemAllocPitch accepts only 4, 8 or 16 as the size of element
+        let element_size = match elem_bytes {
+            0..=4 => 4,
+            5..=8 => 8,
+            _ => 16,
+        };
+        let (ptr, pitch) = contexted_call!(
+            context,
+            mem_alloc_pitch,
+            dim.width() * elem_bytes,
+            dim.height() * dim.depth(),
+            element_size
+        )
+        .expect("Cannot allocate pitched memory");
+        PitchedMemory {
+            raw: PitchedPtrMut {
+                ptr: ptr as *mut T,
+                pitch,
+                height: dim.height(),
+            },
+            dim,
+            context: context.clone(),
+        }
+    }
+}
+
+impl<T: Scalar, Dim: Dimension> Memcpy<[T]> for PitchedMemory<T, Dim> {
+    fn copy_from(&mut self, src: &[T]) {
+        assert_eq!(self.num_elem(), src.num_elem());
+        let side = Side::linear(src.as_ptr(), self.row_bytes(), self.dim.height());
+        let param = memcpy3d_param(
+            (&side, (0, 0, 0)),
+            (&self.side(), (0, 0, 0)),
+            self.extent(),
+            self.elem_bytes(),
+        );
+        unsafe { memcpy_pitched(self, &param) }.expect("memcpy into pitched memory failed");
+    }
+
+    fn copy_from_async<'a>(&'a mut self, src: &'a [T]) -> BoxFuture<'a, ()> {
+        assert_eq!(self.num_elem(), src.num_elem());
+        let side = Side::linear(src.as_ptr(), self.row_bytes(), self.dim.height());
+        let param = memcpy3d_param(
+            (&side, (0, 0, 0)),
+            (&self.side(), (0, 0, 0)),
+            self.extent(),
+            self.elem_bytes(),
+        );
+        memcpy_pitched_async(self, &param)
+    }
+}
+
+impl<T: Scalar, Dim: Dimension> Memcpy<PitchedMemory<T, Dim>> for [T] {
+    fn copy_from(&mut self, src: &PitchedMemory<T, Dim>) {
+        assert_eq!(self.num_elem(), src.num_elem());
+        let side = Side::linear(self.as_ptr(), src.row_bytes(), src.dim.height());
+        let param = memcpy3d_param(
+            (&src.side(), (0, 0, 0)),
+            (&side, (0, 0, 0)),
+            src.extent(),
+            src.elem_bytes(),
+        );
+        unsafe { memcpy_pitched(src, &param) }.expect("memcpy from pitched memory failed");
+    }
+
+    fn copy_from_async<'a>(&'a mut self, src: &'a PitchedMemory<T, Dim>) -> BoxFuture<'a, ()> {
+        assert_eq!(self.num_elem(), src.num_elem());
+        let side = Side::linear(self.as_ptr(), src.row_bytes(), src.dim.height());
+        let param = memcpy3d_param(
+            (&src.side(), (0, 0, 0)),
+            (&side, (0, 0, 0)),
+            src.extent(),
+            src.elem_bytes(),
+        );
+        memcpy_pitched_async(src, &param)
+    }
+}
+
+macro_rules! impl_memcpy_pitched {
+    ($t:path) => {
+        impl<T: Scalar, Dim: Dimension> Memcpy<PitchedMemory<T, Dim>> for $t {
+            fn copy_from(&mut self, src: &PitchedMemory<T, Dim>) {
+                self.as_mut_slice().copy_from(src);
+            }
+            fn copy_from_async<'a>(
+                &'a mut self,
+                src: &'a PitchedMemory<T, Dim>,
+            ) -> BoxFuture<'a, ()> {
+                self.as_mut_slice().copy_from_async(src)
+            }
+        }
+
+        impl<T: Scalar, Dim: Dimension> Memcpy<$t> for PitchedMemory<T, Dim> {
+            fn copy_from(&mut self, src: &$t) {
+                self.copy_from(src.as_slice());
+            }
+            fn copy_from_async<'a>(&'a mut self, src: &'a $t) -> BoxFuture<'a, ()> {
+                self.copy_from_async(src.as_slice())
+            }
+        }
+    };
+}
+
+impl_memcpy_pitched!(ManagedMemory::<T>);
+impl_memcpy_pitched!(PageLockedMemory::<T>);
+impl_memcpy_pitched!(RegisteredMemory::<'_, T>);
+
+impl<T: Scalar, Dim: Dimension> Memcpy<DeviceMemory<T>> for PitchedMemory<T, Dim> {
+    fn copy_from(&mut self, src: &DeviceMemory<T>) {
+        assert_eq!(self.num_elem(), src.num_elem());
+        let side = Side::linear(src.head_addr(), self.row_bytes(), self.dim.height());
+        let param = memcpy3d_param(
+            (&side, (0, 0, 0)),
+            (&self.side(), (0, 0, 0)),
+            self.extent(),
+            self.elem_bytes(),
+        );
+        unsafe { memcpy_pitched(self, &param) }.expect("memcpy into pitched memory failed");
+    }
+
+    fn copy_from_async<'a>(&'a mut self, src: &'a DeviceMemory<T>) -> BoxFuture<'a, ()> {
+        assert_eq!(self.num_elem(), src.num_elem());
+        let side = Side::linear(src.head_addr(), self.row_bytes(), self.dim.height());
+        let param = memcpy3d_param(
+            (&side, (0, 0, 0)),
+            (&self.side(), (0, 0, 0)),
+            self.extent(),
+            self.elem_bytes(),
+        );
+        memcpy_pitched_async(self, &param)
+    }
+}
+
+impl<T: Scalar, Dim: Dimension> Memcpy<PitchedMemory<T, Dim>> for DeviceMemory<T> {
+    fn copy_from(&mut self, src: &PitchedMemory<T, Dim>) {
+        assert_eq!(self.num_elem(), src.num_elem());
+        let side = Side::linear(self.head_addr(), src.row_bytes(), src.dim.height());
+        let param = memcpy3d_param(
+            (&src.side(), (0, 0, 0)),
+            (&side, (0, 0, 0)),
+            src.extent(),
+            src.elem_bytes(),
+        );
+        unsafe { memcpy_pitched(src, &param) }.expect("memcpy from pitched memory failed");
+    }
+
+    fn copy_from_async<'a>(&'a mut self, src: &'a PitchedMemory<T, Dim>) -> BoxFuture<'a, ()> {
+        assert_eq!(self.num_elem(), src.num_elem());
+        let side = Side::linear(self.head_addr(), src.row_bytes(), src.dim.height());
+        let param = memcpy3d_param(
+            (&src.side(), (0, 0, 0)),
+            (&side, (0, 0, 0)),
+            src.extent(),
+            src.elem_bytes(),
+        );
+        memcpy_pitched_async(src, &param)
+    }
+}
+
+impl<T: Scalar, Dim: Dimension> Memcpy<Array<T, Dim>> for PitchedMemory<T, Dim> {
+    fn copy_from(&mut self, src: &Array<T, Dim>) {
+        assert_eq!(self.dim, *src.dim());
+        let param = memcpy3d_param(
+            (&Side::array(src), (0, 0, 0)),
+            (&self.side(), (0, 0, 0)),
+            self.extent(),
+            self.elem_bytes(),
+        );
+        unsafe { memcpy_pitched(self, &param) }.expect("memcpy from array failed");
+    }
+
+    fn copy_from_async<'a>(&'a mut self, src: &'a Array<T, Dim>) -> BoxFuture<'a, ()> {
+        assert_eq!(self.dim, *src.dim());
+        let param = memcpy3d_param(
+            (&Side::array(src), (0, 0, 0)),
+            (&self.side(), (0, 0, 0)),
+            self.extent(),
+            self.elem_bytes(),
+        );
+        memcpy_pitched_async(self, &param)
+    }
+}
+
+impl<T: Scalar, Dim: Dimension> Memcpy<PitchedMemory<T, Dim>> for Array<T, Dim> {
+    fn copy_from(&mut self, src: &PitchedMemory<T, Dim>) {
+        assert_eq!(*self.dim(), src.dim);
+        let param = memcpy3d_param(
+            (&src.side(), (0, 0, 0)),
+            (&Side::array(self), (0, 0, 0)),
+            src.extent(),
+            src.elem_bytes(),
+        );
+        unsafe { memcpy_pitched(src, &param) }.expect("memcpy into array failed");
+    }
+
+    fn copy_from_async<'a>(&'a mut self, src: &'a PitchedMemory<T, Dim>) -> BoxFuture<'a, ()> {
+        assert_eq!(*self.dim(), src.dim);
+        let param = memcpy3d_param(
+            (&src.side(), (0, 0, 0)),
+            (&Side::array(self), (0, 0, 0)),
+            src.extent(),
+            src.elem_bytes(),
+        );
+        memcpy_pitched_async(src, &param)
+    }
+}
+
+impl<T: Scalar, Dim: Dimension> Memcpy<PitchedMemory<T, Dim>> for PitchedMemory<T, Dim> {
+    fn copy_from(&mut self, src: &PitchedMemory<T, Dim>) {
+        assert_ne!(self.head_addr(), src.head_addr());
+        assert_eq!(self.dim, src.dim);
+        self.copy_region_from((0, 0, 0), src, (0, 0, 0), self.extent());
+    }
+
+    fn copy_from_async<'a>(&'a mut self, src: &'a PitchedMemory<T, Dim>) -> BoxFuture<'a, ()> {
+        assert_ne!(self.head_addr(), src.head_addr());
+        assert_eq!(self.dim, src.dim);
+        let param = memcpy3d_param(
+            (&src.side(), (0, 0, 0)),
+            (&self.side(), (0, 0, 0)),
+            self.extent(),
+            self.elem_bytes(),
+        );
+        memcpy_pitched_async(self, &param)
+    }
+}
+
+impl<T: Scalar, Dim: Dimension> DeviceSend for &PitchedMemory<T, Dim> {
+    type Target = PitchedPtr<T>;
+    fn as_kernel_parameter(&self) -> *mut c_void {
+        // PitchedPtrMut has the same layout as PitchedPtr
+        &self.raw as *const PitchedPtrMut<T> as *mut c_void
+    }
+}
+
+impl<T: Scalar, Dim: Dimension> DeviceSend for &mut PitchedMemory<T, Dim> {
+    type Target = PitchedPtrMut<T>;
+    fn as_kernel_parameter(&self) -> *mut c_void {
+        &self.raw as *const PitchedPtrMut<T> as *mut c_void
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pitch() -> Result<()> {
+        let device = Device::nth(0)?;
+        let context = device.create_context();
+        let mem = PitchedMemory::<u8, Ix2>::zeros(&context, (3, 5).into());
+        assert!(mem.pitch() >= 3);
+        assert_eq!(mem.num_elem(), 15);
+        assert_eq!(mem.memory_type(), MemoryType::Device);
+        let raw = mem.as_pitched_ptr();
+        assert_eq!(raw.ptr, mem.head_addr());
+        assert_eq!(raw.height, 5);
+        Ok(())
+    }
+
+    #[should_panic(expected = "Zero-sized malloc is forbidden")]
+    #[test]
+    fn pitched_new_zero() {
+        let device = Device::nth(0).unwrap();
+        let context = device.create_context();
+        let _a = PitchedMemory::<u32, Ix2>::zeros(&context, (0, 3).into());
+    }
+
+    #[test]
+    fn memcpy_h2p2h_3d() -> Result<()> {
+        let device = Device::nth(0)?;
+        let context = device.create_context();
+        let (n, m, l) = (3, 4, 2);
+        let src: Vec<u32> = (0..(n * m * l) as u32).collect();
+        let mut dst = vec![0; n * m * l];
+        let mut mem = PitchedMemory::<u32, Ix3>::zeros(&context, (n, m, l).into());
+        mem.copy_from(src.as_slice());
+        dst.as_mut_slice().copy_from(&mem);
+        assert_eq!(dst, src);
+        Ok(())
+    }
+
+    #[test]
+    fn memcpy_d2p2d_2d() -> Result<()> {
+        let device = Device::nth(0)?;
+        let context = device.create_context();
+        let (n, m) = (3, 4);
+        let src: Vec<f32> = (0..n * m).map(|i| i as f32).collect();
+        let mut dev = DeviceMemory::<f32>::zeros(&context, n * m);
+        dev.copy_from(src.as_slice());
+        let mut mem = PitchedMemory::<f32, Ix2>::zeros(&context, (n, m).into());
+        mem.copy_from(&dev);
+        let mut dst = DeviceMemory::<f32>::zeros(&context, n * m);
+        dst.copy_from(&mem);
+        assert_eq!(dst.to_vec(), src);
+        Ok(())
+    }
+
+    #[test]
+    fn memcpy_p2a2p_2d() -> Result<()> {
+        let device = Device::nth(0)?;
+        let context = device.create_context();
+        let (n, m) = (3, 4);
+        let src: Vec<i32> = (0..(n * m) as i32).collect();
+        let mut mem = PitchedMemory::<i32, Ix2>::zeros(&context, (n, m).into());
+        mem.copy_from(src.as_slice());
+        let mut array = Array::<i32, Ix2>::zeros(&context, (n, m).into());
+        array.copy_from(&mem);
+        let mut back = PitchedMemory::<i32, Ix2>::zeros(&context, (n, m).into());
+        back.copy_from(&array);
+        let mut dst = vec![0; n * m];
+        dst.as_mut_slice().copy_from(&back);
+        assert_eq!(dst, src);
+        Ok(())
+    }
+
+    #[test]
+    fn copy_region() -> Result<()> {
+        let device = Device::nth(0)?;
+        let context = device.create_context();
+        let mut mem = PitchedMemory::<u16, Ix2>::from_elem(&context, (4, 3).into(), 9);
+        mem.copy_region_from_slice((1, 1, 0), &[1, 2, 3, 4], (2, 2, 1));
+        let mut row = vec![0; 4];
+        mem.copy_region_to_slice((0, 2, 0), &mut row, (4, 1, 1));
+        assert_eq!(row, vec![9, 3, 4, 9]);
+
+        let mut other = PitchedMemory::<u16, Ix2>::zeros(&context, (2, 2).into());
+        other.copy_region_from((0, 0, 0), &mem, (1, 1, 0), (2, 2, 1));
+        let mut dst = vec![0; 4];
+        dst.as_mut_slice().copy_from(&other);
+        assert_eq!(dst, vec![1, 2, 3, 4]);
+        Ok(())
+    }
+
+    #[should_panic(expected = "Region is out of memory")]
+    #[test]
+    fn copy_region_out_of_range() {
+        let device = Device::nth(0).unwrap();
+        let context = device.create_context();
+        let mut mem = PitchedMemory::<u16, Ix2>::zeros(&context, (4, 3).into());
+        mem.copy_region_from_slice((3, 0, 0), &[1, 2], (2, 1, 1));
+    }
+
+    #[tokio::test]
+    async fn memcpy_async() -> Result<()> {
+        let device = Device::nth(0)?;
+        let context = device.create_context();
+        let src = PageLockedMemory::from_elem(&context, 12, 3_u32);
+        let mut dst = PageLockedMemory::zeros(&context, 12);
+        let mut mem = PitchedMemory::<u32, Ix2>::zeros(&context, (4, 3).into());
+        mem.copy_from_async(&src).await;
+        dst.copy_from_async(&mem).await;
+        assert_eq!(dst.as_slice(), src.as_slice());
+        Ok(())
+    }
+
+    #[test]
+    fn kernel_parameter() -> Result<()> {
+        let device = Device::nth(0)?;
+        let context = device.create_context();
+        let mut mem = PitchedMemory::<f32, Ix2>::zeros(&context, (4, 3).into());
+        let param = (&mem).as_kernel_parameter() as *const PitchedPtr<f32>;
+        assert_eq!(unsafe { *param }, mem.as_pitched_ptr());
+        let param = (&mut mem).as_kernel_parameter() as *const PitchedPtrMut<f32>;
+        assert_eq!(unsafe { *param }, mem.as_pitched_ptr_mut());
+        Ok(())
+    }
+}
@@ -0,0 +1,956 @@
+//! Caching allocator of device, managed and page-locked memory
+//!
+//! Allocating and freeing memory by the driver synchronizes the device,
+//! and dominates runtime if many short-lived buffers are used.
+//! [MemoryPool] keeps freed blocks in free lists of size classes, and reuses them for later allocations:
+//!
+//! - Requests are rounded up to a size class, i.e. a power of two from 512 bytes,
+//!   or a multiple of 2MB for requests larger than 2MB. Cached blocks are reused only for the same class.
+//! - Cached blocks exceeding [PoolConfig::release_threshold] are returned to the driver, largest first.
+//! - Blocks larger than [PoolConfig::max_cached_block] are not cached.
+//! - Returned blocks may still be used by asynchronous operations, e.g. a dropped future of `copy_from_async`.
+//!   They are pending until the context is synchronized, which is done once for all pending blocks
+//!   when a block of their size class is requested.
+//! - If the driver fails to allocate, every cached block is freed and the allocation is retried once.
+//!
+//! Bookkeeping is done by [PoolCache] through the [RawAllocator] trait,
+//! which is implemented for the driver by [DeviceAllocator], [ManagedAllocator] and [PageLockedAllocator].
+//! Buffers of a [HostAccessible] allocator can be treated as a Rust slice.
+//!
+//! ```
+//! use accel::{*, memory::pool::*};
+//! let device = Device::nth(0).unwrap();
+//! let ctx = device.create_context();
+//! let pool = DevicePool::new(DeviceAllocator::new(&ctx), PoolConfig::default());
+//! for _ in 0..10 {
+//!     let mut a = pool.zeros::<f32>(1000);
+//!     let b = vec![1.0; 1000];
+//!     a.copy_from(b.as_slice());
+//! }
+//! let stats = pool.stats();
+//! assert_eq!(stats.misses, 1); // allocated by the driver only once
+//! assert_eq!(stats.hits, 9);
+//! ```
+//!
+//! [MemoryPool]: struct.MemoryPool.html
+//! [PoolCache]: struct.PoolCache.html
+//! [RawAllocator]: trait.RawAllocator.html
+//! [DeviceAllocator]: struct.DeviceAllocator.html
+//! [ManagedAllocator]: struct.ManagedAllocator.html
+//! [HostAccessible]: trait.HostAccessible.html
+//! [PageLockedAllocator]: struct.PageLockedAllocator.html
+//! [PoolConfig::release_threshold]: struct.PoolConfig.html#structfield.release_threshold
+//! [PoolConfig::max_cached_block]: struct.PoolConfig.html#structfield.max_cached_block
+
+use super::{fill::memset, *};
+use crate::error::*;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+
+/// Smallest size class in bytes
+pub const MIN_BLOCK_SIZE: usize = 512;
+
+/// Size classes larger than this are multiples of it instead of powers of two
+pub const LARGE_BLOCK_UNIT: usize = 2 * 1024 * 1024;
+
+/// Size class of a request of `bytes`
+///
+/// ```
+/// use accel::memory::pool::*;
+/// assert_eq!(size_class(1), MIN_BLOCK_SIZE);
+/// assert_eq!(size_class(1000), 1024);
+/// assert_eq!(size_class(LARGE_BLOCK_UNIT + 1), 2 * LARGE_BLOCK_UNIT);
+/// ```
+pub fn size_class(bytes: usize) -> usize {
+    if bytes <= LARGE_BLOCK_UNIT {
+        bytes.next_power_of_two().max(MIN_BLOCK_SIZE)
+    } else {
+        (bytes + LARGE_BLOCK_UNIT - 1) / LARGE_BLOCK_UNIT * LARGE_BLOCK_UNIT
+    }
+}
+
+/// Allocation and free of raw memory, e.g. by the driver
+pub trait RawAllocator: Send {
+    /// Type of allocated memory
+    fn memory_type(&self) -> MemoryType;
+
+    /// Allocate `bytes` bytes, and returns its address
+    fn allocate(&self, bytes: usize) -> Result<usize>;
+
+    /// Free memory allocated by [allocate](#tymethod.allocate)
+    ///
+    /// Safety
+    /// ------
+    /// - `addr` must be allocated by this allocator, and not be used after free
+    unsafe fn free(&self, addr: usize) -> Result<()>;
+
+    /// Wait until every operation which may use the allocated memory has completed
+    fn synchronize(&self) -> Result<()>;
+}
+
+/// Allocators of memory accessible from the host
+pub trait HostAccessible: RawAllocator {}
+
+/// Allocator of device memory with `cuMemAlloc`, same as [DeviceMemory](../struct.DeviceMemory.html)
+#[derive(Debug, Contexted)]
+pub struct DeviceAllocator {
+    context: Context,
+}
+
+impl DeviceAllocator {
+    pub fn new(context: &Context) -> Self {
+        DeviceAllocator {
+            context: context.clone(),
+        }
+    }
+}
+
+impl RawAllocator for DeviceAllocator {
+    fn memory_type(&self) -> MemoryType {
+        MemoryType::Device
+    }
+
+    fn allocate(&self, bytes: usize) -> Result<usize> {
+        let ptr = unsafe { contexted_call!(self, mem_alloc, bytes) }?;
+        Ok(ptr as usize)
+    }
+
+    unsafe fn free(&self, addr: usize) -> Result<()> {
+        contexted_call!(self, mem_free, addr as CUdeviceptr)
+    }
+
+    fn synchronize(&self) -> Result<()> {
+        self.context.sync()
+    }
+}
+
+/// Allocator of managed memory with `cuMemAllocManaged`, same as [ManagedMemory](../struct.ManagedMemory.html)
+#[derive(Debug, Contexted)]
+pub struct ManagedAllocator {
+    context: Context,
+}
+
+impl ManagedAllocator {
+    pub fn new(context: &Context) -> Self {
+        ManagedAllocator {
+            context: context.clone(),
+        }
+    }
+}
+
+impl RawAllocator for ManagedAllocator {
+    fn memory_type(&self) -> MemoryType {
+        MemoryType::Managed
+    }
+
+    fn allocate(&self, bytes: usize) -> Result<usize> {
+        let ptr = unsafe {
+            contexted_call!(
+                self,
+                mem_alloc_managed,
+                bytes,
+                AttachFlag::CU_MEM_ATTACH_GLOBAL as u32
+            )
+        }?;
+        Ok(ptr as usize)
+    }
+
+    unsafe fn free(&self, addr: usize) -> Result<()> {
+        contexted_call!(self, mem_free, addr as CUdeviceptr)
+    }
+
+    fn synchronize(&self) -> Result<()> {
+        self.context.sync()
+    }
+}
+
+impl HostAccessible for ManagedAllocator {}
+
+/// Allocator of page-locked host memory with `cuMemAllocHost`, same as [PageLockedMemory](../struct.PageLockedMemory.html)
+#[derive(Debug, Contexted)]
+pub struct PageLockedAllocator {
+    context: Context,
+}
+
+impl PageLockedAllocator {
+    pub fn new(context: &Context) -> Self {
+        PageLockedAllocator {
+            context: context.clone(),
+        }
+    }
+}
+
+impl RawAllocator for PageLockedAllocator {
+    fn memory_type(&self) -> MemoryType {
+        MemoryType::PageLocked
+    }
+
+    fn allocate(&self, bytes: usize) -> Result<usize> {
+        let ptr = unsafe { contexted_call!(self, mem_alloc_host, bytes) }?;
+        Ok(ptr as usize)
+    }
+
+    unsafe fn free(&self, addr: usize) -> Result<()> {
+        contexted_call!(self, mem_free_host, addr as *mut c_void)
+    }
+
+    fn synchronize(&self) -> Result<()> {
+        self.context.sync()
+    }
+}
+
+impl HostAccessible for PageLockedAllocator {}
+
+/// Configuration of [MemoryPool](struct.MemoryPool.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// Cached blocks are returned to the driver while the cached bytes exceed this. Unlimited by default.
+    pub release_threshold: usize,
+    /// Blocks larger than this are returned to the driver instead of cached. Unlimited by default.
+    pub max_cached_block: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            release_threshold: std::usize::MAX,
+            max_cached_block: std::usize::MAX,
+        }
+    }
+}
+
+/// Statistics of [MemoryPool](struct.MemoryPool.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PoolStats {
+    /// Bytes allocated by the driver and not freed, i.e. in use and cached
+    pub reserved_bytes: usize,
+    /// Peak of `reserved_bytes`
+    pub peak_reserved_bytes: usize,
+    /// Bytes of blocks handed out and not returned
+    pub in_use_bytes: usize,
+    /// Bytes of blocks in the free lists, including pending ones
+    pub cached_bytes: usize,
+    /// Number of requests served by a cached block
+    pub hits: usize,
+    /// Number of requests allocated by the driver
+    pub misses: usize,
+    /// Number of blocks freed by the driver
+    pub releases: usize,
+}
+
+/// Block handed out by [PoolCache](struct.PoolCache.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    pub addr: usize,
+    /// Size class in bytes
+    pub size: usize,
+}
+
+/// Size-class free lists of blocks allocated by `A`
+///
+/// This is the bookkeeping of [MemoryPool](struct.MemoryPool.html) without synchronization,
+/// and does not touch the memory itself.
+pub struct PoolCache<A: RawAllocator> {
+    allocator: A,
+    config: PoolConfig,
+    /// Cached blocks keyed by its size class, without empty classes
+    free: BTreeMap<usize, Vec<usize>>,
+    /// Released blocks keyed by its size class, which may be in use until the allocator is synchronized
+    pending: BTreeMap<usize, Vec<usize>>,
+    stats: PoolStats,
+}
+
+impl<A: RawAllocator> fmt::Debug for PoolCache<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoolCache")
+            .field("config", &self.config)
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+impl<A: RawAllocator> Drop for PoolCache<A> {
+    fn drop(&mut self) {
+        self.trim(0);
+    }
+}
+
+impl<A: RawAllocator> PoolCache<A> {
+    pub fn new(allocator: A, config: PoolConfig) -> Self {
+        PoolCache {
+            allocator,
+            config,
+            free: BTreeMap::new(),
+            pending: BTreeMap::new(),
+            stats: PoolStats::default(),
+        }
+    }
+
+    pub fn allocator(&self) -> &A {
+        &self.allocator
+    }
+
+    pub fn config(&self) -> &PoolConfig {
+        &self.config
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.stats
+    }
+
+    /// Take a cached block of the size class of `bytes`, or allocate a new one
+    ///
+    /// The allocator is synchronized if only pending blocks are cached for the class.
+    pub fn acquire(&mut self, bytes: usize) -> Result<Block> {
+        let size = size_class(bytes);
+        if !self.free.contains_key(&size) && self.pending.contains_key(&size) {
+            self.allocator.synchronize()?;
+            for (size, mut blocks) in std::mem::take(&mut self.pending) {
+                self.free.entry(size).or_default().append(&mut blocks);
+            }
+        }
+        if let Some(addr) = pop_block(&mut self.free, size) {
+            self.stats.hits += 1;
+            self.stats.cached_bytes -= size;
+            self.stats.in_use_bytes += size;
+            return Ok(Block { addr, size });
+        }
+        let addr = match self.allocator.allocate(size) {
+            Ok(addr) => addr,
+            Err(_) if self.stats.cached_bytes > 0 => {
+                // Retry after returning cached blocks to the driver
+                self.trim(0);
+                self.allocator.allocate(size)?
+            }
+            Err(e) => return Err(e),
+        };
+        self.stats.misses += 1;
+        self.stats.in_use_bytes += size;
+        self.stats.reserved_bytes += size;
+        self.stats.peak_reserved_bytes = self
+            .stats
+            .peak_reserved_bytes
+            .max(self.stats.reserved_bytes);
+        Ok(Block { addr, size })
+    }
+
+    /// Return a block as pending, and release cached blocks exceeding the threshold
+    ///
+    /// The block is not reused until the allocator is synchronized,
+    /// i.e. it may be used by operations already submitted to the device.
+    ///
+    /// Safety
+    /// ------
+    /// - `block` must be acquired from this cache, and not be used by operations submitted after release
+    pub unsafe fn release(&mut self, block: Block) {
+        self.stats.in_use_bytes -= block.size;
+        if block.size > self.config.max_cached_block {
+            self.free_block(block);
+            return;
+        }
+        self.pending.entry(block.size).or_default().push(block.addr);
+        self.stats.cached_bytes += block.size;
+        if self.stats.cached_bytes > self.config.release_threshold {
+            self.trim(self.config.release_threshold);
+        }
+    }
+
+    /// Return cached blocks to the driver, largest first, until cached bytes are at most `max_cached_bytes`
+    ///
+    /// Pending blocks are also returned since the driver frees memory after operations using it.
+    pub fn trim(&mut self, max_cached_bytes: usize) {
+        while self.stats.cached_bytes > max_cached_bytes {
+            let free = self.free.keys().next_back().cloned();
+            let pending = self.pending.keys().next_back().cloned();
+            let (blocks, size) = match (free, pending) {
+                (Some(free), Some(pending)) if free >= pending => (&mut self.free, free),
+                (_, Some(pending)) => (&mut self.pending, pending),
+                (Some(free), None) => (&mut self.free, free),
+                (None, None) => break,
+            };
+            let addr = pop_block(blocks, size).unwrap();
+            self.stats.cached_bytes -= size;
+            unsafe { self.free_block(Block { addr, size }) };
+        }
+    }
+
+    unsafe fn free_block(&mut self, block: Block) {
+        if let Err(e) = self.allocator.free(block.addr) {
+            log::error!("Failed to free pooled memory: {:?}", e);
+        }
+        self.stats.reserved_bytes -= block.size;
+        self.stats.releases += 1;
+    }
+}
+
+/// Pop a block of the size class, and remove the class if no block remains
+fn pop_block(blocks: &mut BTreeMap<usize, Vec<usize>>, size: usize) -> Option<usize> {
+    let addrs = blocks.get_mut(&size)?;
+    let addr = addrs.pop();
+    if addrs.is_empty() {
+        blocks.remove(&size);
+    }
+    addr
+}
+
+/// Caching allocator shared by its clones, see [module level document](index.html)
+pub struct MemoryPool<A: RawAllocator> {
+    cache: Arc<Mutex<PoolCache<A>>>,
+}
+
+/// Pool of device memory
+pub type DevicePool = MemoryPool<DeviceAllocator>;
+/// Pool of managed memory
+pub type ManagedPool = MemoryPool<ManagedAllocator>;
+/// Pool of page-locked host memory
+pub type PageLockedPool = MemoryPool<PageLockedAllocator>;
+
+impl<A: RawAllocator> Clone for MemoryPool<A> {
+    fn clone(&self) -> Self {
+        MemoryPool {
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<A: RawAllocator> fmt::Debug for MemoryPool<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryPool")
+            .field("cache", &*self.cache.lock().unwrap())
+            .finish()
+    }
+}
+
+impl<A: RawAllocator + Contexted> MemoryPool<A> {
+    pub fn new(allocator: A, config: PoolConfig) -> Self {
+        MemoryPool {
+            cache: Arc::new(Mutex::new(PoolCache::new(allocator, config))),
+        }
+    }
+
+    /// Allocate a buffer of `size` elements without initialization
+    ///
+    /// Safety
+    /// ------
+    /// - Cause undefined behavior when read before write
+    ///
+    /// Panic
+    /// ------
+    /// - if size is zero
+    pub unsafe fn uninitialized<T>(&self, size: usize) -> PooledMemory<T, A> {
+        assert!(size > 0, "Zero-sized malloc is forbidden");
+        let mut cache = self.cache.lock().unwrap();
+        let block = cache
+            .acquire(size * std::mem::size_of::<T>())
+            .expect("Cannot allocate pooled memory");
+        PooledMemory {
+            ptr: block.addr as CUdeviceptr,
+            size,
+            block,
+            memory_type: cache.allocator().memory_type(),
+            context: cache.allocator().get_ref(),
+            cache: self.cache.clone(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Allocate a buffer uniformly initialized by `elem`
+    pub fn from_elem<T>(&self, size: usize, elem: T) -> PooledMemory<T, A>
+    where
+        T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized,
+    {
+        let mut mem = unsafe { self.uninitialized(size) };
+        mem.set(elem);
+        mem
+    }
+
+    /// Allocate a buffer initialized by zero
+    pub fn zeros<T>(&self, size: usize) -> PooledMemory<T, A>
+    where
+        T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized,
+    {
+        let mut mem = unsafe { self.uninitialized(size) };
+        mem.set_zero_u8();
+        mem
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.cache.lock().unwrap().stats()
+    }
+
+    /// Return cached blocks to the driver until cached bytes are at most `max_cached_bytes`
+    pub fn trim(&self, max_cached_bytes: usize) {
+        self.cache.lock().unwrap().trim(max_cached_bytes)
+    }
+}
+
+/// Buffer allocated by [MemoryPool](struct.MemoryPool.html), which returns its block to the pool on drop
+///
+/// This can be treated as a Rust slice only if allocated by a [HostAccessible](trait.HostAccessible.html) allocator.
+pub struct PooledMemory<T, A: RawAllocator> {
+    ptr: CUdeviceptr,
+    size: usize,
+    block: Block,
+    memory_type: MemoryType,
+    context: ContextRef,
+    cache: Arc<Mutex<PoolCache<A>>>,
+    phantom: PhantomData<T>,
+}
+
+/// Device memory allocated by [DevicePool](type.DevicePool.html)
+pub type PooledDeviceMemory<T> = PooledMemory<T, DeviceAllocator>;
+/// Managed memory allocated by [ManagedPool](type.ManagedPool.html)
+pub type PooledManagedMemory<T> = PooledMemory<T, ManagedAllocator>;
+/// Page-locked host memory allocated by [PageLockedPool](type.PageLockedPool.html)
+pub type PooledPageLockedMemory<T> = PooledMemory<T, PageLockedAllocator>;
+
+unsafe impl<T, A: RawAllocator> Sync for PooledMemory<T, A> {}
+unsafe impl<T, A: RawAllocator> Send for PooledMemory<T, A> {}
+
+impl<T, A: RawAllocator> Contexted for PooledMemory<T, A> {
+    fn sync(&self) -> Result<()> {
+        self.context.sync()
+    }
+
+    fn version(&self) -> Result<u32> {
+        self.context.version()
+    }
+
+    fn guard(&self) -> Result<ContextGuard> {
+        self.context.guard()
+    }
+
+    fn get_ref(&self) -> ContextRef {
+        self.context
+    }
+}
+
+impl<T, A: RawAllocator> Drop for PooledMemory<T, A> {
+    fn drop(&mut self) {
+        unsafe { self.cache.lock().unwrap().release(self.block) };
+    }
+}
+
+impl<T, A: RawAllocator> fmt::Debug for PooledMemory<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledMemory")
+            .field("context", &self.context)
+            .field("memory_type", &self.memory_type)
+            .field("ptr", &self.ptr)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+impl<T, A: HostAccessible> Deref for PooledMemory<T, A> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.ptr as _, self.size) }
+    }
+}
+
+impl<T, A: HostAccessible> DerefMut for PooledMemory<T, A> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr as _, self.size) }
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized, A: RawAllocator>
+    MemoryRef for PooledMemory<T, A>
+{
+    type Elem = T;
+    fn head_addr(&self) -> *const T {
+        self.ptr as _
+    }
+
+    fn num_elem(&self) -> usize {
+        self.size
+    }
+
+    fn memory_type(&self) -> MemoryType {
+        self.memory_type
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized, A: RawAllocator> Memory
+    for PooledMemory<T, A>
+{
+    fn head_addr_mut(&mut self) -> *mut T {
+        self.ptr as _
+    }
+
+    fn set(&mut self, value: T) {
+        match self.memory_type {
+            MemoryType::PageLocked => unsafe {
+                std::slice::from_raw_parts_mut(self.ptr as *mut T, self.size)
+                    .iter_mut()
+                    .for_each(|v| *v = value)
+            },
+            _ => memset(self, self.ptr, value, self.size),
+        }
+    }
+
+    fn set_zero_u8(&mut self) {
+        let bytes = self.size * std::mem::size_of::<T>();
+        match self.memory_type {
+            MemoryType::PageLocked => unsafe {
+                std::ptr::write_bytes(self.ptr as *mut u8, 0, bytes)
+            },
+            _ => unsafe { contexted_call!(self, memset_d8, self.ptr, 0u8, bytes) }
+                .expect("zero memset failed for pooled memory"),
+        }
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized, A: HostAccessible>
+    Continuous for PooledMemory<T, A>
+{
+    fn as_slice(&self) -> &[T] {
+        self
+    }
+    fn as_mut_slice(&mut self) -> &mut [T] {
+        self
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized, A: RawAllocator>
+    Memcpy<[T]> for PooledMemory<T, A>
+{
+    fn copy_from(&mut self, src: &[T]) {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy(self, self.ptr as *mut T, src.as_ptr(), self.size);
+    }
+    fn copy_from_async<'a>(&'a mut self, src: &'a [T]) -> BoxFuture<'a, ()> {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy_async(self, self.ptr as *mut T, src.as_ptr(), self.size)
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized, A: RawAllocator>
+    Memcpy<PooledMemory<T, A>> for [T]
+{
+    fn copy_from(&mut self, src: &PooledMemory<T, A>) {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy(src, self.as_mut_ptr(), src.ptr as *const T, src.size);
+    }
+    fn copy_from_async<'a>(&'a mut self, src: &'a PooledMemory<T, A>) -> BoxFuture<'a, ()> {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy_async(src, self.as_mut_ptr(), src.ptr as *const T, src.size)
+    }
+}
+
+impl<T, A, B> Memcpy<PooledMemory<T, B>> for PooledMemory<T, A>
+where
+    T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized,
+    A: RawAllocator,
+    B: RawAllocator,
+{
+    fn copy_from(&mut self, src: &PooledMemory<T, B>) {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy(self, self.ptr as *mut T, src.ptr as *const T, self.size);
+    }
+    fn copy_from_async<'a>(&'a mut self, src: &'a PooledMemory<T, B>) -> BoxFuture<'a, ()> {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy_async(self, self.ptr as *mut T, src.ptr as *const T, self.size)
+    }
+}
+
+macro_rules! impl_memcpy_pooled {
+    ($t:path) => {
+        impl<
+                T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized,
+                A: RawAllocator,
+            > Memcpy<$t> for PooledMemory<T, A>
+        {
+            fn copy_from(&mut self, src: &$t) {
+                assert_eq!(self.num_elem(), src.num_elem());
+                copy(self, self.ptr as *mut T, src.head_addr(), self.size);
+            }
+            fn copy_from_async<'a>(&'a mut self, src: &'a $t) -> BoxFuture<'a, ()> {
+                assert_eq!(self.num_elem(), src.num_elem());
+                copy_async(self, self.ptr as *mut T, src.head_addr(), self.size)
+            }
+        }
+
+        impl<
+                T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized,
+                A: RawAllocator,
+            > Memcpy<PooledMemory<T, A>> for $t
+        {
+            fn copy_from(&mut self, src: &PooledMemory<T, A>) {
+                assert_eq!(self.num_elem(), src.num_elem());
+                copy(src, self.head_addr_mut(), src.ptr as *const T, src.size);
+            }
+            fn copy_from_async<'a>(&'a mut self, src: &'a PooledMemory<T, A>) -> BoxFuture<'a, ()> {
+                assert_eq!(self.num_elem(), src.num_elem());
+                copy_async(src, self.head_addr_mut(), src.ptr as *const T, src.size)
+            }
+        }
+    };
+}
+
+impl_memcpy_pooled!(DeviceMemory::<T>);
+impl_memcpy_pooled!(ManagedMemory::<T>);
+impl_memcpy_pooled!(PageLockedMemory::<T>);
+impl_memcpy_pooled!(RegisteredMemory::<'_, T>);
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized, A: RawAllocator>
+    DeviceSend for &PooledMemory<T, A>
+{
+    type Target = *const T;
+    fn as_kernel_parameter(&self) -> *mut c_void {
+        &self.ptr as *const CUdeviceptr as *mut c_void
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized, A: RawAllocator>
+    DeviceSend for &mut PooledMemory<T, A>
+{
+    type Target = *mut T;
+    fn as_kernel_parameter(&self) -> *mut c_void {
+        &self.ptr as *const CUdeviceptr as *mut c_void
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, collections::HashSet};
+
+    /// Allocator giving fake addresses, which fails beyond `limit` bytes
+    #[derive(Default)]
+    struct MockAllocator {
+        next: Cell<usize>,
+        live: Mutex<HashSet<usize>>,
+        reserved: Cell<usize>,
+        sizes: Mutex<BTreeMap<usize, usize>>,
+        limit: usize,
+        syncs: Cell<usize>,
+    }
+
+    unsafe impl Send for MockAllocator {}
+
+    impl MockAllocator {
+        fn with_limit(limit: usize) -> Self {
+            MockAllocator {
+                next: Cell::new(0x1000),
+                limit,
+                ..Default::default()
+            }
+        }
+    }
+
+    impl RawAllocator for MockAllocator {
+        fn memory_type(&self) -> MemoryType {
+            MemoryType::Device
+        }
+
+        fn allocate(&self, bytes: usize) -> Result<usize> {
+            if self.reserved.get() + bytes > self.limit {
+                return Err(AccelError::CUDAError {
+                    api_name: "mock".into(),
+                    error: cudaError_enum::CUDA_ERROR_OUT_OF_MEMORY,
+                });
+            }
+            let addr = self.next.get();
+            self.next.set(addr + bytes);
+            self.reserved.set(self.reserved.get() + bytes);
+            self.live.lock().unwrap().insert(addr);
+            self.sizes.lock().unwrap().insert(addr, bytes);
+            Ok(addr)
+        }
+
+        unsafe fn free(&self, addr: usize) -> Result<()> {
+            assert!(self.live.lock().unwrap().remove(&addr), "double free");
+            let size = self.sizes.lock().unwrap()[&addr];
+            self.reserved.set(self.reserved.get() - size);
+            Ok(())
+        }
+
+        fn synchronize(&self) -> Result<()> {
+            self.syncs.set(self.syncs.get() + 1);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn size_classes() {
+        assert_eq!(size_class(0), MIN_BLOCK_SIZE);
+        assert_eq!(size_class(MIN_BLOCK_SIZE + 1), 2 * MIN_BLOCK_SIZE);
+        assert_eq!(size_class(LARGE_BLOCK_UNIT), LARGE_BLOCK_UNIT);
+        assert_eq!(size_class(5 * LARGE_BLOCK_UNIT - 1), 5 * LARGE_BLOCK_UNIT);
+    }
+
+    #[test]
+    fn reuse() -> Result<()> {
+        let mut cache = PoolCache::new(
+            MockAllocator::with_limit(std::usize::MAX),
+            PoolConfig::default(),
+        );
+        let a = cache.acquire(1000)?;
+        assert_eq!(a.size, 1024);
+        unsafe { cache.release(a) };
+        let b = cache.acquire(600)?;
+        assert_eq!(b, a);
+        let c = cache.acquire(100)?; // another class
+        assert_ne!(c.addr, a.addr);
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.in_use_bytes, 1024 + 512);
+        assert_eq!(stats.reserved_bytes, 1024 + 512);
+        assert_eq!(stats.cached_bytes, 0);
+        unsafe {
+            cache.release(b);
+            cache.release(c);
+        }
+        assert_eq!(cache.stats().cached_bytes, 1024 + 512);
+        drop(cache); // must free without double free
+        Ok(())
+    }
+
+    #[test]
+    fn sync_before_reuse() -> Result<()> {
+        let mut cache = PoolCache::new(
+            MockAllocator::with_limit(std::usize::MAX),
+            PoolConfig::default(),
+        );
+        let a = cache.acquire(1024)?;
+        let b = cache.acquire(1024)?;
+        let c = cache.acquire(512)?;
+        unsafe {
+            cache.release(a);
+            cache.release(b);
+        }
+        assert_eq!(cache.allocator().syncs.get(), 0);
+        // no pending block of this class
+        let small = cache.acquire(100)?;
+        unsafe { cache.release(small) };
+        assert_eq!(cache.allocator().syncs.get(), 0);
+        // every pending block becomes reusable by a single synchronization
+        let d = cache.acquire(1024)?;
+        let e = cache.acquire(1024)?;
+        let f = cache.acquire(512)?;
+        assert_eq!(cache.allocator().syncs.get(), 1);
+        assert_eq!(cache.stats().misses, 4);
+        assert_ne!(f.addr, c.addr);
+        unsafe {
+            cache.release(c);
+            cache.release(d);
+            cache.release(e);
+            cache.release(f);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn release_threshold() -> Result<()> {
+        let config = PoolConfig {
+            release_threshold: 2048,
+            max_cached_block: 4096,
+        };
+        let mut cache = PoolCache::new(MockAllocator::with_limit(std::usize::MAX), config);
+        let blocks = [
+            cache.acquire(512)?,
+            cache.acquire(1024)?,
+            cache.acquire(2048)?,
+        ];
+        for block in blocks.iter() {
+            unsafe { cache.release(*block) };
+        }
+        // 2048 is released first since it is the largest
+        let stats = cache.stats();
+        assert_eq!(stats.cached_bytes, 512 + 1024);
+        assert_eq!(stats.reserved_bytes, 512 + 1024);
+        assert_eq!(stats.releases, 1);
+
+        // not cached
+        let large = cache.acquire(8192)?;
+        unsafe { cache.release(large) };
+        assert_eq!(cache.stats().cached_bytes, 512 + 1024);
+        assert_eq!(cache.stats().releases, 2);
+
+        cache.trim(512);
+        assert_eq!(cache.stats().cached_bytes, 512);
+        cache.trim(0);
+        assert_eq!(cache.stats().reserved_bytes, 0);
+        assert_eq!(cache.allocator().reserved.get(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn retry_after_trim() -> Result<()> {
+        let mut cache = PoolCache::new(MockAllocator::with_limit(4096), PoolConfig::default());
+        let a = cache.acquire(4096)?;
+        unsafe { cache.release(a) };
+        // cached 4096 bytes are freed to allocate another class
+        let b = cache.acquire(1024)?;
+        assert_eq!(cache.stats().reserved_bytes, 1024);
+        assert_eq!(cache.stats().cached_bytes, 0);
+        assert!(cache.acquire(4096).is_err());
+        unsafe { cache.release(b) };
+        Ok(())
+    }
+
+    #[test]
+    fn device_pool() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let pool = DevicePool::new(DeviceAllocator::new(&ctx), PoolConfig::default());
+        let mut a = pool.from_elem(12, 3_i32);
+        assert_eq!(a.memory_type(), MemoryType::Device);
+        let addr = a.head_addr();
+
+        let mut host = PageLockedMemory::<i32>::zeros(&ctx, 12);
+        host.copy_from(&a);
+        assert_eq!(host.as_slice(), &[3; 12]);
+        a.copy_from(&DeviceMemory::<i32>::zeros(&ctx, 12));
+        host.copy_from(&a);
+        assert_eq!(host.as_slice(), &[0; 12]);
+        drop(a);
+
+        let b = pool.zeros::<u32>(10);
+        assert_eq!(b.head_addr() as usize, addr as usize);
+        assert_eq!(pool.stats().hits, 1);
+        drop(b);
+        pool.trim(0);
+        assert_eq!(pool.stats().reserved_bytes, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn page_locked_pool() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let pool = PageLockedPool::new(PageLockedAllocator::new(&ctx), PoolConfig::default());
+        let mut a = pool.from_elem(12, 1.0_f64);
+        assert_eq!(a.memory_type(), MemoryType::PageLocked);
+        assert_eq!(a.as_slice().memory_type(), MemoryType::PageLocked);
+        let devices = DevicePool::new(DeviceAllocator::new(&ctx), PoolConfig::default());
+        let mut b = devices.zeros::<f64>(12);
+        b.copy_from(&a);
+        a.set_zero_u8();
+        a.copy_from(&b);
+        assert_eq!(a.as_slice(), &[1.0; 12]);
+        Ok(())
+    }
+
+    #[test]
+    fn managed_pool() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let pool = ManagedPool::new(ManagedAllocator::new(&ctx), PoolConfig::default());
+        let mut a = pool.from_elem(12, 2_u16);
+        assert_eq!(a.as_slice().memory_type(), MemoryType::Managed);
+        a[0] = 1;
+        let mut b = DeviceMemory::<u16>::zeros(&ctx, 12);
+        b.copy_from(&a);
+        assert_eq!(b.to_vec()[..2], [1, 2]);
+        Ok(())
+    }
+}
@@ -1,7 +1,6 @@
 use super::*;
 use crate::*;
 use crate::{error::Result};
-use cuda::*;
 use std::{
     ffi::c_void,
     ops::{Deref, DerefMut},
@@ -47,7 +46,7 @@ impl<T> Drop for RegisteredMemory<'_, T> {
         if let Err(e) = unsafe {
             contexted_call!(
                 &self.context,
-                cuMemHostUnregister,
+                mem_host_unregister,
                 self.data.as_mut_ptr() as *mut c_void
             )
         } {
@@ -61,7 +60,7 @@ impl<'a, T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized>
         unsafe {
             contexted_call!(
                 context,
-                cuMemHostRegister_v2,
+                mem_host_register,
                 data.as_mut_ptr() as *mut c_void,
                 data.len() * core::mem::size_of::<T>(),
                 0
@@ -75,17 +74,13 @@ impl<'a, T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized>
     }
 }
 
-impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memory for RegisteredMemory<'_, T> {
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> MemoryRef for RegisteredMemory<'_, T> {
     type Elem = T;
 
     fn head_addr(&self) -> *const T {
         self.data.as_ptr()
     }
 
-    fn head_addr_mut(&mut self) -> *mut T {
-        self.data.as_mut_ptr()
-    }
-
     fn num_elem(&self) -> usize {
         self.data.len()
     }
@@ -93,6 +88,12 @@ impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memo
     fn memory_type(&self) -> MemoryType {
         MemoryType::Host
     }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memory for RegisteredMemory<'_, T> {
+    fn head_addr_mut(&mut self) -> *mut T {
+        self.data.as_mut_ptr()
+    }
 
     fn set(&mut self, value: Self::Elem) {
         self.iter_mut().for_each(|v| *v = value);
@@ -49,6 +49,25 @@ impl_array_scalar!(u32, u32, CU_AD_FORMAT_UNSIGNED_INT32);
 impl_array_scalar!(i8, u8, CU_AD_FORMAT_SIGNED_INT8);
 impl_array_scalar!(i16, u16, CU_AD_FORMAT_SIGNED_INT16);
 impl_array_scalar!(i32, u32, CU_AD_FORMAT_SIGNED_INT32);
-// FIXME f16 is not supported yet
-// impl_array_scalar!(f16, u16, CU_AD_FORMAT_HALF);
 impl_array_scalar!(f32, u32, CU_AD_FORMAT_FLOAT);
+
+#[cfg(feature = "half")]
+impl_array_scalar!(half::f16, u16, CU_AD_FORMAT_HALF);
+// There is no bfloat16 format for CUDA arrays. Texture fetches return raw bits as `u16`.
+#[cfg(feature = "half")]
+impl_array_scalar!(half::bf16, u16, CU_AD_FORMAT_UNSIGNED_INT16);
+
+#[cfg(all(test, feature = "half"))]
+mod tests {
+    use super::*;
+    use half::{bf16, f16};
+
+    #[test]
+    fn half_scalar() {
+        assert_eq!(f16::format(), ArrayFormatTag::CU_AD_FORMAT_HALF);
+        assert_eq!(f16::size_of(), 2);
+        assert_eq!(f16::ONE.to_le_u16(), Some(0x3c00));
+        assert_eq!(bf16::format(), ArrayFormatTag::CU_AD_FORMAT_UNSIGNED_INT16);
+        assert_eq!(bf16::ONE.to_le_u16(), Some(0x3f80));
+    }
+}
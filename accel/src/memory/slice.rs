@@ -4,11 +4,10 @@ use super::*;
 fn get_attr<T, Attr>(ptr: *const T, attr: CUpointer_attribute) -> error::Result<Attr> {
     let mut data = MaybeUninit::<Attr>::uninit();
     unsafe {
-        ffi_call!(
-            cuPointerGetAttribute,
+        backend::get().pointer_get_attribute(
             data.as_mut_ptr() as *mut c_void,
             attr,
-            ptr as CUdeviceptr
+            ptr as CUdeviceptr,
         )?;
         Ok(data.assume_init())
     }
@@ -21,7 +20,13 @@ fn get_attr<T, Attr>(ptr: *const T, attr: CUpointer_attribute) -> error::Result<
 fn memory_type<T>(ptr: *const T) -> MemoryType {
     match get_attr(ptr, CUpointer_attribute::CU_POINTER_ATTRIBUTE_MEMORY_TYPE) {
         Ok(CUmemorytype_enum::CU_MEMORYTYPE_HOST) => MemoryType::PageLocked,
-        Ok(CUmemorytype_enum::CU_MEMORYTYPE_DEVICE) => MemoryType::Device,
+        Ok(CUmemorytype_enum::CU_MEMORYTYPE_DEVICE) => {
+            if is_managed(ptr) {
+                MemoryType::Managed
+            } else {
+                MemoryType::Device
+            }
+        }
         Ok(CUmemorytype_enum::CU_MEMORYTYPE_ARRAY) => MemoryType::Array,
         Ok(CUmemorytype_enum::CU_MEMORYTYPE_UNIFIED) => {
             unreachable!("CU_POINTER_ATTRIBUTE_MEMORY_TYPE never be UNIFED")
@@ -33,22 +38,32 @@ fn memory_type<T>(ptr: *const T) -> MemoryType {
     }
 }
 
+fn is_managed<T>(ptr: *const T) -> bool {
+    // Read as u32 since the driver writes a C boolean
+    let mut flag = 0_u32;
+    unsafe {
+        backend::get().pointer_get_attribute(
+            &mut flag as *mut u32 as *mut c_void,
+            CUpointer_attribute::CU_POINTER_ATTRIBUTE_IS_MANAGED,
+            ptr as CUdeviceptr,
+        )
+    }
+    .map(|_| flag != 0)
+    .unwrap_or(false)
+}
+
 fn get_context<T>(ptr: *const T) -> Option<ContextRef> {
     let ptr =
         get_attr::<_, CUcontext>(ptr, CUpointer_attribute::CU_POINTER_ATTRIBUTE_CONTEXT).ok()?;
     Some(ContextRef::from_ptr(ptr))
 }
 
-impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memory for [T] {
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> MemoryRef for [T] {
     type Elem = T;
     fn head_addr(&self) -> *const T {
         self.as_ptr()
     }
 
-    fn head_addr_mut(&mut self) -> *mut T {
-        self.as_mut_ptr()
-    }
-
     fn num_elem(&self) -> usize {
         self.len()
     }
@@ -56,6 +71,12 @@ impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memo
     fn memory_type(&self) -> MemoryType {
         memory_type(self.as_ptr())
     }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memory for [T] {
+    fn head_addr_mut(&mut self) -> *mut T {
+        self.as_mut_ptr()
+    }
 
     fn set(&mut self, value: T) {
         for val in self {
@@ -81,7 +102,7 @@ impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memc
             unsafe {
                 contexted_call!(
                     &ctx,
-                    cuMemcpy,
+                    memcpy,
                     self.head_addr_mut() as CUdeviceptr,
                     src.as_ptr() as CUdeviceptr,
                     self.num_elem() * core::mem::size_of::<T>()
@@ -104,9 +125,9 @@ impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memc
             unsafe {
                 contexted_call!(
                     &ctx,
-                    cuMemcpyAsync,
-                    src.as_ptr() as CUdeviceptr,
+                    memcpy_async,
                     self.as_mut_ptr() as CUdeviceptr,
+                    src.as_ptr() as CUdeviceptr,
                     byte_count,
                     stream.stream
                 )
@@ -147,7 +168,7 @@ macro_rules! impl_memcpy_slice {
     };
 }
 
-impl_memcpy_slice!(DeviceMemory::<T>);
+impl_memcpy_slice!(ManagedMemory::<T>);
 impl_memcpy_slice!(PageLockedMemory::<T>);
 impl_memcpy_slice!(RegisteredMemory::<'_, T>);
 
@@ -164,13 +185,13 @@ macro_rules! impl_memcpy {
     };
 }
 
-impl_memcpy!(DeviceMemory::<T>, DeviceMemory::<T>);
-impl_memcpy!(DeviceMemory::<T>, RegisteredMemory::<'_, T>);
-impl_memcpy!(DeviceMemory::<T>, PageLockedMemory::<T>);
-impl_memcpy!(PageLockedMemory::<T>, DeviceMemory::<T>);
+impl_memcpy!(ManagedMemory::<T>, ManagedMemory::<T>);
+impl_memcpy!(ManagedMemory::<T>, RegisteredMemory::<'_, T>);
+impl_memcpy!(ManagedMemory::<T>, PageLockedMemory::<T>);
+impl_memcpy!(PageLockedMemory::<T>, ManagedMemory::<T>);
 impl_memcpy!(PageLockedMemory::<T>, RegisteredMemory::<'_, T>);
 impl_memcpy!(PageLockedMemory::<T>, PageLockedMemory::<T>);
-impl_memcpy!(RegisteredMemory::<'_, T>, DeviceMemory::<T>);
+impl_memcpy!(RegisteredMemory::<'_, T>, ManagedMemory::<T>);
 impl_memcpy!(RegisteredMemory::<'_, T>, RegisteredMemory::<'_, T>);
 impl_memcpy!(RegisteredMemory::<'_, T>, PageLockedMemory::<T>);
 
@@ -237,7 +258,7 @@ mod tests {
     async fn memcpy_async_d2h() {
         let device = Device::nth(0).unwrap();
         let ctx = device.create_context();
-        let a = DeviceMemory::from_elem(&ctx, 12, 1_u32);
+        let a = ManagedMemory::from_elem(&ctx, 12, 1_u32);
         let mut b1 = vec![0_u32; 12];
         let mut b2 = vec![0_u32; 12];
         let mut b3 = vec![0_u32; 12];
@@ -257,9 +278,9 @@ mod tests {
         let device = Device::nth(0).unwrap();
         let ctx = device.create_context();
         let a = PageLockedMemory::from_elem(&ctx, 12, 1_u32);
-        let mut b1 = DeviceMemory::from_elem(&ctx, 12, 0_u32);
-        let mut b2 = DeviceMemory::from_elem(&ctx, 12, 0_u32);
-        let mut b3 = DeviceMemory::from_elem(&ctx, 12, 0_u32);
+        let mut b1 = ManagedMemory::from_elem(&ctx, 12, 0_u32);
+        let mut b2 = ManagedMemory::from_elem(&ctx, 12, 0_u32);
+        let mut b3 = ManagedMemory::from_elem(&ctx, 12, 0_u32);
         let fut1 = b1.copy_from_async(&a);
         let fut2 = b2.copy_from_async(&a);
         let fut3 = b3.copy_from_async(&a);
@@ -275,10 +296,10 @@ mod tests {
     async fn memcpy_async_d2d() {
         let device = Device::nth(0).unwrap();
         let ctx = device.create_context();
-        let a = DeviceMemory::from_elem(&ctx, 12, 1_u32);
-        let mut b1 = DeviceMemory::from_elem(&ctx, 12, 0_u32);
-        let mut b2 = DeviceMemory::from_elem(&ctx, 12, 0_u32);
-        let mut b3 = DeviceMemory::from_elem(&ctx, 12, 0_u32);
+        let a = ManagedMemory::from_elem(&ctx, 12, 1_u32);
+        let mut b1 = ManagedMemory::from_elem(&ctx, 12, 0_u32);
+        let mut b2 = ManagedMemory::from_elem(&ctx, 12, 0_u32);
+        let mut b3 = ManagedMemory::from_elem(&ctx, 12, 0_u32);
         let fut1 = b1.copy_from_async(&a);
         let fut2 = b2.copy_from_async(&a);
         let fut3 = b3.copy_from_async(&a);
@@ -289,4 +310,18 @@ mod tests {
         assert_eq!(a.as_slice(), b2.as_slice());
         assert_eq!(a.as_slice(), b3.as_slice());
     }
+
+    #[tokio::test]
+    async fn memcpy_async_device() {
+        let device = Device::nth(0).unwrap();
+        let ctx = device.create_context();
+        let a = PageLockedMemory::from_elem(&ctx, 12, 1_u32);
+        let mut b = DeviceMemory::zeros(&ctx, 12);
+        let mut c = DeviceMemory::zeros(&ctx, 12);
+        let mut d = vec![0_u32; 12];
+        b.copy_from_async(&a).await;
+        c.copy_from_async(&b).await;
+        d.as_mut_slice().copy_from_async(&c).await;
+        assert_eq!(a.as_slice(), d.as_slice());
+    }
 }
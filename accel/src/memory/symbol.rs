@@ -0,0 +1,288 @@
+//! Variables declared in a module
+
+use super::{fill::*, *};
+use crate::{error::*, module::Module};
+use std::{fmt, marker::PhantomData};
+
+/// `.global` or `.const` variable of a loaded module, given by [Module::global]
+///
+/// The memory is owned by the module, and not accessible from the host directly.
+/// Use [Memcpy] to read or write it:
+///
+/// ```
+/// # use accel::*;
+/// # let device = Device::nth(0).unwrap();
+/// # let ctx = device.create_context();
+/// let ptx = r#"
+///     .version 6.5
+///     .target sm_30
+///     .address_size 64
+///     .const .align 4 .f32 coef[4];
+/// "#;
+/// let module = Module::from_str(&ctx, ptx).unwrap();
+/// let mut coef = module.global::<f32>("coef").unwrap();
+/// coef.copy_from(&[1.0, 2.0, 3.0, 4.0][..]);
+///
+/// let mut host = vec![0.0; 4];
+/// host.copy_from(&coef);
+/// assert_eq!(host, [1.0, 2.0, 3.0, 4.0]);
+/// ```
+///
+/// [Module::global]: ../module/struct.Module.html#method.global
+/// [Memcpy]: ./trait.Memcpy.html
+pub struct DeviceSymbol<'module, T> {
+    ptr: CUdeviceptr,
+    size: usize,
+    module: &'module Module,
+    phantom: PhantomData<T>,
+}
+
+impl<'module, T> DeviceSymbol<'module, T> {
+    /// `size` is the number of elements
+    pub(crate) fn new(ptr: CUdeviceptr, size: usize, module: &'module Module) -> Self {
+        DeviceSymbol {
+            ptr,
+            size,
+            module,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> fmt::Debug for DeviceSymbol<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceSymbol")
+            .field("ptr", &self.ptr)
+            .field("size", &self.size)
+            .field("module", &self.module)
+            .finish()
+    }
+}
+
+impl<T> Contexted for DeviceSymbol<'_, T> {
+    fn sync(&self) -> Result<()> {
+        self.module.sync()
+    }
+
+    fn version(&self) -> Result<u32> {
+        self.module.version()
+    }
+
+    fn guard(&self) -> Result<ContextGuard> {
+        self.module.guard()
+    }
+
+    fn get_ref(&self) -> ContextRef {
+        self.module.get_ref()
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> MemoryRef
+    for DeviceSymbol<'_, T>
+{
+    type Elem = T;
+
+    fn head_addr(&self) -> *const T {
+        self.ptr as _
+    }
+
+    fn num_elem(&self) -> usize {
+        self.size
+    }
+
+    fn memory_type(&self) -> MemoryType {
+        MemoryType::Device
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memory
+    for DeviceSymbol<'_, T>
+{
+    fn head_addr_mut(&mut self) -> *mut T {
+        self.ptr as _
+    }
+
+    fn set(&mut self, value: T) {
+        memset(self, self.ptr, value, self.size);
+    }
+
+    fn set_zero_u8(&mut self) {
+        unsafe {
+            contexted_call!(
+                self,
+                memset_d8,
+                self.ptr,
+                0u8,
+                self.size * std::mem::size_of::<T>()
+            )
+        }
+        .expect("zero memset failed for device symbol");
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memcpy<[T]>
+    for DeviceSymbol<'_, T>
+{
+    fn copy_from(&mut self, src: &[T]) {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy(self, self.ptr as *mut T, src.as_ptr(), self.size);
+    }
+
+    fn copy_from_async<'a>(&'a mut self, src: &'a [T]) -> BoxFuture<'a, ()> {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy_async(self, self.ptr as *mut T, src.as_ptr(), self.size)
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized>
+    Memcpy<DeviceSymbol<'_, T>> for [T]
+{
+    fn copy_from(&mut self, src: &DeviceSymbol<'_, T>) {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy(src, self.as_mut_ptr(), src.head_addr(), src.size);
+    }
+
+    fn copy_from_async<'a>(&'a mut self, src: &'a DeviceSymbol<'_, T>) -> BoxFuture<'a, ()> {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy_async(src, self.as_mut_ptr(), src.head_addr(), src.size)
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized>
+    Memcpy<DeviceSymbol<'_, T>> for DeviceSymbol<'_, T>
+{
+    fn copy_from(&mut self, src: &DeviceSymbol<'_, T>) {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy(self, self.ptr as *mut T, src.head_addr(), self.size);
+    }
+
+    fn copy_from_async<'a>(&'a mut self, src: &'a DeviceSymbol<'_, T>) -> BoxFuture<'a, ()> {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy_async(self, self.ptr as *mut T, src.head_addr(), self.size)
+    }
+}
+
+macro_rules! impl_memcpy_symbol {
+    ($t:path) => {
+        impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized>
+            Memcpy<DeviceSymbol<'_, T>> for $t
+        {
+            fn copy_from(&mut self, src: &DeviceSymbol<'_, T>) {
+                self.as_mut_slice().copy_from(src);
+            }
+            fn copy_from_async<'a>(
+                &'a mut self,
+                src: &'a DeviceSymbol<'_, T>,
+            ) -> BoxFuture<'a, ()> {
+                self.as_mut_slice().copy_from_async(src)
+            }
+        }
+
+        impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memcpy<$t>
+            for DeviceSymbol<'_, T>
+        {
+            fn copy_from(&mut self, src: &$t) {
+                self.copy_from(src.as_slice());
+            }
+            fn copy_from_async<'a>(&'a mut self, src: &'a $t) -> BoxFuture<'a, ()> {
+                self.copy_from_async(src.as_slice())
+            }
+        }
+    };
+}
+
+impl_memcpy_symbol!(ManagedMemory::<T>);
+impl_memcpy_symbol!(PageLockedMemory::<T>);
+impl_memcpy_symbol!(RegisteredMemory::<'_, T>);
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized>
+    Memcpy<DeviceSymbol<'_, T>> for DeviceMemory<T>
+{
+    fn copy_from(&mut self, src: &DeviceSymbol<'_, T>) {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy(src, self.head_addr_mut(), src.head_addr(), src.size);
+    }
+
+    fn copy_from_async<'a>(&'a mut self, src: &'a DeviceSymbol<'_, T>) -> BoxFuture<'a, ()> {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy_async(src, self.head_addr_mut(), src.head_addr(), src.size)
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memcpy<DeviceMemory<T>>
+    for DeviceSymbol<'_, T>
+{
+    fn copy_from(&mut self, src: &DeviceMemory<T>) {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy(self, self.ptr as *mut T, src.head_addr(), self.size);
+    }
+
+    fn copy_from_async<'a>(&'a mut self, src: &'a DeviceMemory<T>) -> BoxFuture<'a, ()> {
+        assert_eq!(self.num_elem(), src.num_elem());
+        copy_async(self, self.ptr as *mut T, src.head_addr(), self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PTX: &str = r#"
+    .version 6.5
+    .target sm_30
+    .address_size 64
+    .global .align 4 .u32 counter;
+    .const .align 4 .f32 coef[16];
+    "#;
+
+    #[test]
+    fn copy_coefficients() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let module = Module::from_str(&ctx, PTX)?;
+        let mut coef = module.global::<f32>("coef")?;
+        assert_eq!(coef.num_elem(), 16);
+
+        let table: Vec<f32> = (0..16).map(|i| i as f32).collect();
+        let mut src = DeviceMemory::<f32>::zeros(&ctx, 16);
+        src.copy_from(table.as_slice());
+        coef.copy_from(&src);
+
+        let mut dest = PageLockedMemory::<f32>::zeros(&ctx, 16);
+        dest.copy_from(&coef);
+        assert_eq!(dest.as_slice(), table.as_slice());
+
+        coef.set_zero_u8();
+        dest.copy_from(&coef);
+        assert!(dest.iter().all(|&v| v == 0.0));
+        Ok(())
+    }
+
+    #[test]
+    fn whole_array() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let module = Module::from_str(&ctx, PTX)?;
+        let mut coef = module.global::<[f32; 16]>("coef")?;
+        assert_eq!(coef.num_elem(), 1);
+        coef.set([1.0; 16]);
+        let mut host = [[0.0; 16]];
+        host.copy_from(&coef);
+        assert_eq!(host[0], [1.0; 16]);
+        Ok(())
+    }
+
+    #[test]
+    fn size_mismatch() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let module = Module::from_str(&ctx, PTX)?;
+        module.global::<u32>("counter")?;
+        assert!(matches!(
+            module.global::<u64>("counter"),
+            Err(AccelError::SymbolSizeMismatch { .. })
+        ));
+        assert!(module.global::<u32>("unknown").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,505 @@
+//! [Texture] and [Surface] objects over [Array]
+//!
+//! - [TextureObject] fetches an array through the texture cache with filtering and address modes
+//! - [SurfaceObject] reads and writes an array created with [ArrayFlag::SURFACE_LDST]
+//!
+//! Both are sent to kernels as 64-bit handles, and accessed by `accel_core::tex1d`, `accel_core::tex2d`
+//! and `accel_core::surf2d_write` in kernels.
+//!
+//! [Texture]: https://docs.nvidia.com/cuda/cuda-driver-api/group__CUDA__TEXOBJECT.html
+//! [Surface]: https://docs.nvidia.com/cuda/cuda-driver-api/group__CUDA__SURFOBJECT.html
+//! [Array]: ./struct.Array.html
+//! [TextureObject]: ./struct.TextureObject.html
+//! [SurfaceObject]: ./struct.SurfaceObject.html
+//! [ArrayFlag::SURFACE_LDST]: ./struct.ArrayFlag.html#associatedconstant.SURFACE_LDST
+
+use super::*;
+use crate::error::*;
+use log::error;
+use std::{fmt, mem::ManuallyDrop, ptr};
+
+pub use cuda::CUaddress_mode_enum as AddressMode;
+pub use cuda::CUfilter_mode_enum as FilterMode;
+
+/// Type of values fetched from texture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadMode {
+    /// Fetch elements as they are
+    ElementType,
+    /// Integer elements are promoted into `f32`,
+    /// normalized into `[0.0, 1.0]` for unsigned and `[-1.0, 1.0]` for signed integers
+    NormalizedFloat,
+}
+
+/// How [TextureObject] fetches the array
+///
+/// ```
+/// # use accel::*;
+/// let desc = TextureDesc::new()
+///     .address_mode(AddressMode::CU_TR_ADDRESS_MODE_WRAP)
+///     .filter_mode(FilterMode::CU_TR_FILTER_MODE_LINEAR)
+///     .normalized_coords(true);
+/// assert!(desc.validate::<f32>().is_ok());
+///
+/// // Linear filtering requires floating point values
+/// assert!(desc.validate::<u8>().is_err());
+/// assert!(desc.read_mode(ReadMode::NormalizedFloat).validate::<u8>().is_ok());
+/// ```
+///
+/// [TextureObject]: ./struct.TextureObject.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureDesc {
+    /// Handling of out-of-range coordinates for each dimension
+    pub address_mode: [AddressMode; 3],
+    pub filter_mode: FilterMode,
+    pub read_mode: ReadMode,
+    /// Coordinates are in `[0.0, 1.0)` instead of `[0, width)`
+    pub normalized_coords: bool,
+    /// Value for out-of-range coordinates with `CU_TR_ADDRESS_MODE_BORDER`
+    pub border_color: [f32; 4],
+}
+
+impl Default for TextureDesc {
+    /// Clamped, not filtered, and read as element type in unnormalized coordinates
+    fn default() -> Self {
+        TextureDesc {
+            address_mode: [AddressMode::CU_TR_ADDRESS_MODE_CLAMP; 3],
+            filter_mode: FilterMode::CU_TR_FILTER_MODE_POINT,
+            read_mode: ReadMode::ElementType,
+            normalized_coords: false,
+            border_color: [0.0; 4],
+        }
+    }
+}
+
+impl TextureDesc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the address mode of all dimensions
+    pub fn address_mode(mut self, mode: AddressMode) -> Self {
+        self.address_mode = [mode; 3];
+        self
+    }
+
+    pub fn filter_mode(mut self, mode: FilterMode) -> Self {
+        self.filter_mode = mode;
+        self
+    }
+
+    pub fn read_mode(mut self, mode: ReadMode) -> Self {
+        self.read_mode = mode;
+        self
+    }
+
+    pub fn normalized_coords(mut self, normalized: bool) -> Self {
+        self.normalized_coords = normalized;
+        self
+    }
+
+    pub fn border_color(mut self, color: [f32; 4]) -> Self {
+        self.border_color = color;
+        self
+    }
+
+    /// Check the descriptor for the texture of element `T`
+    ///
+    /// - `CU_TR_ADDRESS_MODE_WRAP` and `CU_TR_ADDRESS_MODE_MIRROR` require normalized coordinates
+    /// - `CU_TR_FILTER_MODE_LINEAR` requires floating point values, i.e. `T` is float or read as normalized float
+    pub fn validate<T: Scalar>(&self) -> Result<()> {
+        let invalid = |message: String| Err(AccelError::InvalidTextureDesc { message });
+        for (axis, mode) in ["x", "y", "z"].iter().zip(self.address_mode.iter()) {
+            let periodic = *mode == AddressMode::CU_TR_ADDRESS_MODE_WRAP
+                || *mode == AddressMode::CU_TR_ADDRESS_MODE_MIRROR;
+            if periodic && !self.normalized_coords {
+                return invalid(format!(
+                    "{:?} of {} axis requires normalized coordinates",
+                    mode, axis
+                ));
+            }
+        }
+        if self.filter_mode == FilterMode::CU_TR_FILTER_MODE_LINEAR
+            && self.read_mode == ReadMode::ElementType
+            && !is_float::<T>()
+        {
+            return invalid(format!(
+                "Linear filtering of {} requires ReadMode::NormalizedFloat",
+                std::any::type_name::<T>()
+            ));
+        }
+        Ok(())
+    }
+
+    fn as_raw(&self) -> CUDA_TEXTURE_DESC {
+        let mut flags = 0;
+        if self.read_mode == ReadMode::ElementType {
+            flags |= CU_TRSF_READ_AS_INTEGER;
+        }
+        if self.normalized_coords {
+            flags |= CU_TRSF_NORMALIZED_COORDINATES;
+        }
+        CUDA_TEXTURE_DESC {
+            addressMode: self.address_mode,
+            filterMode: self.filter_mode,
+            flags,
+            maxAnisotropy: 0,
+            mipmapFilterMode: FilterMode::CU_TR_FILTER_MODE_POINT,
+            mipmapLevelBias: 0.0,
+            minMipmapLevelClamp: 0.0,
+            maxMipmapLevelClamp: 0.0,
+            borderColor: self.border_color,
+            reserved: [0; 12],
+        }
+    }
+}
+
+fn is_float<T: Scalar>() -> bool {
+    matches!(
+        T::format(),
+        ArrayFormatTag::CU_AD_FORMAT_FLOAT | ArrayFormatTag::CU_AD_FORMAT_HALF
+    )
+}
+
+/// `CUDA_RESOURCE_DESC` of an array
+fn resource_desc(array: CUarray) -> CUDA_RESOURCE_DESC {
+    CUDA_RESOURCE_DESC {
+        resType: CUresourcetype::CU_RESOURCE_TYPE_ARRAY,
+        res: CUDA_RESOURCE_DESC_st__bindgen_ty_1 {
+            array: CUDA_RESOURCE_DESC_st__bindgen_ty_1__bindgen_ty_1 { hArray: array },
+        },
+        flags: 0,
+    }
+}
+
+/// Texture object created by `cuTexObjectCreate`, which owns the [Array]
+///
+/// ```
+/// # use accel::*;
+/// # let device = Device::nth(0).unwrap();
+/// # let ctx = device.create_context();
+/// let array = Array::<f32, Ix2>::zeros(&ctx, (16, 16).into());
+/// let desc = TextureDesc::new().filter_mode(FilterMode::CU_TR_FILTER_MODE_LINEAR);
+/// let texture = TextureObject::new(array, desc).unwrap();
+/// assert_eq!(texture.array().dim(), &(16, 16).into());
+///
+/// // Take back the array
+/// let _array = texture.into_array();
+/// ```
+///
+/// [Array]: ./struct.Array.html
+pub struct TextureObject<T, Dim> {
+    tex: CUtexObject,
+    desc: TextureDesc,
+    array: Array<T, Dim>,
+}
+
+unsafe impl<T, Dim> Send for TextureObject<T, Dim> {}
+unsafe impl<T, Dim> Sync for TextureObject<T, Dim> {}
+
+impl<T, Dim> Contexted for TextureObject<T, Dim> {
+    fn sync(&self) -> Result<()> {
+        self.array.sync()
+    }
+
+    fn version(&self) -> Result<u32> {
+        self.array.version()
+    }
+
+    fn guard(&self) -> Result<ContextGuard> {
+        self.array.guard()
+    }
+
+    fn get_ref(&self) -> ContextRef {
+        self.array.get_ref()
+    }
+}
+
+impl<T, Dim> Drop for TextureObject<T, Dim> {
+    fn drop(&mut self) {
+        if let Err(e) = unsafe { contexted_call!(self, tex_object_destroy, self.tex) } {
+            error!("Failed to destroy texture object: {:?}", e);
+        }
+    }
+}
+
+impl<T: Scalar, Dim: Dimension> fmt::Debug for TextureObject<T, Dim> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TextureObject")
+            .field("tex", &self.tex)
+            .field("desc", &self.desc)
+            .field("array", &self.array)
+            .finish()
+    }
+}
+
+impl<T: Scalar, Dim: Dimension> TextureObject<T, Dim> {
+    /// Create a texture object of `array` after validating `desc`
+    pub fn new(array: Array<T, Dim>, desc: TextureDesc) -> Result<Self> {
+        desc.validate::<T>()?;
+        let tex = unsafe {
+            contexted_call!(
+                &array,
+                tex_object_create,
+                &resource_desc(array.raw()),
+                &desc.as_raw()
+            )
+        }?;
+        Ok(TextureObject { tex, desc, array })
+    }
+
+    pub fn desc(&self) -> &TextureDesc {
+        &self.desc
+    }
+
+    pub fn array(&self) -> &Array<T, Dim> {
+        &self.array
+    }
+
+    /// The array can be rewritten while kernels are not running
+    pub fn array_mut(&mut self) -> &mut Array<T, Dim> {
+        &mut self.array
+    }
+
+    /// Destroy the texture object, and take back the array
+    pub fn into_array(self) -> Array<T, Dim> {
+        let this = ManuallyDrop::new(self);
+        if let Err(e) = unsafe { contexted_call!(&*this, tex_object_destroy, this.tex) } {
+            error!("Failed to destroy texture object: {:?}", e);
+        }
+        unsafe { ptr::read(&this.array) }
+    }
+}
+
+impl<T: Scalar, Dim: Dimension> DeviceSend for &TextureObject<T, Dim> {
+    type Target = CUtexObject;
+    fn as_kernel_parameter(&self) -> *mut c_void {
+        &self.tex as *const CUtexObject as *mut c_void
+    }
+}
+
+/// Surface object created by `cuSurfObjectCreate`, which owns the [Array]
+///
+/// The array must be created with [ArrayFlag::SURFACE_LDST]:
+///
+/// ```
+/// # use accel::*;
+/// # let device = Device::nth(0).unwrap();
+/// # let ctx = device.create_context();
+/// let array = unsafe {
+///     Array::<f32, Ix2>::uninitialized_with_flags(&ctx, (16, 16).into(), ArrayFlag::SURFACE_LDST)
+/// };
+/// let surface = SurfaceObject::new(array).unwrap();
+///
+/// let array = Array::<f32, Ix2>::zeros(&ctx, (16, 16).into());
+/// assert!(SurfaceObject::new(array).is_err());
+/// ```
+///
+/// [Array]: ./struct.Array.html
+/// [ArrayFlag::SURFACE_LDST]: ./struct.ArrayFlag.html#associatedconstant.SURFACE_LDST
+pub struct SurfaceObject<T, Dim> {
+    surf: CUsurfObject,
+    array: Array<T, Dim>,
+}
+
+unsafe impl<T, Dim> Send for SurfaceObject<T, Dim> {}
+unsafe impl<T, Dim> Sync for SurfaceObject<T, Dim> {}
+
+impl<T, Dim> Contexted for SurfaceObject<T, Dim> {
+    fn sync(&self) -> Result<()> {
+        self.array.sync()
+    }
+
+    fn version(&self) -> Result<u32> {
+        self.array.version()
+    }
+
+    fn guard(&self) -> Result<ContextGuard> {
+        self.array.guard()
+    }
+
+    fn get_ref(&self) -> ContextRef {
+        self.array.get_ref()
+    }
+}
+
+impl<T, Dim> Drop for SurfaceObject<T, Dim> {
+    fn drop(&mut self) {
+        if let Err(e) = unsafe { contexted_call!(self, surf_object_destroy, self.surf) } {
+            error!("Failed to destroy surface object: {:?}", e);
+        }
+    }
+}
+
+impl<T: Scalar, Dim: Dimension> fmt::Debug for SurfaceObject<T, Dim> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SurfaceObject")
+            .field("surf", &self.surf)
+            .field("array", &self.array)
+            .finish()
+    }
+}
+
+impl<T: Scalar, Dim: Dimension> SurfaceObject<T, Dim> {
+    /// Create a surface object of `array`, which must be created with `ArrayFlag::SURFACE_LDST`
+    ///
+    /// Panic
+    /// ------
+    /// - if `array` has multiple channels, which `accel_core::surf2d_write` does not support
+    pub fn new(array: Array<T, Dim>) -> Result<Self> {
+        assert_eq!(
+            array.dim().num_channels(),
+            NumChannels::One,
+            "Surface of multi-channel array is not supported"
+        );
+        let surf =
+            unsafe { contexted_call!(&array, surf_object_create, &resource_desc(array.raw())) }?;
+        Ok(SurfaceObject { surf, array })
+    }
+
+    pub fn array(&self) -> &Array<T, Dim> {
+        &self.array
+    }
+
+    pub fn array_mut(&mut self) -> &mut Array<T, Dim> {
+        &mut self.array
+    }
+
+    /// Destroy the surface object, and take back the array
+    pub fn into_array(self) -> Array<T, Dim> {
+        let this = ManuallyDrop::new(self);
+        if let Err(e) = unsafe { contexted_call!(&*this, surf_object_destroy, this.surf) } {
+            error!("Failed to destroy surface object: {:?}", e);
+        }
+        unsafe { ptr::read(&this.array) }
+    }
+}
+
+impl<T: Scalar, Dim: Dimension> DeviceSend for &SurfaceObject<T, Dim> {
+    type Target = CUsurfObject;
+    fn as_kernel_parameter(&self) -> *mut c_void {
+        &self.surf as *const CUsurfObject as *mut c_void
+    }
+}
+
+impl<T: Scalar, Dim: Dimension> DeviceSend for &mut SurfaceObject<T, Dim> {
+    type Target = CUsurfObject;
+    fn as_kernel_parameter(&self) -> *mut c_void {
+        &self.surf as *const CUsurfObject as *mut c_void
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_desc() {
+        let raw = TextureDesc::new().as_raw();
+        assert_eq!(raw.addressMode, [AddressMode::CU_TR_ADDRESS_MODE_CLAMP; 3]);
+        assert_eq!(raw.filterMode, FilterMode::CU_TR_FILTER_MODE_POINT);
+        assert_eq!(raw.flags, CU_TRSF_READ_AS_INTEGER);
+        assert_eq!(raw.borderColor, [0.0; 4]);
+    }
+
+    #[test]
+    fn normalized_desc() {
+        let desc = TextureDesc::new()
+            .address_mode(AddressMode::CU_TR_ADDRESS_MODE_BORDER)
+            .read_mode(ReadMode::NormalizedFloat)
+            .normalized_coords(true)
+            .border_color([1.0, 0.0, 0.0, 1.0]);
+        let raw = desc.as_raw();
+        assert_eq!(raw.addressMode, [AddressMode::CU_TR_ADDRESS_MODE_BORDER; 3]);
+        assert_eq!(raw.flags, CU_TRSF_NORMALIZED_COORDINATES);
+        assert_eq!(raw.borderColor, [1.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn validate() {
+        let wrap = TextureDesc::new().address_mode(AddressMode::CU_TR_ADDRESS_MODE_WRAP);
+        assert!(matches!(
+            wrap.validate::<f32>(),
+            Err(AccelError::InvalidTextureDesc { .. })
+        ));
+        assert!(wrap.normalized_coords(true).validate::<f32>().is_ok());
+
+        let mut mirror_z = TextureDesc::new();
+        mirror_z.address_mode[2] = AddressMode::CU_TR_ADDRESS_MODE_MIRROR;
+        assert!(mirror_z.validate::<f32>().is_err());
+
+        let linear = TextureDesc::new().filter_mode(FilterMode::CU_TR_FILTER_MODE_LINEAR);
+        assert!(linear.validate::<f32>().is_ok());
+        assert!(linear.validate::<i16>().is_err());
+        assert!(linear
+            .read_mode(ReadMode::NormalizedFloat)
+            .validate::<i16>()
+            .is_ok());
+    }
+
+    #[test]
+    fn array_resource() {
+        let array = 0x1234 as CUarray;
+        let desc = resource_desc(array);
+        assert_eq!(desc.resType, CUresourcetype::CU_RESOURCE_TYPE_ARRAY);
+        assert_eq!(unsafe { desc.res.array.hArray }, array);
+    }
+
+    #[test]
+    fn texture_object() -> Result<()> {
+        let device = Device::nth(0)?;
+        let context = device.create_context();
+        let array = Array::<u8, Ix1>::from_elem(&context, 12.into(), 3);
+        let texture = TextureObject::new(array, TextureDesc::new())?;
+        assert_eq!(texture.desc(), &TextureDesc::new());
+        let param = (&texture).as_kernel_parameter() as *const CUtexObject;
+        assert_eq!(unsafe { *param }, texture.tex);
+
+        let array = texture.into_array();
+        let mut dst = vec![0; 12];
+        dst.as_mut_slice().copy_from(&array);
+        assert_eq!(dst, vec![3; 12]);
+
+        let linear = TextureDesc::new().filter_mode(FilterMode::CU_TR_FILTER_MODE_LINEAR);
+        assert!(TextureObject::new(array, linear).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn surface_object() -> Result<()> {
+        let device = Device::nth(0)?;
+        let context = device.create_context();
+        let array = Array::<f32, Ix2>::zeros(&context, (4, 4).into());
+        assert!(SurfaceObject::new(array).is_err());
+
+        let array = unsafe {
+            Array::<f32, Ix2>::uninitialized_with_flags(
+                &context,
+                (4, 4).into(),
+                ArrayFlag::SURFACE_LDST,
+            )
+        };
+        let mut surface = SurfaceObject::new(array)?;
+        surface.array_mut().set(1.0);
+        let mut dst = vec![0.0; 16];
+        dst.as_mut_slice().copy_from(surface.array());
+        assert_eq!(dst, vec![1.0; 16]);
+        Ok(())
+    }
+
+    #[should_panic(expected = "Surface of multi-channel array is not supported")]
+    #[test]
+    fn surface_object_multi_channel() {
+        let device = Device::nth(0).unwrap();
+        let context = device.create_context();
+        let dim = Ix2 {
+            width: 4,
+            height: 4,
+            num_channels: NumChannels::Two,
+        };
+        let array = unsafe {
+            Array::<f32, Ix2>::uninitialized_with_flags(&context, dim, ArrayFlag::SURFACE_LDST)
+        };
+        let _surface = SurfaceObject::new(array);
+    }
+}
@@ -0,0 +1,612 @@
+//! Borrowed sub-range views of device-accessible memories
+//!
+//! [DeviceMemory] cannot be casted into a Rust slice since it is not accessible from the host.
+//! [DeviceSlice] and [DeviceSliceMut] borrow a part of it instead,
+//! and they can be copied and sent to kernels as the whole memories:
+//!
+//! ```
+//! # use accel::*;
+//! # let device = Device::nth(0).unwrap();
+//! # let ctx = device.create_context();
+//! let mut mem = DeviceMemory::<i32>::zeros(&ctx, 8);
+//! let mut view = mem.as_device_slice_mut();
+//! let (mut head, mut tail) = view.split_at_mut(4);
+//! head.set(1);
+//! tail.copy_from(&[5, 6, 7, 8][..]);
+//! assert_eq!(mem.to_vec(), vec![1, 1, 1, 1, 5, 6, 7, 8]);
+//! ```
+//!
+//! [DeviceMemory]: ./struct.DeviceMemory.html
+//! [DeviceSlice]: ./struct.DeviceSlice.html
+//! [DeviceSliceMut]: ./struct.DeviceSliceMut.html
+
+use super::{fill::*, *};
+use crate::error::*;
+use std::{
+    fmt,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+};
+
+/// Shared view of a sub-range of [DeviceMemory], [ManagedMemory], [PageLockedMemory] or [RegisteredMemory]
+///
+/// Since this is a read-only view, this implements [MemoryRef] but not [Memory].
+/// It can be copied from by [Memcpy] and sent to kernels as `*const T`.
+///
+/// [DeviceMemory]: ./struct.DeviceMemory.html
+/// [ManagedMemory]: ./struct.ManagedMemory.html
+/// [PageLockedMemory]: ./struct.PageLockedMemory.html
+/// [RegisteredMemory]: ./struct.RegisteredMemory.html
+/// [MemoryRef]: ./trait.MemoryRef.html
+/// [Memory]: ./trait.Memory.html
+/// [Memcpy]: ./trait.Memcpy.html
+#[derive(Contexted)]
+pub struct DeviceSlice<'a, T> {
+    ptr: CUdeviceptr,
+    len: usize,
+    memory_type: MemoryType,
+    context: ContextRef,
+    phantom: PhantomData<&'a [T]>,
+}
+
+/// Mutable view of a sub-range of [DeviceMemory], [ManagedMemory], [PageLockedMemory] or [RegisteredMemory]
+///
+/// The memory cannot be accessed while the view exists:
+///
+/// ```compile_fail
+/// # use accel::*;
+/// # let device = Device::nth(0).unwrap();
+/// # let ctx = device.create_context();
+/// let mut mem = DeviceMemory::<i32>::zeros(&ctx, 8);
+/// let mut view = mem.as_device_slice_mut();
+/// let v = mem.to_vec(); // `mem` is mutably borrowed by `view`
+/// view.set(1);
+/// ```
+///
+/// [DeviceMemory]: ./struct.DeviceMemory.html
+/// [ManagedMemory]: ./struct.ManagedMemory.html
+/// [PageLockedMemory]: ./struct.PageLockedMemory.html
+/// [RegisteredMemory]: ./struct.RegisteredMemory.html
+#[derive(Contexted)]
+pub struct DeviceSliceMut<'a, T> {
+    ptr: CUdeviceptr,
+    len: usize,
+    memory_type: MemoryType,
+    context: ContextRef,
+    phantom: PhantomData<&'a mut [T]>,
+}
+
+unsafe impl<T: Sync> Sync for DeviceSlice<'_, T> {}
+unsafe impl<T: Sync> Send for DeviceSlice<'_, T> {}
+unsafe impl<T: Sync> Sync for DeviceSliceMut<'_, T> {}
+unsafe impl<T: Send> Send for DeviceSliceMut<'_, T> {}
+
+impl<T> Clone for DeviceSlice<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for DeviceSlice<'_, T> {}
+
+impl<T> fmt::Debug for DeviceSlice<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceSlice")
+            .field("context", &self.context)
+            .field("ptr", &self.ptr)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl<T> fmt::Debug for DeviceSliceMut<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceSliceMut")
+            .field("context", &self.context)
+            .field("ptr", &self.ptr)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+/// Resolve `range` into `(start, end)` in `[0, len]`
+///
+/// Panic
+/// ------
+/// - if `range` is out of `[0, len]`, or decreasing
+fn resolve_range(range: impl RangeBounds<usize>, len: usize) -> (usize, usize) {
+    let start = match range.start_bound() {
+        Bound::Included(&start) => start,
+        Bound::Excluded(&start) => start + 1,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => end + 1,
+        Bound::Excluded(&end) => end,
+        Bound::Unbounded => len,
+    };
+    assert!(
+        start <= end,
+        "slice index starts at {} but ends at {}",
+        start,
+        end
+    );
+    assert!(
+        end <= len,
+        "range end index {} out of range for slice of length {}",
+        end,
+        len
+    );
+    (start, end)
+}
+
+/// `(offset, len)` of the chunks of `size` elements
+fn chunk_ranges(len: usize, size: usize) -> impl Iterator<Item = (usize, usize)> {
+    assert_ne!(size, 0, "chunk size must be non-zero");
+    (0..(len + size - 1) / size).map(move |i| (i * size, size.min(len - i * size)))
+}
+
+/// `(offset, len)` of the overlapping windows of `size` elements
+fn window_ranges(len: usize, size: usize) -> impl Iterator<Item = (usize, usize)> {
+    assert_ne!(size, 0, "window size must be non-zero");
+    (0..(len + 1).saturating_sub(size)).map(move |i| (i, size))
+}
+
+impl<'a, T> DeviceSlice<'a, T> {
+    /// Sub-range `[offset, offset + len)` of this view, which must be checked by callers
+    fn sub(&self, offset: usize, len: usize) -> Self {
+        DeviceSlice {
+            ptr: self.ptr + (offset * std::mem::size_of::<T>()) as CUdeviceptr,
+            len,
+            ..*self
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// View of a sub-range
+    ///
+    /// Panic
+    /// ------
+    /// - if `range` is out of this view
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> DeviceSlice<'a, T> {
+        let (start, end) = resolve_range(range, self.len);
+        self.sub(start, end - start)
+    }
+
+    /// Divide into `[0, mid)` and `[mid, len)`
+    ///
+    /// Panic
+    /// ------
+    /// - if `mid > len`
+    pub fn split_at(&self, mid: usize) -> (DeviceSlice<'a, T>, DeviceSlice<'a, T>) {
+        assert!(mid <= self.len, "mid {} > len {}", mid, self.len);
+        (self.sub(0, mid), self.sub(mid, self.len - mid))
+    }
+
+    /// Non-overlapping views of `size` elements. The last one may be shorter.
+    ///
+    /// Panic
+    /// ------
+    /// - if `size` is zero
+    pub fn chunks(&self, size: usize) -> impl Iterator<Item = DeviceSlice<'a, T>> {
+        let this = *self;
+        chunk_ranges(self.len, size).map(move |(offset, len)| this.sub(offset, len))
+    }
+
+    /// Overlapping views of `size` elements, which are empty if `size > len`
+    ///
+    /// Panic
+    /// ------
+    /// - if `size` is zero
+    pub fn windows(&self, size: usize) -> impl Iterator<Item = DeviceSlice<'a, T>> {
+        let this = *self;
+        window_ranges(self.len, size).map(move |(offset, len)| this.sub(offset, len))
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> DeviceSlice<'_, T> {
+    /// Copy into a new `Vec` on the host
+    pub fn to_vec(&self) -> Vec<T> {
+        let mut v = vec![T::default(); self.len];
+        v.as_mut_slice().copy_from(self);
+        v
+    }
+}
+
+impl<'a, T> DeviceSliceMut<'a, T> {
+    /// Sub-range `[offset, offset + len)` of this view
+    ///
+    /// Safety
+    /// ------
+    /// - The range must be in this view, and must not overlap with other mutable views living in `'b`
+    unsafe fn sub_unchecked<'b>(&self, offset: usize, len: usize) -> DeviceSliceMut<'b, T> {
+        DeviceSliceMut {
+            ptr: self.ptr + (offset * std::mem::size_of::<T>()) as CUdeviceptr,
+            len,
+            memory_type: self.memory_type,
+            context: self.context,
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Shared view of the whole range
+    pub fn as_device_slice(&self) -> DeviceSlice<'_, T> {
+        DeviceSlice {
+            ptr: self.ptr,
+            len: self.len,
+            memory_type: self.memory_type,
+            context: self.context,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Reborrow the whole range
+    pub fn as_device_slice_mut(&mut self) -> DeviceSliceMut<'_, T> {
+        unsafe { self.sub_unchecked(0, self.len) }
+    }
+
+    /// Shared view of a sub-range
+    ///
+    /// Panic
+    /// ------
+    /// - if `range` is out of this view
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> DeviceSlice<'_, T> {
+        self.as_device_slice().slice(range)
+    }
+
+    /// Mutable view of a sub-range
+    ///
+    /// Panic
+    /// ------
+    /// - if `range` is out of this view
+    pub fn slice_mut(&mut self, range: impl RangeBounds<usize>) -> DeviceSliceMut<'_, T> {
+        let (start, end) = resolve_range(range, self.len);
+        unsafe { self.sub_unchecked(start, end - start) }
+    }
+
+    /// Divide into shared views of `[0, mid)` and `[mid, len)`
+    ///
+    /// Panic
+    /// ------
+    /// - if `mid > len`
+    pub fn split_at(&self, mid: usize) -> (DeviceSlice<'_, T>, DeviceSlice<'_, T>) {
+        self.as_device_slice().split_at(mid)
+    }
+
+    /// Divide into mutable views of `[0, mid)` and `[mid, len)`
+    ///
+    /// Panic
+    /// ------
+    /// - if `mid > len`
+    pub fn split_at_mut(&mut self, mid: usize) -> (DeviceSliceMut<'_, T>, DeviceSliceMut<'_, T>) {
+        assert!(mid <= self.len, "mid {} > len {}", mid, self.len);
+        // Two views do not overlap
+        unsafe {
+            (
+                self.sub_unchecked(0, mid),
+                self.sub_unchecked(mid, self.len - mid),
+            )
+        }
+    }
+
+    /// Non-overlapping shared views of `size` elements. The last one may be shorter.
+    ///
+    /// Panic
+    /// ------
+    /// - if `size` is zero
+    pub fn chunks(&self, size: usize) -> impl Iterator<Item = DeviceSlice<'_, T>> {
+        self.as_device_slice().chunks(size)
+    }
+
+    /// Non-overlapping mutable views of `size` elements. The last one may be shorter.
+    ///
+    /// Panic
+    /// ------
+    /// - if `size` is zero
+    pub fn chunks_mut(&mut self, size: usize) -> impl Iterator<Item = DeviceSliceMut<'_, T>> {
+        let len = self.len;
+        let this = self.as_device_slice_mut();
+        // Chunks do not overlap
+        chunk_ranges(len, size).map(move |(offset, len)| unsafe { this.sub_unchecked(offset, len) })
+    }
+
+    /// Overlapping shared views of `size` elements, which are empty if `size > len`
+    ///
+    /// Panic
+    /// ------
+    /// - if `size` is zero
+    pub fn windows(&self, size: usize) -> impl Iterator<Item = DeviceSlice<'_, T>> {
+        self.as_device_slice().windows(size)
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> DeviceSliceMut<'_, T> {
+    /// Copy into a new `Vec` on the host
+    pub fn to_vec(&self) -> Vec<T> {
+        self.as_device_slice().to_vec()
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> MemoryRef
+    for DeviceSlice<'_, T>
+{
+    type Elem = T;
+
+    fn head_addr(&self) -> *const T {
+        self.ptr as _
+    }
+
+    fn num_elem(&self) -> usize {
+        self.len
+    }
+
+    fn memory_type(&self) -> MemoryType {
+        self.memory_type
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> MemoryRef
+    for DeviceSliceMut<'_, T>
+{
+    type Elem = T;
+
+    fn head_addr(&self) -> *const T {
+        self.ptr as _
+    }
+
+    fn num_elem(&self) -> usize {
+        self.len
+    }
+
+    fn memory_type(&self) -> MemoryType {
+        self.memory_type
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memory
+    for DeviceSliceMut<'_, T>
+{
+    fn head_addr_mut(&mut self) -> *mut T {
+        self.ptr as _
+    }
+
+    fn set(&mut self, value: T) {
+        memset(self, self.ptr, value, self.len);
+    }
+
+    fn set_zero_u8(&mut self) {
+        let bytes = self.len * std::mem::size_of::<T>();
+        unsafe { contexted_call!(self, memset_d8, self.ptr, 0u8, bytes) }
+            .expect("zero memset failed for device slice");
+    }
+}
+
+macro_rules! impl_device_slice {
+    ($t:ty) => {
+        impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> $t {
+            /// Shared view of the whole memory
+            pub fn as_device_slice(&self) -> DeviceSlice<'_, T> {
+                DeviceSlice {
+                    ptr: self.head_addr() as CUdeviceptr,
+                    len: self.num_elem(),
+                    memory_type: self.memory_type(),
+                    context: self.get_ref(),
+                    phantom: PhantomData,
+                }
+            }
+
+            /// Mutable view of the whole memory
+            pub fn as_device_slice_mut(&mut self) -> DeviceSliceMut<'_, T> {
+                DeviceSliceMut {
+                    ptr: self.head_addr_mut() as CUdeviceptr,
+                    len: self.num_elem(),
+                    memory_type: self.memory_type(),
+                    context: self.get_ref(),
+                    phantom: PhantomData,
+                }
+            }
+        }
+    };
+}
+
+impl_device_slice!(DeviceMemory<T>);
+impl_device_slice!(ManagedMemory<T>);
+impl_device_slice!(PageLockedMemory<T>);
+impl_device_slice!(RegisteredMemory<'_, T>);
+
+macro_rules! impl_memcpy_into_view {
+    ($src:ty) => {
+        impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memcpy<$src>
+            for DeviceSliceMut<'_, T>
+        {
+            fn copy_from(&mut self, src: &$src) {
+                assert_eq!(self.num_elem(), src.num_elem());
+                copy(&self.context, self.ptr as *mut T, src.head_addr(), self.len);
+            }
+
+            fn copy_from_async<'a>(&'a mut self, src: &'a $src) -> BoxFuture<'a, ()> {
+                assert_eq!(self.num_elem(), src.num_elem());
+                copy_async(&self.context, self.ptr as *mut T, src.head_addr(), self.len)
+            }
+        }
+    };
+}
+
+impl_memcpy_into_view!([T]);
+impl_memcpy_into_view!(DeviceMemory<T>);
+impl_memcpy_into_view!(ManagedMemory<T>);
+impl_memcpy_into_view!(PageLockedMemory<T>);
+impl_memcpy_into_view!(RegisteredMemory<'_, T>);
+impl_memcpy_into_view!(DeviceSlice<'_, T>);
+impl_memcpy_into_view!(DeviceSliceMut<'_, T>);
+
+macro_rules! impl_memcpy_from_view {
+    ($view:ty, $dst:ty) => {
+        impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> Memcpy<$view>
+            for $dst
+        {
+            fn copy_from(&mut self, src: &$view) {
+                assert_eq!(self.num_elem(), src.num_elem());
+                copy(
+                    &src.context,
+                    self.head_addr_mut(),
+                    src.ptr as *const T,
+                    src.len,
+                );
+            }
+
+            fn copy_from_async<'a>(&'a mut self, src: &'a $view) -> BoxFuture<'a, ()> {
+                assert_eq!(self.num_elem(), src.num_elem());
+                copy_async(
+                    &src.context,
+                    self.head_addr_mut(),
+                    src.ptr as *const T,
+                    src.len,
+                )
+            }
+        }
+    };
+}
+
+impl_memcpy_from_view!(DeviceSlice<'_, T>, [T]);
+impl_memcpy_from_view!(DeviceSlice<'_, T>, DeviceMemory<T>);
+impl_memcpy_from_view!(DeviceSlice<'_, T>, ManagedMemory<T>);
+impl_memcpy_from_view!(DeviceSlice<'_, T>, PageLockedMemory<T>);
+impl_memcpy_from_view!(DeviceSlice<'_, T>, RegisteredMemory<'_, T>);
+impl_memcpy_from_view!(DeviceSliceMut<'_, T>, [T]);
+impl_memcpy_from_view!(DeviceSliceMut<'_, T>, DeviceMemory<T>);
+impl_memcpy_from_view!(DeviceSliceMut<'_, T>, ManagedMemory<T>);
+impl_memcpy_from_view!(DeviceSliceMut<'_, T>, PageLockedMemory<T>);
+impl_memcpy_from_view!(DeviceSliceMut<'_, T>, RegisteredMemory<'_, T>);
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> DeviceSend
+    for &DeviceSlice<'_, T>
+{
+    type Target = *const T;
+    fn as_kernel_parameter(&self) -> *mut c_void {
+        &self.ptr as *const CUdeviceptr as *mut c_void
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> DeviceSend
+    for &DeviceSliceMut<'_, T>
+{
+    type Target = *const T;
+    fn as_kernel_parameter(&self) -> *mut c_void {
+        &self.ptr as *const CUdeviceptr as *mut c_void
+    }
+}
+
+impl<T: PartialEq + std::fmt::Debug + Copy + Send + Sync + Default + Sized> DeviceSend
+    for &mut DeviceSliceMut<'_, T>
+{
+    type Target = *mut T;
+    fn as_kernel_parameter(&self) -> *mut c_void {
+        &self.ptr as *const CUdeviceptr as *mut c_void
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges() {
+        assert_eq!(resolve_range(.., 4), (0, 4));
+        assert_eq!(resolve_range(1..=2, 4), (1, 3));
+        assert_eq!(resolve_range(4.., 4), (4, 4));
+        assert_eq!(
+            chunk_ranges(7, 3).collect::<Vec<_>>(),
+            vec![(0, 3), (3, 3), (6, 1)]
+        );
+        assert_eq!(chunk_ranges(6, 3).collect::<Vec<_>>(), vec![(0, 3), (3, 3)]);
+        assert_eq!(
+            window_ranges(4, 3).collect::<Vec<_>>(),
+            vec![(0, 3), (1, 3)]
+        );
+        assert_eq!(window_ranges(2, 3).count(), 0);
+    }
+
+    #[should_panic(expected = "range end index 5 out of range for slice of length 4")]
+    #[test]
+    fn range_out_of_bounds() {
+        resolve_range(2..5, 4);
+    }
+
+    #[test]
+    fn slice_copy() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let mut mem = DeviceMemory::<i32>::zeros(&ctx, 8);
+        mem.as_device_slice_mut()
+            .slice_mut(2..5)
+            .copy_from(&[1, 2, 3][..]);
+        assert_eq!(mem.to_vec(), vec![0, 0, 1, 2, 3, 0, 0, 0]);
+
+        let view = mem.as_device_slice().slice(1..4);
+        assert_eq!(view.len(), 3);
+        assert_eq!(view.memory_type(), MemoryType::Device);
+        assert_eq!(view.to_vec(), vec![0, 1, 2]);
+
+        // Read-only view is usable as `MemoryRef` as well as other memories
+        fn head<M: MemoryRef + ?Sized>(mem: &M) -> usize {
+            mem.head_addr() as usize
+        }
+        assert_eq!(head(&view), head(&mem) + std::mem::size_of::<i32>());
+
+        let mut host = PageLockedMemory::<i32>::zeros(&ctx, 3);
+        host.copy_from(&view);
+        assert_eq!(host.as_slice(), &[0, 1, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn split_and_set() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let mut mem = PageLockedMemory::<u64>::zeros(&ctx, 6);
+        let mut view = mem.as_device_slice_mut();
+        let (mut head, mut tail) = view.split_at_mut(2);
+        head.set(1);
+        tail.set(2);
+        tail.slice_mut(..1).copy_from(&head.slice(1..));
+        assert_eq!(mem.as_slice(), &[1, 1, 1, 2, 2, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn chunks_and_windows() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let mut mem = DeviceMemory::<i32>::zeros(&ctx, 7);
+        let mut view = mem.as_device_slice_mut();
+        for (i, mut chunk) in view.chunks_mut(3).enumerate() {
+            chunk.set(i as i32);
+        }
+        assert_eq!(view.to_vec(), vec![0, 0, 0, 1, 1, 1, 2]);
+
+        let chunks: Vec<_> = view.chunks(3).map(|c| c.to_vec()).collect();
+        assert_eq!(chunks, vec![vec![0, 0, 0], vec![1, 1, 1], vec![2]]);
+        let windows: Vec<_> = view.windows(6).map(|w| w.to_vec()).collect();
+        assert_eq!(
+            windows,
+            vec![vec![0, 0, 0, 1, 1, 1], vec![0, 0, 1, 1, 1, 2]]
+        );
+        Ok(())
+    }
+}
@@ -1,8 +1,18 @@
 //! CUDA Module (i.e. loaded PTX or cubin)
 
-use crate::{contexted_call, contexted_new, device::*, error::*, *};
+use crate::{contexted_call, device::*, error::*, fatbin::*, *};
 use cuda::*;
-use std::ffi::*;
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    ffi::*,
+    fs,
+    os::raw::c_void,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
 
 /// CUDA Kernel function
 #[derive(Debug)]
@@ -29,50 +39,250 @@ impl Contexted for Kernel<'_> {
     }
 }
 
+impl Kernel<'_> {
+    /// Wrapper of `cuFuncGetAttribute`
+    pub fn get_attribute(&self, attrib: CUfunction_attribute) -> Result<i32> {
+        unsafe { contexted_call!(self, func_get_attribute, attrib, self.func) }
+    }
+
+    /// Wrapper of `cuFuncSetAttribute`
+    pub(crate) fn set_attribute(&self, attrib: CUfunction_attribute, value: i32) -> Result<()> {
+        unsafe { contexted_call!(self, func_set_attribute, self.func, attrib, value) }?;
+        // Query again at the next use
+        let mut cache = self.module.attributes.lock().unwrap();
+        cache.remove(&(self.func as usize));
+        Ok(())
+    }
+
+    /// Attributes used to validate launches, cached in the module at the first call
+    pub(crate) fn attributes(&self) -> Result<KernelAttributes> {
+        use CUfunction_attribute::*;
+        let key = self.func as usize;
+        if let Some(attributes) = self.module.attributes.lock().unwrap().get(&key) {
+            return Ok(*attributes);
+        }
+        let max_threads = self.get_attribute(CU_FUNC_ATTRIBUTE_MAX_THREADS_PER_BLOCK)?;
+        let static_shared = self.get_attribute(CU_FUNC_ATTRIBUTE_SHARED_SIZE_BYTES)?;
+        let max_dynamic_shared =
+            self.get_attribute(CU_FUNC_ATTRIBUTE_MAX_DYNAMIC_SHARED_SIZE_BYTES)?;
+        let attributes = KernelAttributes {
+            max_threads_per_block: max_threads as u32,
+            static_shared_mem_bytes: static_shared as usize,
+            max_dynamic_shared_mem_bytes: max_dynamic_shared,
+        };
+        let mut cache = self.module.attributes.lock().unwrap();
+        cache.insert(key, attributes);
+        Ok(attributes)
+    }
+
+    /// Size of statically allocated shared memory in bytes
+    pub fn static_shared_mem_bytes(&self) -> Result<usize> {
+        Ok(self.attributes()?.static_shared_mem_bytes)
+    }
+
+    /// Maximum number of threads per block, which depends on the resource usage of the kernel
+    pub fn max_threads_per_block(&self) -> Result<u32> {
+        Ok(self.attributes()?.max_threads_per_block)
+    }
+}
+
+/// Attributes of a kernel checked by [LaunchConfig::validate]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct KernelAttributes {
+    pub max_threads_per_block: u32,
+    pub static_shared_mem_bytes: usize,
+    pub max_dynamic_shared_mem_bytes: i32,
+}
+
+/// Context of a module
+///
+/// Modules in the registry do not own the context to be evicted when the context is dropped.
+/// They are unloaded at the eviction, and then expire, i.e. every call through them fails with
+/// [AccelError::ModuleExpired] instead of touching the destroyed context.
+#[derive(Debug)]
+enum ModuleContext {
+    Owned(Context),
+    Registered {
+        context: ContextRef,
+        expired: AtomicBool,
+    },
+}
+
+impl ModuleContext {
+    fn is_expired(&self) -> bool {
+        match self {
+            ModuleContext::Owned(_) => false,
+            ModuleContext::Registered { expired, .. } => expired.load(Ordering::SeqCst),
+        }
+    }
+
+    fn check(&self) -> Result<ContextRef> {
+        if self.is_expired() {
+            return Err(AccelError::ModuleExpired);
+        }
+        Ok(self.get_ref())
+    }
+}
+
+impl Contexted for ModuleContext {
+    fn sync(&self) -> Result<()> {
+        self.check()?.sync()
+    }
+
+    fn version(&self) -> Result<u32> {
+        self.check()?.version()
+    }
+
+    fn guard(&self) -> Result<ContextGuard> {
+        self.check()?.guard()
+    }
+
+    fn get_ref(&self) -> ContextRef {
+        match self {
+            ModuleContext::Owned(ctx) => ctx.get_ref(),
+            ModuleContext::Registered { context, .. } => *context,
+        }
+    }
+}
+
 /// OOP-like wrapper of `cuModule*` APIs
 #[derive(Debug, Contexted)]
 pub struct Module {
     module: CUmodule,
-    context: Context,
+    context: ModuleContext,
+    report: Option<JitReport>,
+    /// Attributes of kernels keyed by `CUfunction`
+    attributes: Mutex<HashMap<usize, KernelAttributes>>,
 }
 
-impl Drop for Module {
-    fn drop(&mut self) {
-        if let Err(e) = unsafe { contexted_call!(&self.context, cuModuleUnload, self.module) } {
+unsafe impl Send for Module {}
+unsafe impl Sync for Module {}
+
+lazy_static! {
+    /// Modules loaded by [Module::cached], keyed by context and [PtxKey]
+    static ref REGISTRY: Mutex<HashMap<ContextRef, HashMap<PtxKey, Arc<Module>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Address and length of a static PTX string
+///
+/// A static string is never deallocated, and thus it identifies the PTX without hashing its contents.
+type PtxKey = (usize, usize);
+
+fn ptx_key(ptx: &'static str) -> PtxKey {
+    (ptx.as_ptr() as usize, ptx.len())
+}
+
+/// Unload modules of the context in the registry, called when the context is dropped
+///
+/// Modules still shared outside of the registry expire here.
+pub(crate) fn evict_registry(ctx: ContextRef) {
+    let evicted = REGISTRY.lock().unwrap().remove(&ctx);
+    // Modules are unloaded here, out of the lock
+    for (_, module) in evicted.into_iter().flat_map(|modules| modules.into_iter()) {
+        module.unload();
+    }
+}
+
+impl Module {
+    /// Unload the module, which expires the module in the registry not to be unloaded twice
+    fn unload(&self) {
+        if let ModuleContext::Registered { expired, .. } = &self.context {
+            if expired.swap(true, Ordering::SeqCst) {
+                return;
+            }
+        }
+        let ctx = self.context.get_ref();
+        if let Err(e) = unsafe { contexted_call!(&ctx, module_unload, self.module) } {
             log::error!("Failed to unload module: {:?}", e);
         }
     }
 }
 
+impl Drop for Module {
+    fn drop(&mut self) {
+        self.unload();
+    }
+}
+
 impl Module {
     /// integrated loader of Instruction
+    ///
+    /// PTX is compiled with an error log, and a failure of the JIT compiler is reported as `AccelError::JitFailed`.
+    /// Options are overridden by `ACCEL_JIT_*` environment variables, see [JITConfigBuilder::env_overrides].
+    /// For fatbin, the image for the compute capability of the device is chosen by [Fatbin::select].
+    ///
+    /// [Fatbin::select]: ../fatbin/struct.Fatbin.html#method.select
+    /// [JITConfigBuilder::env_overrides]: ../linker/struct.JITConfigBuilder.html#method.env_overrides
     pub fn load(context: &Context, data: &Instruction) -> Result<Self> {
-        match *data {
+        Self::load_with(context, data, &mut JITConfig::error_log())
+    }
+
+    /// Load with options of the JIT compiler, and collect its messages into the log buffers of `cfg`
+    ///
+    /// Global symbols bound to `cfg` must be unresolved `.extern .global` variables of PTX.
+    /// Host objects and libraries cannot be loaded directly, and must be linked by [Linker].
+    ///
+    /// ```
+    /// # use accel::*;
+    /// let device = Device::nth(0).unwrap();
+    /// let ctx = device.create_context();
+    /// let mut cfg = JITConfig {
+    ///     error_log_buffer: Some(LogBuffer::default()),
+    ///     ..Default::default()
+    /// };
+    /// let data = Instruction::ptx(".version 6.5\n.target sm_30\n.visible .entry f(");
+    /// assert!(Module::load_with(&ctx, &data, &mut cfg).is_err());
+    /// assert!(!cfg.error_log_buffer.unwrap().log().is_empty());
+    /// ```
+    ///
+    /// [Linker]: ../linker/struct.Linker.html
+    pub fn load_with(context: &Context, data: &Instruction, cfg: &mut JITConfig) -> Result<Self> {
+        cfg.validate()?;
+        if !cfg.global_symbol.is_empty() {
+            // Invalid PTX is reported by the JIT compiler
+            if let Ok(ptx) = <ptx::PtxModule as ptx::FromInstruction>::from_instruction(data) {
+                cfg.check_global_symbols(&[ptx])?;
+            }
+        }
+        let (module, report) = match *data {
             Instruction::PTX(ref ptx) => {
-                let module =
-                    unsafe { contexted_new!(context, cuModuleLoadData, ptx.as_ptr() as *const _)? };
-                Ok(Module {
-                    module,
-                    context: context.clone(),
-                })
+                let (module, report) = unsafe { load_data(context, ptx.as_ptr() as _, cfg)? };
+                (module, Some(report))
             }
             Instruction::Cubin(ref bin) => {
-                let module =
-                    unsafe { contexted_new!(context, cuModuleLoadData, bin.as_ptr() as *const _)? };
-                Ok(Module {
-                    module,
-                    context: context.clone(),
-                })
+                let (module, _) = unsafe { load_data(context, bin.as_ptr() as _, cfg)? };
+                (module, None)
+            }
+            Instruction::PTXFile(ref path) => {
+                let ptx = fs::read(path).map_err(|e| file_error(path, e))?;
+                let ptx = CString::new(ptx).expect("Invalid PTX file");
+                let (module, report) = unsafe { load_data(context, ptx.as_ptr() as _, cfg)? };
+                (module, Some(report))
             }
-            Instruction::PTXFile(ref path) | Instruction::CubinFile(ref path) => {
-                let filename = CString::new(path.to_str().unwrap()).expect("Invalid Path");
-                let module = unsafe { contexted_new!(context, cuModuleLoad, filename.as_ptr())? };
-                Ok(Module {
-                    module,
-                    context: context.clone(),
+            Instruction::Fatbin(ref bin) => load_fatbin(context, bin, cfg)?,
+            Instruction::CubinFile(ref path) => {
+                (contexted_call!(context, module_load, path)?, None)
+            }
+            Instruction::FatbinFile(ref path) => {
+                let bin = fs::read(path).map_err(|e| file_error(path, e))?;
+                load_fatbin(context, &bin, cfg)?
+            }
+            Instruction::Object(_)
+            | Instruction::ObjectFile(_)
+            | Instruction::Library(_)
+            | Instruction::LibraryFile(_) => {
+                return Err(AccelError::InvalidInstruction {
+                    message: format!("{:?} must be linked by Linker", data.input_type()),
                 })
             }
-        }
+        };
+        Ok(Module {
+            module,
+            context: ModuleContext::Owned(context.clone()),
+            report,
+            attributes: Mutex::new(HashMap::new()),
+        })
     }
 
     pub fn from_str(context: &Context, ptx: &str) -> Result<Self> {
@@ -80,13 +290,161 @@ impl Module {
         Self::load(context, &data)
     }
 
+    /// Load PTX once per context, and share it
+    ///
+    /// The loaded module is kept in a registry keyed by the context and the address of PTX,
+    /// and unloaded when the context is dropped.
+    /// Since the PTX is not compared by its contents,
+    /// the same PTX in different static strings may be loaded separately.
+    /// The returned module does not own the context, i.e. it expires if the context is dropped,
+    /// and then its methods return [AccelError::ModuleExpired].
+    ///
+    /// ```
+    /// # use accel::*;
+    /// # let ptx = ".version 6.5\n.target sm_30\n.address_size 64\n.visible .entry f()\n{\n  ret;\n}";
+    /// let device = Device::nth(0).unwrap();
+    /// let ctx = device.create_context();
+    /// let m1 = Module::cached(&ctx, ptx).unwrap();
+    /// let m2 = Module::cached(&ctx, ptx).unwrap(); // not loaded again
+    /// assert!(std::sync::Arc::ptr_eq(&m1, &m2));
+    /// ```
+    pub fn cached(context: &impl Contexted, ptx: &'static str) -> Result<Arc<Self>> {
+        let ctx = context.get_ref();
+        let key = ptx_key(ptx);
+        if let Some(module) = REGISTRY
+            .lock()
+            .unwrap()
+            .get(&ctx)
+            .and_then(|modules| modules.get(&key))
+        {
+            return Ok(module.clone());
+        }
+
+        // Load out of the lock not to serialize JIT compilations.
+        // The same PTX may be loaded by racing threads, and the first registered one is shared.
+        let image = CString::new(ptx).expect("Invalid PTX string");
+        let (module, report) =
+            unsafe { load_data(context, image.as_ptr() as _, &mut JITConfig::error_log())? };
+        let module = Arc::new(Module {
+            module,
+            context: ModuleContext::Registered {
+                context: ctx,
+                expired: AtomicBool::new(false),
+            },
+            report: Some(report),
+            attributes: Mutex::new(HashMap::new()),
+        });
+        let registered = REGISTRY
+            .lock()
+            .unwrap()
+            .entry(ctx)
+            .or_default()
+            .entry(key)
+            .or_insert(module)
+            .clone();
+        Ok(registered)
+    }
+
+    /// Report of the JIT compiler, `None` if no PTX is compiled, e.g. loaded from cubin
+    ///
+    /// ```
+    /// # use accel::*;
+    /// # let ptx = ".version 6.5\n.target sm_30\n.address_size 64\n.visible .entry f()\n{\n  ret;\n}";
+    /// let device = Device::nth(0).unwrap();
+    /// let ctx = device.create_context();
+    /// let mut cfg = JITConfig {
+    ///     info_log_buffer: Some(LogBuffer::default()),
+    ///     ..Default::default()
+    /// };
+    /// let module = Module::load_with(&ctx, &Instruction::ptx(ptx), &mut cfg).unwrap();
+    /// let report = module.jit_report().unwrap();
+    /// assert!(report.info_log.contains("'f'"));
+    /// assert!(report.error_log.is_empty());
+    /// ```
+    pub fn jit_report(&self) -> Option<&JitReport> {
+        self.report.as_ref()
+    }
+
     /// Wrapper of `cuModuleGetFunction`
     pub fn get_kernel(&self, name: &str) -> Result<Kernel> {
         let name = CString::new(name).expect("Invalid Kernel name");
-        let func =
-            unsafe { contexted_new!(self, cuModuleGetFunction, self.module, name.as_ptr()) }?;
+        let func = unsafe { contexted_call!(self, module_get_function, self.module, &name) }?;
         Ok(Kernel { func, module: self })
     }
+
+    /// Wrapper of `cuModuleGetGlobal` for `.global` and `.const` variables
+    ///
+    /// The size of the variable must be a multiple of `size_of::<T>()`,
+    /// e.g. `.const .f32 coef[16]` can be accessed as `DeviceSymbol<f32>` of 16 elements,
+    /// or as `DeviceSymbol<[f32; 16]>` of a single element.
+    pub fn global<T>(&self, name: &str) -> Result<DeviceSymbol<'_, T>> {
+        let cname = CString::new(name).expect("Invalid symbol name");
+        let (ptr, size) = unsafe { contexted_call!(self, module_get_global, self.module, &cname) }?;
+        let elem_size = std::mem::size_of::<T>();
+        if elem_size == 0 || size % elem_size != 0 {
+            return Err(AccelError::SymbolSizeMismatch {
+                name: name.into(),
+                size,
+                elem_size,
+            });
+        }
+        Ok(DeviceSymbol::new(ptr, size / elem_size, self))
+    }
+}
+
+/// Load the image of fatbin chosen for the device of the context, with the report if PTX is chosen
+///
+/// Fatbin which cannot be parsed, e.g. with compressed images, is passed to the driver as it is.
+fn load_fatbin(
+    context: &Context,
+    bin: &[u8],
+    cfg: &mut JITConfig,
+) -> Result<(CUmodule, Option<JitReport>)> {
+    let fatbin = match Fatbin::from_bytes(bin) {
+        Ok(fatbin) => fatbin,
+        Err(_) => {
+            let (module, _) = unsafe { load_data(context, bin.as_ptr() as _, cfg)? };
+            return Ok((module, None));
+        }
+    };
+    let (major, minor) = Device::from_context(context)?.compute_capability()?;
+    let image = fatbin
+        .select(major, minor)
+        .ok_or(AccelError::UnsupportedArchitecture {
+            major: major as i32,
+            minor: minor as i32,
+        })?;
+    match image.kind {
+        ImageKind::Cubin => {
+            let (module, _) = unsafe { load_data(context, image.data.as_ptr() as _, cfg)? };
+            Ok((module, None))
+        }
+        ImageKind::Ptx => {
+            let ptx = CString::new(image.data.clone()).expect("Invalid PTX in fatbin");
+            let (module, report) = unsafe { load_data(context, ptx.as_ptr() as _, cfg)? };
+            Ok((module, Some(report)))
+        }
+    }
+}
+
+/// Wrapper of `cuModuleLoadDataEx` collecting logs and OUT values into `cfg`
+unsafe fn load_data(
+    context: &impl Contexted,
+    image: *const c_void,
+    cfg: &mut JITConfig,
+) -> Result<(CUmodule, JitReport)> {
+    let marks = cfg.log_marks();
+    let mut opts = cfg.pack();
+    let result = contexted_call!(
+        context,
+        module_load_data_ex,
+        image,
+        opts.count(),
+        opts.keys(),
+        opts.values()
+    );
+    let module = cfg.collect_outputs(&opts, result)?;
+    Ok((module, cfg.report(marks)))
 }
 
 #[cfg(test)]
@@ -110,4 +468,90 @@ mod tests {
         let _mod = Module::from_str(&ctx, ptx)?;
         Ok(())
     }
+
+    #[test]
+    fn kernel_attributes_cached() -> Result<()> {
+        let ptx = r#"
+        .version 3.2
+        .target sm_30
+        .address_size 64
+        .visible .entry do_nothing()
+        {
+          ret;
+        }
+        "#;
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let module = Module::from_str(&ctx, ptx)?;
+        let kernel = module.get_kernel("do_nothing")?;
+        let key = kernel.func as usize;
+        let max_threads = kernel.max_threads_per_block()?;
+        assert!(module.attributes.lock().unwrap().contains_key(&key));
+        // Cached attributes are shared by kernels got from the module
+        let kernel = module.get_kernel("do_nothing")?;
+        assert_eq!(kernel.attributes()?.max_threads_per_block, max_threads);
+        // and invalidated by setting an attribute
+        let attrib = CUfunction_attribute::CU_FUNC_ATTRIBUTE_MAX_DYNAMIC_SHARED_SIZE_BYTES;
+        kernel.set_attribute(attrib, 1024)?;
+        assert!(!module.attributes.lock().unwrap().contains_key(&key));
+        assert_eq!(kernel.attributes()?.max_dynamic_shared_mem_bytes, 1024);
+        Ok(())
+    }
+
+    #[test]
+    fn load_object() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let library = Instruction::library(b"!<arch>\n");
+        assert!(matches!(
+            Module::load(&ctx, &library),
+            Err(AccelError::InvalidInstruction { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn cached() -> Result<()> {
+        let ptx = r#"
+        .version 3.2
+        .target sm_30
+        .address_size 64
+        .visible .entry do_nothing()
+        {
+          ret;
+        }
+        "#;
+        let device = Device::nth(0)?;
+        let ctx1 = device.create_context();
+        let ctx2 = device.create_context();
+        let m1 = Module::cached(&ctx1, ptx)?;
+        let m2 = Module::cached(&ctx1, ptx)?;
+        let m3 = Module::cached(&ctx2, ptx)?;
+        assert!(Arc::ptr_eq(&m1, &m2));
+        assert!(!Arc::ptr_eq(&m1, &m3));
+        m1.get_kernel("do_nothing")?;
+
+        let key = ctx1.get_ref();
+        let count = || {
+            REGISTRY
+                .lock()
+                .unwrap()
+                .get(&key)
+                .map_or(0, |modules| modules.len())
+        };
+        assert_eq!(count(), 1);
+        drop(ctx1);
+        assert_eq!(count(), 0);
+        // Module of the dropped context expires
+        assert!(matches!(
+            m1.get_kernel("do_nothing"),
+            Err(AccelError::ModuleExpired)
+        ));
+        drop(m1);
+        drop(m2);
+        // Registry keeps the last reference
+        assert_eq!(Arc::strong_count(&m3), 2);
+        m3.get_kernel("do_nothing")?;
+        Ok(())
+    }
 }
@@ -0,0 +1,532 @@
+//! Occupancy calculator
+//!
+//! Occupancy is the ratio of active warps on a streaming multiprocessor (SM) to the maximum number of warps it supports.
+//! The number of blocks which are active simultaneously on an SM is limited by
+//!
+//! - the number of warps and blocks per SM,
+//! - registers used by each thread, which are allocated per warp,
+//! - shared memory used by each block, both static and dynamic.
+//!
+//! This module reimplements these rules of the CUDA occupancy calculator in Rust
+//! using the resource tables of each SM architecture ([SmLimits]),
+//! and thus the result is deterministic and computed without GPU.
+//!
+//! ```
+//! use accel::occupancy::*;
+//!
+//! let sm70 = SmLimits::new(7, 0).unwrap();
+//! let kernel = KernelResources {
+//!     registers_per_thread: Some(64),
+//!     static_shared_mem_bytes: 0,
+//!     max_threads_per_block: 1024,
+//! };
+//! let occupancy = sm70.occupancy(&kernel, 256, 0);
+//! assert_eq!(occupancy.active_blocks_per_sm, 4);
+//! assert_eq!(occupancy.limited_by, Limiter::Registers);
+//! assert_eq!(occupancy.ratio, 0.5);
+//! ```
+//!
+//! [Grid::for_elements] chooses the launch shape for a loaded kernel using the limits of its device.
+//!
+//! [SmLimits]: struct.SmLimits.html
+//! [Grid::for_elements]: ../struct.Grid.html#method.for_elements
+
+use crate::{cubin::KernelInfo, error::*, ptx::*, *};
+use cuda::*;
+
+/// Resource limits of an SM for a compute capability
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SmLimits {
+    /// Compute capability, e.g. `(7, 0)` for sm_70
+    pub compute_capability: (u32, u32),
+    pub warp_size: u32,
+    pub max_threads_per_block: u32,
+    pub max_warps_per_sm: u32,
+    pub max_blocks_per_sm: u32,
+    /// Number of 32-bit registers per SM
+    pub registers_per_sm: u32,
+    pub max_registers_per_block: u32,
+    pub max_registers_per_thread: u32,
+    /// Registers are allocated to each warp in this unit
+    pub register_allocation_unit: u32,
+    /// Register file is split into this number of partitions, and warps are distributed over them
+    pub register_partitions: u32,
+    /// Shared memory per SM in bytes with the maximum carveout
+    pub shared_mem_per_sm: u32,
+    /// Shared memory per block in bytes including the opt-in region
+    pub max_shared_mem_per_block: u32,
+    /// Shared memory is allocated to each block in this unit
+    pub shared_mem_allocation_unit: u32,
+    /// Shared memory reserved by the system for each block
+    pub reserved_shared_mem_per_block: u32,
+}
+
+macro_rules! sm {
+    (
+        $major:expr, $minor:expr,
+        warps: $warps:expr,
+        blocks: $blocks:expr,
+        registers: $registers:expr,
+        registers_per_block: $registers_per_block:expr,
+        registers_per_thread: $registers_per_thread:expr,
+        shared: $shared:expr,
+        shared_per_block: $shared_per_block:expr,
+        shared_unit: $shared_unit:expr,
+        reserved: $reserved:expr
+    ) => {
+        SmLimits {
+            compute_capability: ($major, $minor),
+            warp_size: 32,
+            max_threads_per_block: 1024,
+            max_warps_per_sm: $warps,
+            max_blocks_per_sm: $blocks,
+            registers_per_sm: $registers,
+            max_registers_per_block: $registers_per_block,
+            max_registers_per_thread: $registers_per_thread,
+            register_allocation_unit: 256,
+            register_partitions: 4,
+            shared_mem_per_sm: $shared,
+            max_shared_mem_per_block: $shared_per_block,
+            shared_mem_allocation_unit: $shared_unit,
+            reserved_shared_mem_per_block: $reserved,
+        }
+    };
+}
+
+/// Known architectures in ascending order of compute capability
+#[rustfmt::skip]
+const ARCHITECTURES: &[SmLimits] = &[
+    sm!(3, 0, warps: 64, blocks: 16, registers: 65536, registers_per_block: 65536, registers_per_thread: 63,
+        shared: 49152, shared_per_block: 49152, shared_unit: 256, reserved: 0),
+    sm!(3, 2, warps: 64, blocks: 16, registers: 65536, registers_per_block: 65536, registers_per_thread: 255,
+        shared: 49152, shared_per_block: 49152, shared_unit: 256, reserved: 0),
+    sm!(3, 7, warps: 64, blocks: 16, registers: 131072, registers_per_block: 65536, registers_per_thread: 255,
+        shared: 114688, shared_per_block: 49152, shared_unit: 256, reserved: 0),
+    sm!(5, 0, warps: 64, blocks: 32, registers: 65536, registers_per_block: 65536, registers_per_thread: 255,
+        shared: 65536, shared_per_block: 49152, shared_unit: 256, reserved: 0),
+    sm!(5, 2, warps: 64, blocks: 32, registers: 65536, registers_per_block: 65536, registers_per_thread: 255,
+        shared: 98304, shared_per_block: 49152, shared_unit: 256, reserved: 0),
+    sm!(5, 3, warps: 64, blocks: 32, registers: 65536, registers_per_block: 32768, registers_per_thread: 255,
+        shared: 65536, shared_per_block: 49152, shared_unit: 256, reserved: 0),
+    sm!(6, 0, warps: 64, blocks: 32, registers: 65536, registers_per_block: 65536, registers_per_thread: 255,
+        shared: 65536, shared_per_block: 49152, shared_unit: 256, reserved: 0),
+    sm!(6, 1, warps: 64, blocks: 32, registers: 65536, registers_per_block: 65536, registers_per_thread: 255,
+        shared: 98304, shared_per_block: 49152, shared_unit: 256, reserved: 0),
+    sm!(6, 2, warps: 64, blocks: 32, registers: 65536, registers_per_block: 32768, registers_per_thread: 255,
+        shared: 65536, shared_per_block: 49152, shared_unit: 256, reserved: 0),
+    sm!(7, 0, warps: 64, blocks: 32, registers: 65536, registers_per_block: 65536, registers_per_thread: 255,
+        shared: 98304, shared_per_block: 98304, shared_unit: 256, reserved: 0),
+    sm!(7, 5, warps: 32, blocks: 16, registers: 65536, registers_per_block: 65536, registers_per_thread: 255,
+        shared: 65536, shared_per_block: 65536, shared_unit: 256, reserved: 0),
+    sm!(8, 0, warps: 64, blocks: 32, registers: 65536, registers_per_block: 65536, registers_per_thread: 255,
+        shared: 167936, shared_per_block: 166912, shared_unit: 128, reserved: 1024),
+    sm!(8, 6, warps: 48, blocks: 16, registers: 65536, registers_per_block: 65536, registers_per_thread: 255,
+        shared: 102400, shared_per_block: 101376, shared_unit: 128, reserved: 1024),
+    sm!(8, 7, warps: 48, blocks: 16, registers: 65536, registers_per_block: 65536, registers_per_thread: 255,
+        shared: 167936, shared_per_block: 166912, shared_unit: 128, reserved: 1024),
+    sm!(8, 9, warps: 48, blocks: 24, registers: 65536, registers_per_block: 65536, registers_per_thread: 255,
+        shared: 102400, shared_per_block: 101376, shared_unit: 128, reserved: 1024),
+    sm!(9, 0, warps: 64, blocks: 32, registers: 65536, registers_per_block: 65536, registers_per_thread: 255,
+        shared: 233472, shared_per_block: 232448, shared_unit: 128, reserved: 1024),
+];
+
+/// Resource which limits the number of active blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limiter {
+    /// Block size exceeds the limit of the kernel or the device, and the kernel cannot be launched
+    BlockSize,
+    /// Number of warps per SM
+    Warps,
+    /// Number of blocks per SM
+    Blocks,
+    /// Registers per SM, or per block if the kernel cannot be launched
+    Registers,
+    /// Shared memory per SM, or per block if the kernel cannot be launched
+    SharedMemory,
+}
+
+/// Occupancy of a kernel launched with a block size
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Occupancy {
+    pub active_blocks_per_sm: u32,
+    pub active_warps_per_sm: u32,
+    /// Ratio of active warps to the maximum number of warps per SM
+    pub ratio: f64,
+    pub limited_by: Limiter,
+}
+
+impl Occupancy {
+    fn not_launchable(limited_by: Limiter) -> Self {
+        Occupancy {
+            active_blocks_per_sm: 0,
+            active_warps_per_sm: 0,
+            ratio: 0.0,
+            limited_by,
+        }
+    }
+}
+
+/// Resource usage of a kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelResources {
+    /// Registers per thread. `None` if unknown, which is regarded as the maximum of the architecture
+    pub registers_per_thread: Option<u32>,
+    /// Statically allocated shared memory per block in bytes
+    pub static_shared_mem_bytes: u32,
+    /// Maximum number of threads per block allowed for the kernel
+    pub max_threads_per_block: u32,
+}
+
+impl KernelResources {
+    /// Resource usage of a loaded kernel reported by `cuFuncGetAttribute`
+    pub fn from_kernel(kernel: &Kernel) -> Result<Self> {
+        let registers = kernel.get_attribute(CUfunction_attribute::CU_FUNC_ATTRIBUTE_NUM_REGS)?;
+        Ok(KernelResources {
+            registers_per_thread: Some(registers as u32),
+            static_shared_mem_bytes: kernel.static_shared_mem_bytes()? as u32,
+            max_threads_per_block: kernel.max_threads_per_block()?,
+        })
+    }
+
+    /// Estimate resource usage of a kernel from PTX
+    ///
+    /// Registers are allocated by ptxas, and thus only known if `.maxnreg` is given.
+    /// Every sized module-scope `.shared` variable is counted as the static shared memory.
+    /// Returns `None` if the kernel is not found.
+    pub fn from_ptx(ptx: &PtxModule, name: &str) -> Option<Self> {
+        let kernel = ptx.kernel(name)?;
+        let static_shared_mem_bytes = ptx
+            .variables
+            .iter()
+            .filter(|v| v.space == StateSpace::Shared && v.linkage != Linkage::Extern)
+            .filter_map(|v| v.size())
+            .sum::<usize>() as u32;
+        Some(KernelResources {
+            registers_per_thread: kernel.max_registers,
+            static_shared_mem_bytes,
+            max_threads_per_block: kernel.max_threads.unwrap_or(1024),
+        })
+    }
+
+    /// Resource usage of a kernel recorded in a cubin by ptxas
+    pub fn from_cubin(kernel: &KernelInfo) -> Self {
+        let threads = kernel.required_threads.or(kernel.max_threads);
+        KernelResources {
+            registers_per_thread: kernel.registers,
+            static_shared_mem_bytes: kernel.static_shared_mem_bytes,
+            max_threads_per_block: threads.map_or(1024, |[x, y, z]| x * y * z),
+        }
+    }
+}
+
+fn round_up(n: u32, unit: u32) -> u32 {
+    (n + unit - 1) / unit * unit
+}
+
+impl SmLimits {
+    /// Limits of the compute capability `major.minor`
+    ///
+    /// Unknown versions use the table of the nearest older known architecture, e.g. sm_35 uses sm_32.
+    /// Returns `None` for architectures older than sm_30.
+    pub fn new(major: u32, minor: u32) -> Option<Self> {
+        let limits = ARCHITECTURES
+            .iter()
+            .rev()
+            .find(|limits| limits.compute_capability <= (major, minor))?;
+        Some(SmLimits {
+            compute_capability: (major, minor),
+            ..*limits
+        })
+    }
+
+    /// Limits of the device determined by its compute capability
+    pub fn from_device(device: &Device) -> Result<Self> {
+        let (major, minor) = device.compute_capability()?;
+        Self::new(major, minor).ok_or(AccelError::UnsupportedArchitecture {
+            major: major as i32,
+            minor: minor as i32,
+        })
+    }
+
+    /// Occupancy of the kernel launched with `block_size` threads and dynamic shared memory per block
+    pub fn occupancy(
+        &self,
+        kernel: &KernelResources,
+        block_size: u32,
+        dynamic_shared_mem_bytes: u32,
+    ) -> Occupancy {
+        if block_size == 0
+            || block_size > self.max_threads_per_block.min(kernel.max_threads_per_block)
+        {
+            return Occupancy::not_launchable(Limiter::BlockSize);
+        }
+        let warps_per_block = round_up(block_size, self.warp_size) / self.warp_size;
+
+        let registers = kernel
+            .registers_per_thread
+            .unwrap_or(self.max_registers_per_thread);
+        if registers > self.max_registers_per_thread {
+            return Occupancy::not_launchable(Limiter::Registers);
+        }
+        let registers_per_warp =
+            round_up(registers * self.warp_size, self.register_allocation_unit);
+        if registers_per_warp * warps_per_block > self.max_registers_per_block {
+            return Occupancy::not_launchable(Limiter::Registers);
+        }
+
+        let shared = kernel
+            .static_shared_mem_bytes
+            .saturating_add(dynamic_shared_mem_bytes);
+        if shared > self.max_shared_mem_per_block {
+            return Occupancy::not_launchable(Limiter::SharedMemory);
+        }
+        let shared_per_block = round_up(
+            shared + self.reserved_shared_mem_per_block,
+            self.shared_mem_allocation_unit,
+        );
+
+        let by_warps = self.max_warps_per_sm / warps_per_block;
+        let by_blocks = self.max_blocks_per_sm;
+        // Warps are distributed over partitions of the register file
+        let by_registers = (self.registers_per_sm / self.register_partitions)
+            .checked_div(registers_per_warp)
+            .map_or(std::u32::MAX, |warps| {
+                warps * self.register_partitions / warps_per_block
+            });
+        let by_shared = self
+            .shared_mem_per_sm
+            .checked_div(shared_per_block)
+            .unwrap_or(std::u32::MAX);
+
+        // The first one is reported if several resources give the same limit
+        let (active_blocks_per_sm, limited_by) = [
+            (by_warps, Limiter::Warps),
+            (by_blocks, Limiter::Blocks),
+            (by_registers, Limiter::Registers),
+            (by_shared, Limiter::SharedMemory),
+        ]
+        .iter()
+        .cloned()
+        .min_by_key(|(blocks, _)| *blocks)
+        .unwrap();
+        let active_warps_per_sm = active_blocks_per_sm * warps_per_block;
+        Occupancy {
+            active_blocks_per_sm,
+            active_warps_per_sm,
+            ratio: active_warps_per_sm as f64 / self.max_warps_per_sm as f64,
+            limited_by,
+        }
+    }
+
+    /// Block size which maximizes the number of active threads per SM, and its occupancy
+    ///
+    /// Candidates are multiples of warp size up to the limit of the kernel,
+    /// and the larger one is chosen if several candidates give the same number of active threads.
+    /// Returns `None` if the kernel cannot be launched with any block size.
+    pub fn optimal_block_size(
+        &self,
+        kernel: &KernelResources,
+        dynamic_shared_mem_bytes: u32,
+    ) -> Option<(u32, Occupancy)> {
+        let limit = self.max_threads_per_block.min(kernel.max_threads_per_block);
+        let mut best: Option<(u32, Occupancy)> = None;
+        for warps in (1..=round_up(limit, self.warp_size) / self.warp_size).rev() {
+            let block_size = (warps * self.warp_size).min(limit);
+            let occupancy = self.occupancy(kernel, block_size, dynamic_shared_mem_bytes);
+            let threads = |(block_size, occupancy): (u32, Occupancy)| {
+                block_size * occupancy.active_blocks_per_sm
+            };
+            if threads((block_size, occupancy)) > best.map_or(0, threads) {
+                best = Some((block_size, occupancy));
+            }
+            if occupancy.active_warps_per_sm == self.max_warps_per_sm {
+                break;
+            }
+        }
+        best
+    }
+
+    /// 1D launch shape covering `n` elements by one thread per element
+    ///
+    /// The block size is [optimal_block_size](#method.optimal_block_size) without dynamic shared memory,
+    /// shrunk to a multiple of warp size covering `n` for small problems.
+    pub fn for_elements(&self, kernel: &KernelResources, n: usize) -> Result<(Grid, Block)> {
+        let (block_size, _) =
+            self.optimal_block_size(kernel, 0)
+                .ok_or_else(|| AccelError::InvalidLaunchConfig {
+                    message: "Kernel cannot be launched with any block size".into(),
+                })?;
+        let block_size = if (n as u64) < block_size as u64 {
+            round_up((n as u32).max(1), self.warp_size).min(block_size)
+        } else {
+            block_size
+        };
+        let grid_size = ((n as u64 + block_size as u64 - 1) / block_size as u64).max(1);
+        if grid_size > std::i32::MAX as u64 {
+            return Err(AccelError::InvalidLaunchConfig {
+                message: format!("{} elements exceed the maximum grid size", n),
+            });
+        }
+        Ok((Grid::x(grid_size), Block::x(block_size)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kernel(registers: u32, shared: u32) -> KernelResources {
+        KernelResources {
+            registers_per_thread: Some(registers),
+            static_shared_mem_bytes: shared,
+            max_threads_per_block: 1024,
+        }
+    }
+
+    #[test]
+    fn new() {
+        assert!(SmLimits::new(2, 0).is_none());
+        let sm35 = SmLimits::new(3, 5).unwrap();
+        assert_eq!(sm35.compute_capability, (3, 5));
+        assert_eq!(sm35.max_registers_per_thread, 255);
+        assert_eq!(SmLimits::new(7, 2).unwrap().shared_mem_per_sm, 98304);
+        // newer architecture uses the latest table
+        assert_eq!(SmLimits::new(12, 0).unwrap().max_warps_per_sm, 64);
+    }
+
+    #[test]
+    fn occupancy() {
+        let sm70 = SmLimits::new(7, 0).unwrap();
+        let full = sm70.occupancy(&kernel(32, 0), 256, 0);
+        assert_eq!(full.active_blocks_per_sm, 8);
+        assert_eq!(full.active_warps_per_sm, 64);
+        assert_eq!(full.limited_by, Limiter::Warps);
+        assert_eq!(full.ratio, 1.0);
+
+        let regs = sm70.occupancy(&kernel(64, 0), 256, 0);
+        assert_eq!(regs.active_blocks_per_sm, 4);
+        assert_eq!(regs.limited_by, Limiter::Registers);
+
+        // small blocks hit the limit of blocks per SM
+        let blocks = sm70.occupancy(&kernel(16, 0), 32, 0);
+        assert_eq!(blocks.active_blocks_per_sm, 32);
+        assert_eq!(blocks.limited_by, Limiter::Blocks);
+        assert_eq!(blocks.ratio, 0.5);
+
+        // 48KB + reserved 1KB per block on sm_80
+        let sm80 = SmLimits::new(8, 0).unwrap();
+        let shared = sm80.occupancy(&kernel(32, 16 * 1024), 128, 32 * 1024);
+        assert_eq!(shared.active_blocks_per_sm, 3);
+        assert_eq!(shared.limited_by, Limiter::SharedMemory);
+        assert_eq!(shared.ratio, 12.0 / 64.0);
+
+        let sm75 = SmLimits::new(7, 5).unwrap();
+        assert_eq!(sm75.occupancy(&kernel(32, 0), 1024, 0).ratio, 1.0);
+    }
+
+    #[test]
+    fn not_launchable() {
+        let sm70 = SmLimits::new(7, 0).unwrap();
+        assert_eq!(
+            sm70.occupancy(&kernel(255, 0), 256, 0).active_blocks_per_sm,
+            1
+        );
+        let regs = sm70.occupancy(&kernel(255, 0), 512, 0);
+        assert_eq!(regs.active_blocks_per_sm, 0);
+        assert_eq!(regs.limited_by, Limiter::Registers);
+        // does not overflow
+        let regs = sm70.occupancy(&kernel(std::u32::MAX, 0), 256, 0);
+        assert_eq!(regs.limited_by, Limiter::Registers);
+        let shared = sm70.occupancy(&kernel(32, 0), 256, 96 * 1024 + 1);
+        assert_eq!(shared.limited_by, Limiter::SharedMemory);
+        let block = sm70.occupancy(&kernel(32, 0), 2048, 0);
+        assert_eq!(block.limited_by, Limiter::BlockSize);
+    }
+
+    #[test]
+    fn optimal_block_size() {
+        let sm70 = SmLimits::new(7, 0).unwrap();
+        let (block, occupancy) = sm70.optimal_block_size(&kernel(32, 0), 0).unwrap();
+        assert_eq!(block, 1024);
+        assert_eq!(occupancy.ratio, 1.0);
+
+        // 128 registers allows 16 warps per SM, and a block of 512 threads is the largest one
+        let (block, occupancy) = sm70.optimal_block_size(&kernel(128, 0), 0).unwrap();
+        assert_eq!(block, 512);
+        assert_eq!(occupancy.active_warps_per_sm, 16);
+
+        let limited = KernelResources {
+            max_threads_per_block: 100,
+            ..kernel(32, 0)
+        };
+        assert_eq!(sm70.optimal_block_size(&limited, 0).unwrap().0, 100);
+        assert!(sm70.optimal_block_size(&kernel(32, 0), 97 * 1024).is_none());
+    }
+
+    #[test]
+    fn for_elements() -> Result<()> {
+        let sm70 = SmLimits::new(7, 0).unwrap();
+        let (grid, block) = sm70.for_elements(&kernel(32, 0), 1 << 20)?;
+        assert_eq!(block, Block::x(1024));
+        assert_eq!(grid, Grid::x(1024));
+        let (grid, block) = sm70.for_elements(&kernel(32, 0), 100)?;
+        assert_eq!(block, Block::x(128));
+        assert_eq!(grid, Grid::x(1));
+        let (grid, _) = sm70.for_elements(&kernel(32, 0), 0)?;
+        assert_eq!(grid, Grid::x(1));
+        Ok(())
+    }
+
+    #[test]
+    fn from_ptx() -> Result<()> {
+        let ptx = PtxModule::parse(
+            r#"
+            .version 6.5
+            .target sm_70
+            .address_size 64
+            .shared .align 4 .b8 tile[1024];
+            .extern .shared .align 4 .b8 dynamic[];
+            .visible .entry reduce()
+            .maxntid 256, 1, 1
+            .maxnreg 64
+            {
+                ret;
+            }
+            "#,
+        )?;
+        let resources = KernelResources::from_ptx(&ptx, "reduce").unwrap();
+        assert_eq!(
+            resources,
+            KernelResources {
+                registers_per_thread: Some(64),
+                static_shared_mem_bytes: 1024,
+                max_threads_per_block: 256,
+            }
+        );
+        assert!(KernelResources::from_ptx(&ptx, "not_found").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn grid_for_elements() -> Result<()> {
+        let device = Device::nth(0)?;
+        let ctx = device.create_context();
+        let module = Module::from_str(
+            &ctx,
+            r#"
+            .version 3.2
+            .target sm_30
+            .address_size 64
+            .visible .entry do_nothing()
+            {
+              ret;
+            }
+            "#,
+        )?;
+        let kernel = module.get_kernel("do_nothing")?;
+        let (grid, block) = Grid::for_elements(1000, &kernel)?;
+        assert!(grid.x as usize * block.x as usize >= 1000);
+        assert_eq!(block.x % 32, 0);
+        Ok(())
+    }
+}
@@ -1,7 +1,6 @@
 //! Profiling GPU kernels and host CUDA API calls
 
 use crate::*;
-use cuda::*;
 
 /// RAII handler for nvprof profiling
 ///
@@ -17,7 +16,7 @@ pub struct Profiler {
 
 impl Drop for Profiler {
     fn drop(&mut self) {
-        if let Err(e) = unsafe { contexted_call!(&self.ctx, cuProfilerStop) } {
+        if let Err(e) = contexted_call!(&self.ctx, profiler_stop) {
             log::error!("Failed to stop profiling: {:?}", e);
         }
     }
@@ -25,7 +24,7 @@ impl Drop for Profiler {
 
 impl Profiler {
     pub fn start(ctx: &Context) -> Self {
-        unsafe { contexted_call!(ctx, cuProfilerStart) }.expect("Profiler has already started");
+        contexted_call!(ctx, profiler_start).expect("Profiler has already started");
         Self { ctx: ctx.clone() }
     }
 }
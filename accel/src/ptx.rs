@@ -0,0 +1,104 @@
+//! Parser of [PTX] module headers
+//!
+//! The parser is shared with `#[kernel]` proc-macro, and re-exported from `accel_codegen::ptx`.
+//! See [PtxModule] for the parsed information.
+//!
+//! ```
+//! use accel::ptx::*;
+//!
+//! let ptx = PtxModule::parse(r#"
+//! .version 6.5
+//! .target sm_30
+//! .address_size 64
+//!
+//! .visible .entry add(
+//!     .param .u64 add_param_0,
+//!     .param .u32 add_param_1
+//! )
+//! {
+//!     ret;
+//! }
+//! "#).unwrap();
+//! assert_eq!(ptx.version, (6, 5));
+//! assert_eq!(ptx.target, vec!["sm_30"]);
+//!
+//! let add = ptx.kernel("add").unwrap();
+//! assert_eq!(add.params.len(), 2);
+//! assert_eq!(add.params[0].ty, PtxType::U64);
+//! assert_eq!(add.params[1].ty, PtxType::U32);
+//! ```
+//!
+//! [PTX]: https://docs.nvidia.com/cuda/parallel-thread-execution/index.html
+//! [PtxModule]: struct.PtxModule.html
+
+use crate::{error::*, *};
+use std::fs;
+
+pub use accel_codegen::ptx::{
+    Function, FunctionKind, Linkage, Param, PtxError, PtxModule, PtxType, StateSpace, Variable,
+};
+
+/// Read [PtxModule](struct.PtxModule.html) from [Instruction](../instruction/enum.Instruction.html)
+pub trait FromInstruction: Sized {
+    /// Read PTX from `Instruction::PTX` or `Instruction::PTXFile`
+    ///
+    /// The other formats are not PTX, and return `AccelError::InvalidPtx`
+    fn from_instruction(data: &Instruction) -> Result<Self>;
+}
+
+impl FromInstruction for PtxModule {
+    fn from_instruction(data: &Instruction) -> Result<Self> {
+        match data {
+            Instruction::PTX(ptx) => Ok(Self::parse(&ptx.to_string_lossy())?),
+            Instruction::PTXFile(path) => {
+                let ptx = fs::read_to_string(path).map_err(|e| file_error(path, e))?;
+                Ok(Self::parse(&ptx)?)
+            }
+            _ => Err(AccelError::InvalidPtx {
+                line: 0,
+                message: format!("{:?} is not a PTX", data.input_type()),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn add_ptx() -> Result<()> {
+        let data = Instruction::ptx_file(Path::new("tests/data/add.ptx"))?;
+        let ptx = PtxModule::from_instruction(&data)?;
+        assert_eq!(ptx.version, (6, 5));
+        assert_eq!(ptx.target, vec!["sm_30"]);
+        assert_eq!(ptx.address_size, 64);
+        assert_eq!(ptx.variables, vec![]);
+
+        let kernels: Vec<_> = ptx.kernels().collect();
+        assert_eq!(kernels.len(), 1);
+        let add = kernels[0];
+        assert_eq!(add.name, "_Z3addPKiS0_Pi");
+        assert_eq!(add.linkage, Linkage::Visible);
+        assert!(add.defined);
+        assert_eq!(add.params.len(), 3);
+        for (i, param) in add.params.iter().enumerate() {
+            assert_eq!(param.name, format!("_Z3addPKiS0_Pi_param_{}", i));
+            assert_eq!(param.ty, PtxType::U64);
+            assert_eq!(param.size(), 8);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() {
+        match PtxModule::from_instruction(&Instruction::ptx(
+            ".version 6.5\n.entry f(.param .u65 a) {}",
+        )) {
+            Err(AccelError::InvalidPtx { line, .. }) => assert_eq!(line, 2),
+            _ => panic!("must fail"),
+        }
+        assert!(PtxModule::from_instruction(&Instruction::cubin(b"\x7fELF")).is_err());
+    }
+}
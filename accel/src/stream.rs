@@ -1,4 +1,4 @@
-use crate::{contexted_call, contexted_new, device::*, error::*};
+use crate::{contexted_call, device::*, error::*};
 use cuda::*;
 use std::future::Future;
 
@@ -14,7 +14,7 @@ unsafe impl Send for Stream {}
 
 impl Drop for Stream {
     fn drop(&mut self) {
-        if let Err(e) = unsafe { contexted_call!(self, cuStreamDestroy_v2, self.stream) } {
+        if let Err(e) = unsafe { contexted_call!(self, stream_destroy, self.stream) } {
             log::error!("Failed to delete CUDA stream: {:?}", e);
         }
     }
@@ -23,20 +23,18 @@ impl Drop for Stream {
 impl Stream {
     /// Create a new non-blocking CUDA stream on the current context
     pub fn new(context: ContextRef) -> Self {
-        let stream = unsafe {
-            contexted_new!(
-                &context,
-                cuStreamCreate,
-                CUstream_flags::CU_STREAM_NON_BLOCKING as u32
-            )
-        }
+        let stream = contexted_call!(
+            &context,
+            stream_create,
+            CUstream_flags::CU_STREAM_NON_BLOCKING as u32
+        )
         .expect("Failed to create CUDA stream");
         Stream { context, stream }
     }
 
     /// Check all tasks in this stream have been completed
     pub fn query(&self) -> bool {
-        match unsafe { contexted_call!(self, cuStreamQuery, self.stream) } {
+        match unsafe { contexted_call!(self, stream_query, self.stream) } {
             Ok(_) => true,
             Err(AccelError::AsyncOperationNotReady) => false,
             Err(e) => panic!("Unknown error is happened while cuStreamQuery: {:?}", e),
@@ -45,7 +43,7 @@ impl Stream {
 
     /// Wait until all tasks in this stream have been completed
     pub fn sync(&self) -> Result<()> {
-        unsafe { contexted_call!(self, cuStreamSynchronize, self.stream) }?;
+        unsafe { contexted_call!(self, stream_synchronize, self.stream) }?;
         Ok(())
     }
 
@@ -56,7 +54,7 @@ impl Stream {
 
     /// Wait event to sync another stream
     pub fn wait_event(&mut self, event: &Event) {
-        unsafe { contexted_call!(self, cuStreamWaitEvent, self.stream, event.event, 0) }
+        unsafe { contexted_call!(self, stream_wait_event, self.stream, event.event, 0) }
             .expect("Failed to register an CUDA event waiting on CUDA stream");
     }
 }
@@ -72,7 +70,7 @@ unsafe impl Send for Event {}
 
 impl Drop for Event {
     fn drop(&mut self) {
-        if let Err(e) = unsafe { contexted_call!(self, cuEventDestroy_v2, self.event) } {
+        if let Err(e) = unsafe { contexted_call!(self, event_destroy, self.event) } {
             log::error!("Failed to delete CUDA event: {:?}", e);
         }
     }
@@ -80,25 +78,23 @@ impl Drop for Event {
 
 impl Event {
     pub fn new(context: ContextRef) -> Self {
-        let event = unsafe {
-            contexted_new!(
-                &context,
-                cuEventCreate,
-                CUevent_flags_enum::CU_EVENT_BLOCKING_SYNC as u32
-            )
-        }
+        let event = contexted_call!(
+            &context,
+            event_create,
+            CUevent_flags_enum::CU_EVENT_BLOCKING_SYNC as u32
+        )
         .expect("Failed to create CUDA event");
         Event { context, event }
     }
 
-    pub fn record(&mut self, stream: &mut Stream) {
-        unsafe { contexted_call!(self, cuEventRecord, self.event, stream.stream) }
+    pub fn record(&mut self, stream: &Stream) {
+        unsafe { contexted_call!(self, event_record, self.event, stream.stream) }
             .expect("Failed to set event record");
     }
 
     /// Query if the event has occur, returns true if already occurs
     pub fn query(&self) -> bool {
-        match unsafe { contexted_call!(self, cuEventQuery, self.event) } {
+        match unsafe { contexted_call!(self, event_query, self.event) } {
             Ok(_) => true,
             Err(AccelError::AsyncOperationNotReady) => false,
             Err(e) => panic!("Unknown error occurs while cuEventQuery: {:?}", e),
@@ -107,9 +103,14 @@ impl Event {
 
     /// Wait until the event occurs with blocking
     pub fn sync(&self) -> Result<()> {
-        unsafe { contexted_call!(self, cuEventSynchronize, self.event) }?;
+        unsafe { contexted_call!(self, event_synchronize, self.event) }?;
         Ok(())
     }
+
+    /// Consume and wait until the event occurs without blocking the executor
+    pub async fn into_future(self) -> Result<()> {
+        tokio::task::spawn_blocking(move || self.sync()).await?
+    }
 }
 
 #[cfg(test)]
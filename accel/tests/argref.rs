@@ -8,11 +8,11 @@ fn f(a: &i32, b: &mut i32) {
 }
 
 #[test]
-fn mut_ref_dev() -> error::Result<()> {
+fn mut_ref_managed() -> error::Result<()> {
     let device = Device::nth(0)?;
     let ctx = device.create_context();
-    let mut a = DeviceMemory::<i32>::zeros(&ctx, 1);
-    let mut b = DeviceMemory::<i32>::zeros(&ctx, 1);
+    let mut a = ManagedMemory::<i32>::zeros(&ctx, 1);
+    let mut b = ManagedMemory::<i32>::zeros(&ctx, 1);
     a[0] = 1;
     f(&ctx, 1, 1, (&a[0], &mut b[0]))?;
     assert_eq!(a, b);
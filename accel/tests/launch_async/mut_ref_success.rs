@@ -13,9 +13,9 @@ async fn main() -> error::Result<()> {
     let device = Device::nth(0)?;
     let ctx = device.create_context();
     let n = 16;
-    let mut a = DeviceMemory::<u32>::zeros(&ctx, n);
-    let mut b = DeviceMemory::<u32>::zeros(&ctx, n);
-    let mut c = DeviceMemory::<u32>::zeros(&ctx, n);
+    let mut a = ManagedMemory::<u32>::zeros(&ctx, n);
+    let mut b = ManagedMemory::<u32>::zeros(&ctx, n);
+    let mut c = ManagedMemory::<u32>::zeros(&ctx, n);
 
     for i in 0..n {
         a[i] = i as u32;
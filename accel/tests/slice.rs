@@ -26,6 +26,17 @@ fn slice_to_pointer_dev() -> error::Result<()> {
     let n = 12;
     let mut a = DeviceMemory::<i32>::zeros(&ctx, n);
     set1(&ctx, 1, n, (&mut a, n))?;
+    assert_eq!(a.to_vec(), vec![1_i32; n]);
+    Ok(())
+}
+
+#[test]
+fn slice_to_pointer_managed() -> error::Result<()> {
+    let device = Device::nth(0)?;
+    let ctx = device.create_context();
+    let n = 12;
+    let mut a = ManagedMemory::<i32>::zeros(&ctx, n);
+    set1(&ctx, 1, n, (&mut a, n))?;
     assert_eq!(a.as_slice(), vec![1_i32; n].as_slice());
     Ok(())
 }
@@ -41,3 +52,18 @@ fn slice_to_pointer_registered() -> error::Result<()> {
     assert_eq!(a.as_slice(), vec![1_i32; n].as_slice());
     Ok(())
 }
+
+#[test]
+fn device_slice_to_pointer() -> error::Result<()> {
+    let device = Device::nth(0)?;
+    let ctx = device.create_context();
+    let n = 12;
+    let mut a = DeviceMemory::<i32>::zeros(&ctx, n);
+    let mut view = a.as_device_slice_mut();
+    let mut tail = view.slice_mut(4..);
+    set1(&ctx, 1, n, (&mut tail, n - 4))?;
+    let mut expected = vec![1_i32; n];
+    expected[..4].copy_from_slice(&[0; 4]);
+    assert_eq!(a.to_vec(), expected);
+    Ok(())
+}
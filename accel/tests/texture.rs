@@ -0,0 +1,58 @@
+use accel::*;
+
+#[kernel]
+unsafe fn fetch(tex: u64, out: *mut f32, width: usize, height: usize) {
+    let i = accel_core::index() as usize;
+    if i < width * height {
+        let (x, y) = ((i % width) as f32 + 0.5, (i / width) as f32 + 0.5);
+        let texel: [f32; 4] = accel_core::tex2d(tex, x, y);
+        *out.add(i) = texel[0];
+    }
+}
+
+#[kernel]
+unsafe fn store(surf: u64, width: usize, height: usize) {
+    let i = accel_core::index() as usize;
+    if i < width * height {
+        let (x, y) = (i % width, i / width);
+        accel_core::surf2d_write(surf, x as i32, y as i32, i as f32);
+    }
+}
+
+#[test]
+fn texture_fetch() -> error::Result<()> {
+    let device = Device::nth(0)?;
+    let ctx = device.create_context();
+    let (width, height) = (8, 4);
+    let mut array = Array::<f32, Ix2>::zeros(&ctx, (width, height).into());
+    let input: Vec<f32> = (0..width * height).map(|i| i as f32).collect();
+    array.copy_from(input.as_slice());
+
+    let texture = TextureObject::new(array, TextureDesc::new())?;
+    let mut out = ManagedMemory::<f32>::zeros(&ctx, width * height);
+    fetch(&ctx, 1, width * height, (&texture, &mut out, width, height))?;
+    assert_eq!(out.as_slice(), input.as_slice());
+    Ok(())
+}
+
+#[test]
+fn surface_store() -> error::Result<()> {
+    let device = Device::nth(0)?;
+    let ctx = device.create_context();
+    let (width, height) = (8, 4);
+    let array = unsafe {
+        Array::<f32, Ix2>::uninitialized_with_flags(
+            &ctx,
+            (width, height).into(),
+            ArrayFlag::SURFACE_LDST,
+        )
+    };
+    let mut surface = SurfaceObject::new(array)?;
+    store(&ctx, 1, width * height, (&mut surface, width, height))?;
+
+    let mut output = vec![0.0_f32; width * height];
+    output.copy_from(surface.array());
+    let expected: Vec<f32> = (0..width * height).map(|i| i as f32).collect();
+    assert_eq!(output, expected);
+    Ok(())
+}